@@ -203,7 +203,7 @@ impl TaskBattleList {
         TaskSimpleAction::create(
             move |client| {
                 client.connection.send_packet(&packets::s2c::BattleInfoJoinBattle {
-                    var_625: team,
+                    team,
                 })?;
                 Ok(())
             }, 
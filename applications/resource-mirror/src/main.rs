@@ -0,0 +1,80 @@
+use std::{collections::BTreeSet, path::PathBuf};
+
+use anyhow::Context;
+use clap::Parser;
+use tracing::{info, Level};
+use tracing_subscriber::EnvFilter;
+
+mod registry;
+use registry::*;
+
+mod mirror;
+use mirror::*;
+
+#[derive(Parser, Debug)]
+struct Args {
+    /// Registry files to mirror. Either the json of captured `ResourceLoaderRegisterResources`
+    /// packets or the registry files loaded by the server.
+    #[arg(required = true)]
+    registry: Vec<PathBuf>,
+
+    /// Base URL (http or https) or local directory the resources are mirrored from
+    #[arg(short, long)]
+    source: String,
+
+    /// Directory of the local mirror
+    #[arg(short, long)]
+    output: PathBuf,
+
+    /// Write the server registry of all successfully mirrored resources to this file
+    #[arg(short, long)]
+    registry_output: Option<PathBuf>,
+
+    /// Amount of resources mirrored in parallel
+    #[arg(long, default_value_t = 8)]
+    concurrency: usize,
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt()
+        .with_max_level(Level::TRACE)
+        .with_env_filter(EnvFilter::from_default_env())
+        .init();
+
+    let args = Args::parse();
+
+    /* registries of multiple captures overlap, mirror every resource version once */
+    let mut known_resources = BTreeSet::new();
+    let mut resources = Vec::new();
+    for path in args.registry.iter() {
+        let payload = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        let registry = parse_registry(&payload)
+            .with_context(|| format!("failed to parse {}", path.display()))?;
+
+        for resource in registry {
+            if known_resources.insert((resource.id()?, resource.version()?)) {
+                resources.push(resource);
+            }
+        }
+    }
+
+    info!("Mirroring {} resources from {} into {}", resources.len(), args.source, args.output.display());
+    let mirror = Mirror::new(Source::parse(&args.source), args.output.clone());
+    let (mirrored, stats) = mirror.mirror_all(&resources, args.concurrency).await;
+    info!("Fetched {} resources, {} were already present, {} failed.", stats.fetched, stats.present, stats.failed);
+
+    if let Some(path) = &args.registry_output {
+        let payload = serde_json::to_string_pretty(&mirrored)?;
+        std::fs::write(path, payload)
+            .with_context(|| format!("failed to write {}", path.display()))?;
+        info!("Wrote the registry of {} resources to {}", mirrored.len(), path.display());
+    }
+
+    if stats.failed > 0 {
+        anyhow::bail!("failed to mirror {} resources", stats.failed);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,282 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use fost_protocol::resources::{build_resource_path, Resource};
+use futures::StreamExt;
+use tracing::{debug, warn};
+
+use crate::registry::resource_files;
+
+/// Location the resources are mirrored from.
+pub enum Source {
+    /// Resource host using the same layout as the client (`<base>/<resource path>/<file name>`).
+    Http { client: reqwest::Client, base: String },
+    /// Local copy of a resource host.
+    Directory(PathBuf),
+}
+
+impl Source {
+    /// Http(s) URLs will be downloaded, everything else is considered a local directory.
+    pub fn parse(source: &str) -> Self {
+        if source.starts_with("http://") || source.starts_with("https://") {
+            Self::Http {
+                client: reqwest::Client::new(),
+                base: source.trim_end_matches('/').to_string(),
+            }
+        } else {
+            let directory = source.strip_prefix("file://").unwrap_or(source);
+            Self::Directory(PathBuf::from(directory))
+        }
+    }
+
+    /// Fetch the file. Returns `None` if the source does not have the file.
+    async fn fetch(&self, path: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        match self {
+            Self::Http { client, base } => {
+                let url = format!("{}/{}", base, path);
+                let response = client.get(&url)
+                    .send().await
+                    .with_context(|| format!("failed to request {}", url))?;
+                if response.status() == reqwest::StatusCode::NOT_FOUND {
+                    return Ok(None);
+                }
+
+                let payload = response.error_for_status()?
+                    .bytes().await?;
+                Ok(Some(payload.to_vec()))
+            },
+            Self::Directory(directory) => {
+                match tokio::fs::read(directory.join(path)).await {
+                    Ok(payload) => Ok(Some(payload)),
+                    Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                    Err(error) => Err(error.into()),
+                }
+            }
+        }
+    }
+}
+
+/// Check if the payload looks like a valid file of its type.
+/// The registry contains no checksums so this catches truncated files and error pages.
+pub fn verify_file(name: &str, payload: &[u8]) -> bool {
+    if payload.is_empty() {
+        return false;
+    }
+
+    let extension = Path::new(name).extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or_default();
+    match extension {
+        "swf" => payload.starts_with(b"FWS") || payload.starts_with(b"CWS") || payload.starts_with(b"ZWS"),
+        "jpg" | "jpeg" => payload.starts_with(&[0xFF, 0xD8, 0xFF]),
+        "png" => payload.starts_with(b"\x89PNG\r\n\x1a\n"),
+        "mp3" => payload.starts_with(b"ID3") || (payload.len() > 1 && payload[0] == 0xFF && payload[1] & 0xE0 == 0xE0),
+        "xml" => {
+            let payload = payload.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(payload);
+            payload.iter()
+                .find(|byte| !byte.is_ascii_whitespace())
+                .map_or(false, |byte| *byte == b'<')
+        },
+        _ => true,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MirrorOutcome {
+    /// At least one file has been downloaded or copied.
+    Fetched,
+    /// All files were already present in the mirror.
+    Present,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MirrorStats {
+    pub fetched: usize,
+    pub present: usize,
+    pub failed: usize,
+}
+
+pub struct Mirror {
+    source: Source,
+    directory: PathBuf,
+}
+
+impl Mirror {
+    pub fn new(source: Source, directory: PathBuf) -> Self {
+        Self {
+            source,
+            directory,
+        }
+    }
+
+    /// Mirror all files of the resource. Files already present and valid will be skipped.
+    pub async fn mirror_resource(&self, resource: &Resource) -> anyhow::Result<MirrorOutcome> {
+        let resource_path = build_resource_path(resource.id()?, resource.version()?);
+        let resource_directory = self.directory.join(&resource_path);
+
+        let mut outcome = MirrorOutcome::Present;
+        for file in resource_files(resource)? {
+            let file_path = resource_directory.join(&file.name);
+            if let Ok(payload) = tokio::fs::read(&file_path).await {
+                if verify_file(&file.name, &payload) {
+                    continue;
+                }
+
+                debug!("Replacing invalid file {}", file_path.display());
+            }
+
+            let remote_path = format!("{}/{}", resource_path, file.name);
+            let payload = match self.source.fetch(&remote_path).await? {
+                Some(payload) => payload,
+                None if file.optional => continue,
+                None => anyhow::bail!("{} does not exist", remote_path),
+            };
+
+            if !verify_file(&file.name, &payload) {
+                anyhow::bail!("{} is not a valid {} file", remote_path, file.name);
+            }
+
+            /* write to a temporary file first so interrupted runs do not leave partial files behind */
+            tokio::fs::create_dir_all(&resource_directory).await?;
+            let partial_path = resource_directory.join(format!("{}.part", file.name));
+            tokio::fs::write(&partial_path, &payload).await?;
+            tokio::fs::rename(&partial_path, &file_path).await?;
+            outcome = MirrorOutcome::Fetched;
+        }
+
+        Ok(outcome)
+    }
+
+    /// Mirror the resources. Returns the successfully mirrored resources in their original order.
+    pub async fn mirror_all(&self, resources: &[Resource], concurrency: usize) -> (Vec<Resource>, MirrorStats) {
+        let results = futures::stream::iter(resources)
+            .map(|resource| async move { (resource, self.mirror_resource(resource).await) })
+            .buffered(concurrency.max(1))
+            .collect::<Vec<_>>()
+            .await;
+
+        let mut stats = MirrorStats::default();
+        let mut mirrored = Vec::with_capacity(results.len());
+        for (resource, result) in results {
+            match result {
+                Ok(MirrorOutcome::Fetched) => stats.fetched += 1,
+                Ok(MirrorOutcome::Present) => stats.present += 1,
+                Err(error) => {
+                    warn!("Failed to mirror resource {}.{}: {:#}", resource.idhigh, resource.idlow, error);
+                    stats.failed += 1;
+                    continue;
+                }
+            }
+
+            mirrored.push(resource.clone());
+        }
+
+        (mirrored, stats)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashMap, net::SocketAddr, path::PathBuf, sync::{Arc, atomic::{AtomicUsize, Ordering}}};
+
+    use axum::{Router, routing::get, extract::{Path, State}, http::StatusCode};
+    use fost_protocol::resources::{Resource, ResourceType};
+    use super::{Mirror, MirrorStats, Source, verify_file};
+
+    const JPEG: &[u8] = b"\xFF\xD8\xFF\xE0 image";
+
+    #[derive(Clone)]
+    struct StandIn {
+        files: Arc<HashMap<String, Vec<u8>>>,
+        requests: Arc<AtomicUsize>,
+    }
+
+    async fn serve_file(State(stand_in): State<StandIn>, Path(path): Path<String>) -> Result<Vec<u8>, StatusCode> {
+        stand_in.requests.fetch_add(1, Ordering::Relaxed);
+        stand_in.files.get(path.trim_start_matches('/'))
+            .cloned()
+            .ok_or(StatusCode::NOT_FOUND)
+    }
+
+    /// Local HTTP server standing in for the resource host.
+    fn start_stand_in(files: &[(&str, &[u8])]) -> (SocketAddr, Arc<AtomicUsize>) {
+        let stand_in = StandIn {
+            files: Arc::new(files.iter().map(|(path, payload)| (path.to_string(), payload.to_vec())).collect()),
+            requests: Default::default(),
+        };
+        let requests = stand_in.requests.clone();
+
+        let router = Router::new()
+            .route("/*path", get(serve_file))
+            .with_state(stand_in);
+        let server = axum::Server::bind(&"127.0.0.1:0".parse().unwrap())
+            .serve(router.into_make_service());
+        let address = server.local_addr();
+        tokio::spawn(server);
+
+        (address, requests)
+    }
+
+    fn image(id: u32, alpha: bool) -> Resource {
+        Resource {
+            alpha: Some(alpha),
+            ..Resource::new(id as u64, 1, ResourceType::Image, false)
+        }
+    }
+
+    fn mirror_directory(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("fost-mirror-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&directory);
+        directory
+    }
+
+    #[test]
+    fn test_verify_file() {
+        assert!(verify_file("image.jpg", JPEG));
+        assert!(!verify_file("image.jpg", b"<html>Not Found</html>"));
+        assert!(verify_file("library.swf", b"CWS\x0a"));
+        assert!(verify_file("images.xml", b"\xEF\xBB\xBF  <images/>"));
+        assert!(!verify_file("object.3ds", b""));
+    }
+
+    #[tokio::test]
+    async fn test_mirror_http() {
+        let (address, requests) = start_stand_in(&[
+            ("0/0/1/1/1/image.jpg", JPEG),
+            ("0/0/1/2/1/image.jpg", JPEG),
+            ("0/0/1/2/1/alpha.jpg", JPEG),
+            ("0/0/1/3/1/image.jpg", b"<html>error</html>"),
+        ]);
+        let directory = mirror_directory("http");
+        let mirror = Mirror::new(Source::parse(&format!("http://{}/", address)), directory.clone());
+
+        /* the stand in answers 259 with an error page and does not know 260 */
+        let resources = vec![image(257, false), image(258, true), image(259, false), image(260, false)];
+        let (mirrored, stats) = mirror.mirror_all(&resources, 2).await;
+        assert_eq!(stats, MirrorStats { fetched: 2, present: 0, failed: 2 });
+        assert_eq!(mirrored, vec![image(257, false), image(258, true)]);
+        assert_eq!(std::fs::read(directory.join("0/0/1/2/1/alpha.jpg")).unwrap(), JPEG);
+
+        /* files already present are not requested again, broken files are replaced */
+        std::fs::write(directory.join("0/0/1/1/1/image.jpg"), b"truncated").unwrap();
+        let requests_before = requests.load(Ordering::Relaxed);
+        let (_, stats) = mirror.mirror_all(&resources[..2], 2).await;
+        assert_eq!(stats, MirrorStats { fetched: 1, present: 1, failed: 0 });
+        assert_eq!(requests.load(Ordering::Relaxed), requests_before + 1);
+        assert_eq!(std::fs::read(directory.join("0/0/1/1/1/image.jpg")).unwrap(), JPEG);
+    }
+
+    #[tokio::test]
+    async fn test_mirror_directory() {
+        let source = mirror_directory("source");
+        std::fs::create_dir_all(source.join("0/0/1/1/1")).unwrap();
+        std::fs::write(source.join("0/0/1/1/1/image.jpg"), JPEG).unwrap();
+
+        let directory = mirror_directory("copy");
+        let mirror = Mirror::new(Source::parse(source.to_str().unwrap()), directory.clone());
+        let (mirrored, stats) = mirror.mirror_all(&[image(257, false)], 1).await;
+        assert_eq!(stats, MirrorStats { fetched: 1, present: 0, failed: 0 });
+        assert_eq!(mirrored.len(), 1);
+        assert_eq!(std::fs::read(directory.join("0/0/1/1/1/image.jpg")).unwrap(), JPEG);
+    }
+}
@@ -0,0 +1,112 @@
+use anyhow::Context;
+use fost_protocol::resources::{Resource, ResourceRegistry, ResourceType};
+use serde_json::Value;
+
+/// File belonging to a resource.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResourceFile {
+    pub name: String,
+    /// The client does not require the file, e.g. textures of a model.
+    pub optional: bool,
+}
+
+impl ResourceFile {
+    fn required(name: &str) -> Self {
+        Self { name: name.to_string(), optional: false }
+    }
+
+    fn optional(name: &str) -> Self {
+        Self { name: name.to_string(), optional: true }
+    }
+}
+
+/// File names are taken from the (possibly captured) registry and must not escape the resource directory.
+/// Accepts the same names as the resource server of the server.
+fn is_plain_file_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('.')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '_' || c == '-')
+}
+
+/// Files the client requests for the resource.
+pub fn resource_files(resource: &Resource) -> anyhow::Result<Vec<ResourceFile>> {
+    let files = match resource.resource_type()? {
+        ResourceType::SwfLibrary | ResourceType::MovieClip => vec![ResourceFile::required("library.swf")],
+        ResourceType::A3D => vec![ResourceFile::required("object.a3d"), ResourceFile::optional("images.xml")],
+        ResourceType::Sound => vec![ResourceFile::required("sound.mp3")],
+        ResourceType::Map => vec![ResourceFile::required("map.xml")],
+        ResourceType::PropLib => vec![ResourceFile::required("library.tara")],
+        ResourceType::Model3DS | ResourceType::Tanks3DS => vec![ResourceFile::required("object.3ds"), ResourceFile::optional("images.xml")],
+        ResourceType::Image => {
+            let mut files = vec![ResourceFile::required("image.jpg")];
+            if resource.alpha.context("missing alpha flag")? {
+                files.push(ResourceFile::required("alpha.jpg"));
+            }
+            files
+        },
+        ResourceType::MultiframeImage => vec![ResourceFile::required("image.jpg")],
+        ResourceType::LocalizedImage => {
+            let file_names = resource.file_names.as_ref().context("missing filenames")?;
+            if let Some(name) = file_names.iter().find(|name| !is_plain_file_name(name)) {
+                anyhow::bail!("invalid file name {:?}", name);
+            }
+
+            file_names.iter()
+                .map(|name| ResourceFile::required(name))
+                .collect()
+        },
+    };
+
+    Ok(files)
+}
+
+/// Parse a registry. Accepts the json of a captured `ResourceLoaderRegisterResources`
+/// packet (`{ "resources": [ ... ] }`) as well as a server registry file (`[ ... ]`).
+pub fn parse_registry(payload: &str) -> anyhow::Result<Vec<Resource>> {
+    let payload = serde_json::from_str::<Value>(payload)?;
+    let resources = match payload {
+        Value::Array(_) => serde_json::from_value::<Vec<Resource>>(payload)?,
+        Value::Object(_) => serde_json::from_value::<ResourceRegistry>(payload)?.resources,
+        _ => anyhow::bail!("expected a resource list"),
+    };
+
+    Ok(resources)
+}
+
+#[cfg(test)]
+mod test {
+    use fost_protocol::resources::{Resource, ResourceType};
+
+    use super::{parse_registry, resource_files, ResourceFile};
+
+    #[test]
+    fn test_parse_registry() {
+        let resource = r#"{ "idhigh": "1", "idlow": 1395316, "versionhigh": "0", "versionlow": 10, "lazy": false, "alpha": true, "type": 10 }"#;
+        let packet = parse_registry(&format!(r#"{{ "resources": [ {} ] }}"#, resource)).unwrap();
+        let registry = parse_registry(&format!("[ {} ]", resource)).unwrap();
+        assert_eq!(packet, registry);
+
+        assert_eq!(packet[0].id().unwrap(), (1 << 32) | 1395316);
+        assert_eq!(packet[0].version().unwrap(), 10);
+        assert_eq!(
+            resource_files(&packet[0]).unwrap(),
+            vec![ResourceFile::required("image.jpg"), ResourceFile::required("alpha.jpg")]
+        );
+    }
+
+    #[test]
+    fn test_localized_file_names() {
+        let mut resource = Resource::new(1395316, 1, ResourceType::LocalizedImage, false);
+        resource.alpha = Some(false);
+        resource.file_names = Some(vec!["en.jpg".to_string(), "ru.jpg".to_string()]);
+        assert_eq!(
+            resource_files(&resource).unwrap(),
+            vec![ResourceFile::required("en.jpg"), ResourceFile::required("ru.jpg")]
+        );
+
+        for name in ["../../x", "/etc/x", "..", "sub/en.jpg", "sub\\en.jpg", ""] {
+            resource.file_names = Some(vec!["en.jpg".to_string(), name.to_string()]);
+            assert!(resource_files(&resource).is_err(), "{}", name);
+        }
+    }
+}
@@ -131,6 +131,9 @@ impl Session {
         let handler_id = packet_handler.register_handler(HandlerAwaitMatching{ matcher, sender: Some(tx) });
 
         tokio::select! {
+            /* check the result first, polling the session would handle the next packet */
+            biased;
+
             result = rx => {
                 /* since the channel completed the packet handler will be removed due to polling */
                 Ok(result?)
@@ -13,6 +13,11 @@ enum PacketDirection {
 #[serde_as]
 #[derive(Serialize, Deserialize, Debug)]
 struct PacketDescription {
+    /// Packet name (without the model prefix) if it differs from the key.
+    /// Allows a C2S and a S2C packet to share the same name.
+    #[serde(default)]
+    name: Option<String>,
+
     direction: PacketDirection,
     
     packet_id: i32,
@@ -199,7 +204,7 @@ fn main() -> anyhow::Result<()> {
     let flat_packets = packet_schema.iter()
         .map(|(m, v)| {
             v.packets.iter()
-                .map(move |(p, v)| (format!("{}{}", m, p), v))
+                .map(move |(p, v)| (format!("{}{}", m, v.name.as_ref().unwrap_or(p)), v))
         })
         .flatten()
         .collect::<Vec<_>>();
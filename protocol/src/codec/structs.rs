@@ -42,11 +42,35 @@ impl Codeable for ResourceReference {
         self.resource_id.decode(reader)
     }
 }
+impl ResourceReference {
+    /// Path of the resource files. The version is not part of the reference and must be looked up in the registry.
+    pub fn resource_path(&self, version: u64) -> String {
+        resources::build_resource_path(self.resource_id as u64, version)
+    }
+
+    /// Debug output including the resource path of the version.
+    pub fn debug_with_version(&self, version: u64) -> ResourceReferenceDebug<'_> {
+        ResourceReferenceDebug { reference: self, version }
+    }
+}
 impl Debug for ResourceReference {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("ResourceReference")
             .field("resource_id", &self.resource_id)
-            .field("url", &resources::build_resource_path(self.resource_id as u64, 1))
+            .finish()
+    }
+}
+
+pub struct ResourceReferenceDebug<'a> {
+    reference: &'a ResourceReference,
+    version: u64,
+}
+impl Debug for ResourceReferenceDebug<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ResourceReference")
+            .field("resource_id", &self.reference.resource_id)
+            .field("version", &self.version)
+            .field("url", &self.reference.resource_path(self.version))
             .finish()
     }
 }
@@ -64,15 +88,15 @@ codec_struct!(class_14 {
 
 codec_struct!(WeeklyQuestRewardItem {
     count: i32
-    method_2511: ResourceReference
+    item_image: ResourceReference
 });
 
 codec_struct!(WeeklyQuestDescription {
-    method_2704: i32
-    name_23: i32
-    method_798: bool
-    name_35: ResourceReference
-    name_61: ResourceReference
+    current_quest_level: i32
+    current_quest_streak: i32
+    done_for_today: bool
+    quest_image: ResourceReference
+    reward_image: ResourceReference
 });
 
 codec_struct!(UserStatus {
@@ -90,8 +114,8 @@ codec_struct!(UserStat {
 });
 
 codec_struct!(UserReward {
-    name_6: i32
-    name_59: i32
+    beginner_bonus_reward: i32
+    premium_bonus_reward: i32
     reward: i32
     user_id: String
 });
@@ -304,13 +328,13 @@ codec_struct!(DailyQuestPrizeInfo {
 });
 
 codec_struct!(DailyQuestInfo {
-    method_2718: bool
+    can_skip_for_free: bool
     description: String
-    method_2630: i32
+    finish_criteria: i32
     image: ResourceReference
-    method_562: Vec<DailyQuestPrizeInfo>
+    prizes: Vec<DailyQuestPrizeInfo>
     progress: i32
-    method_2366: i32
+    quest_id: i32
 });
 
 codec_struct!(ControlPointsCC {
@@ -384,6 +408,12 @@ codec_struct!(CaptureTheFlagCC {
     name_8: CaptureTheFlagSoundFX
 });
 
+codec_struct!(BonusSpawnData {
+    bonus_id: String
+    position: Option<Vector3<f32>>
+    life_time: i32
+});
+
 codec_struct!(BonusInfoCC {
     bottom_text: String
     image: ResourceReference
@@ -397,21 +427,21 @@ codec_struct!(BattleNotifierData {
 
 codec_struct!(BattleMineCC {
     method_1937: ResourceReference
-    method_1406: i32
-    method_2407: Vec<BattleMine>
+    activate_time_msec: i32
+    battle_mines: Vec<BattleMine>
     method_2285: ResourceReference
     method_2634: ResourceReference
     method_2393: ResourceReference
     explosion_mark_texture: ResourceReference
     explosion_sound: ResourceReference
-    method_2024: f32
+    far_visibility_radius: f32
     method_2226: ResourceReference
     method_1764: ResourceReference
     impact_force: f32
     method_2618: ResourceReference
-    name_45: f32
+    min_distance_from_base: f32
     method_2145: ResourceReference
-    method_472: f32
+    near_visibility_radius: f32
     radius: f32
     method_1957: ResourceReference
 });
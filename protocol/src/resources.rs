@@ -1,16 +1,226 @@
-use std::io::Cursor;
+use std::{io::Cursor, num::ParseIntError};
 
 use byteorder::{ReadBytesExt, BigEndian};
+use serde::{Serialize, Deserialize};
+use thiserror::Error;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum ResourceType {
     SwfLibrary = 1,
     A3D = 2,
     MovieClip = 3,
     Sound = 4,
+    Map = 7,
+    PropLib = 8,
     Model3DS = 9,
     Image = 10,
     MultiframeImage = 11,
     LocalizedImage = 13,
+    Tanks3DS = 17,
+}
+
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[error("unknown resource type {0}")]
+pub struct UnknownResourceType(pub u32);
+
+impl TryFrom<u32> for ResourceType {
+    type Error = UnknownResourceType;
+
+    fn try_from(value: u32) -> Result<Self, Self::Error> {
+        Ok(match value {
+            1 => Self::SwfLibrary,
+            2 => Self::A3D,
+            3 => Self::MovieClip,
+            4 => Self::Sound,
+            7 => Self::Map,
+            8 => Self::PropLib,
+            9 => Self::Model3DS,
+            10 => Self::Image,
+            11 => Self::MultiframeImage,
+            13 => Self::LocalizedImage,
+            17 => Self::Tanks3DS,
+            _ => return Err(UnknownResourceType(value)),
+        })
+    }
+}
+
+impl From<ResourceType> for u32 {
+    fn from(value: ResourceType) -> Self {
+        value as u32
+    }
+}
+
+/// Type of a resource together with the attributes the type requires.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ResourceInfo {
+    SwfLibrary,
+    A3D,
+    MovieClip,
+    Sound,
+    Model3DS,
+
+    Map,
+    PropLib,
+
+    Image { alpha: bool },
+    MultiframeImage { fps: u32, height: u32, width: u32, frames: u32 },
+    LocalizedImage { file_names: Vec<String>, alpha: bool },
+
+    Tanks3DS
+}
+
+impl ResourceInfo {
+    pub fn resource_type(&self) -> ResourceType {
+        match self {
+            ResourceInfo::SwfLibrary => ResourceType::SwfLibrary,
+            ResourceInfo::A3D => ResourceType::A3D,
+            ResourceInfo::MovieClip => ResourceType::MovieClip,
+            ResourceInfo::Sound => ResourceType::Sound,
+            ResourceInfo::Model3DS => ResourceType::Model3DS,
+            ResourceInfo::Map => ResourceType::Map,
+            ResourceInfo::PropLib => ResourceType::PropLib,
+            ResourceInfo::Image { .. } => ResourceType::Image,
+            ResourceInfo::MultiframeImage { .. } => ResourceType::MultiframeImage,
+            ResourceInfo::LocalizedImage { .. } => ResourceType::LocalizedImage,
+            ResourceInfo::Tanks3DS => ResourceType::Tanks3DS,
+        }
+    }
+}
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum InvalidResourceInfo {
+    #[error(transparent)]
+    UnknownType(#[from] UnknownResourceType),
+    #[error("missing {0}")]
+    MissingAttribute(&'static str),
+}
+
+/// Entry of the resource registry json sent with `ResourceLoaderRegisterResources`.
+/// Ids and versions are split into a high (string) and a low (number) part.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Resource {
+    pub idhigh: String,
+    pub idlow: u32,
+
+    pub versionhigh: String,
+    pub versionlow: u32,
+
+    #[serde(rename = "type")]
+    pub resource_type: u32,
+    pub lazy: bool,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub alpha: Option<bool>,
+
+    /// The client expects the width of multiframe images as `weight`.
+    #[serde(rename = "weight")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub width: Option<u32>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub height: Option<u32>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub num_frames: Option<u32>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub fps: Option<u32>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub file_names: Option<Vec<String>>,
+}
+
+impl Resource {
+    pub fn new(id: u64, version: u64, resource_type: ResourceType, lazy: bool) -> Self {
+        Self {
+            idhigh: format!("{}", id >> 32),
+            idlow: (id & 0xFFFFFFFF) as u32,
+
+            versionhigh: format!("{}", version >> 32),
+            versionlow: (version & 0xFFFFFFFF) as u32,
+
+            resource_type: resource_type.into(),
+            lazy,
+            ..Default::default()
+        }
+    }
+
+    pub fn id(&self) -> Result<u64, ParseIntError> {
+        Ok((self.idhigh.parse::<u64>()? << 32) | self.idlow as u64)
+    }
+
+    pub fn version(&self) -> Result<u64, ParseIntError> {
+        Ok((self.versionhigh.parse::<u64>()? << 32) | self.versionlow as u64)
+    }
+
+    pub fn resource_type(&self) -> Result<ResourceType, UnknownResourceType> {
+        ResourceType::try_from(self.resource_type)
+    }
+
+    /// Create the entry setting the attributes of the resource type.
+    pub fn with_info(id: u64, version: u64, info: &ResourceInfo, lazy: bool) -> Self {
+        let mut resource = Self::new(id, version, info.resource_type(), lazy);
+        match info {
+            ResourceInfo::Image { alpha } => {
+                resource.alpha = Some(*alpha);
+            },
+            ResourceInfo::MultiframeImage { fps, height, width, frames } => {
+                resource.fps = Some(*fps);
+                resource.height = Some(*height);
+                resource.width = Some(*width);
+                resource.num_frames = Some(*frames);
+            },
+            ResourceInfo::LocalizedImage { alpha, file_names } => {
+                resource.alpha = Some(*alpha);
+                resource.file_names = Some(file_names.clone());
+            },
+            _ => {}
+        }
+        resource
+    }
+
+    /// Type of the resource together with the attributes the type requires.
+    pub fn info(&self) -> Result<ResourceInfo, InvalidResourceInfo> {
+        let missing = InvalidResourceInfo::MissingAttribute;
+        Ok(match self.resource_type()? {
+            ResourceType::SwfLibrary => ResourceInfo::SwfLibrary,
+            ResourceType::A3D => ResourceInfo::A3D,
+            ResourceType::MovieClip => ResourceInfo::MovieClip,
+            ResourceType::Sound => ResourceInfo::Sound,
+
+            ResourceType::Map => ResourceInfo::Map,
+            ResourceType::PropLib => ResourceInfo::PropLib,
+
+            ResourceType::Model3DS => ResourceInfo::Model3DS,
+            ResourceType::Image => ResourceInfo::Image {
+                alpha: self.alpha.ok_or(missing("alpha flag"))?
+            },
+            ResourceType::MultiframeImage => ResourceInfo::MultiframeImage {
+                fps: self.fps.ok_or(missing("fps"))?,
+                height: self.height.ok_or(missing("height"))?,
+                width: self.width.ok_or(missing("width"))?,
+                frames: self.num_frames.ok_or(missing("frames"))?
+            },
+            ResourceType::LocalizedImage => ResourceInfo::LocalizedImage {
+                file_names: self.file_names.clone().ok_or(missing("filenames"))?,
+                alpha: self.alpha.ok_or(missing("alpha flag"))?
+            },
+            ResourceType::Tanks3DS => ResourceInfo::Tanks3DS,
+        })
+    }
+}
+
+/// Json payload of `ResourceLoaderRegisterResources`.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ResourceRegistry {
+    pub resources: Vec<Resource>,
 }
 
 pub fn build_resource_path(resource_id: u64, version: u64) -> String {
@@ -29,7 +239,7 @@ pub fn build_resource_path(resource_id: u64, version: u64) -> String {
 
 #[cfg(test)]
 mod test {
-    use crate::resources::build_resource_path;
+    use crate::{codec::ResourceReference, resources::{build_resource_path, InvalidResourceInfo, Resource, ResourceInfo, ResourceRegistry, ResourceType, UnknownResourceType}};
 
     #[test]
     fn resource_ids() {
@@ -37,10 +247,61 @@ mod test {
             build_resource_path(1395316, 1),
             "0/21/74/116/1"
         );
-        
+
         assert_eq!(
             build_resource_path(1395316, 10),
             "0/21/74/116/12"
         );
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn resource_types() {
+        assert_eq!(ResourceType::try_from(17), Ok(ResourceType::Tanks3DS));
+        assert_eq!(ResourceType::try_from(5), Err(UnknownResourceType(5)));
+        assert_eq!(u32::from(ResourceType::LocalizedImage), 13);
+    }
+
+    #[test]
+    fn resource_json() {
+        let mut resource = Resource::new((3 << 32) | 1395316, 10, ResourceType::MultiframeImage, true);
+        resource.width = Some(64);
+        resource.num_frames = Some(8);
+
+        let json = serde_json::to_value(&ResourceRegistry{ resources: vec![resource.clone()] }).unwrap();
+        assert_eq!(json["resources"][0]["idhigh"], "3");
+        assert_eq!(json["resources"][0]["idlow"], 1395316);
+        assert_eq!(json["resources"][0]["type"], 11);
+        assert_eq!(json["resources"][0]["weight"], 64);
+        assert_eq!(json["resources"][0]["numFrames"], 8);
+        assert!(json["resources"][0].get("alpha").is_none());
+
+        let parsed = serde_json::from_value::<ResourceRegistry>(json).unwrap();
+        assert_eq!(parsed.resources[0], resource);
+        assert_eq!(parsed.resources[0].id(), Ok((3 << 32) | 1395316));
+        assert_eq!(parsed.resources[0].version(), Ok(10));
+    }
+
+    #[test]
+    fn resource_info() {
+        let info = ResourceInfo::MultiframeImage { fps: 25, height: 64, width: 64, frames: 8 };
+        let resource = Resource::with_info(1395316, 1, &info, false);
+        assert_eq!(resource.resource_type, 11);
+        assert_eq!(resource.info(), Ok(info));
+
+        let resource = Resource::new(1395316, 1, ResourceType::Image, false);
+        assert_eq!(resource.info(), Err(InvalidResourceInfo::MissingAttribute("alpha flag")));
+
+        let resource = Resource { resource_type: 5, ..resource };
+        assert_eq!(resource.info(), Err(InvalidResourceInfo::UnknownType(UnknownResourceType(5))));
+    }
+
+    #[test]
+    fn resource_reference_debug() {
+        let reference = ResourceReference{ resource_id: 1395316 };
+        assert_eq!(format!("{:?}", reference), "ResourceReference { resource_id: 1395316 }");
+        assert_eq!(
+            format!("{:?}", reference.debug_with_version(10)),
+            r#"ResourceReference { resource_id: 1395316, version: 10, url: "0/21/74/116/12" }"#
+        );
+    }
+}
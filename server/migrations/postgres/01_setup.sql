@@ -0,0 +1,30 @@
+CREATE TABLE "user"(
+        "user_id" VARCHAR(32) NOT NULL PRIMARY KEY,
+        "email" VARCHAR(128) DEFAULT NULL,
+        "email_confirmed" BOOLEAN NOT NULL DEFAULT FALSE,
+
+        "timestamp_register" TIMESTAMPTZ NOT NULL,
+        "timestamp_active" TIMESTAMPTZ NOT NULL,
+
+        "crystals" INT NOT NULL,
+        "double_crystals" TIMESTAMPTZ DEFAULT NULL,
+
+        "experience" INT NOT NULL,
+        "premium" TIMESTAMPTZ DEFAULT NULL
+);
+
+CREATE TABLE "user_authentication"(
+        "user_id" VARCHAR(32) NOT NULL PRIMARY KEY,
+        "login_user" VARCHAR(32),
+        "password_hash" VARCHAR(64),
+        "password_salt" VARCHAR(16),
+        FOREIGN KEY("user_id") REFERENCES "user"("user_id")
+);
+
+CREATE TABLE "user_authentication_token"(
+        "user_id" VARCHAR(32) NOT NULL PRIMARY KEY,
+        "timestamp_created" TIMESTAMPTZ NOT NULL,
+        "timestamp_last_used" TIMESTAMPTZ NOT NULL,
+        "token" VARCHAR(64),
+        FOREIGN KEY("user_id") REFERENCES "user"("user_id")
+);
@@ -0,0 +1,7 @@
+CREATE TABLE "user_item"(
+        "user_id" VARCHAR(32) NOT NULL,
+        "item_id" VARCHAR(64) NOT NULL,
+        "count" INT NOT NULL,
+        PRIMARY KEY("user_id", "item_id"),
+        FOREIGN KEY("user_id") REFERENCES "user"("user_id")
+);
@@ -0,0 +1,12 @@
+ALTER TABLE "user" ADD COLUMN "moderator_level" INT NOT NULL DEFAULT 0;
+
+CREATE TABLE "user_punishment"(
+        "user_id" VARCHAR(32) NOT NULL,
+        "kind" INT NOT NULL,
+        "reason" VARCHAR(256) NOT NULL,
+        "issued_by" VARCHAR(32) NOT NULL,
+        "timestamp_created" TIMESTAMPTZ NOT NULL,
+        "timestamp_expires" TIMESTAMPTZ DEFAULT NULL,
+        PRIMARY KEY("user_id", "kind"),
+        FOREIGN KEY("user_id") REFERENCES "user"("user_id")
+);
@@ -0,0 +1,12 @@
+CREATE TABLE "user_email_token"(
+        "user_id" VARCHAR(32) NOT NULL,
+        "kind" INT NOT NULL,
+        "token" VARCHAR(64) NOT NULL UNIQUE,
+        "email" VARCHAR(128) NOT NULL,
+        "timestamp_created" TIMESTAMPTZ NOT NULL,
+        "timestamp_expires" TIMESTAMPTZ NOT NULL,
+        PRIMARY KEY("user_id", "kind"),
+        FOREIGN KEY("user_id") REFERENCES "user"("user_id")
+);
+
+CREATE INDEX "user_email" ON "user"("email");
@@ -0,0 +1,13 @@
+DROP TABLE "user_authentication_token";
+
+CREATE TABLE "user_authentication_token"(
+        "token_hash" VARCHAR(64) NOT NULL PRIMARY KEY,
+        "user_id" VARCHAR(32) NOT NULL,
+        "timestamp_created" TIMESTAMPTZ NOT NULL,
+        "timestamp_expires" TIMESTAMPTZ NOT NULL,
+        FOREIGN KEY("user_id") REFERENCES "user"("user_id")
+);
+
+CREATE INDEX "user_authentication_token_user" ON "user_authentication_token"("user_id");
+
+ALTER TABLE "user_authentication" ALTER COLUMN "password_hash" TYPE VARCHAR(256);
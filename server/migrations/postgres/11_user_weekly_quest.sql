@@ -0,0 +1,8 @@
+CREATE TABLE "user_weekly_quest"(
+        "user_id" VARCHAR(32) NOT NULL,
+        "level" INT NOT NULL DEFAULT 0,
+        "streak" INT NOT NULL DEFAULT 0,
+        "day" INT NOT NULL,
+        PRIMARY KEY("user_id"),
+        FOREIGN KEY("user_id") REFERENCES "user"("user_id")
+);
@@ -0,0 +1,8 @@
+CREATE TABLE `user_garage_item`(
+        `user_id` VARCHAR(32) NOT NULL,
+        `item_id` VARCHAR(64) NOT NULL,
+        `modification` INT NOT NULL,
+        `mounted` INT NOT NULL DEFAULT 0,
+        PRIMARY KEY(`user_id`, `item_id`),
+        FOREIGN KEY(`user_id`) REFERENCES `user`(`user_id`)
+);
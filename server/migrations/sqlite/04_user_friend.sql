@@ -0,0 +1,9 @@
+CREATE TABLE `user_friend`(
+        `user_id` VARCHAR(32) NOT NULL,
+        `friend_id` VARCHAR(32) NOT NULL,
+        `state` INT NOT NULL,
+        `viewed` INT NOT NULL DEFAULT 0,
+        PRIMARY KEY(`user_id`, `friend_id`),
+        FOREIGN KEY(`user_id`) REFERENCES `user`(`user_id`),
+        FOREIGN KEY(`friend_id`) REFERENCES `user`(`user_id`)
+);
@@ -0,0 +1,12 @@
+CREATE TABLE `user_quest`(
+        `user_id` VARCHAR(32) NOT NULL,
+        `quest_id` INT NOT NULL,
+        `template_id` VARCHAR(64) NOT NULL,
+        `goal` INT NOT NULL,
+        `progress` INT NOT NULL DEFAULT 0,
+        `day` INT NOT NULL,
+        `can_skip_free` INT NOT NULL DEFAULT 1,
+        `rewarded` INT NOT NULL DEFAULT 0,
+        PRIMARY KEY(`user_id`, `quest_id`),
+        FOREIGN KEY(`user_id`) REFERENCES `user`(`user_id`)
+);
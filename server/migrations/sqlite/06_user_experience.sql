@@ -0,0 +1 @@
+CREATE INDEX `user_experience` ON `user`(`experience`);
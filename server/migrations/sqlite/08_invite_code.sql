@@ -0,0 +1,11 @@
+CREATE TABLE `invite_code`(
+        `code` VARCHAR(32) NOT NULL PRIMARY KEY,
+        `created_by` VARCHAR(32) NOT NULL,
+        `max_uses` INT NOT NULL,
+        `uses` INT NOT NULL DEFAULT 0,
+        `timestamp_created` DATETIME NOT NULL,
+        `timestamp_expires` DATETIME DEFAULT NULL
+);
+
+ALTER TABLE `user` ADD COLUMN `invite_code` VARCHAR(32) DEFAULT NULL;
+CREATE INDEX `user_invite_code` ON `user`(`invite_code`);
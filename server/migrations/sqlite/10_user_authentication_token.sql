@@ -0,0 +1,11 @@
+DROP TABLE `user_authentication_token`;
+
+CREATE TABLE `user_authentication_token`(
+        `token_hash` VARCHAR(64) NOT NULL PRIMARY KEY,
+        `user_id` VARCHAR(32) NOT NULL,
+        `timestamp_created` DATETIME NOT NULL,
+        `timestamp_expires` DATETIME NOT NULL,
+        FOREIGN KEY(`user_id`) REFERENCES `user`(`user_id`)
+);
+
+CREATE INDEX `user_authentication_token_user` ON `user_authentication_token`(`user_id`);
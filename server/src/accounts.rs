@@ -0,0 +1,237 @@
+use chrono::{Duration, Utc};
+use rand::{distributions::Alphanumeric, Rng};
+use thiserror::Error;
+
+use crate::{config::ConfigHandle, storage::{StorageHandle, model}, users::UserRegistry, Mail, MailerHandle};
+
+/// Length of the codes mailed to the users.
+const CODE_LENGTH: usize = 16;
+const MAX_EMAIL_LENGTH: usize = 128;
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum AccountError {
+    #[error("The email address is invalid.")]
+    InvalidEmail,
+    #[error("The email address is already used by another account.")]
+    EmailInUse,
+    #[error("The password must consist of {0} to {1} characters.")]
+    InvalidPasswordLength(i32, i32),
+    #[error("The account does not exist.")]
+    UnknownUser,
+}
+
+/// Result of submitting a mailed code.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CodeRedemption {
+    /// The user may set new credentials.
+    PasswordReset { user_id: String, email: String },
+    EmailConfirmed { user_id: String, email: String },
+    Invalid,
+}
+
+/// Trim and lowercase the email. Returns `None` if it is not a plausible address.
+fn normalize_email(email: &str) -> Option<String> {
+    let email = email.trim().to_lowercase();
+    if email.len() > MAX_EMAIL_LENGTH || email.chars().any(|c| c.is_whitespace() || c.is_control() || c == '<' || c == '>') {
+        return None;
+    }
+
+    let (local, domain) = email.split_once('@')?;
+    if local.is_empty() || domain.contains('@') || !domain.contains('.') || domain.starts_with('.') || domain.ends_with('.') {
+        return None;
+    }
+
+    Some(email)
+}
+
+fn generate_code() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(CODE_LENGTH)
+        .map(char::from)
+        .collect::<String>()
+}
+
+/// Password recovery and email confirmation via mailed codes.
+pub struct Accounts {
+    storage: StorageHandle,
+    config: ConfigHandle,
+    mailer: MailerHandle,
+}
+
+impl Accounts {
+    pub fn new(storage: StorageHandle, config: ConfigHandle, mailer: MailerHandle) -> Self {
+        Self {
+            storage,
+            config,
+            mailer,
+        }
+    }
+
+    fn code_expiry(&self) -> Duration {
+        let hours = self.config.read()
+            .map_or(24, |config| config.mail.token_expiry_hours);
+
+        Duration::hours(hours.max(1))
+    }
+
+    fn password_length_range(&self) -> (i32, i32) {
+        self.config.read()
+            .map_or((5, 100), |config| (config.register.min_password_length, config.register.max_password_length))
+    }
+
+    async fn send_code(&self, user_id: &str, kind: model::EmailTokenKind, email: &str) -> anyhow::Result<()> {
+        let now = Utc::now();
+        let token = model::UserEmailToken {
+            user_id: user_id.to_string(),
+            kind,
+            token: generate_code(),
+            email: email.to_string(),
+            timestamp_created: now,
+            timestamp_expires: now + self.code_expiry(),
+        };
+        self.storage.set_user_email_token(&token).await?;
+
+        let (subject, reason) = match kind {
+            model::EmailTokenKind::PasswordReset => ("Password recovery", "to set a new password for"),
+            model::EmailTokenKind::EmailConfirmation => ("Email confirmation", "to confirm your email address for"),
+        };
+        self.mailer.send(&Mail {
+            to: email.to_string(),
+            subject: subject.to_string(),
+            body: format!(
+                "Hello {},\n\nenter the code {} {} your account.\nThe code expires at {}.\n\nIf you did not request this code you can ignore this mail.",
+                user_id, token.token, reason, token.timestamp_expires.format("%Y-%m-%d %H:%M UTC")
+            ),
+        }).await
+    }
+
+    /// Mail a password reset code to the user who confirmed the email.
+    /// Returns `false` if no user confirmed the email.
+    pub async fn request_password_reset(&self, email: &str) -> anyhow::Result<bool> {
+        let email = match normalize_email(email) {
+            Some(email) => email,
+            None => return Ok(false),
+        };
+
+        let user = match self.storage.find_user_by_email(&email).await? {
+            Some(user) => user,
+            None => return Ok(false),
+        };
+
+        self.send_code(&user.user_id, model::EmailTokenKind::PasswordReset, &email).await?;
+        Ok(true)
+    }
+
+    /// Set the (unconfirmed) email of the user and mail a confirmation code.
+    /// Returns the normalized email.
+    pub async fn change_email(&self, user_id: &str, email: &str) -> anyhow::Result<Result<String, AccountError>> {
+        let email = match normalize_email(email) {
+            Some(email) => email,
+            None => return Ok(Err(AccountError::InvalidEmail)),
+        };
+
+        if let Some(owner) = self.storage.find_user_by_email(&email).await? {
+            if owner.user_id != user_id {
+                return Ok(Err(AccountError::EmailInUse));
+            }
+        }
+
+        if !self.storage.set_user_email(user_id, Some(&email), false).await? {
+            return Ok(Err(AccountError::UnknownUser));
+        }
+
+        self.send_code(user_id, model::EmailTokenKind::EmailConfirmation, &email).await?;
+        Ok(Ok(email))
+    }
+
+    /// Redeem a mailed code. Email confirmation codes are applied immediately.
+    pub async fn redeem_code(&self, code: &str) -> anyhow::Result<CodeRedemption> {
+        let token = match self.storage.use_user_email_token(code.trim(), Utc::now()).await? {
+            Some(token) => token,
+            None => return Ok(CodeRedemption::Invalid),
+        };
+
+        match token.kind {
+            model::EmailTokenKind::PasswordReset => Ok(CodeRedemption::PasswordReset {
+                user_id: token.user_id,
+                email: token.email,
+            }),
+            model::EmailTokenKind::EmailConfirmation => {
+                let user = match self.storage.find_user(&token.user_id).await? {
+                    Some(user) => user,
+                    None => return Ok(CodeRedemption::Invalid),
+                };
+
+                if user.email.as_deref() != Some(token.email.as_str()) {
+                    /* the user changed the email after the code has been sent */
+                    return Ok(CodeRedemption::Invalid);
+                }
+
+                if let Some(owner) = self.storage.find_user_by_email(&token.email).await? {
+                    if owner.user_id != user.user_id {
+                        return Ok(CodeRedemption::Invalid);
+                    }
+                }
+
+                self.storage.set_user_email(&user.user_id, Some(&token.email), true).await?;
+                Ok(CodeRedemption::EmailConfirmed {
+                    user_id: user.user_id,
+                    email: token.email,
+                })
+            }
+        }
+    }
+
+    /// Set a new password after a password reset code has been redeemed.
+    /// A different email will be confirmed by mail.
+    pub async fn change_credentials(&self, user_id: &str, password: &str, email: &str) -> anyhow::Result<Result<(), AccountError>> {
+        let (min_password_length, max_password_length) = self.password_length_range();
+        let password_length = password.chars().count() as i32;
+        if password_length < min_password_length || password_length > max_password_length {
+            return Ok(Err(AccountError::InvalidPasswordLength(min_password_length, max_password_length)));
+        }
+
+        let user = match self.storage.find_user(user_id).await? {
+            Some(user) => user,
+            None => return Ok(Err(AccountError::UnknownUser)),
+        };
+
+        let new_email = match normalize_email(email) {
+            Some(email) if user.email.as_ref() != Some(&email) => Some(email),
+            Some(_) => None,
+            None if email.trim().is_empty() => None,
+            None => return Ok(Err(AccountError::InvalidEmail)),
+        };
+
+        let password_hash = UserRegistry::hash_password(password.to_string()).await?;
+        if !self.storage.set_user_password(user_id, &password_hash, "").await? {
+            return Ok(Err(AccountError::UnknownUser));
+        }
+
+        /* log out all devices as the old password might have been compromised */
+        self.storage.remove_user_authentication_tokens(user_id).await?;
+
+        if let Some(email) = new_email {
+            if let Err(error) = self.change_email(user_id, &email).await? {
+                return Ok(Err(error));
+            }
+        }
+
+        Ok(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::normalize_email;
+
+    #[test]
+    fn test_normalize_email() {
+        assert_eq!(normalize_email(" Tanker@Example.org "), Some("tanker@example.org".to_string()));
+        assert_eq!(normalize_email("tanker"), None);
+        assert_eq!(normalize_email("tanker@localhost"), None);
+        assert_eq!(normalize_email("tan ker@example.org"), None);
+        assert_eq!(normalize_email("a@b@example.org"), None);
+    }
+}
@@ -0,0 +1,424 @@
+use std::{net::SocketAddr, sync::{Arc, Mutex, MutexGuard}, time::Duration};
+
+use anyhow::Context;
+use axum::{Router, Json, routing::{get, post, delete}, extract::{State, Path}, http::StatusCode, response::{IntoResponse, Response}};
+use fost_protocol::packets::s2c;
+use serde::{Serialize, Deserialize};
+use tokio::task::JoinHandle;
+use tracing::info;
+
+use crate::{GarageError, InviteError, ModerationCommand, ModerationError, Subscription, parse_duration, server::{Server, ClientInfo}, storage::model};
+
+/// Longest time a subscription can be extended by at once (ten years).
+const MAX_SUBSCRIPTION_SECONDS: i64 = 10 * 365 * 24 * 60 * 60;
+
+/// Error response of the admin API.
+struct ApiError {
+    status: StatusCode,
+    message: String,
+}
+
+impl ApiError {
+    fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        Self { status, message: message.into() }
+    }
+}
+
+impl From<anyhow::Error> for ApiError {
+    fn from(error: anyhow::Error) -> Self {
+        tracing::error!("admin api error: {:#}", error);
+        Self::new(StatusCode::INTERNAL_SERVER_ERROR, "internal server error")
+    }
+}
+
+impl From<ModerationError> for ApiError {
+    fn from(error: ModerationError) -> Self {
+        let status = match error {
+            ModerationError::UnknownUser(_) => StatusCode::NOT_FOUND,
+            ModerationError::NotPunished(_) => StatusCode::CONFLICT,
+            _ => StatusCode::BAD_REQUEST,
+        };
+        Self::new(status, error.to_string())
+    }
+}
+
+impl From<GarageError> for ApiError {
+    fn from(error: GarageError) -> Self {
+        let status = match error {
+            GarageError::UnknownItem => StatusCode::NOT_FOUND,
+            GarageError::Unavailable => StatusCode::NOT_FOUND,
+            GarageError::AlreadyOwned => StatusCode::CONFLICT,
+            _ => StatusCode::BAD_REQUEST,
+        };
+        Self::new(status, error.to_string())
+    }
+}
+
+impl From<InviteError> for ApiError {
+    fn from(error: InviteError) -> Self {
+        let status = match error {
+            InviteError::AlreadyExists(_) => StatusCode::CONFLICT,
+            _ => StatusCode::BAD_REQUEST,
+        };
+        Self::new(status, error.to_string())
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.status, Json(ErrorResponse { error: self.message })).into_response()
+    }
+}
+
+type ApiResult<T> = Result<Json<T>, ApiError>;
+
+#[derive(Serialize)]
+struct ErrorResponse {
+    error: String,
+}
+
+#[derive(Serialize)]
+struct MessageResponse {
+    message: String,
+}
+
+#[derive(Deserialize)]
+struct BroadcastRequest {
+    text: String,
+}
+
+#[derive(Serialize)]
+struct BroadcastResponse {
+    clients: usize,
+}
+
+#[derive(Deserialize)]
+struct ShutdownRequest {
+    delay_seconds: u64,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct PunishRequest {
+    /// Duration like `30m`, `12h`, `7d` or `perm`.
+    duration: Option<String>,
+    reason: String,
+}
+
+#[derive(Deserialize)]
+struct CrystalsRequest {
+    amount: i32,
+}
+
+#[derive(Serialize)]
+struct CrystalsResponse {
+    crystals: i32,
+}
+
+#[derive(Deserialize)]
+struct ItemRequest {
+    /// Item id optionally including the modification (e.g. `smoky_m2`).
+    item_id: String,
+    #[serde(default = "default_item_count")]
+    count: i32,
+}
+
+fn default_item_count() -> i32 {
+    1
+}
+
+#[derive(Deserialize)]
+struct SubscriptionRequest {
+    /// Time the subscription will be extended by.
+    duration_seconds: u64,
+}
+
+#[derive(Serialize)]
+struct SubscriptionResponse {
+    /// RFC 3339 timestamp
+    until: String,
+}
+
+#[derive(Serialize)]
+struct RevokeTokensResponse {
+    revoked: u64,
+}
+
+#[derive(Deserialize)]
+struct InviteCodeRequest {
+    /// Random code if not set.
+    code: Option<String>,
+    #[serde(default = "default_invite_code_uses")]
+    max_uses: i32,
+    /// Duration like `12h` or `7d`. The code never expires if not set.
+    expires_in: Option<String>,
+}
+
+fn default_invite_code_uses() -> i32 {
+    1
+}
+
+#[derive(Serialize)]
+struct InviteCodeInfo {
+    code: String,
+    created_by: String,
+    max_uses: i32,
+    uses: i32,
+    /// RFC 3339 timestamps
+    created: String,
+    expires: Option<String>,
+}
+
+impl From<model::InviteCode> for InviteCodeInfo {
+    fn from(invite_code: model::InviteCode) -> Self {
+        Self {
+            code: invite_code.code,
+            created_by: invite_code.created_by,
+            max_uses: invite_code.max_uses,
+            uses: invite_code.uses,
+            created: invite_code.timestamp_created.to_rfc3339(),
+            expires: invite_code.timestamp_expires.map(|expires| expires.to_rfc3339()),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct BattleUserInfo {
+    user_id: String,
+    team: String,
+    kills: i32,
+    deaths: i32,
+    score: i32,
+}
+
+#[derive(Serialize)]
+struct BattleInfo {
+    battle_id: String,
+    name: String,
+    mode: String,
+    map: String,
+    fund: i32,
+    team_scores: [i32; 2],
+    users: Vec<BattleUserInfo>,
+}
+
+type AdminState = Arc<Mutex<Server>>;
+
+fn lock_server(server: &AdminState) -> Result<MutexGuard<'_, Server>, ApiError> {
+    server.lock()
+        .ok()
+        .context("failed to lock the server")
+        .map_err(ApiError::from)
+}
+
+/// Start the admin HTTP API on the given address.
+pub fn spawn_admin_api(address: SocketAddr, server: AdminState) -> anyhow::Result<JoinHandle<()>> {
+    /* the api has no authentication and must not be reachable from the network */
+    if !address.ip().is_loopback() {
+        anyhow::bail!("the admin api must be bound to a loopback address, not {}", address);
+    }
+
+    let router = Router::new()
+        .route("/clients", get(list_clients))
+        .route("/battles", get(list_battles))
+        .route("/broadcast", post(broadcast))
+        .route("/shutdown", post(schedule_shutdown).delete(cancel_shutdown))
+        .route("/users/:user_id/kick", post(kick_user))
+        .route("/users/:user_id/ban", post(ban_user).delete(unban_user))
+        .route("/users/:user_id/mute", post(mute_user).delete(unmute_user))
+        .route("/users/:user_id/crystals", post(grant_crystals))
+        .route("/users/:user_id/items", post(grant_item))
+        .route("/users/:user_id/premium", post(grant_premium))
+        .route("/users/:user_id/double_crystals", post(grant_double_crystals))
+        .route("/users/:user_id/tokens", delete(revoke_tokens))
+        .route("/invite_codes", post(create_invite_code))
+        .route("/invite_codes/:code", get(find_invite_code))
+        .with_state(server);
+
+    let http_server = axum::Server::try_bind(&address)
+        .with_context(|| format!("failed to bind the admin api to {}", address))?
+        .serve(router.into_make_service());
+    info!("Admin api started on {}", address);
+
+    Ok(tokio::spawn(async move {
+        if let Err(error) = http_server.await {
+            tracing::error!("admin api error: {}", error);
+        }
+    }))
+}
+
+async fn list_clients(State(server): State<AdminState>) -> ApiResult<Vec<ClientInfo>> {
+    Ok(Json(lock_server(&server)?.client_infos()))
+}
+
+async fn list_battles(State(server): State<AdminState>) -> ApiResult<Vec<BattleInfo>> {
+    let battles = lock_server(&server)?.battles().clone();
+    let battles = battles.read()
+        .ok()
+        .context("failed to lock the battles")?;
+
+    let battles = battles.battles()
+        .filter_map(|battle| {
+            let battle = battle.lock().ok()?;
+            let entry = battle.list_entry();
+            Some(BattleInfo {
+                battle_id: entry.battle_id,
+                name: entry.name,
+                mode: entry.battle_mode,
+                map: entry.map,
+                fund: battle.fund(),
+                team_scores: battle.team_scores(),
+                users: battle.users()
+                    .map(|user| BattleUserInfo {
+                        user_id: user.user_id.clone(),
+                        team: format!("{:?}", user.tank.team),
+                        kills: user.kills,
+                        deaths: user.deaths,
+                        score: user.score,
+                    })
+                    .collect(),
+            })
+        })
+        .collect();
+
+    Ok(Json(battles))
+}
+
+async fn broadcast(State(server): State<AdminState>, Json(request): Json<BroadcastRequest>) -> ApiResult<BroadcastResponse> {
+    let clients = lock_server(&server)?.broadcast_packet(&s2c::AlertShow{ text: request.text });
+    Ok(Json(BroadcastResponse { clients }))
+}
+
+async fn schedule_shutdown(State(server): State<AdminState>, Json(request): Json<ShutdownRequest>) -> ApiResult<MessageResponse> {
+    lock_server(&server)?.schedule_shutdown(Duration::from_secs(request.delay_seconds));
+    Ok(Json(MessageResponse { message: format!("Shutdown in {} seconds.", request.delay_seconds) }))
+}
+
+async fn cancel_shutdown(State(server): State<AdminState>) -> ApiResult<MessageResponse> {
+    let mut server = lock_server(&server)?;
+    if !server.cancel_shutdown() {
+        return Err(ApiError::new(StatusCode::CONFLICT, "No shutdown has been scheduled."));
+    }
+
+    server.broadcast_packet(&s2c::AlertShow{ text: "The server shutdown has been canceled.".to_string() });
+    Ok(Json(MessageResponse { message: "Canceled the shutdown.".to_string() }))
+}
+
+async fn execute_moderation(server: &AdminState, command: ModerationCommand) -> ApiResult<MessageResponse> {
+    let moderation = lock_server(server)?.moderation().clone();
+    let message = moderation.execute_console(command).await??;
+    Ok(Json(MessageResponse { message }))
+}
+
+fn punishment_duration(request: &PunishRequest) -> Result<Option<chrono::Duration>, ApiError> {
+    let duration = request.duration.as_deref().unwrap_or("perm");
+    parse_duration(duration)
+        .ok_or_else(|| ApiError::new(StatusCode::BAD_REQUEST, format!("Invalid duration {}.", duration)))
+}
+
+async fn kick_user(State(server): State<AdminState>, Path(user_id): Path<String>, request: Option<Json<PunishRequest>>) -> ApiResult<MessageResponse> {
+    let Json(request) = request.unwrap_or_default();
+    execute_moderation(&server, ModerationCommand::Kick { user_id, reason: request.reason }).await
+}
+
+async fn ban_user(State(server): State<AdminState>, Path(user_id): Path<String>, Json(request): Json<PunishRequest>) -> ApiResult<MessageResponse> {
+    let duration = punishment_duration(&request)?;
+    execute_moderation(&server, ModerationCommand::Ban { user_id, duration, reason: request.reason }).await
+}
+
+async fn unban_user(State(server): State<AdminState>, Path(user_id): Path<String>) -> ApiResult<MessageResponse> {
+    execute_moderation(&server, ModerationCommand::Unban { user_id }).await
+}
+
+async fn mute_user(State(server): State<AdminState>, Path(user_id): Path<String>, Json(request): Json<PunishRequest>) -> ApiResult<MessageResponse> {
+    let duration = punishment_duration(&request)?;
+    execute_moderation(&server, ModerationCommand::Mute { user_id, duration, reason: request.reason }).await
+}
+
+async fn unmute_user(State(server): State<AdminState>, Path(user_id): Path<String>) -> ApiResult<MessageResponse> {
+    execute_moderation(&server, ModerationCommand::Unmute { user_id }).await
+}
+
+async fn grant_crystals(State(server): State<AdminState>, Path(user_id): Path<String>, Json(request): Json<CrystalsRequest>) -> ApiResult<CrystalsResponse> {
+    let (storage, notifier) = {
+        let server = lock_server(&server)?;
+        (server.storage().clone(), server.notifier().clone())
+    };
+
+    if !storage.user_exists(&user_id).await? {
+        return Err(ApiError::new(StatusCode::NOT_FOUND, format!("The user {} does not exist.", user_id)));
+    }
+
+    let crystals = match storage.add_user_crystals(&user_id, request.amount).await? {
+        Some(crystals) => crystals,
+        None => return Err(ApiError::new(StatusCode::CONFLICT, "The user does not have enough crystals or the balance would exceed the limit.")),
+    };
+
+    if let Ok(notifier) = notifier.read() {
+        notifier.send_to(&user_id, Arc::new(s2c::AccountRankUpdateCrystals { change_by: crystals }));
+    }
+    Ok(Json(CrystalsResponse { crystals }))
+}
+
+async fn grant_item(State(server): State<AdminState>, Path(user_id): Path<String>, Json(request): Json<ItemRequest>) -> ApiResult<MessageResponse> {
+    let garage = lock_server(&server)?.garage().clone();
+    garage.grant_item(&user_id, &request.item_id, request.count).await??;
+    Ok(Json(MessageResponse { message: format!("Granted {} to {}.", request.item_id, user_id) }))
+}
+
+async fn grant_premium(State(server): State<AdminState>, Path(user_id): Path<String>, Json(request): Json<SubscriptionRequest>) -> ApiResult<SubscriptionResponse> {
+    grant_subscription(&server, user_id, Subscription::Premium, &request).await
+}
+
+async fn grant_double_crystals(State(server): State<AdminState>, Path(user_id): Path<String>, Json(request): Json<SubscriptionRequest>) -> ApiResult<SubscriptionResponse> {
+    grant_subscription(&server, user_id, Subscription::DoubleCrystals, &request).await
+}
+
+async fn grant_subscription(server: &AdminState, user_id: String, subscription: Subscription, request: &SubscriptionRequest) -> ApiResult<SubscriptionResponse> {
+    let duration = match i64::try_from(request.duration_seconds) {
+        Ok(seconds) if seconds > 0 && seconds <= MAX_SUBSCRIPTION_SECONDS => chrono::Duration::seconds(seconds),
+        _ => return Err(ApiError::new(StatusCode::BAD_REQUEST, format!("Invalid duration of {} seconds.", request.duration_seconds))),
+    };
+
+    let premium = lock_server(server)?.premium().clone();
+    let until = match premium.grant(&user_id, subscription, duration).await? {
+        Some(until) => until,
+        None => return Err(ApiError::new(StatusCode::NOT_FOUND, format!("The user {} does not exist.", user_id))),
+    };
+
+    info!("Granted {:?} to {} until {}.", subscription, user_id, until);
+    Ok(Json(SubscriptionResponse { until: until.to_rfc3339() }))
+}
+
+/// Log out all remembered devices of the user.
+async fn revoke_tokens(State(server): State<AdminState>, Path(user_id): Path<String>) -> ApiResult<RevokeTokensResponse> {
+    let storage = lock_server(&server)?.storage().clone();
+    if !storage.user_exists(&user_id).await? {
+        return Err(ApiError::new(StatusCode::NOT_FOUND, format!("The user {} does not exist.", user_id)));
+    }
+
+    let revoked = storage.remove_user_authentication_tokens(&user_id).await?;
+    info!("Revoked {} login tokens of {}.", revoked, user_id);
+    Ok(Json(RevokeTokensResponse { revoked }))
+}
+
+async fn create_invite_code(State(server): State<AdminState>, Json(request): Json<InviteCodeRequest>) -> ApiResult<InviteCodeInfo> {
+    let expires_in = match request.expires_in.as_deref() {
+        Some(value) => parse_duration(value)
+            .ok_or_else(|| ApiError::new(StatusCode::BAD_REQUEST, format!("Invalid duration {}.", value)))?,
+        None => None,
+    };
+
+    let invites = lock_server(&server)?.invites().clone();
+    let invite_code = invites.create("console", request.code, request.max_uses, expires_in).await??;
+    info!("Created the invite code {} with {} uses.", invite_code.code, invite_code.max_uses);
+    Ok(Json(invite_code.into()))
+}
+
+async fn find_invite_code(State(server): State<AdminState>, Path(code): Path<String>) -> ApiResult<InviteCodeInfo> {
+    let invites = lock_server(&server)?.invites().clone();
+    match invites.find(&code).await? {
+        Some(invite_code) => Ok(Json(invite_code.into())),
+        None => Err(ApiError::new(StatusCode::NOT_FOUND, format!("The invite code {} does not exist.", code))),
+    }
+}
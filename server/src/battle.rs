@@ -1 +0,0 @@
-pub struct Battle {}
\ No newline at end of file
@@ -0,0 +1,217 @@
+use std::time::Instant;
+
+use fost_protocol::codec::{BattleTeam, AssaultCC, AssaultBase};
+use fost_protocol::packets::s2c;
+use nalgebra::Vector3;
+use tracing::debug;
+
+use crate::{MapGeometry, QuestKind};
+use super::{Battle, ModeState, SharedPacket, TankState, Flag, FlagState, shared};
+use super::{FLAG_PICKUP_RADIUS, FLAG_DELIVERY_SCORE, FLAG_DELIVERY_FUND, FLAG_RETURN_SCORE};
+
+/// Team which carries the flags to the bases.
+pub const ASSAULT_ATTACKER_TEAM: BattleTeam = BattleTeam::Red;
+/// Team which defends the bases. Earns team score by destroying attackers.
+pub const ASSAULT_DEFENDER_TEAM: BattleTeam = BattleTeam::Blue;
+
+/// State of an assault battle.
+pub struct Assault {
+    flags: Vec<Flag>,
+    bases: Vec<Vector3<f32>>,
+}
+
+impl Assault {
+    pub fn new(geometry: &MapGeometry) -> Self {
+        Self {
+            flags: geometry.assault_flags.iter().copied().map(Flag::new).collect(),
+            bases: geometry.assault_bases.clone(),
+        }
+    }
+
+    pub fn flag(&self, flag_id: usize) -> Option<&Flag> {
+        self.flags.get(flag_id)
+    }
+
+    pub fn carried_flag(&self, tank_id: &str) -> Option<usize> {
+        self.flags.iter().position(|flag| flag.carrier() == Some(tank_id))
+    }
+
+    pub fn init_packet(&self) -> SharedPacket {
+        shared(s2c::BattleCTFFlagInit {
+            init_params: AssaultCC {
+                method_874: self.flags.iter()
+                    .enumerate()
+                    .map(|(index, flag)| flag.client_assault_flag(index as i32))
+                    .collect(),
+                method_993: self.bases.iter()
+                    .enumerate()
+                    .map(|(index, position)| AssaultBase {
+                        id: index as i32,
+                        position: Some(*position),
+                    })
+                    .collect(),
+                ..Default::default()
+            }
+        })
+    }
+}
+
+enum AssaultAction {
+    Take(usize),
+    Return(usize),
+    Deliver { flag_id: usize, base_id: usize },
+}
+
+impl Battle {
+    fn assault_mut(&mut self) -> Option<&mut Assault> {
+        match &mut self.mode {
+            ModeState::Assault(assault) => Some(assault),
+            _ => None,
+        }
+    }
+
+    /// Attackers take flags and deliver them to the bases.
+    /// Defenders return dropped flags.
+    pub(super) fn assault_tank_moved(&mut self, user_id: &str) {
+        let now = Instant::now();
+        let (team, position) = match self.users.get(user_id) {
+            Some(user) if user.tank.state == TankState::Active => (user.tank.team, user.tank.position),
+            _ => return,
+        };
+
+        let assault = match self.assault_mut() {
+            Some(assault) => assault,
+            None => return,
+        };
+
+        let action = if team == ASSAULT_ATTACKER_TEAM {
+            match assault.carried_flag(user_id) {
+                Some(flag_id) => assault.bases.iter()
+                    .position(|base| (base - position).norm() <= FLAG_PICKUP_RADIUS)
+                    .map(|base_id| AssaultAction::Deliver { flag_id, base_id }),
+                None => assault.flags.iter()
+                    .position(|flag| flag.is_touched_by(&position, FLAG_PICKUP_RADIUS) && flag.can_be_taken_by(user_id, now))
+                    .map(AssaultAction::Take),
+            }
+        } else {
+            assault.flags.iter()
+                .position(|flag| flag.is_dropped() && flag.is_touched_by(&position, FLAG_PICKUP_RADIUS))
+                .map(AssaultAction::Return)
+        };
+
+        match action {
+            Some(AssaultAction::Take(flag_id)) => {
+                assault.flags[flag_id].state = FlagState::Carried { carrier: user_id.to_string() };
+                self.broadcast(shared(s2c::BattleCTFFlagFlagTaken {
+                    flag_id: flag_id as i32,
+                    tank_id: user_id.to_string(),
+                }));
+            },
+            Some(AssaultAction::Return(flag_id)) => {
+                assault.flags[flag_id].state = FlagState::AtBase;
+                self.broadcast(shared(s2c::BattleCTFFlagFlagReturned {
+                    flag_id: flag_id as i32,
+                    tank: user_id.to_string(),
+                }));
+                self.add_user_score(user_id, FLAG_RETURN_SCORE);
+            },
+            Some(AssaultAction::Deliver { flag_id, base_id }) => {
+                assault.flags[flag_id].state = FlagState::AtBase;
+                self.broadcast(shared(s2c::BattleCTFFlagFlagDelivered {
+                    flag_id: flag_id as i32,
+                    base_id: base_id as i32,
+                    deliverer_tank_id: user_id.to_string(),
+                }));
+                debug!("User {} delivered flag {} in battle {}.", user_id, flag_id, self.battle_id);
+                self.system_message(format!("{} delivered a flag.", user_id));
+
+                self.context.quests.record(user_id, QuestKind::Flags, 1);
+                self.add_team_score(team, 1);
+                self.add_user_score(user_id, FLAG_DELIVERY_SCORE);
+                self.add_fund(FLAG_DELIVERY_FUND);
+                if self.score_limit_reached() {
+                    self.finish_round(now);
+                }
+            },
+            None => {},
+        }
+    }
+
+    /// The client requested to drop the carried flag.
+    pub fn assault_drop_carried_flag(&mut self, user_id: &str) {
+        let position = match self.users.get(user_id) {
+            Some(user) if user.tank.state == TankState::Active => user.tank.position,
+            _ => return,
+        };
+
+        self.assault_drop_flag(user_id, position);
+    }
+
+    /// Drop the flag carried by the tank at the given position.
+    pub(super) fn assault_drop_flag(&mut self, user_id: &str, position: Vector3<f32>) {
+        let now = Instant::now();
+        let assault = match self.assault_mut() {
+            Some(assault) => assault,
+            None => return,
+        };
+
+        let flag_id = match assault.carried_flag(user_id) {
+            Some(flag_id) => flag_id,
+            None => return,
+        };
+
+        if !assault.flags[flag_id].drop_at(position, now) {
+            return;
+        }
+
+        self.broadcast(shared(s2c::BattleCTFFlagFlagDropped {
+            flag_id: flag_id as i32,
+            position: Some(position),
+        }));
+    }
+
+    /// Return all flags which have been dropped for too long.
+    pub(super) fn assault_tick(&mut self, now: Instant) {
+        let assault = match self.assault_mut() {
+            Some(assault) => assault,
+            None => return,
+        };
+
+        let mut returned = Vec::new();
+        for (flag_id, flag) in assault.flags.iter_mut().enumerate() {
+            if flag.return_expired(now) {
+                flag.state = FlagState::AtBase;
+                returned.push(flag_id);
+            }
+        }
+
+        for flag_id in returned {
+            self.broadcast(shared(s2c::BattleCTFFlagFlagReturned {
+                flag_id: flag_id as i32,
+                tank: "".to_string(),
+            }));
+        }
+    }
+
+    pub(super) fn assault_reset(&mut self) {
+        let assault = match self.assault_mut() {
+            Some(assault) => assault,
+            None => return,
+        };
+
+        let mut returned = Vec::new();
+        for (flag_id, flag) in assault.flags.iter_mut().enumerate() {
+            if flag.state != FlagState::AtBase {
+                flag.state = FlagState::AtBase;
+                returned.push(flag_id);
+            }
+        }
+
+        for flag_id in returned {
+            self.broadcast(shared(s2c::BattleCTFFlagFlagReturned {
+                flag_id: flag_id as i32,
+                tank: "".to_string(),
+            }));
+        }
+    }
+}
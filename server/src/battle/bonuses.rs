@@ -0,0 +1,415 @@
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use fost_protocol::codec::BonusSpawnData;
+use fost_protocol::packets::s2c;
+use nalgebra::Vector3;
+use rand::seq::SliceRandom;
+use serde::{Serialize, Deserialize};
+use tracing::debug;
+
+use super::{Battle, SharedPacket, TankState, TANK_HEALTH_MAX, shared};
+
+pub static BONUSES_JSON: &'static str = include_str!("../../resources/bonuses.json");
+
+/// Distance at which a tank driving over a bonus picks it up.
+const BONUS_PICKUP_RADIUS: f32 = 200.0;
+/// Bonuses reported as taken by the client may be slightly further away
+/// as the bonus falls down on the client side.
+const BONUS_TAKE_RADIUS: f32 = 400.0;
+/// Upper limit of bonuses lying on the map at the same time.
+const MAX_SPAWNED_BONUSES: usize = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum BonusEffect {
+    /// Fully repairs the tank.
+    Repair,
+    /// Divides the received damage by the factor.
+    Armor,
+    /// Multiplies the dealt damage by the factor.
+    Damage,
+    /// Multiplies the speed of the tank by the factor.
+    Nitro,
+    /// Pays out crystals to the user.
+    Crystals,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BonusDefinition {
+    /// Type name used by the map bonus regions and the bonus ids (e.g. `medkit`).
+    pub bonus_type: String,
+    pub effect: BonusEffect,
+    /// Effect id shown by the client.
+    #[serde(default)]
+    pub effect_id: i32,
+    #[serde(default)]
+    pub duration_ms: u32,
+    #[serde(default = "default_factor")]
+    pub factor: f32,
+    #[serde(default)]
+    pub crystals: i32,
+    pub spawn_interval_sec: u32,
+    pub life_time_sec: u32,
+    /// Spawn in any bonus region of the map instead of the regions listing the type.
+    #[serde(default)]
+    pub any_region: bool,
+}
+
+pub(super) fn default_factor() -> f32 {
+    1.0
+}
+
+impl BonusDefinition {
+    pub fn duration(&self) -> Duration {
+        Duration::from_millis(self.duration_ms as u64)
+    }
+
+    pub fn spawn_interval(&self) -> Duration {
+        Duration::from_secs(self.spawn_interval_sec as u64)
+    }
+
+    pub fn life_time(&self) -> Duration {
+        Duration::from_secs(self.life_time_sec as u64)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BonusRegistry {
+    bonuses: Vec<BonusDefinition>,
+}
+
+impl BonusRegistry {
+    pub fn parse(payload: &str) -> anyhow::Result<Self> {
+        Ok(serde_json::from_str(payload)?)
+    }
+
+    pub fn find(&self, bonus_type: &str) -> Option<&BonusDefinition> {
+        self.bonuses.iter().find(|bonus| bonus.bonus_type == bonus_type)
+    }
+
+    pub fn bonuses(&self) -> &[BonusDefinition] {
+        &self.bonuses
+    }
+}
+
+/// Temporary effect of a taken bonus or an activated supply.
+#[derive(Debug, Clone)]
+pub struct TankEffect {
+    pub effect: BonusEffect,
+    pub factor: f32,
+    pub until: Instant,
+}
+
+/// Active bonus and supply effects of a tank.
+#[derive(Debug, Clone, Default)]
+pub struct TankEffects {
+    effects: Vec<TankEffect>,
+}
+
+impl TankEffects {
+    /// Activate the effect.
+    /// Taking the same effect again only extends the duration.
+    pub fn apply(&mut self, effect: TankEffect) {
+        self.effects.retain(|active| active.effect != effect.effect);
+        self.effects.push(effect);
+    }
+
+    /// Factor of the effect or 1.0 if the effect is not active.
+    pub fn factor(&self, effect: BonusEffect) -> f32 {
+        self.effects.iter()
+            .find(|active| active.effect == effect)
+            .map(|active| active.factor)
+            .unwrap_or(1.0)
+    }
+
+    pub fn is_active(&self, effect: BonusEffect) -> bool {
+        self.effects.iter().any(|active| active.effect == effect)
+    }
+
+    /// Remove all effects which ran out.
+    /// Returns the removed effects.
+    pub fn expire(&mut self, now: Instant) -> Vec<TankEffect> {
+        let (expired, active) = std::mem::take(&mut self.effects)
+            .into_iter()
+            .partition(|effect| effect.until <= now);
+        self.effects = active;
+        expired
+    }
+
+    pub fn clear(&mut self) -> Vec<TankEffect> {
+        std::mem::take(&mut self.effects)
+    }
+}
+
+struct SpawnedBonus {
+    /// Index into the bonus registry.
+    definition: usize,
+    position: Vector3<f32>,
+    expires_at: Instant,
+}
+
+/// Bonuses lying on the map of a battle.
+pub struct BonusDrops {
+    spawned: BTreeMap<String, SpawnedBonus>,
+    /// Next spawn time, indexed like the bonus registry.
+    next_spawn: Vec<Instant>,
+    counter: u32,
+}
+
+impl BonusDrops {
+    pub fn new(registry: &BonusRegistry, now: Instant) -> Self {
+        Self {
+            spawned: Default::default(),
+            next_spawn: registry.bonuses().iter()
+                .map(|bonus| now + bonus.spawn_interval())
+                .collect(),
+            counter: 0,
+        }
+    }
+
+    pub fn init_packet(&self, now: Instant) -> SharedPacket {
+        shared(s2c::BattleBonusInit {
+            bonuses: self.spawned.iter()
+                .map(|(bonus_id, bonus)| BonusSpawnData {
+                    bonus_id: bonus_id.clone(),
+                    position: Some(bonus.position),
+                    life_time: bonus.expires_at.saturating_duration_since(now).as_secs() as i32,
+                })
+                .collect(),
+        })
+    }
+}
+
+impl Battle {
+    /// Spawn due bonuses and remove expired bonuses and tank effects.
+    pub(super) fn tick_bonuses(&mut self, now: Instant) {
+        let expired = self.drops.spawned.iter()
+            .filter(|(_, bonus)| bonus.expires_at <= now)
+            .map(|(bonus_id, _)| bonus_id.clone())
+            .collect::<Vec<_>>();
+        for bonus_id in expired {
+            self.drops.spawned.remove(&bonus_id);
+            self.broadcast(shared(s2c::BattleBonusDestroy { bonus_id }));
+        }
+
+        for index in 0..self.context.bonuses.bonuses().len() {
+            if self.drops.next_spawn[index] > now {
+                continue;
+            }
+
+            let definition = &self.context.bonuses.bonuses()[index];
+            self.drops.next_spawn[index] = now + definition.spawn_interval();
+            if self.bonus_enabled(definition.effect) && self.drops.spawned.len() < MAX_SPAWNED_BONUSES {
+                self.spawn_bonus(index, now);
+            }
+        }
+
+        let expired = self.users.values_mut()
+            .map(|user| (user.user_id.clone(), user.tank.effects.expire(now)))
+            .filter(|(_, expired)| expired.iter().any(|effect| effect.effect == BonusEffect::Nitro))
+            .map(|(user_id, _)| user_id)
+            .collect::<Vec<_>>();
+        for user_id in expired {
+            self.broadcast_tank_speed(&user_id);
+        }
+    }
+
+    fn bonus_enabled(&self, effect: BonusEffect) -> bool {
+        match effect {
+            BonusEffect::Crystals => !self.parameters.without_crystals,
+            _ => !self.parameters.without_bonuses,
+        }
+    }
+
+    fn spawn_bonus(&mut self, definition_index: usize, now: Instant) {
+        let definition = &self.context.bonuses.bonuses()[definition_index];
+        let regions = self.geometry.bonus_regions(self.parameters.battle_mode)
+            .filter(|region| definition.any_region || region.bonus_types.contains(&definition.bonus_type))
+            .collect::<Vec<_>>();
+        let position = match regions.choose(&mut rand::thread_rng()) {
+            Some(region) => region.random_position(),
+            None => return,
+        };
+
+        self.drops.counter = self.drops.counter.wrapping_add(1);
+        let bonus_id = format!("{}_{}", definition.bonus_type, self.drops.counter);
+        let life_time = definition.life_time();
+        debug!("Spawned bonus {} in battle {}.", bonus_id, self.battle_id);
+
+        self.drops.spawned.insert(bonus_id.clone(), SpawnedBonus {
+            definition: definition_index,
+            position,
+            expires_at: now + life_time,
+        });
+        self.broadcast(shared(s2c::BattleBonusItems {
+            bonus_id,
+            position: Some(position),
+            life_time: life_time.as_secs() as i32,
+        }));
+    }
+
+    pub(super) fn bonuses_tank_moved(&mut self, user_id: &str) {
+        let position = match self.users.get(user_id) {
+            Some(user) if user.tank.state == TankState::Active => user.tank.position,
+            _ => return,
+        };
+
+        let touched = self.drops.spawned.iter()
+            .filter(|(_, bonus)| (bonus.position - position).norm() <= BONUS_PICKUP_RADIUS)
+            .map(|(bonus_id, _)| bonus_id.clone())
+            .collect::<Vec<_>>();
+        for bonus_id in touched {
+            self.take_bonus_within(user_id, &bonus_id, BONUS_PICKUP_RADIUS);
+        }
+    }
+
+    /// The client reported touching the bonus.
+    pub fn take_bonus(&mut self, user_id: &str, bonus_id: &str) {
+        self.take_bonus_within(user_id, bonus_id, BONUS_TAKE_RADIUS);
+    }
+
+    fn take_bonus_within(&mut self, user_id: &str, bonus_id: &str, radius: f32) {
+        let position = match self.users.get(user_id) {
+            Some(user) if user.tank.state == TankState::Active => user.tank.position,
+            _ => return,
+        };
+
+        match self.drops.spawned.get(bonus_id) {
+            Some(bonus) if (bonus.position - position).norm() <= radius => {},
+            Some(_) => {
+                debug!("Rejected taking bonus {} by {} in battle {}.", bonus_id, user_id, self.battle_id);
+                return;
+            },
+            None => return,
+        }
+
+        let bonus = match self.drops.spawned.remove(bonus_id) {
+            Some(bonus) => bonus,
+            None => return,
+        };
+        self.broadcast(shared(s2c::BattleBonusTaken { bonus_id: bonus_id.to_string() }));
+
+        let definition = self.context.bonuses.bonuses()[bonus.definition].clone();
+        self.apply_bonus(user_id, &definition);
+    }
+
+    fn apply_bonus(&mut self, user_id: &str, definition: &BonusDefinition) {
+        match definition.effect {
+            BonusEffect::Crystals => self.pay_crystals(user_id, definition.crystals),
+            effect => self.apply_tank_effect(user_id, effect, definition.effect_id, definition.duration(), definition.factor),
+        }
+    }
+
+    /// Apply the effect of a bonus or supply to the tank and notify the clients.
+    pub(super) fn apply_tank_effect(&mut self, user_id: &str, effect: BonusEffect, effect_id: i32, duration: Duration, factor: f32) {
+        let now = Instant::now();
+        let tank = match self.users.get_mut(user_id) {
+            Some(user) => &mut user.tank,
+            None => return,
+        };
+
+        match effect {
+            BonusEffect::Crystals => return,
+            BonusEffect::Repair => {
+                tank.health = TANK_HEALTH_MAX;
+                let packet = shared(s2c::TankHealth {
+                    tank_id: user_id.to_string(),
+                    health: tank.health as f32,
+                });
+                self.broadcast(packet);
+            },
+            BonusEffect::Armor | BonusEffect::Damage | BonusEffect::Nitro => {
+                tank.effects.apply(TankEffect {
+                    effect,
+                    factor,
+                    until: now + duration,
+                });
+            },
+        }
+
+        self.broadcast(shared(s2c::TankEffectApply {
+            tank_id: user_id.to_string(),
+            effect_id,
+            duration: duration.as_millis() as i32,
+            active_after_death: false,
+            effect_level: 0,
+        }));
+
+        if effect == BonusEffect::Nitro {
+            self.broadcast_tank_speed(user_id);
+        }
+    }
+
+    /// Remove all effects of a destroyed tank.
+    pub(super) fn clear_tank_effects(&mut self, user_id: &str) {
+        let cleared = match self.users.get_mut(user_id) {
+            Some(user) => user.tank.effects.clear(),
+            None => return,
+        };
+
+        if cleared.iter().any(|effect| effect.effect == BonusEffect::Nitro) {
+            self.broadcast_tank_speed(user_id);
+        }
+    }
+
+    /// Send the speed of the tank including the nitro effect.
+    fn broadcast_tank_speed(&self, user_id: &str) {
+        let tank = match self.users.get(user_id) {
+            Some(user) => &user.tank,
+            None => return,
+        };
+
+        let factor = tank.effects.factor(BonusEffect::Nitro);
+        let specification = &tank.specification;
+        self.broadcast(shared(s2c::TankUpdateSpeed {
+            tank_id: user_id.to_string(),
+            max_speed: specification.max_speed * factor,
+            max_turn_speed: specification.max_turn_speed * factor,
+            max_turret_rotation_speed: specification.turret_turn_speed,
+            acceleration: specification.acceleration * factor,
+            specification_id: tank.incarnation,
+        }));
+    }
+
+    /// Remove all bonuses from the map and restart the spawn timers.
+    pub(super) fn bonuses_reset(&mut self, now: Instant) {
+        let drops = std::mem::replace(&mut self.drops, BonusDrops::new(&self.context.bonuses, now));
+        for bonus_id in drops.spawned.into_keys() {
+            self.broadcast(shared(s2c::BattleBonusDestroy { bonus_id }));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use super::{BonusRegistry, BonusEffect, TankEffect, TankEffects, BONUSES_JSON};
+
+    #[test]
+    fn test_bonus_registry() {
+        let registry = BonusRegistry::parse(BONUSES_JSON).unwrap();
+        let medkit = registry.find("medkit").unwrap();
+        assert_eq!(medkit.effect, BonusEffect::Repair);
+        assert_eq!(medkit.factor, 1.0);
+        assert!(registry.find("gold").unwrap().any_region);
+        assert!(registry.find("rocket").is_none());
+    }
+
+    #[test]
+    fn test_tank_effects() {
+        let now = Instant::now();
+        let mut effects = TankEffects::default();
+        effects.apply(TankEffect { effect: BonusEffect::Armor, factor: 2.0, until: now + Duration::from_secs(10) });
+        effects.apply(TankEffect { effect: BonusEffect::Damage, factor: 2.0, until: now + Duration::from_secs(5) });
+        assert_eq!(effects.factor(BonusEffect::Armor), 2.0);
+        assert_eq!(effects.factor(BonusEffect::Nitro), 1.0);
+
+        let expired = effects.expire(now + Duration::from_secs(5));
+        assert_eq!(expired.len(), 1);
+        assert!(!effects.is_active(BonusEffect::Damage));
+        assert!(effects.is_active(BonusEffect::Armor));
+    }
+}
@@ -0,0 +1,41 @@
+use fost_protocol::packets::s2c;
+
+use super::{Battle, shared};
+
+impl Battle {
+    /// Send a chat message of the user to the battle.
+    /// Team messages are only delivered to the members of the senders team.
+    pub fn chat_message(&mut self, user_id: &str, message: &str, team_only: bool) {
+        let team = match self.users.get(user_id) {
+            Some(user) => user.tank.team,
+            None => return,
+        };
+
+        if team_only && self.is_team_mode() {
+            let packet = shared(s2c::BattleMessageTeamMessage {
+                user_id: user_id.to_string(),
+                message: message.to_string(),
+                team,
+            });
+            for user in self.users.values().filter(|user| user.tank.team == team) {
+                user.send_packet(packet.clone());
+            }
+        } else {
+            self.broadcast(shared(s2c::BattleMessageMessage {
+                user_id: user_id.to_string(),
+                message: message.to_string(),
+                team,
+            }));
+        }
+    }
+
+    /// Announce a system message to all users of the battle.
+    pub fn system_message(&self, message: String) {
+        self.broadcast(shared(s2c::BattleMessageSystemMessage { message }));
+    }
+
+    /// Announce a system message to all users except the given one.
+    pub(super) fn system_message_except(&self, user_id: &str, message: String) {
+        self.broadcast_except(user_id, shared(s2c::BattleMessageSystemMessage { message }));
+    }
+}
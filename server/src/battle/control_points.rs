@@ -0,0 +1,410 @@
+use std::{collections::BTreeSet, time::{Duration, Instant}};
+
+use fost_protocol::codec::{BattleTeam, ClientPointData, ControlPointState, ControlPointsCC};
+use fost_protocol::packets::s2c;
+use nalgebra::Vector3;
+use tracing::debug;
+
+use crate::MapGeometry;
+use super::{Battle, ModeState, SharedPacket, TankState, shared, team_index, team_name};
+
+/// Distance in which a tank is inside a control point.
+pub const CONTROL_POINT_RADIUS: f32 = 400.0;
+/// Progress needed to capture a point.
+/// Red captures towards the negative, blue towards the positive limit.
+pub const CONTROL_POINT_PROGRESS_MAX: f32 = 100.0;
+/// Capture progress per second for every tank outnumbering the enemy.
+pub const CONTROL_POINT_SPEED_PER_TANK: f32 = 10.0;
+/// Maximum number of outnumbering tanks which increase the capture speed.
+const CONTROL_POINT_MAX_TANKS: usize = 3;
+/// Team score per second for every captured point.
+pub const CONTROL_POINT_TEAM_SCORE_PER_SECOND: f32 = 0.2;
+/// Score the users inside a point receive when capturing it.
+pub const CONTROL_POINT_CAPTURE_SCORE: i32 = 20;
+/// Crystals added to the battle fund for every captured point.
+pub const CONTROL_POINT_CAPTURE_FUND: i32 = 5;
+
+pub struct BattleControlPoint {
+    pub name: String,
+    pub position: Vector3<f32>,
+    pub state: ControlPointState,
+    pub progress: f32,
+    /// Progress per second. Changes every time a tank enters or leaves the point.
+    pub speed: f32,
+    pub tanks: BTreeSet<String>,
+}
+
+impl BattleControlPoint {
+    fn new(name: String, position: Vector3<f32>) -> Self {
+        Self {
+            name,
+            position,
+            state: ControlPointState::Neutral,
+            progress: 0.0,
+            speed: 0.0,
+            tanks: Default::default(),
+        }
+    }
+
+    pub fn contains(&self, position: &Vector3<f32>) -> bool {
+        (self.position - position).norm() <= CONTROL_POINT_RADIUS
+    }
+
+    /// Progress the point returns to when no tank is inside.
+    fn resting_progress(&self) -> f32 {
+        match self.state {
+            ControlPointState::Red => -CONTROL_POINT_PROGRESS_MAX,
+            ControlPointState::Blue => CONTROL_POINT_PROGRESS_MAX,
+            _ => 0.0,
+        }
+    }
+
+    /// Calculate the capture speed from the tanks inside the point.
+    /// A point with tanks of both teams is contested and does not progress
+    /// unless one team outnumbers the other.
+    fn capture_speed(&self, teams: impl Fn(&str) -> Option<BattleTeam>) -> f32 {
+        let (mut red, mut blue) = (0usize, 0usize);
+        for tank_id in &self.tanks {
+            match teams(tank_id) {
+                Some(BattleTeam::Red) => red += 1,
+                Some(BattleTeam::Blue) => blue += 1,
+                _ => {},
+            }
+        }
+
+        if red == 0 && blue == 0 {
+            let resting = self.resting_progress();
+            return if self.progress < resting {
+                CONTROL_POINT_SPEED_PER_TANK
+            } else if self.progress > resting {
+                -CONTROL_POINT_SPEED_PER_TANK
+            } else {
+                0.0
+            };
+        }
+
+        let tanks = blue.min(CONTROL_POINT_MAX_TANKS) as f32 - red.min(CONTROL_POINT_MAX_TANKS) as f32;
+        let speed = tanks * CONTROL_POINT_SPEED_PER_TANK;
+        if (speed < 0.0 && self.progress <= -CONTROL_POINT_PROGRESS_MAX) || (speed > 0.0 && self.progress >= CONTROL_POINT_PROGRESS_MAX) {
+            0.0
+        } else {
+            speed
+        }
+    }
+
+    fn client_point(&self, id: usize) -> ClientPointData {
+        ClientPointData {
+            id: id as i32,
+            name: self.name.clone(),
+            position: Some(self.position),
+            score: self.progress,
+            method_2190: self.speed,
+            state: self.state,
+            method_2697: Some(self.tanks.iter().cloned().collect()),
+        }
+    }
+}
+
+/// State of a control points battle.
+pub struct ControlPoints {
+    points: Vec<BattleControlPoint>,
+    /// Fractional team score of the captured points indexed by `team_index`.
+    pending_score: [f32; 2],
+}
+
+impl ControlPoints {
+    pub fn new(geometry: &MapGeometry) -> Self {
+        Self {
+            points: geometry.control_points.iter()
+                .map(|point| BattleControlPoint::new(point.name.clone(), point.position))
+                .collect(),
+            pending_score: [0.0, 0.0],
+        }
+    }
+
+    pub fn point(&self, point_id: usize) -> Option<&BattleControlPoint> {
+        self.points.get(point_id)
+    }
+
+    pub fn init_packet(&self) -> SharedPacket {
+        shared(s2c::BattleCPInit {
+            init_params: ControlPointsCC {
+                method_337: CONTROL_POINT_RADIUS,
+                name_43: self.points.iter()
+                    .enumerate()
+                    .map(|(index, point)| point.client_point(index))
+                    .collect(),
+                ..Default::default()
+            }
+        })
+    }
+}
+
+/// Team owning a point in the state.
+fn point_team(state: ControlPointState) -> Option<BattleTeam> {
+    match state {
+        ControlPointState::Red => Some(BattleTeam::Red),
+        ControlPointState::Blue => Some(BattleTeam::Blue),
+        _ => None,
+    }
+}
+
+impl Battle {
+    fn control_points_mut(&mut self) -> Option<&mut ControlPoints> {
+        match &mut self.mode {
+            ModeState::ControlPoints(control_points) => Some(control_points),
+            _ => None,
+        }
+    }
+
+    /// Update which points the tank is inside.
+    pub(super) fn cp_tank_moved(&mut self, user_id: &str) {
+        let position = match self.users.get(user_id) {
+            Some(user) if user.tank.state == TankState::Active => user.tank.position,
+            _ => return,
+        };
+
+        self.cp_update_tank(user_id, Some(position));
+    }
+
+    /// The tank has been destroyed or left the battle.
+    pub(super) fn cp_tank_removed(&mut self, user_id: &str) {
+        self.cp_update_tank(user_id, None);
+    }
+
+    fn cp_update_tank(&mut self, user_id: &str, position: Option<Vector3<f32>>) {
+        let control_points = match self.control_points_mut() {
+            Some(control_points) => control_points,
+            None => return,
+        };
+
+        let mut changed = Vec::new();
+        for (point_id, point) in control_points.points.iter_mut().enumerate() {
+            let inside = position
+                .map(|position| point.contains(&position))
+                .unwrap_or(false);
+
+            if inside && point.tanks.insert(user_id.to_string()) {
+                changed.push((point_id, true));
+            } else if !inside && point.tanks.remove(user_id) {
+                changed.push((point_id, false));
+            }
+        }
+
+        for (point_id, entered) in changed {
+            let packet = if entered {
+                shared(s2c::BattleCPTankEnteredPoint { point_id: point_id as i32, tank_id: user_id.to_string() })
+            } else {
+                shared(s2c::BattleCPTankLeftPoint { point_id: point_id as i32, tank_id: user_id.to_string() })
+            };
+            self.broadcast(packet);
+            self.cp_update_speed(point_id);
+        }
+    }
+
+    /// Recalculate the capture speed of the point and notify the clients if it changed.
+    fn cp_update_speed(&mut self, point_id: usize) {
+        let users = &self.users;
+        let control_points = match &mut self.mode {
+            ModeState::ControlPoints(control_points) => control_points,
+            _ => return,
+        };
+
+        let point = match control_points.points.get_mut(point_id) {
+            Some(point) => point,
+            None => return,
+        };
+
+        let speed = point.capture_speed(|tank_id| users.get(tank_id).map(|user| user.tank.team));
+        if speed == point.speed {
+            return;
+        }
+
+        point.speed = speed;
+        let packet = shared(s2c::BattleCPSetPointProgress {
+            point_id: point_id as i32,
+            progress: point.progress,
+            progress_speed: speed,
+        });
+        self.broadcast(packet);
+    }
+
+    /// Advance the capture progress of all points and award the team score of captured points.
+    pub(super) fn cp_tick(&mut self, now: Instant, elapsed: Duration) {
+        let control_points = match self.control_points_mut() {
+            Some(control_points) => control_points,
+            None => return,
+        };
+
+        let seconds = elapsed.as_secs_f32();
+        let mut moved = Vec::new();
+        let mut state_changes = Vec::new();
+        for (point_id, point) in control_points.points.iter_mut().enumerate() {
+            if point.speed != 0.0 {
+                let resting = point.resting_progress();
+                let mut progress = point.progress + point.speed * seconds;
+                if point.tanks.is_empty() && ((point.speed > 0.0 && progress > resting) || (point.speed < 0.0 && progress < resting)) {
+                    /* do not overshoot when returning to the resting progress */
+                    progress = resting;
+                }
+                point.progress = progress.clamp(-CONTROL_POINT_PROGRESS_MAX, CONTROL_POINT_PROGRESS_MAX);
+                moved.push(point_id);
+
+                let state = match point.state {
+                    _ if point.progress <= -CONTROL_POINT_PROGRESS_MAX => ControlPointState::Red,
+                    _ if point.progress >= CONTROL_POINT_PROGRESS_MAX => ControlPointState::Blue,
+                    /* the owner loses the point as soon as the progress reaches the neutral state */
+                    ControlPointState::Red if point.progress >= 0.0 => ControlPointState::Neutral,
+                    ControlPointState::Blue if point.progress <= 0.0 => ControlPointState::Neutral,
+                    state => state,
+                };
+
+                if state != point.state {
+                    point.state = state;
+                    state_changes.push(point_id);
+                }
+            }
+
+            if let Some(index) = point_team(point.state).and_then(team_index) {
+                control_points.pending_score[index] += CONTROL_POINT_TEAM_SCORE_PER_SECOND * seconds;
+            }
+        }
+
+        let mut scores = Vec::new();
+        for (index, team) in [ BattleTeam::Red, BattleTeam::Blue ].into_iter().enumerate() {
+            let pending = &mut control_points.pending_score[index];
+            if *pending >= 1.0 {
+                let amount = pending.floor();
+                *pending -= amount;
+                scores.push((team, amount as i32));
+            }
+        }
+
+        for point_id in state_changes {
+            self.cp_point_state_changed(point_id);
+        }
+
+        for point_id in moved {
+            /* the speed drops to zero once the progress reaches its limit */
+            self.cp_update_speed(point_id);
+        }
+
+        for (team, amount) in scores {
+            self.add_team_score(team, amount);
+        }
+
+        if self.score_limit_reached() {
+            self.finish_round(now);
+        }
+    }
+
+    /// Notify the clients about the new point state and reward the capturing tanks.
+    fn cp_point_state_changed(&mut self, point_id: usize) {
+        let users = &self.users;
+        let control_points = match &self.mode {
+            ModeState::ControlPoints(control_points) => control_points,
+            _ => return,
+        };
+
+        let point = match control_points.points.get(point_id) {
+            Some(point) => point,
+            None => return,
+        };
+
+        let state = point.state;
+        let capturing_users = match point_team(state) {
+            Some(team) => point.tanks.iter()
+                .filter(|tank_id| users.get(*tank_id).map(|user| user.tank.team) == Some(team))
+                .cloned()
+                .collect::<Vec<_>>(),
+            None => Vec::new(),
+        };
+        let announcement = point_team(state)
+            .map(|team| format!("The {} team captured point {}.", team_name(team), point.name));
+        debug!("Point {} changed to {:?} in battle {}.", point.name, state, self.battle_id);
+
+        self.broadcast(shared(s2c::BattleCPSetPointState {
+            point_id: point_id as i32,
+            state,
+        }));
+        if let Some(announcement) = announcement {
+            self.system_message(announcement);
+        }
+
+        if capturing_users.is_empty() {
+            return;
+        }
+
+        for user_id in &capturing_users {
+            self.add_user_score(user_id, CONTROL_POINT_CAPTURE_SCORE);
+        }
+        self.add_fund(CONTROL_POINT_CAPTURE_FUND);
+    }
+
+    /// Reset all points to neutral.
+    pub(super) fn cp_reset(&mut self) {
+        let control_points = match self.control_points_mut() {
+            Some(control_points) => control_points,
+            None => return,
+        };
+
+        control_points.pending_score = [0.0, 0.0];
+        let mut reset = Vec::new();
+        for (point_id, point) in control_points.points.iter_mut().enumerate() {
+            point.state = ControlPointState::Neutral;
+            point.progress = 0.0;
+            point.speed = 0.0;
+            reset.push(point_id);
+        }
+
+        for point_id in reset {
+            self.broadcast(shared(s2c::BattleCPSetPointState {
+                point_id: point_id as i32,
+                state: ControlPointState::Neutral,
+            }));
+            self.broadcast(shared(s2c::BattleCPSetPointProgress {
+                point_id: point_id as i32,
+                progress: 0.0,
+                progress_speed: 0.0,
+            }));
+            self.cp_update_speed(point_id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use fost_protocol::codec::{BattleTeam, ControlPointState};
+    use nalgebra::Vector3;
+
+    use super::{BattleControlPoint, CONTROL_POINT_SPEED_PER_TANK, CONTROL_POINT_PROGRESS_MAX};
+
+    #[test]
+    fn test_capture_speed() {
+        let mut point = BattleControlPoint::new("A".to_string(), Vector3::zeros());
+        let teams = |tank_id: &str| match tank_id {
+            "red_1" | "red_2" => Some(BattleTeam::Red),
+            "blue_1" => Some(BattleTeam::Blue),
+            _ => None,
+        };
+        assert_eq!(point.capture_speed(teams), 0.0);
+
+        point.tanks.insert("red_1".to_string());
+        assert_eq!(point.capture_speed(teams), -CONTROL_POINT_SPEED_PER_TANK);
+
+        point.tanks.insert("blue_1".to_string());
+        assert_eq!(point.capture_speed(teams), 0.0);
+
+        point.tanks.insert("red_2".to_string());
+        assert_eq!(point.capture_speed(teams), -CONTROL_POINT_SPEED_PER_TANK);
+
+        point.progress = -CONTROL_POINT_PROGRESS_MAX;
+        point.state = ControlPointState::Red;
+        assert_eq!(point.capture_speed(teams), 0.0);
+
+        /* an empty point returns to the owner */
+        point.tanks.clear();
+        point.progress = -50.0;
+        assert_eq!(point.capture_speed(teams), -CONTROL_POINT_SPEED_PER_TANK);
+    }
+}
@@ -0,0 +1,212 @@
+use std::time::Instant;
+
+use fost_protocol::codec::{BattleTeam, CaptureTheFlagCC};
+use fost_protocol::packets::s2c;
+use nalgebra::Vector3;
+use tracing::debug;
+
+use crate::{MapGeometry, QuestKind};
+use super::{Battle, ModeState, SharedPacket, TankState, Flag, FlagState, shared, team_index, team_name};
+use super::{FLAG_PICKUP_RADIUS, FLAG_TOUCH_RADIUS, FLAG_DELIVERY_SCORE, FLAG_DELIVERY_FUND, FLAG_RETURN_SCORE};
+
+const FLAG_TEAMS: [BattleTeam; 2] = [ BattleTeam::Red, BattleTeam::Blue ];
+
+/// State of a capture the flag battle.
+pub struct CaptureTheFlag {
+    /// Flags indexed by `team_index`.
+    flags: [Flag; 2],
+}
+
+impl CaptureTheFlag {
+    pub fn new(geometry: &MapGeometry) -> Self {
+        let [red, blue] = geometry.ctf_flags.unwrap_or_else(|| [Vector3::zeros(); 2]);
+        Self {
+            flags: [ Flag::new(red), Flag::new(blue) ],
+        }
+    }
+
+    pub fn flag(&self, team: BattleTeam) -> Option<&Flag> {
+        team_index(team).map(|index| &self.flags[index])
+    }
+
+    /// Team of the flag carried by the tank.
+    pub fn carried_flag(&self, tank_id: &str) -> Option<BattleTeam> {
+        self.flags.iter()
+            .position(|flag| flag.carrier() == Some(tank_id))
+            .map(|index| FLAG_TEAMS[index])
+    }
+
+    pub fn init_packet(&self) -> SharedPacket {
+        shared(s2c::BattleCTFInit {
+            init_params: CaptureTheFlagCC {
+                method_2047: self.flags[0].client_flag(),
+                method_1229: self.flags[1].client_flag(),
+                ..Default::default()
+            }
+        })
+    }
+}
+
+impl Battle {
+    fn ctf_mut(&mut self) -> Option<&mut CaptureTheFlag> {
+        match &mut self.mode {
+            ModeState::CaptureTheFlag(ctf) => Some(ctf),
+            _ => None,
+        }
+    }
+
+    pub(super) fn ctf_tank_moved(&mut self, user_id: &str) {
+        for team in FLAG_TEAMS {
+            self.ctf_touch_flag(user_id, team, FLAG_PICKUP_RADIUS);
+        }
+    }
+
+    /// The client reported touching the flag of the team.
+    pub fn ctf_take_flag(&mut self, user_id: &str, flag_team: BattleTeam) {
+        self.ctf_touch_flag(user_id, flag_team, FLAG_TOUCH_RADIUS);
+    }
+
+    /// A tank touched a flag.
+    /// Enemy flags will be taken, dropped own flags returned and
+    /// the carried enemy flag delivered if the own flag is at its base.
+    fn ctf_touch_flag(&mut self, user_id: &str, flag_team: BattleTeam, radius: f32) {
+        let now = Instant::now();
+        let (tank_team, position) = match self.users.get(user_id) {
+            Some(user) if user.tank.state == TankState::Active => (user.tank.team, user.tank.position),
+            _ => return,
+        };
+
+        let (flag_index, enemy_index) = match (team_index(flag_team), team_index(tank_team)) {
+            (Some(flag_index), Some(tank_index)) => (flag_index, 1 - tank_index),
+            _ => return,
+        };
+
+        let ctf = match self.ctf_mut() {
+            Some(ctf) => ctf,
+            None => return,
+        };
+
+        let carried_flag = ctf.carried_flag(user_id);
+        let flag = &mut ctf.flags[flag_index];
+        if !flag.is_touched_by(&position, radius) {
+            return;
+        }
+
+        if flag_team != tank_team {
+            if carried_flag.is_some() || !flag.can_be_taken_by(user_id, now) {
+                return;
+            }
+
+            flag.state = FlagState::Carried { carrier: user_id.to_string() };
+            self.broadcast(shared(s2c::BattleCTFFlagTaken {
+                tank_id: user_id.to_string(),
+                flag_team,
+            }));
+            debug!("User {} took the {} flag in battle {}.", user_id, team_name(flag_team), self.battle_id);
+        } else if flag.is_dropped() {
+            flag.state = FlagState::AtBase;
+            self.broadcast(shared(s2c::BattleCTFFlagReturned {
+                flag_team,
+                tank: user_id.to_string(),
+            }));
+            self.add_user_score(user_id, FLAG_RETURN_SCORE);
+        } else if flag.state == FlagState::AtBase && carried_flag.is_some() {
+            ctf.flags[enemy_index].state = FlagState::AtBase;
+            self.broadcast(shared(s2c::BattleCTFFlagDelivered {
+                winner_team: tank_team,
+                deliverer_tank_id: user_id.to_string(),
+            }));
+            debug!("User {} delivered a flag in battle {}.", user_id, self.battle_id);
+            if let Some(captured_team) = carried_flag {
+                self.system_message(format!("{} captured the {} flag.", user_id, team_name(captured_team)));
+            }
+
+            self.context.quests.record(user_id, QuestKind::Flags, 1);
+            self.add_team_score(tank_team, 1);
+            self.add_user_score(user_id, FLAG_DELIVERY_SCORE);
+            self.add_fund(FLAG_DELIVERY_FUND);
+            if self.score_limit_reached() {
+                self.finish_round(now);
+            }
+        }
+    }
+
+    /// The client requested to drop the carried flag.
+    pub fn ctf_drop_carried_flag(&mut self, user_id: &str) {
+        let position = match self.users.get(user_id) {
+            Some(user) if user.tank.state == TankState::Active => user.tank.position,
+            _ => return,
+        };
+
+        self.ctf_drop_flag(user_id, position);
+    }
+
+    /// Drop the flag carried by the tank at the given position.
+    pub(super) fn ctf_drop_flag(&mut self, user_id: &str, position: Vector3<f32>) {
+        let now = Instant::now();
+        let ctf = match self.ctf_mut() {
+            Some(ctf) => ctf,
+            None => return,
+        };
+
+        let flag_team = match ctf.carried_flag(user_id) {
+            Some(team) => team,
+            None => return,
+        };
+
+        let flag_index = team_index(flag_team).expect("flags to belong to a team");
+        if !ctf.flags[flag_index].drop_at(position, now) {
+            return;
+        }
+
+        self.broadcast(shared(s2c::BattleCTFFlagDropped {
+            position: Some(position),
+            flag_team,
+        }));
+    }
+
+    /// Return all flags which have been dropped for too long.
+    pub(super) fn ctf_tick(&mut self, now: Instant) {
+        let ctf = match self.ctf_mut() {
+            Some(ctf) => ctf,
+            None => return,
+        };
+
+        let mut returned = Vec::new();
+        for (index, flag) in ctf.flags.iter_mut().enumerate() {
+            if flag.return_expired(now) {
+                flag.state = FlagState::AtBase;
+                returned.push(FLAG_TEAMS[index]);
+            }
+        }
+
+        for flag_team in returned {
+            self.broadcast(shared(s2c::BattleCTFFlagReturned {
+                flag_team,
+                tank: "".to_string(),
+            }));
+        }
+    }
+
+    pub(super) fn ctf_reset(&mut self) {
+        let ctf = match self.ctf_mut() {
+            Some(ctf) => ctf,
+            None => return,
+        };
+
+        let mut returned = Vec::new();
+        for (index, flag) in ctf.flags.iter_mut().enumerate() {
+            if flag.state != FlagState::AtBase {
+                flag.state = FlagState::AtBase;
+                returned.push(FLAG_TEAMS[index]);
+            }
+        }
+
+        for flag_team in returned {
+            self.broadcast(shared(s2c::BattleCTFFlagReturned {
+                flag_team,
+                tank: "".to_string(),
+            }));
+        }
+    }
+}
@@ -0,0 +1,122 @@
+use std::time::{Duration, Instant};
+
+use fost_protocol::codec::{ClientFlag, ClientAssaultFlag};
+use nalgebra::Vector3;
+
+/// Distance in which a tank touches a flag.
+pub const FLAG_PICKUP_RADIUS: f32 = 250.0;
+/// Touch requests of the client are validated with a larger radius
+/// as the server only knows the position of the last movement command.
+pub const FLAG_TOUCH_RADIUS: f32 = 2.0 * FLAG_PICKUP_RADIUS;
+/// Time after which a dropped flag returns to its base.
+pub const FLAG_RETURN_DELAY: Duration = Duration::from_secs(30);
+/// Time the previous carrier has to wait until it can take a dropped flag again.
+const FLAG_RETAKE_DELAY: Duration = Duration::from_secs(3);
+/// Score the user receives for delivering a flag.
+pub const FLAG_DELIVERY_SCORE: i32 = 50;
+/// Crystals added to the battle fund for every delivered flag.
+pub const FLAG_DELIVERY_FUND: i32 = 10;
+/// Score the user receives for returning a dropped flag.
+pub const FLAG_RETURN_SCORE: i32 = 10;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FlagState {
+    AtBase,
+    Carried { carrier: String },
+    Dropped {
+        position: Vector3<f32>,
+        previous_carrier: String,
+        dropped_at: Instant,
+    },
+}
+
+/// A flag which can be carried by tanks.
+/// Used by the CTF and the assault mode.
+#[derive(Debug, Clone)]
+pub struct Flag {
+    pub base: Vector3<f32>,
+    pub state: FlagState,
+}
+
+impl Flag {
+    pub fn new(base: Vector3<f32>) -> Self {
+        Self {
+            base,
+            state: FlagState::AtBase,
+        }
+    }
+
+    pub fn carrier(&self) -> Option<&str> {
+        match &self.state {
+            FlagState::Carried { carrier } => Some(carrier),
+            _ => None,
+        }
+    }
+
+    /// Position of the flag if it's not carried.
+    pub fn position(&self) -> Option<Vector3<f32>> {
+        match &self.state {
+            FlagState::AtBase => Some(self.base),
+            FlagState::Carried { .. } => None,
+            FlagState::Dropped { position, .. } => Some(*position),
+        }
+    }
+
+    pub fn is_dropped(&self) -> bool {
+        matches!(self.state, FlagState::Dropped { .. })
+    }
+
+    pub fn is_touched_by(&self, position: &Vector3<f32>, radius: f32) -> bool {
+        self.position()
+            .map(|flag| (flag - position).norm() <= radius)
+            .unwrap_or(false)
+    }
+
+    /// Check if the tank is allowed to take the flag.
+    pub fn can_be_taken_by(&self, tank_id: &str, now: Instant) -> bool {
+        match &self.state {
+            FlagState::AtBase => true,
+            FlagState::Carried { .. } => false,
+            FlagState::Dropped { previous_carrier, dropped_at, .. } => {
+                previous_carrier != tank_id || now.duration_since(*dropped_at) >= FLAG_RETAKE_DELAY
+            },
+        }
+    }
+
+    /// Check if the dropped flag should return to its base.
+    pub fn return_expired(&self, now: Instant) -> bool {
+        match &self.state {
+            FlagState::Dropped { dropped_at, .. } => now.duration_since(*dropped_at) >= FLAG_RETURN_DELAY,
+            _ => false,
+        }
+    }
+
+    /// Drop the flag at the given position.
+    /// Returns false if the flag is not carried.
+    pub fn drop_at(&mut self, position: Vector3<f32>, now: Instant) -> bool {
+        let previous_carrier = match &self.state {
+            FlagState::Carried { carrier } => carrier.clone(),
+            _ => return false,
+        };
+
+        self.state = FlagState::Dropped { position, previous_carrier, dropped_at: now };
+        true
+    }
+
+    pub fn client_flag(&self) -> ClientFlag {
+        ClientFlag {
+            method_1384: Some(self.base),
+            method_1275: self.carrier().unwrap_or_default().to_string(),
+            name_81: self.position(),
+        }
+    }
+
+    pub fn client_assault_flag(&self, id: i32) -> ClientAssaultFlag {
+        ClientAssaultFlag {
+            method_1384: Some(self.base),
+            method_1275: self.carrier().unwrap_or_default().to_string(),
+            name_81: self.position(),
+            id,
+        }
+    }
+}
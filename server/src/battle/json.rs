@@ -0,0 +1,93 @@
+use nalgebra::Vector3;
+use serde::{Serialize, Deserialize};
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Vector3f {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
+
+impl From<Vector3<f32>> for Vector3f {
+    fn from(value: Vector3<f32>) -> Self {
+        Self { x: value.x, y: value.y, z: value.z }
+    }
+}
+
+impl From<Vector3f> for Vector3<f32> {
+    fn from(value: Vector3f) -> Self {
+        Vector3::new(value.x, value.y, value.z)
+    }
+}
+
+/// Entry of the lobby battle list.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BattleListEntry {
+    pub battle_id: String,
+    pub battle_mode: String,
+
+    pub map: String,
+    pub max_people: i64,
+
+    pub name: String,
+
+    pub private_battle: bool,
+    pub pro_battle: bool,
+    pub parkour_mode: bool,
+    pub equipment_constraints_mode: String,
+
+    pub min_rank: i64,
+    pub max_rank: i64,
+
+    pub preview: i64,
+    pub suspicion_level: String,
+
+    #[serde(default)]
+    pub users_blue: Vec<String>,
+    #[serde(default)]
+    pub users_red: Vec<String>,
+    #[serde(default)]
+    pub users: Vec<String>,
+}
+
+/// Payload of the `BattleMapInfo` packet.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MapInfo {
+    pub map_id: String,
+    pub battle_id: String,
+    pub preview: i32,
+    pub kick_period_ms: i32,
+    pub invisible_time: i32,
+    pub spectator: bool,
+    pub active: bool,
+    pub dust_particle: String,
+    pub minimal_rank: i32,
+    pub maximal_rank: i32,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RoundFinish {
+    pub battle_id: String,
+}
+
+/// Payload of the `BattleDrugsInit` packet.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InventoryInit {
+    pub items: Vec<InventoryItem>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InventoryItem {
+    pub id: String,
+    pub count: i32,
+    pub slot_id: i32,
+    /// Duration of the effect in milliseconds.
+    pub item_effect_time: i32,
+    /// Remaining cooldown in seconds.
+    pub item_rest_sec: i32,
+}
@@ -0,0 +1,1126 @@
+use std::{collections::BTreeMap, sync::Arc, time::{Duration, Instant}};
+
+use fost_protocol::{codec::{BattleCreateParameters, BattleTeam, BattleMode, MoveCommand, RotateTurretCommand, StatisticsModelCC, StatisticsDMCC, StatisticsTeamCC, UserInfo, UserStat, UserReward, BattleInfoUser, BattleInfoData, ChatModeratorLevel}, packets::{Packet, s2c}};
+use chrono::Utc;
+use nalgebra::Vector3;
+use tokio::sync::mpsc;
+use tracing::{debug, warn};
+
+use crate::{storage::StorageHandle, StorageTasksHandle, LobbyHandle, MapGeometry, Quests, QuestKind, Progression, Rank, RewardBonuses};
+
+mod json;
+pub use json::*;
+
+mod tank;
+pub use tank::{BattleTank, TankState, TankSpecification, TANK_HEALTH_MAX, team_name};
+
+mod weapons;
+pub use weapons::*;
+
+mod bonuses;
+pub use bonuses::*;
+
+mod supplies;
+pub use supplies::*;
+
+mod chat;
+
+mod flag;
+pub use flag::*;
+
+mod ctf;
+pub use ctf::*;
+
+mod assault;
+pub use assault::*;
+
+mod control_points;
+pub use control_points::*;
+
+/// A packet which will be send to multiple clients.
+pub type SharedPacket = Arc<dyn Packet + Sync>;
+
+fn shared(packet: impl Packet + Sync + 'static) -> SharedPacket {
+    Arc::new(packet)
+}
+
+/// Time a destroyed tank has to wait until it can be placed again.
+const RESPAWN_DELAY: Duration = Duration::from_secs(3);
+/// Time between the end of a round and the start of the next one.
+const ROUND_RESTART_DELAY: Duration = Duration::from_secs(10);
+/// Remaining round time in seconds at which the end of the round will be announced.
+const ROUND_ENDING_ANNOUNCEMENT: i32 = 60;
+/// Score a user receives for destroying an enemy tank.
+const KILL_SCORE: i32 = 10;
+/// Battle time which counts as one minute of playing time for quests.
+const PLAY_TIME_MINUTE: Duration = Duration::from_secs(60);
+
+pub fn battle_mode_name(mode: BattleMode) -> &'static str {
+    match mode {
+        BattleMode::Dm => "DM",
+        BattleMode::Tdm => "TDM",
+        BattleMode::Ctf => "CTF",
+        BattleMode::Cp => "CP",
+        BattleMode::As => "AS",
+        BattleMode::Unknown => "UNKNOWN",
+    }
+}
+
+fn team_index(team: BattleTeam) -> Option<usize> {
+    match team {
+        BattleTeam::Red => Some(0),
+        BattleTeam::Blue => Some(1),
+        _ => None,
+    }
+}
+
+pub struct BattleUser {
+    pub user_id: String,
+    pub rank: i8,
+    /// Account experience including the score earned within this battle.
+    pub experience: i32,
+    /// Premium account, double crystals and beginner pass of the user.
+    pub reward_bonuses: RewardBonuses,
+
+    pub kills: i32,
+    pub deaths: i32,
+    pub score: i32,
+    /// Battle time which has not been counted for quests yet.
+    play_time: Duration,
+
+    pub tank: BattleTank,
+    pub supplies: SupplyInventory,
+    sender: mpsc::UnboundedSender<SharedPacket>,
+}
+
+impl BattleUser {
+    fn user_info(&self) -> UserInfo {
+        UserInfo {
+            chat_moderator_level: ChatModeratorLevel::None,
+            deaths: self.deaths,
+            kills: self.kills,
+            rank: self.rank,
+            score: self.score,
+            uid: self.user_id.clone(),
+        }
+    }
+
+    fn user_stat(&self) -> UserStat {
+        UserStat {
+            deaths: self.deaths,
+            kills: self.kills,
+            score: self.score,
+            user: self.user_id.clone(),
+        }
+    }
+
+    fn send_packet(&self, packet: SharedPacket) {
+        /* the client component will leave the battle when dropped */
+        let _ = self.sender.send(packet);
+    }
+}
+
+/// Mode specific state of a battle.
+enum ModeState {
+    /// DM and TDM do not have any mode specific state.
+    None,
+    CaptureTheFlag(CaptureTheFlag),
+    Assault(Assault),
+    ControlPoints(ControlPoints),
+}
+
+impl ModeState {
+    fn new(mode: BattleMode, geometry: &MapGeometry) -> Self {
+        match mode {
+            BattleMode::Ctf => ModeState::CaptureTheFlag(CaptureTheFlag::new(geometry)),
+            BattleMode::As => ModeState::Assault(Assault::new(geometry)),
+            BattleMode::Cp => ModeState::ControlPoints(ControlPoints::new(geometry)),
+            _ => ModeState::None,
+        }
+    }
+}
+
+enum RoundState {
+    Running { started: Instant },
+    Finished { restart_at: Instant },
+}
+
+/// Registries and services shared by all battles.
+#[derive(Clone)]
+pub struct BattleContext {
+    pub weapons: Arc<WeaponRegistry>,
+    pub bonuses: Arc<BonusRegistry>,
+    pub supplies: Arc<SupplyRegistry>,
+    pub storage: StorageHandle,
+    pub storage_tasks: StorageTasksHandle,
+    pub quests: Arc<Quests>,
+    pub progression: Arc<Progression>,
+    pub lobby: LobbyHandle,
+}
+
+pub struct Battle {
+    battle_id: String,
+    parameters: BattleCreateParameters,
+    preview: i32,
+    geometry: Arc<MapGeometry>,
+    /// Permanent battles will not be closed when the last user leaves.
+    permanent: bool,
+    context: BattleContext,
+
+    users: BTreeMap<String, BattleUser>,
+    team_scores: [i32; 2],
+    fund: i32,
+
+    mode: ModeState,
+    drops: BonusDrops,
+    mines: MineField,
+    round: RoundState,
+    last_tick: Instant,
+    closed: bool,
+}
+
+impl Battle {
+    pub fn new(battle_id: String, parameters: BattleCreateParameters, preview: i32, geometry: Arc<MapGeometry>, permanent: bool, context: BattleContext) -> Self {
+        Self {
+            battle_id,
+            mode: ModeState::new(parameters.battle_mode, &geometry),
+            drops: BonusDrops::new(&context.bonuses, Instant::now()),
+            mines: Default::default(),
+            parameters,
+            preview,
+            geometry,
+            permanent,
+            context,
+
+            users: Default::default(),
+            team_scores: [0, 0],
+            fund: 0,
+
+            round: RoundState::Running { started: Instant::now() },
+            last_tick: Instant::now(),
+            closed: false,
+        }
+    }
+
+    pub fn battle_id(&self) -> &str {
+        &self.battle_id
+    }
+
+    pub fn geometry(&self) -> &Arc<MapGeometry> {
+        &self.geometry
+    }
+
+    pub fn parameters(&self) -> &BattleCreateParameters {
+        &self.parameters
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    pub fn is_team_mode(&self) -> bool {
+        self.parameters.battle_mode != BattleMode::Dm
+    }
+
+    pub fn user(&self, user_id: &str) -> Option<&BattleUser> {
+        self.users.get(user_id)
+    }
+
+    pub fn users(&self) -> impl Iterator<Item = &BattleUser> {
+        self.users.values()
+    }
+
+    /// Scores of the red and blue team.
+    pub fn team_scores(&self) -> [i32; 2] {
+        self.team_scores
+    }
+
+    pub fn fund(&self) -> i32 {
+        self.fund
+    }
+
+    fn team_user_count(&self, team: BattleTeam) -> usize {
+        self.users.values()
+            .filter(|user| user.tank.team == team)
+            .count()
+    }
+
+    /// Seconds until the round ends by time.
+    /// Zero if the battle has no time limit.
+    fn time_left(&self, now: Instant) -> i32 {
+        let time_limit = self.parameters.method_1309.time_limit_in_sec;
+        match self.round {
+            RoundState::Running { started } if time_limit > 0 => {
+                (time_limit - now.duration_since(started).as_secs() as i32).max(0)
+            },
+            _ => 0,
+        }
+    }
+
+    fn broadcast(&self, packet: SharedPacket) {
+        for user in self.users.values() {
+            user.send_packet(packet.clone());
+        }
+    }
+
+    fn broadcast_except(&self, user_id: &str, packet: SharedPacket) {
+        for user in self.users.values() {
+            if user.user_id != user_id {
+                user.send_packet(packet.clone());
+            }
+        }
+    }
+
+    fn broadcast_lobby(&self, packet: SharedPacket) {
+        if let Ok(lobby) = self.context.lobby.lock() {
+            lobby.broadcast(packet);
+        }
+    }
+
+    fn broadcast_lobby_battle(&self, packet: SharedPacket) {
+        if let Ok(lobby) = self.context.lobby.lock() {
+            lobby.broadcast_battle(&self.battle_id, packet);
+        }
+    }
+
+    pub fn list_entry(&self) -> json::BattleListEntry {
+        let users_of = |team: BattleTeam| {
+            self.users.values()
+                .filter(|user| user.tank.team == team)
+                .map(|user| user.user_id.clone())
+                .collect::<Vec<_>>()
+        };
+
+        json::BattleListEntry {
+            battle_id: self.battle_id.clone(),
+            battle_mode: battle_mode_name(self.parameters.battle_mode).to_string(),
+            map: self.parameters.map_id.clone(),
+            max_people: self.parameters.max_people_count as i64,
+            name: self.parameters.name.clone(),
+            private_battle: self.parameters.private_battle,
+            pro_battle: self.parameters.pro_battle,
+            parkour_mode: self.parameters.parkour_mode,
+            equipment_constraints_mode: "NONE".to_string(),
+            min_rank: self.parameters.rank_range.min as i64,
+            max_rank: self.parameters.rank_range.max as i64,
+            preview: self.preview as i64,
+            suspicion_level: "NONE".to_string(),
+            users_red: users_of(BattleTeam::Red),
+            users_blue: users_of(BattleTeam::Blue),
+            users: users_of(BattleTeam::None),
+        }
+    }
+
+    /// Battle description shown to users subscribed to a user within this battle.
+    pub fn info_data(&self, server_number: i32) -> BattleInfoData {
+        BattleInfoData {
+            battle_id: self.battle_id.clone(),
+            map_name: self.parameters.map_id.clone(),
+            mode: self.parameters.battle_mode,
+            private_battle: self.parameters.private_battle,
+            pro_battle: self.parameters.pro_battle,
+            range: self.parameters.rank_range.clone(),
+            server_number,
+        }
+    }
+
+    /// Add a new user to the battle.
+    /// In team battles the max people count applies to each team.
+    pub fn join(&mut self, user_id: &str, experience: i32, reward_bonuses: RewardBonuses, team: BattleTeam, specification: TankSpecification, supplies: BTreeMap<String, i32>, sender: mpsc::UnboundedSender<SharedPacket>) -> anyhow::Result<()> {
+        if self.closed {
+            anyhow::bail!("battle has been closed");
+        }
+
+        if self.users.contains_key(user_id) {
+            anyhow::bail!("user already joined the battle");
+        }
+
+        if self.is_team_mode() == (team_index(team).is_none()) {
+            anyhow::bail!("invalid team {:?} for battle mode {:?}", team, self.parameters.battle_mode);
+        }
+
+        if self.team_user_count(team) >= self.parameters.max_people_count.max(0) as usize {
+            anyhow::bail!("battle team is full");
+        }
+
+        let rank = Rank::from_score(experience.max(0) as u32).value() as i8;
+        let rank_range = &self.parameters.rank_range;
+        if (rank as i32) < rank_range.min || (rank as i32) > rank_range.max {
+            anyhow::bail!("user rank {} not allowed", rank);
+        }
+
+        let user = BattleUser {
+            user_id: user_id.to_string(),
+            rank,
+            experience,
+            reward_bonuses,
+
+            kills: 0,
+            deaths: 0,
+            score: 0,
+            play_time: Duration::ZERO,
+
+            tank: BattleTank::new(user_id.to_string(), team, specification),
+            supplies: SupplyInventory::new(supplies),
+            sender,
+        };
+
+        self.broadcast(shared(s2c::BattleUserInit {
+            json: serde_json::to_string(&user.tank.init_json(&self.battle_id, user_id, rank))?
+        }));
+        self.users.insert(user_id.to_string(), user);
+
+        if self.is_team_mode() {
+            self.broadcast(shared(s2c::BattleUsersUserConnect {
+                user_id: user_id.to_string(),
+                users_info: self.team_user_infos(team),
+                team,
+            }));
+        } else {
+            self.broadcast(shared(s2c::BattleUserStatsUserConnect {
+                user_id: user_id.to_string(),
+                users_info: self.team_user_infos(BattleTeam::None),
+            }));
+        }
+        self.system_message_except(user_id, format!("{} joined the battle.", user_id));
+
+        self.broadcast_lobby(shared(s2c::BattleUserListUserJoinBattle {
+            battle_id: self.battle_id.clone(),
+            user_id: user_id.to_string(),
+            team,
+        }));
+        self.broadcast_lobby_battle(shared(s2c::BattleInfoAddUserToTeam {
+            battle_id: self.battle_id.clone(),
+            user: BattleInfoUser {
+                kills: 0,
+                score: 0,
+                suspicious: false,
+                user: user_id.to_string(),
+            },
+            team,
+        }));
+
+        debug!("User {} joined battle {} ({:?}).", user_id, self.battle_id, team);
+        Ok(())
+    }
+
+    pub fn leave(&mut self, user_id: &str) {
+        let position = match self.users.get(user_id) {
+            Some(user) => user.tank.position,
+            None => return,
+        };
+
+        self.mode_tank_removed(user_id, position);
+        self.remove_user_mines(user_id);
+        self.users.remove(user_id);
+
+        self.broadcast(shared(s2c::TankDestroy { tank: user_id.to_string() }));
+        self.system_message(format!("{} left the battle.", user_id));
+        if self.is_team_mode() {
+            self.broadcast(shared(s2c::BattleUsersRemoveUser { user_id: user_id.to_string() }));
+        } else {
+            self.broadcast(shared(s2c::BattleUserStatsRemoveUser { user_id: user_id.to_string() }));
+        }
+
+        self.broadcast_lobby(shared(s2c::BattleUserListUserLeaveBattle {
+            battle_id: self.battle_id.clone(),
+            user_id: user_id.to_string(),
+        }));
+        self.broadcast_lobby_battle(shared(s2c::BattleInfoRemoveUser {
+            battle_id: self.battle_id.clone(),
+            user_id: user_id.to_string(),
+        }));
+        debug!("User {} left battle {}.", user_id, self.battle_id);
+
+        if self.users.is_empty() && !self.permanent {
+            self.close();
+        }
+    }
+
+    /// Close the battle and remove it from the battle list.
+    pub fn close(&mut self) {
+        if std::mem::replace(&mut self.closed, true) {
+            return;
+        }
+
+        self.broadcast_lobby(shared(s2c::BattleListBattleRemove {
+            battle_id: self.battle_id.clone(),
+        }));
+        debug!("Battle {} closed.", self.battle_id);
+    }
+
+    /// Finish the running round paying out the fund and close the battle.
+    pub fn shutdown(&mut self) {
+        self.finish_round(Instant::now());
+        self.system_message("The server is shutting down.".to_string());
+        self.close();
+    }
+
+    fn team_user_infos(&self, team: BattleTeam) -> Vec<UserInfo> {
+        self.users.values()
+            .filter(|user| user.tank.team == team)
+            .map(BattleUser::user_info)
+            .collect()
+    }
+
+    /// Packets initializing the battle for a newly joined user.
+    pub fn initial_packets(&self, user_id: &str) -> anyhow::Result<Vec<SharedPacket>> {
+        let now = Instant::now();
+        let mut packets = Vec::with_capacity(self.users.len() + 4);
+
+        packets.push(shared(s2c::BattleStatisticsInit {
+            init_params: StatisticsModelCC {
+                battle_mode: self.parameters.battle_mode,
+                equipment_constraints_mode: self.parameters.equipment_constraints_mode,
+                fund: self.fund,
+                method_1309: self.parameters.method_1309.clone(),
+                map_name: self.parameters.name.clone(),
+                max_people_count: self.parameters.max_people_count,
+                parkour_mode: self.parameters.parkour_mode,
+                /* remaining round time */
+                method_2682: self.time_left(now),
+                spectator: false,
+                method_2378: None,
+                name_5: 0,
+            }
+        }));
+
+        if self.is_team_mode() {
+            packets.push(shared(s2c::BattleUsersInit {
+                init_params: StatisticsTeamCC {
+                    method_1860: self.team_scores[0],
+                    method_2648: self.team_scores[1],
+                    method_1840: self.team_user_infos(BattleTeam::Red),
+                    method_1572: self.team_user_infos(BattleTeam::Blue),
+                }
+            }));
+        } else {
+            packets.push(shared(s2c::BattleUserStatsInit {
+                init_params: StatisticsDMCC {
+                    users_info: self.team_user_infos(BattleTeam::None),
+                }
+            }));
+        }
+
+        packets.push(shared(s2c::BattleMapInfo {
+            json: serde_json::to_string(&json::MapInfo {
+                map_id: self.parameters.map_id.clone(),
+                battle_id: self.battle_id.clone(),
+                preview: self.preview,
+                kick_period_ms: 300_000,
+                invisible_time: 3_500,
+                spectator: false,
+                active: matches!(self.round, RoundState::Running { .. }),
+                dust_particle: "summer".to_string(),
+                minimal_rank: self.parameters.rank_range.min,
+                maximal_rank: self.parameters.rank_range.max,
+            })?
+        }));
+
+        for user in self.users.values() {
+            packets.push(shared(s2c::BattleUserInit {
+                json: serde_json::to_string(&user.tank.init_json(&self.battle_id, &user.user_id, user.rank))?
+            }));
+        }
+
+        /* TODO: The flag, base, control point and mine models, textures and sounds use the defaults of the client.
+           Register their resources with `ResourceStage::Map` once their resource ids are known. */
+        if let Some(packet) = self.mode_init_packet() {
+            packets.push(packet);
+        }
+        packets.push(self.drops.init_packet(now));
+        packets.push(self.mines.init_packet(self.context.supplies.mine()));
+        if let Some(packet) = self.supplies_init_packet(user_id, now)? {
+            packets.push(packet);
+        }
+
+        packets.push(shared(s2c::BattleStatisticsUpdateFund { fund: self.fund }));
+        if !self.users.contains_key(user_id) {
+            warn!("Generated initial battle packets for user {} which is not in battle {}.", user_id, self.battle_id);
+        }
+
+        Ok(packets)
+    }
+
+    /// Select a random spawn point of the map for the given team.
+    fn select_spawn_point(&self, team: BattleTeam) -> (Vector3<f32>, Vector3<f32>) {
+        match self.geometry.random_spawn_point(self.parameters.battle_mode, team) {
+            Some(point) => (point.position, point.orientation),
+            None => {
+                warn!("Map {} has no spawn points for team {}.", self.geometry.map_id, team_name(team));
+                (Vector3::new(0.0, 0.0, 200.0), Vector3::zeros())
+            }
+        }
+    }
+
+    /// The client finished loading the battle.
+    pub fn tank_init(&mut self, user_id: &str) {
+        let (position, orientation) = match self.users.get(user_id) {
+            Some(user) if user.tank.state == TankState::NewCome => self.select_spawn_point(user.tank.team),
+            _ => return,
+        };
+
+        let user = match self.users.get_mut(user_id) {
+            Some(user) => user,
+            None => return,
+        };
+        user.tank.state = TankState::Dead { respawn_at: Instant::now() };
+        user.tank.position = position;
+        user.tank.orientation = orientation;
+        user.send_packet(shared(s2c::TankSpawnLocation {
+            position: Some(position),
+            orientation: Some(orientation),
+        }));
+    }
+
+    /// The client requests to place its tank on the map.
+    pub fn tank_ready_to_place(&mut self, user_id: &str) {
+        if !matches!(self.round, RoundState::Running { .. }) {
+            return;
+        }
+
+        let team = match self.users.get(user_id) {
+            Some(user) => match user.tank.state {
+                TankState::Dead { respawn_at } if respawn_at <= Instant::now() => user.tank.team,
+                _ => return,
+            },
+            None => return,
+        };
+
+        let (position, orientation) = self.select_spawn_point(team);
+        let tank = match self.users.get_mut(user_id) {
+            Some(user) => &mut user.tank,
+            None => return,
+        };
+
+        tank.state = TankState::Spawned;
+        tank.incarnation = tank.incarnation.wrapping_add(1);
+        tank.health = TANK_HEALTH_MAX;
+        tank.position = position;
+        tank.orientation = orientation;
+        tank.weapon = Default::default();
+        tank.burn = None;
+
+        let packet = shared(s2c::TankSpawn {
+            tank_id: tank.tank_id.clone(),
+            team: tank.team,
+            position: Some(position),
+            orientation: Some(orientation),
+            health: tank.health,
+            incarnation_id: tank.incarnation,
+        });
+        self.broadcast(packet);
+    }
+
+    pub fn tank_ready_to_activate(&mut self, user_id: &str) {
+        let tank = match self.users.get_mut(user_id) {
+            Some(user) if user.tank.state == TankState::Spawned => &mut user.tank,
+            _ => return,
+        };
+
+        tank.state = TankState::Active;
+        let packet = shared(s2c::TankActivated { tank_id: tank.tank_id.clone() });
+        self.broadcast(packet);
+    }
+
+    /// Get the users tank if it is alive and the incarnation matches.
+    fn alive_tank_mut(&mut self, user_id: &str, incarnation: i16) -> Option<&mut BattleTank> {
+        self.users.get_mut(user_id)
+            .map(|user| &mut user.tank)
+            .filter(|tank| tank.is_alive() && tank.incarnation == incarnation)
+    }
+
+    pub fn tank_move(&mut self, user_id: &str, incarnation: i16, move_command: MoveCommand) {
+        let tank = match self.alive_tank_mut(user_id, incarnation) {
+            Some(tank) => tank,
+            None => return,
+        };
+
+        tank.apply_move_command(&move_command);
+        let position = tank.position;
+        let below_map = self.geometry.is_below_map(&position);
+        self.broadcast_except(user_id, shared(s2c::TankMoveCommand {
+            tank_id: user_id.to_string(),
+            move_command,
+        }));
+
+        if below_map {
+            debug!("Tank {} fell off the map in battle {}.", user_id, self.battle_id);
+            self.destroy_tank(user_id, None);
+        } else {
+            self.mode_tank_moved(user_id);
+            self.bonuses_tank_moved(user_id);
+            self.mines_tank_moved(user_id);
+        }
+    }
+
+    pub fn tank_move_turret(&mut self, user_id: &str, incarnation: i16, move_command: MoveCommand, turret_direction: f32) {
+        let tank = match self.alive_tank_mut(user_id, incarnation) {
+            Some(tank) => tank,
+            None => return,
+        };
+
+        tank.apply_move_command(&move_command);
+        tank.turret_direction = turret_direction;
+        let position = tank.position;
+        let below_map = self.geometry.is_below_map(&position);
+        self.broadcast_except(user_id, shared(s2c::TankMoveTurretCommand {
+            tank_id: user_id.to_string(),
+            move_command,
+            turret_direction,
+        }));
+
+        if below_map {
+            debug!("Tank {} fell off the map in battle {}.", user_id, self.battle_id);
+            self.destroy_tank(user_id, None);
+        } else {
+            self.mode_tank_moved(user_id);
+            self.bonuses_tank_moved(user_id);
+            self.mines_tank_moved(user_id);
+        }
+    }
+
+    pub fn tank_control_flags(&mut self, user_id: &str, incarnation: i16, control: i8) {
+        let tank = match self.alive_tank_mut(user_id, incarnation) {
+            Some(tank) => tank,
+            None => return,
+        };
+
+        tank.control = control;
+        self.broadcast_except(user_id, shared(s2c::TankMoveControlFlags {
+            tank_id: user_id.to_string(),
+            control,
+        }));
+    }
+
+    pub fn tank_turret(&mut self, user_id: &str, incarnation: i16, rotate_turret_command: RotateTurretCommand) {
+        let tank = match self.alive_tank_mut(user_id, incarnation) {
+            Some(tank) => tank,
+            None => return,
+        };
+
+        tank.turret_direction = rotate_turret_command.angle;
+        self.broadcast_except(user_id, shared(s2c::TankTurretCommand {
+            tank_id: user_id.to_string(),
+            rotate_turret_command,
+        }));
+    }
+
+    /// Destroy a tank.
+    /// The killer receives score and the battle fund will be increased by the rank of the destroyed tank.
+    pub fn destroy_tank(&mut self, tank_id: &str, killer_id: Option<&str>) {
+        let now = Instant::now();
+        let (team, rank, position) = match self.users.get_mut(tank_id) {
+            Some(user) if user.tank.is_alive() => {
+                user.tank.state = TankState::Dead { respawn_at: now + RESPAWN_DELAY };
+                user.tank.health = 0;
+                user.deaths += 1;
+                (user.tank.team, user.rank, user.tank.position)
+            },
+            _ => return,
+        };
+
+        self.broadcast(shared(s2c::TankKill {
+            tank_id: tank_id.to_string(),
+            killer_tank_id: killer_id.unwrap_or(tank_id).to_string(),
+            respawn_delay: RESPAWN_DELAY.as_millis() as i32,
+        }));
+        self.mode_tank_removed(tank_id, position);
+        self.clear_tank_effects(tank_id);
+
+        let team_mode = self.is_team_mode();
+        let mut updated_users = vec![ tank_id.to_string() ];
+        let killer = killer_id
+            .filter(|killer_id| *killer_id != tank_id)
+            .and_then(|killer_id| self.users.get_mut(killer_id))
+            .filter(|killer| !team_mode || killer.tank.team != team);
+
+        if let Some(killer) = killer {
+            killer.kills += 1;
+            killer.score += KILL_SCORE;
+            let killer_team = killer.tank.team;
+            let killer_id = killer.user_id.clone();
+
+            self.add_fund((rank as i32).max(1));
+            if self.kills_count_for_team(killer_team) {
+                self.add_team_score(killer_team, 1);
+            }
+
+            self.context.quests.record(&killer_id, QuestKind::Kills, 1);
+            self.add_experience(&killer_id, KILL_SCORE);
+            updated_users.push(killer_id);
+        }
+
+        for user_id in updated_users {
+            self.broadcast_user_stats(&user_id);
+        }
+
+        if self.score_limit_reached() {
+            self.finish_round(now);
+        }
+    }
+
+    /// Check if kills of the team count towards the team score.
+    /// In CTF and CP the team score is only earned by the mode objectives.
+    /// In assault only the defenders earn score by kills.
+    fn kills_count_for_team(&self, team: BattleTeam) -> bool {
+        match self.parameters.battle_mode {
+            BattleMode::Tdm => true,
+            BattleMode::As => team == ASSAULT_DEFENDER_TEAM,
+            _ => false,
+        }
+    }
+
+    fn add_fund(&mut self, amount: i32) {
+        self.fund += amount;
+        self.broadcast(shared(s2c::BattleStatisticsUpdateFund { fund: self.fund }));
+    }
+
+    fn add_team_score(&mut self, team: BattleTeam, amount: i32) {
+        let index = match team_index(team) {
+            Some(index) => index,
+            None => return,
+        };
+
+        self.team_scores[index] += amount;
+        self.broadcast(shared(s2c::BattleUsersUpdateTeamScore {
+            team,
+            score: self.team_scores[index],
+        }));
+        self.broadcast_lobby_battle(shared(s2c::BattleInfoUpdateTeamScore {
+            battle_id: self.battle_id.clone(),
+            team_type: team,
+            score: self.team_scores[index],
+        }));
+    }
+
+    fn add_user_score(&mut self, user_id: &str, amount: i32) {
+        match self.users.get_mut(user_id) {
+            Some(user) => user.score += amount,
+            None => return,
+        }
+
+        self.add_experience(user_id, amount);
+        self.broadcast_user_stats(user_id);
+    }
+
+    /// Add earned score including the users bonuses to the account experience of the user.
+    /// A rank-up will be announced to the battle immediately.
+    fn add_experience(&mut self, user_id: &str, amount: i32) {
+        let user = match self.users.get_mut(user_id) {
+            Some(user) => user,
+            None => return,
+        };
+
+        let amount = user.reward_bonuses.score(amount, Utc::now());
+        user.experience += amount;
+        let rank = Rank::from_score(user.experience.max(0) as u32).value() as i8;
+        if rank > user.rank {
+            user.rank = rank;
+            self.broadcast(shared(s2c::BattleStatisticsUserRankUp {
+                user_id: user_id.to_string(),
+                new_rank: rank as i32,
+            }));
+        }
+
+        self.context.progression.add_experience(user_id, amount);
+        self.context.quests.record(user_id, QuestKind::Score, amount);
+    }
+
+    /// Send the users kills and score to the battle and the lobby.
+    fn broadcast_user_stats(&self, user_id: &str) {
+        let user = match self.users.get(user_id) {
+            Some(user) => user,
+            None => return,
+        };
+
+        if self.is_team_mode() {
+            self.broadcast(shared(s2c::BattleUsersUpdateUserStat {
+                users_stat: user.user_stat(),
+                team: user.tank.team,
+            }));
+        } else {
+            self.broadcast(shared(s2c::BattleUserStatsAddUser {
+                users_stat: user.user_stat(),
+            }));
+        }
+
+        self.broadcast_lobby_battle(shared(s2c::BattleInfoUpdateUserKills {
+            battle_id: self.battle_id.clone(),
+            user_id: user.user_id.clone(),
+            kills: user.kills,
+        }));
+        self.broadcast_lobby_battle(shared(s2c::BattleInfoUpdateUserScore {
+            battle_id: self.battle_id.clone(),
+            user_id: user.user_id.clone(),
+            score: user.score,
+        }));
+    }
+
+    fn mode_init_packet(&self) -> Option<SharedPacket> {
+        match &self.mode {
+            ModeState::None => None,
+            ModeState::CaptureTheFlag(ctf) => Some(ctf.init_packet()),
+            ModeState::Assault(assault) => Some(assault.init_packet()),
+            ModeState::ControlPoints(control_points) => Some(control_points.init_packet()),
+        }
+    }
+
+    fn mode_tank_moved(&mut self, user_id: &str) {
+        match &self.mode {
+            ModeState::None => {},
+            ModeState::CaptureTheFlag(_) => self.ctf_tank_moved(user_id),
+            ModeState::Assault(_) => self.assault_tank_moved(user_id),
+            ModeState::ControlPoints(_) => self.cp_tank_moved(user_id),
+        }
+    }
+
+    /// The tank has been destroyed or left the battle.
+    fn mode_tank_removed(&mut self, user_id: &str, position: Vector3<f32>) {
+        match &self.mode {
+            ModeState::None => {},
+            ModeState::CaptureTheFlag(_) => self.ctf_drop_flag(user_id, position),
+            ModeState::Assault(_) => self.assault_drop_flag(user_id, position),
+            ModeState::ControlPoints(_) => self.cp_tank_removed(user_id),
+        }
+    }
+
+    fn mode_tick(&mut self, now: Instant, elapsed: Duration) {
+        match &self.mode {
+            ModeState::None => {},
+            ModeState::CaptureTheFlag(_) => self.ctf_tick(now),
+            ModeState::Assault(_) => self.assault_tick(now),
+            ModeState::ControlPoints(_) => self.cp_tick(now, elapsed),
+        }
+    }
+
+    /// Return all flags to their bases and neutralize all control points.
+    fn mode_reset(&mut self) {
+        match &self.mode {
+            ModeState::None => {},
+            ModeState::CaptureTheFlag(_) => self.ctf_reset(),
+            ModeState::Assault(_) => self.assault_reset(),
+            ModeState::ControlPoints(_) => self.cp_reset(),
+        }
+    }
+
+    fn score_limit_reached(&self) -> bool {
+        let score_limit = self.parameters.method_1309.score_limit;
+        if score_limit <= 0 {
+            return false;
+        }
+
+        if self.is_team_mode() {
+            self.team_scores.iter().any(|score| *score >= score_limit)
+        } else {
+            self.users.values().any(|user| user.kills >= score_limit)
+        }
+    }
+
+    /// Split the fund between the users.
+    /// In team battles the fund will be split between the teams by their score first.
+    fn distribute_fund(&self) -> Vec<(String, i32)> {
+        let split_by_score = |fund: i32, users: Vec<&BattleUser>| {
+            let total_score = users.iter().map(|user| user.score as i64).sum::<i64>();
+            users.into_iter()
+                .map(|user| {
+                    let reward = if total_score > 0 {
+                        (fund as i64 * user.score as i64 / total_score) as i32
+                    } else {
+                        0
+                    };
+                    (user.user_id.clone(), reward)
+                })
+                .collect::<Vec<_>>()
+        };
+
+        if !self.is_team_mode() {
+            return split_by_score(self.fund, self.users.values().collect());
+        }
+
+        let total_score = self.team_scores.iter().sum::<i32>() as i64;
+        let mut rewards = Vec::with_capacity(self.users.len());
+        for (index, team) in [ BattleTeam::Red, BattleTeam::Blue ].into_iter().enumerate() {
+            let team_fund = if total_score > 0 {
+                (self.fund as i64 * self.team_scores[index] as i64 / total_score) as i32
+            } else {
+                self.fund / 2
+            };
+
+            let users = self.users.values()
+                .filter(|user| user.tank.team == team)
+                .collect::<Vec<_>>();
+            rewards.extend(split_by_score(team_fund, users));
+        }
+
+        rewards
+    }
+
+    fn finish_round(&mut self, now: Instant) {
+        if !matches!(self.round, RoundState::Running { .. }) {
+            return;
+        }
+
+        let restart_at = now + ROUND_RESTART_DELAY;
+        self.round = RoundState::Finished { restart_at };
+
+        let timestamp = Utc::now();
+        let rewards = self.distribute_fund()
+            .into_iter()
+            .filter_map(|(user_id, reward)| {
+                let reward = self.users.get(&user_id)?.reward_bonuses.crystals(reward, timestamp);
+                Some((user_id, reward))
+            })
+            .collect::<Vec<_>>();
+        self.broadcast(shared(s2c::BattleStatisticsRoundFinish {
+            reward: rewards.iter()
+                .map(|(user_id, reward)| UserReward {
+                    beginner_bonus_reward: reward.beginner_bonus,
+                    premium_bonus_reward: reward.premium_bonus,
+                    reward: reward.reward,
+                    user_id: user_id.clone(),
+                })
+                .collect(),
+            time_to_restart: ROUND_RESTART_DELAY.as_millis() as i32,
+        }));
+        self.system_message(format!("The round has finished. The next round starts in {} seconds.", ROUND_RESTART_DELAY.as_secs()));
+
+        match serde_json::to_string(&json::RoundFinish { battle_id: self.battle_id.clone() }) {
+            Ok(json) => self.broadcast_lobby_battle(shared(s2c::BattleInfoRoundFinish { json })),
+            Err(error) => warn!("failed to encode round finish: {}", error),
+        }
+
+        let user_ids = self.users.keys().cloned().collect::<Vec<_>>();
+        for user_id in user_ids {
+            self.clear_tank_effects(&user_id);
+            if let Some(user) = self.users.get_mut(&user_id) {
+                user.tank.state = TankState::Dead { respawn_at: restart_at };
+            }
+        }
+
+        for (user_id, reward) in rewards {
+            if reward.total() > 0 {
+                self.pay_crystals(&user_id, reward.total());
+            }
+        }
+
+        self.context.progression.update_ratings(self.users.keys().cloned().collect());
+
+        debug!("Battle {} round finished.", self.battle_id);
+    }
+
+    /// Add crystals to the account of the user and notify the client.
+    fn pay_crystals(&self, user_id: &str, amount: i32) {
+        let sender = match self.users.get(user_id) {
+            Some(user) => user.sender.clone(),
+            None => return,
+        };
+
+        self.context.quests.record(user_id, QuestKind::Crystals, amount);
+
+        let storage = self.context.storage.clone();
+        let user_id = user_id.to_string();
+        self.context.storage_tasks.spawn(async move {
+            match storage.add_user_crystals(&user_id, amount).await {
+                Ok(Some(crystals)) => {
+                    let _ = sender.send(shared(s2c::AccountRankUpdateCrystals { change_by: crystals }));
+                },
+                Ok(None) => {},
+                Err(error) => tracing::error!("failed to pay out {} crystals to {}: {}", amount, user_id, error),
+            }
+        });
+    }
+
+    fn start_round(&mut self, now: Instant) {
+        self.round = RoundState::Running { started: now };
+        self.fund = 0;
+        self.team_scores = [0, 0];
+        for user in self.users.values_mut() {
+            user.kills = 0;
+            user.deaths = 0;
+            user.score = 0;
+        }
+
+        self.mode_reset();
+        self.bonuses_reset(now);
+        self.mines_reset();
+        self.broadcast(shared(s2c::BattleStatisticsRoundStart {}));
+        self.broadcast(shared(s2c::BattleStatisticsUpdateFund { fund: self.fund }));
+        if self.is_team_mode() {
+            self.broadcast(shared(s2c::BattleUsersUpdateUsers {
+                red_users: self.users.values().filter(|user| user.tank.team == BattleTeam::Red).map(BattleUser::user_stat).collect(),
+                blue_users: self.users.values().filter(|user| user.tank.team == BattleTeam::Blue).map(BattleUser::user_stat).collect(),
+            }));
+            for team in [ BattleTeam::Red, BattleTeam::Blue ] {
+                self.broadcast(shared(s2c::BattleUsersUpdateTeamScore { team, score: 0 }));
+            }
+        } else {
+            self.broadcast(shared(s2c::BattleUserStatsUpdateUsers {
+                users_stat: self.users.values().map(BattleUser::user_stat).collect(),
+            }));
+        }
+        self.broadcast_lobby_battle(shared(s2c::BattleInfoRoundStart {
+            battle_id: self.battle_id.clone(),
+        }));
+
+        let spawn_points = self.users.values()
+            .map(|user| (user.user_id.clone(), self.select_spawn_point(user.tank.team)))
+            .collect::<Vec<_>>();
+        for (user_id, (position, orientation)) in spawn_points {
+            if let Some(user) = self.users.get_mut(&user_id) {
+                user.tank.position = position;
+                user.tank.orientation = orientation;
+                user.send_packet(shared(s2c::TankSpawnLocation {
+                    position: Some(position),
+                    orientation: Some(orientation),
+                }));
+            }
+        }
+
+        debug!("Battle {} round started.", self.battle_id);
+    }
+
+    /// Count the time every user spent within the battle towards their quests.
+    fn tick_play_time(&mut self, elapsed: Duration) {
+        for user in self.users.values_mut() {
+            user.play_time += elapsed;
+            if user.play_time < PLAY_TIME_MINUTE {
+                continue;
+            }
+
+            let minutes = (user.play_time.as_secs() / PLAY_TIME_MINUTE.as_secs()) as u32;
+            user.play_time -= PLAY_TIME_MINUTE * minutes;
+            self.context.quests.record(&user.user_id, QuestKind::PlayTime, minutes as i32);
+        }
+    }
+
+    /// Advance all time based battle logic.
+    pub fn tick(&mut self, now: Instant) {
+        let last_tick = self.last_tick;
+        let elapsed = now.saturating_duration_since(last_tick);
+        self.last_tick = now;
+        self.tick_burning(elapsed);
+        self.tick_play_time(elapsed);
+
+        match self.round {
+            RoundState::Running { .. } => {
+                self.mode_tick(now, elapsed);
+                self.tick_bonuses(now);
+                self.tick_mines(now);
+                if self.parameters.method_1309.time_limit_in_sec > 0 {
+                    let time_left = self.time_left(now);
+                    if time_left <= 0 {
+                        self.finish_round(now);
+                    } else if time_left <= ROUND_ENDING_ANNOUNCEMENT && self.time_left(last_tick) > ROUND_ENDING_ANNOUNCEMENT {
+                        self.system_message(format!("The round ends in {} seconds.", time_left));
+                    }
+                }
+            },
+            RoundState::Finished { restart_at } => {
+                if restart_at <= now {
+                    self.start_round(now);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,462 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::{Duration, Instant};
+
+use fost_protocol::codec::{BattleMine, BattleMineCC, BattleMode, BattleTeam};
+use fost_protocol::packets::s2c;
+use nalgebra::Vector3;
+use rand::Rng;
+use serde::{Serialize, Deserialize};
+use tracing::{debug, warn};
+
+use super::{Battle, BonusEffect, SharedPacket, TankState, json, shared};
+use super::bonuses::default_factor;
+
+pub static SUPPLIES_JSON: &'static str = include_str!("../../resources/supplies.json");
+
+/// Mines reported as hit by the client may be slightly further away than the trigger radius.
+const MINE_HIT_TOLERANCE: f32 = 200.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum SupplyKind {
+    Repair,
+    Armor,
+    Damage,
+    Nitro,
+    Mine,
+}
+
+impl SupplyKind {
+    /// Effect applied to the tank when the supply is used.
+    pub fn effect(self) -> Option<BonusEffect> {
+        match self {
+            SupplyKind::Repair => Some(BonusEffect::Repair),
+            SupplyKind::Armor => Some(BonusEffect::Armor),
+            SupplyKind::Damage => Some(BonusEffect::Damage),
+            SupplyKind::Nitro => Some(BonusEffect::Nitro),
+            SupplyKind::Mine => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SupplyDefinition {
+    /// Inventory item id (e.g. `double_damage`).
+    pub item_id: String,
+    /// Slot of the item in the battle inventory bar.
+    pub slot_id: i32,
+    pub kind: SupplyKind,
+    /// Effect id shown by the client.
+    #[serde(default)]
+    pub effect_id: i32,
+    #[serde(default)]
+    pub duration_ms: u32,
+    #[serde(default = "default_factor")]
+    pub factor: f32,
+    pub cooldown_ms: u32,
+    /// Amount given to newly registered users.
+    #[serde(default)]
+    pub starter_count: i32,
+}
+
+impl SupplyDefinition {
+    pub fn duration(&self) -> Duration {
+        Duration::from_millis(self.duration_ms as u64)
+    }
+
+    pub fn cooldown(&self) -> Duration {
+        Duration::from_millis(self.cooldown_ms as u64)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MineDefinition {
+    /// Distance at which a tank triggers the mine.
+    pub radius: f32,
+    pub impact_force: f32,
+    /// Time until a placed mine becomes armed.
+    pub activate_time_ms: u32,
+    pub damage_min: f32,
+    pub damage_max: f32,
+    /// The oldest mine of a user will be removed when placing more mines.
+    pub max_per_user: usize,
+    /// Mines may not be placed close to flags and bases.
+    pub min_distance_from_base: f32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SupplyRegistry {
+    supplies: Vec<SupplyDefinition>,
+    mine: MineDefinition,
+}
+
+impl SupplyRegistry {
+    pub fn parse(payload: &str) -> anyhow::Result<Self> {
+        Ok(serde_json::from_str(payload)?)
+    }
+
+    pub fn find(&self, item_id: &str) -> Option<&SupplyDefinition> {
+        self.supplies.iter().find(|supply| supply.item_id == item_id)
+    }
+
+    pub fn supplies(&self) -> &[SupplyDefinition] {
+        &self.supplies
+    }
+
+    pub fn mine(&self) -> &MineDefinition {
+        &self.mine
+    }
+
+    /// Items and their amount given to newly registered users.
+    pub fn starter_items(&self) -> Vec<(String, i32)> {
+        self.supplies.iter()
+            .filter(|supply| supply.starter_count > 0)
+            .map(|supply| (supply.item_id.clone(), supply.starter_count))
+            .collect()
+    }
+}
+
+/// Supplies of a user within a battle.
+/// The counts are loaded when joining and the changes are written through to the storage.
+#[derive(Debug, Clone, Default)]
+pub struct SupplyInventory {
+    counts: BTreeMap<String, i32>,
+    ready_at: BTreeMap<String, Instant>,
+}
+
+impl SupplyInventory {
+    pub fn new(counts: BTreeMap<String, i32>) -> Self {
+        Self {
+            counts,
+            ready_at: Default::default(),
+        }
+    }
+
+    pub fn count(&self, item_id: &str) -> i32 {
+        self.counts.get(item_id).copied().unwrap_or(0)
+    }
+
+    /// Remaining cooldown of the item.
+    pub fn cooldown_left(&self, item_id: &str, now: Instant) -> Duration {
+        self.ready_at.get(item_id)
+            .map(|ready_at| ready_at.saturating_duration_since(now))
+            .unwrap_or_default()
+    }
+
+    /// Consume one item if available and not cooling down.
+    pub fn try_use(&mut self, item_id: &str, cooldown: Duration, now: Instant) -> bool {
+        if self.count(item_id) <= 0 || !self.cooldown_left(item_id, now).is_zero() {
+            return false;
+        }
+
+        if let Some(count) = self.counts.get_mut(item_id) {
+            *count -= 1;
+        }
+        self.ready_at.insert(item_id.to_string(), now + cooldown);
+        true
+    }
+}
+
+struct PlacedMine {
+    owner_id: String,
+    team: BattleTeam,
+    position: Vector3<f32>,
+    placed_at: Instant,
+    armed: bool,
+}
+
+/// Mines placed in a battle.
+#[derive(Default)]
+pub struct MineField {
+    mines: BTreeMap<String, PlacedMine>,
+    counter: u32,
+}
+
+impl MineField {
+    pub fn init_packet(&self, definition: &MineDefinition) -> SharedPacket {
+        shared(s2c::BattleMinesInit {
+            init_params: BattleMineCC {
+                activate_time_msec: definition.activate_time_ms as i32,
+                battle_mines: self.mines.iter()
+                    .map(|(mine_id, mine)| BattleMine {
+                        mine_id: mine_id.clone(),
+                        owner_id: mine.owner_id.clone(),
+                        position: Some(mine.position),
+                    })
+                    .collect(),
+                far_visibility_radius: 10.0,
+                impact_force: definition.impact_force,
+                min_distance_from_base: definition.min_distance_from_base,
+                near_visibility_radius: 7.0,
+                radius: definition.radius,
+                ..Default::default()
+            }
+        })
+    }
+
+    /// Mines of the user ordered by their placement.
+    fn owned_by(&self, owner_id: &str) -> Vec<String> {
+        let mut mines = self.mines.iter()
+            .filter(|(_, mine)| mine.owner_id == owner_id)
+            .collect::<Vec<_>>();
+        mines.sort_by_key(|(_, mine)| mine.placed_at);
+        mines.into_iter()
+            .map(|(mine_id, _)| mine_id.clone())
+            .collect()
+    }
+}
+
+impl Battle {
+    pub(super) fn supplies_init_packet(&self, user_id: &str, now: Instant) -> anyhow::Result<Option<SharedPacket>> {
+        if self.parameters.without_supplies {
+            return Ok(None);
+        }
+
+        let user = match self.users.get(user_id) {
+            Some(user) => user,
+            None => return Ok(None),
+        };
+
+        let inventory = json::InventoryInit {
+            items: self.context.supplies.supplies().iter()
+                .map(|supply| json::InventoryItem {
+                    id: supply.item_id.clone(),
+                    count: user.supplies.count(&supply.item_id),
+                    slot_id: supply.slot_id,
+                    item_effect_time: supply.duration_ms as i32,
+                    item_rest_sec: user.supplies.cooldown_left(&supply.item_id, now).as_secs() as i32,
+                })
+                .collect(),
+        };
+
+        Ok(Some(shared(s2c::BattleDrugsInit { json: serde_json::to_string(&inventory)? })))
+    }
+
+    /// The user activated a supply of the inventory.
+    pub fn use_supply(&mut self, user_id: &str, item_id: &str) {
+        let now = Instant::now();
+        if self.parameters.without_supplies {
+            return;
+        }
+
+        let definition = match self.context.supplies.find(item_id) {
+            Some(definition) => definition.clone(),
+            None => {
+                debug!("User {} used unknown supply {}.", user_id, item_id);
+                return;
+            }
+        };
+
+        match self.users.get(user_id) {
+            Some(user) if user.tank.state == TankState::Active => {},
+            _ => return,
+        }
+
+        if definition.kind == SupplyKind::Mine && !self.can_place_mine(user_id) {
+            return;
+        }
+
+        let user = match self.users.get_mut(user_id) {
+            Some(user) => user,
+            None => return,
+        };
+        if !user.supplies.try_use(item_id, definition.cooldown(), now) {
+            return;
+        }
+
+        user.send_packet(shared(s2c::BattleDrugsActivated {
+            item_id: item_id.to_string(),
+            time: definition.cooldown_ms as i32,
+            decrease: true,
+        }));
+
+        let storage = self.context.storage.clone();
+        let (user_id_owned, item_id_owned) = (user_id.to_string(), item_id.to_string());
+        self.context.storage_tasks.spawn(async move {
+            match storage.add_user_item(&user_id_owned, &item_id_owned, -1).await {
+                Ok(Some(_)) => {},
+                Ok(None) => warn!("User {} used supply {} which is not in the inventory.", user_id_owned, item_id_owned),
+                Err(error) => tracing::error!("failed to decrease supply {} of {}: {}", item_id_owned, user_id_owned, error),
+            }
+        });
+
+        match definition.kind.effect() {
+            Some(effect) => self.apply_tank_effect(user_id, effect, definition.effect_id, definition.duration(), definition.factor),
+            None => self.place_mine(user_id, now),
+        }
+    }
+
+    /// Check if the tank is far enough away from all flags and bases to place a mine.
+    fn can_place_mine(&self, user_id: &str) -> bool {
+        let position = match self.users.get(user_id) {
+            Some(user) => user.tank.position,
+            None => return false,
+        };
+
+        let bases = match self.parameters.battle_mode {
+            BattleMode::Ctf => self.geometry.ctf_flags.map(Vec::from).unwrap_or_default(),
+            BattleMode::As => self.geometry.assault_flags.iter()
+                .chain(self.geometry.assault_bases.iter())
+                .copied()
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        let min_distance = self.context.supplies.mine().min_distance_from_base;
+        bases.iter().all(|base| (base - position).norm() >= min_distance)
+    }
+
+    fn place_mine(&mut self, user_id: &str, now: Instant) {
+        let (team, position) = match self.users.get(user_id) {
+            Some(user) => (user.tank.team, user.tank.position),
+            None => return,
+        };
+
+        let owned = self.mines.owned_by(user_id);
+        let max_per_user = self.context.supplies.mine().max_per_user.max(1);
+        if owned.len() >= max_per_user {
+            for mine_id in &owned[..=owned.len() - max_per_user] {
+                self.mines.mines.remove(mine_id);
+                self.broadcast(shared(s2c::BattleMinesRemove { mine_id: mine_id.clone() }));
+            }
+        }
+
+        self.mines.counter = self.mines.counter.wrapping_add(1);
+        let mine_id = format!("mine_{}", self.mines.counter);
+        self.mines.mines.insert(mine_id.clone(), PlacedMine {
+            owner_id: user_id.to_string(),
+            team,
+            position,
+            placed_at: now,
+            armed: false,
+        });
+        self.broadcast(shared(s2c::BattleMinesPlace {
+            mine_id,
+            x: position.x,
+            y: position.y,
+            z: position.z,
+            user_id: user_id.to_string(),
+        }));
+    }
+
+    /// Arm all mines which have been placed long enough.
+    pub(super) fn tick_mines(&mut self, now: Instant) {
+        let activate_time = Duration::from_millis(self.context.supplies.mine().activate_time_ms as u64);
+        let armed = self.mines.mines.iter_mut()
+            .filter(|(_, mine)| !mine.armed && mine.placed_at + activate_time <= now)
+            .map(|(mine_id, mine)| {
+                mine.armed = true;
+                mine_id.clone()
+            })
+            .collect::<Vec<_>>();
+
+        for mine_id in armed {
+            self.broadcast(shared(s2c::BattleMinesActivate { mine_id }));
+        }
+    }
+
+    pub(super) fn mines_tank_moved(&mut self, user_id: &str) {
+        let radius = self.context.supplies.mine().radius;
+        let triggered = self.mines.mines.keys().cloned().collect::<Vec<_>>();
+        for mine_id in triggered {
+            self.trigger_mine(user_id, &mine_id, radius);
+        }
+    }
+
+    /// The client reported driving over the mine.
+    pub fn hit_mine(&mut self, user_id: &str, mine_id: &str) {
+        let radius = self.context.supplies.mine().radius + MINE_HIT_TOLERANCE;
+        self.trigger_mine(user_id, mine_id, radius);
+    }
+
+    /// Explode the mine if the tank is an enemy of the owner and within the radius.
+    fn trigger_mine(&mut self, user_id: &str, mine_id: &str, radius: f32) {
+        let (team, position) = match self.users.get(user_id) {
+            Some(user) if user.tank.state == TankState::Active => (user.tank.team, user.tank.position),
+            _ => return,
+        };
+
+        let owner_id = match self.mines.mines.get(mine_id) {
+            Some(mine) if mine.armed && mine.owner_id != user_id => {
+                let friendly = self.is_team_mode() && mine.team == team && !self.parameters.friendly_fire;
+                if friendly || (mine.position - position).norm() > radius {
+                    return;
+                }
+
+                mine.owner_id.clone()
+            },
+            _ => return,
+        };
+
+        self.mines.mines.remove(mine_id);
+        self.broadcast(shared(s2c::BattleMinesExplode {
+            mine_id: mine_id.to_string(),
+            target_id: user_id.to_string(),
+        }));
+
+        let definition = self.context.supplies.mine();
+        let damage = if definition.damage_max > definition.damage_min {
+            rand::thread_rng().gen_range(definition.damage_min..=definition.damage_max)
+        } else {
+            definition.damage_min
+        };
+        self.damage_tank(user_id, &owner_id, damage, false);
+    }
+
+    /// Remove all mines of the user, e.g. when leaving the battle.
+    pub(super) fn remove_user_mines(&mut self, user_id: &str) {
+        let owned = self.mines.owned_by(user_id);
+        if owned.is_empty() {
+            return;
+        }
+
+        for mine_id in owned {
+            self.mines.mines.remove(&mine_id);
+        }
+        self.broadcast(shared(s2c::BattleMinesRemoveAll { owner_id: user_id.to_string() }));
+    }
+
+    pub(super) fn mines_reset(&mut self) {
+        let owners = self.mines.mines.values()
+            .map(|mine| mine.owner_id.clone())
+            .collect::<BTreeSet<_>>();
+        for owner_id in owners {
+            self.remove_user_mines(&owner_id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+    use std::time::{Duration, Instant};
+
+    use super::{SupplyRegistry, SupplyInventory, SupplyKind, SUPPLIES_JSON};
+
+    #[test]
+    fn test_supply_registry() {
+        let registry = SupplyRegistry::parse(SUPPLIES_JSON).unwrap();
+        assert_eq!(registry.find("mine").unwrap().kind, SupplyKind::Mine);
+        assert!(registry.find("mine").unwrap().kind.effect().is_none());
+        assert_eq!(registry.find("health").unwrap().factor, 1.0);
+        assert!(registry.starter_items().iter().any(|(item_id, _)| item_id == "n2o"));
+    }
+
+    #[test]
+    fn test_supply_cooldown() {
+        let now = Instant::now();
+        let cooldown = Duration::from_secs(30);
+        let mut inventory = SupplyInventory::new(BTreeMap::from([ ("health".to_string(), 2) ]));
+
+        assert!(inventory.try_use("health", cooldown, now));
+        assert!(!inventory.try_use("health", cooldown, now + Duration::from_secs(10)));
+        assert_eq!(inventory.cooldown_left("health", now + Duration::from_secs(10)), Duration::from_secs(20));
+
+        assert!(inventory.try_use("health", cooldown, now + cooldown));
+        assert!(!inventory.try_use("health", cooldown, now + cooldown * 2));
+        assert_eq!(inventory.count("health"), 0);
+        assert!(!inventory.try_use("armor", cooldown, now));
+    }
+}
@@ -0,0 +1,288 @@
+use std::time::Instant;
+
+use fost_protocol::codec::{BattleTeam, MoveCommand};
+use nalgebra::Vector3;
+
+use crate::{GarageCatalog, GarageCategory, UserGarage, format_item_id};
+
+use super::{WeaponState, TankBurn, TankEffects};
+
+/// Health value the client considers as fully repaired.
+pub const TANK_HEALTH_MAX: i16 = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TankState {
+    /// The tank joined the battle but the client did not finished loading.
+    NewCome,
+    /// The tank has been destroyed (or not yet been spawned) and
+    /// can be placed again after the given timestamp.
+    Dead { respawn_at: Instant },
+    /// The tank has been placed on the map but is not yet active.
+    /// Spawned tanks are invulnerable.
+    Spawned,
+    Active,
+}
+
+/// Physical properties and equipment of a tank.
+#[derive(Debug, Clone)]
+pub struct TankSpecification {
+    pub hull_id: String,
+    pub hull_resource: i64,
+    pub turret_id: String,
+    pub turret_resource: i64,
+    pub colormap_id: i64,
+
+    /// Hit points of the hull.
+    pub armor: f32,
+    pub max_speed: f32,
+    pub max_turn_speed: f32,
+    pub acceleration: f32,
+    pub reverse_acceleration: f32,
+    pub side_acceleration: f32,
+    pub turn_acceleration: f32,
+    pub reverse_turn_acceleration: f32,
+    pub mass: f32,
+    pub power: f32,
+    pub damping_coeff: f32,
+
+    pub turret_turn_speed: f32,
+    pub turret_turn_acceleration: f32,
+    pub kickback: f32,
+    pub impact_force: f32,
+}
+
+impl Default for TankSpecification {
+    /// Hunter M0 with Smoky M0 in the green paint, the starter equipment of every user.
+    fn default() -> Self {
+        Self {
+            hull_id: "hunter_m0".to_string(),
+            hull_resource: 227169,
+            turret_id: "smoky_m0".to_string(),
+            turret_resource: 906685,
+            colormap_id: 966681,
+
+            armor: 150.0,
+            max_speed: 8.0,
+            max_turn_speed: 1.3,
+            acceleration: 9.09,
+            reverse_acceleration: 11.5,
+            side_acceleration: 7.5,
+            turn_acceleration: 2.0,
+            reverse_turn_acceleration: 2.5,
+            mass: 1761.0,
+            power: 9.09,
+            damping_coeff: 1500.0,
+
+            turret_turn_speed: 0.9,
+            turret_turn_acceleration: 1.2,
+            kickback: 2.3,
+            impact_force: 3.3,
+        }
+    }
+}
+
+impl TankSpecification {
+    /// Tank equipped with the hull, turret and paint mounted in the users garage.
+    /// The garage catalog has no physical properties, those stay at the Hunter M0 values.
+    pub fn from_garage(catalog: &GarageCatalog, garage: &UserGarage) -> Self {
+        let mounted = |category: GarageCategory| {
+            let item_id = garage.mounted(catalog, category)?;
+            let item = catalog.find(item_id)?;
+            let modification = garage.equipment.get(item_id)?.modification;
+            Some((format_item_id(item_id, modification), item.preview_resource_id))
+        };
+
+        let mut specification = Self::default();
+        if let Some((hull_id, hull_resource)) = mounted(GarageCategory::Armor) {
+            specification.hull_id = hull_id;
+            specification.hull_resource = hull_resource;
+        }
+
+        if let Some((turret_id, turret_resource)) = mounted(GarageCategory::Weapon) {
+            specification.turret_id = turret_id;
+            specification.turret_resource = turret_resource;
+        }
+
+        if let Some((_, colormap_id)) = mounted(GarageCategory::Paint) {
+            specification.colormap_id = colormap_id;
+        }
+
+        specification
+    }
+}
+
+pub struct BattleTank {
+    pub tank_id: String,
+    pub team: BattleTeam,
+    pub specification: TankSpecification,
+
+    pub state: TankState,
+    /// Incremented with every spawn.
+    /// Commands of previous incarnations will be dropped.
+    pub incarnation: i16,
+    pub health: i16,
+
+    pub position: Vector3<f32>,
+    pub orientation: Vector3<f32>,
+    pub turret_direction: f32,
+    pub control: i8,
+
+    pub weapon: WeaponState,
+    pub burn: Option<TankBurn>,
+    pub effects: TankEffects,
+}
+
+impl BattleTank {
+    pub fn new(tank_id: String, team: BattleTeam, specification: TankSpecification) -> Self {
+        Self {
+            tank_id,
+            team,
+            specification,
+
+            state: TankState::NewCome,
+            incarnation: 0,
+            health: 0,
+
+            position: Vector3::zeros(),
+            orientation: Vector3::zeros(),
+            turret_direction: 0.0,
+            control: 0,
+
+            weapon: Default::default(),
+            burn: None,
+            effects: Default::default(),
+        }
+    }
+
+    pub fn is_alive(&self) -> bool {
+        matches!(self.state, TankState::Spawned | TankState::Active)
+    }
+
+    /// Update the tanks position by the latest movement command.
+    /// Commands without a position are ignored.
+    pub fn apply_move_command(&mut self, command: &MoveCommand) {
+        if let Some(position) = command.position {
+            self.position = position;
+        }
+
+        if let Some(orientation) = command.orientation {
+            self.orientation = orientation;
+        }
+
+        self.control = command.control;
+    }
+
+    /// Reduce the health by the damage in hit points.
+    /// Returns true if the tank has no health left.
+    pub fn apply_damage(&mut self, damage: f32) -> bool {
+        let damage = damage / self.specification.armor * TANK_HEALTH_MAX as f32;
+        self.health = (self.health as f32 - damage.max(0.0)).round().max(0.0) as i16;
+        self.health == 0
+    }
+
+    fn state_name(&self) -> &'static str {
+        match self.state {
+            TankState::NewCome => "newcome",
+            TankState::Dead { .. } => "suicide",
+            TankState::Spawned | TankState::Active => "active",
+        }
+    }
+
+    pub fn init_json(&self, battle_id: &str, nickname: &str, rank: i8) -> json::TankInit {
+        let specification = &self.specification;
+        json::TankInit {
+            battle_id: battle_id.to_string(),
+            colormap_id: specification.colormap_id,
+            hull_id: specification.hull_id.clone(),
+            turret_id: specification.turret_id.clone(),
+            team_type: team_name(self.team).to_string(),
+            parts_object: "{\"engineIdleSound\":386284,\"engineStartMovingSound\":226985,\"engineMovingSound\":75329,\"turretSequenceSound\":242699}".to_string(),
+            hull_resource: specification.hull_resource,
+            turret_resource: specification.turret_resource,
+            sfx_data: "".to_string(),
+            position: self.position.into(),
+            orientation: self.orientation.into(),
+            incarnation: self.incarnation,
+            tank_id: self.tank_id.clone(),
+            nickname: nickname.to_string(),
+            state: self.state_name().to_string(),
+            max_speed: specification.max_speed,
+            max_turn_speed: specification.max_turn_speed,
+            acceleration: specification.acceleration,
+            reverse_acceleration: specification.reverse_acceleration,
+            side_acceleration: specification.side_acceleration,
+            turn_acceleration: specification.turn_acceleration,
+            reverse_turn_acceleration: specification.reverse_turn_acceleration,
+            mass: specification.mass,
+            power: specification.power,
+            damping_coeff: specification.damping_coeff,
+            turret_turn_speed: specification.turret_turn_speed,
+            health: self.health as f32,
+            rank: rank as i64,
+            kickback: specification.kickback,
+            turret_turn_acceleration: specification.turret_turn_acceleration,
+            impact_force: specification.impact_force,
+            state_null: matches!(self.state, TankState::NewCome),
+        }
+    }
+}
+
+pub fn team_name(team: BattleTeam) -> &'static str {
+    match team {
+        BattleTeam::Red => "RED",
+        BattleTeam::Blue => "BLUE",
+        _ => "NONE",
+    }
+}
+
+pub mod json {
+    use serde::{Serialize, Deserialize};
+
+    use crate::Vector3f;
+
+    /// Payload of the `BattleUserInit` packet.
+    #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct TankInit {
+        pub battle_id: String,
+        #[serde(rename = "colormap_id")]
+        pub colormap_id: i64,
+        #[serde(rename = "hull_id")]
+        pub hull_id: String,
+        #[serde(rename = "turret_id")]
+        pub turret_id: String,
+        #[serde(rename = "team_type")]
+        pub team_type: String,
+        pub parts_object: String,
+        pub hull_resource: i64,
+        pub turret_resource: i64,
+        pub sfx_data: String,
+        pub position: Vector3f,
+        pub orientation: Vector3f,
+        pub incarnation: i16,
+        #[serde(rename = "tank_id")]
+        pub tank_id: String,
+        pub nickname: String,
+        pub state: String,
+        pub max_speed: f32,
+        pub max_turn_speed: f32,
+        pub acceleration: f32,
+        pub reverse_acceleration: f32,
+        pub side_acceleration: f32,
+        pub turn_acceleration: f32,
+        pub reverse_turn_acceleration: f32,
+        pub mass: f32,
+        pub power: f32,
+        pub damping_coeff: f32,
+        #[serde(rename = "turret_turn_speed")]
+        pub turret_turn_speed: f32,
+        pub health: f32,
+        pub rank: i64,
+        pub kickback: f32,
+        pub turret_turn_acceleration: f32,
+        #[serde(rename = "impact_force")]
+        pub impact_force: f32,
+        #[serde(rename = "state_null")]
+        pub state_null: bool,
+    }
+}
@@ -0,0 +1,471 @@
+use std::time::{Duration, Instant};
+
+use fost_protocol::codec::{DamageIndicatorType, TargetTankDamage};
+use fost_protocol::packets::s2c;
+use nalgebra::Vector3;
+use rand::Rng;
+use serde::{Serialize, Deserialize};
+use tracing::debug;
+
+use super::{Battle, BonusEffect, TankState, shared};
+
+pub static WEAPONS_JSON: &'static str = include_str!("../../resources/weapons.json");
+
+/// Shots are accepted slightly before the reload finished to compensate network jitter.
+const RELOAD_TOLERANCE: f32 = 0.8;
+/// Upper limit of the time a single stream weapon hit report accounts for.
+const MAX_STREAM_HIT_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum WeaponKind {
+    Smoky,
+    Firebird,
+}
+
+/// Damage falloff over the distance to the target.
+/// Full damage up to `max_damage_range` which then decreases linearly
+/// until `min_damage_percent` is reached at `min_damage_range`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WeaponRange {
+    pub max_damage_range: f32,
+    pub min_damage_range: f32,
+    pub min_damage_percent: f32,
+}
+
+/// Burn effect applied by stream weapons.
+/// The temperature of the target raises while being hit and burns the tank until it cooled down.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WeaponBurn {
+    pub heat_per_second: f32,
+    pub cooling_per_second: f32,
+    /// Damage per second at the maximal temperature.
+    pub damage_per_second: f32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WeaponModification {
+    /// Damage per shot or damage per second for stream weapons.
+    pub damage_min: f32,
+    pub damage_max: f32,
+    /// Time between two shots or two hit reports for stream weapons.
+    pub reload_ms: u32,
+    /// Hits on targets further away will be rejected.
+    pub max_distance: f32,
+    #[serde(default)]
+    pub range: Option<WeaponRange>,
+    #[serde(default)]
+    pub critical_chance: f32,
+    #[serde(default)]
+    pub critical_damage: f32,
+    #[serde(default)]
+    pub burn: Option<WeaponBurn>,
+}
+
+impl WeaponModification {
+    pub fn reload(&self) -> Duration {
+        Duration::from_millis(self.reload_ms as u64)
+    }
+
+    /// Damage multiplier for a target at the given distance.
+    pub fn weakening(&self, distance: f32) -> f32 {
+        let range = match &self.range {
+            Some(range) => range,
+            None => return 1.0,
+        };
+
+        let min_factor = range.min_damage_percent / 100.0;
+        if distance <= range.max_damage_range {
+            1.0
+        } else if distance >= range.min_damage_range {
+            min_factor
+        } else {
+            let progress = (distance - range.max_damage_range) / (range.min_damage_range - range.max_damage_range);
+            1.0 - (1.0 - min_factor) * progress
+        }
+    }
+
+    /// Roll the base damage of a shot.
+    /// Returns the damage and if the shot has been a critical hit.
+    pub fn roll_damage(&self, rng: &mut impl Rng) -> (f32, bool) {
+        if self.critical_chance > 0.0 && rng.gen::<f32>() < self.critical_chance {
+            return (self.critical_damage, true);
+        }
+
+        let damage = if self.damage_max > self.damage_min {
+            rng.gen_range(self.damage_min..=self.damage_max)
+        } else {
+            self.damage_min
+        };
+        (damage, false)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WeaponDefinition {
+    pub turret_id: String,
+    pub kind: WeaponKind,
+    /// Modifications indexed by their level (m0 to m3).
+    pub modifications: Vec<WeaponModification>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WeaponRegistry {
+    turrets: Vec<WeaponDefinition>,
+}
+
+impl WeaponRegistry {
+    pub fn parse(payload: &str) -> anyhow::Result<Self> {
+        Ok(serde_json::from_str(payload)?)
+    }
+
+    /// Lookup the weapon of a turret item id (e.g. `smoky_m0`).
+    pub fn find(&self, turret_id: &str) -> Option<(WeaponKind, &WeaponModification)> {
+        let (turret, modification) = turret_id.rsplit_once("_m")?;
+        let modification = modification.parse::<usize>().ok()?;
+
+        let weapon = self.turrets.iter().find(|weapon| weapon.turret_id == turret)?;
+        weapon.modifications.get(modification)
+            .map(|modification| (weapon.kind, modification))
+    }
+}
+
+/// Weapon related state of a tank.
+#[derive(Debug, Clone, Default)]
+pub struct WeaponState {
+    pub last_shot: Option<Instant>,
+    /// Stream weapons only. Set between the start and stop packet.
+    pub firing: bool,
+}
+
+/// A tank which has been set on fire.
+#[derive(Debug, Clone)]
+pub struct TankBurn {
+    /// User who will be credited for the kill.
+    pub attacker: String,
+    pub temperature: f32,
+    pub cooling_per_second: f32,
+    pub damage_per_second: f32,
+}
+
+impl Battle {
+    /// Weapon of the user's tank if the tank is alive.
+    fn active_weapon(&self, user_id: &str, kind: WeaponKind) -> Option<WeaponModification> {
+        let user = self.users.get(user_id)?;
+        if user.tank.state != TankState::Active {
+            return None;
+        }
+
+        match self.context.weapons.find(&user.tank.specification.turret_id) {
+            Some((weapon_kind, modification)) if weapon_kind == kind => Some(modification.clone()),
+            _ => {
+                debug!("User {} fired a {:?} with turret {}.", user_id, kind, user.tank.specification.turret_id);
+                None
+            }
+        }
+    }
+
+    /// Check and update the reload time of the user's weapon.
+    fn try_reload(&mut self, user_id: &str, reload: Duration, now: Instant) -> bool {
+        let state = match self.users.get_mut(user_id) {
+            Some(user) => &mut user.tank.weapon,
+            None => return false,
+        };
+
+        if let Some(last_shot) = state.last_shot {
+            if now.duration_since(last_shot) < reload.mul_f32(RELOAD_TOLERANCE) {
+                return false;
+            }
+        }
+
+        state.last_shot = Some(now);
+        true
+    }
+
+    /// Validate a hit of the shooter on the target.
+    /// Returns the distance between both tanks.
+    fn validate_hit(&self, shooter_id: &str, target_id: &str, incarnation: i16, max_distance: f32) -> Option<f32> {
+        if shooter_id == target_id {
+            return None;
+        }
+
+        let shooter = self.users.get(shooter_id)?;
+        let target = self.users.get(target_id)?;
+
+        /* spawned tanks are invulnerable until they have been activated */
+        if target.tank.state != TankState::Active || target.tank.incarnation != incarnation {
+            return None;
+        }
+
+        if self.is_team_mode() && shooter.tank.team == target.tank.team && !self.parameters.friendly_fire {
+            return None;
+        }
+
+        let distance = (target.tank.position - shooter.tank.position).norm();
+        if distance > max_distance {
+            debug!("Rejected hit of {} on {} at distance {}.", shooter_id, target_id, distance);
+            return None;
+        }
+
+        Some(distance)
+    }
+
+    /// Apply damage (in hit points) to a tank and notify the clients.
+    /// The damage is scaled by the damage bonus of the attacker and the armor bonus of the target.
+    /// The tank will be destroyed if no health is left.
+    pub fn damage_tank(&mut self, target_id: &str, attacker_id: &str, damage: f32, critical: bool) {
+        let damage_factor = self.users.get(attacker_id)
+            .map(|attacker| attacker.tank.effects.factor(BonusEffect::Damage))
+            .unwrap_or(1.0);
+        let (damage, health, destroyed) = match self.users.get_mut(target_id) {
+            Some(user) if user.tank.state == TankState::Active => {
+                let damage = damage * damage_factor / user.tank.effects.factor(BonusEffect::Armor);
+                let destroyed = user.tank.apply_damage(damage);
+                (damage, user.tank.health, destroyed)
+            },
+            _ => return,
+        };
+
+        self.broadcast(shared(s2c::TankHealth {
+            tank_id: target_id.to_string(),
+            health: health as f32,
+        }));
+
+        let indicator = if destroyed {
+            DamageIndicatorType::Fatal
+        } else if critical {
+            DamageIndicatorType::Critical
+        } else {
+            DamageIndicatorType::Normal
+        };
+
+        if let Some(attacker) = self.users.get(attacker_id) {
+            let _ = attacker.sender.send(shared(s2c::BattleDamageIndicator {
+                damages: vec![
+                    TargetTankDamage {
+                        method_2673: damage,
+                        method_2351: indicator,
+                        target: target_id.to_string(),
+                    }
+                ],
+            }));
+        }
+
+        if destroyed {
+            self.destroy_tank(target_id, Some(attacker_id));
+        }
+    }
+
+    pub fn smoky_shot(&mut self, shooter_id: &str, target_id: &str, incarnation: i16, hit_point: Option<Vector3<f32>>) {
+        let weapon = match self.active_weapon(shooter_id, WeaponKind::Smoky) {
+            Some(weapon) => weapon,
+            None => return,
+        };
+
+        if !self.try_reload(shooter_id, weapon.reload(), Instant::now()) {
+            return;
+        }
+
+        let distance = match self.validate_hit(shooter_id, target_id, incarnation, weapon.max_distance) {
+            Some(distance) => distance,
+            None => {
+                /* still show the shot to the other players */
+                self.broadcast_except(shooter_id, shared(s2c::WeaponSmokyShotDummy {
+                    shooter: shooter_id.to_string(),
+                }));
+                return;
+            }
+        };
+
+        let weakening = weapon.weakening(distance);
+        let (damage, critical) = weapon.roll_damage(&mut rand::thread_rng());
+        self.broadcast_except(shooter_id, shared(s2c::WeaponSmokyShotTarget {
+            shooter: shooter_id.to_string(),
+            target: target_id.to_string(),
+            hit_point,
+            weakening_coeff: weakening,
+            is_critical: critical,
+        }));
+
+        self.damage_tank(target_id, shooter_id, damage * weakening, critical);
+    }
+
+    pub fn smoky_shot_static(&mut self, shooter_id: &str, hit_point: Option<Vector3<f32>>) {
+        let weapon = match self.active_weapon(shooter_id, WeaponKind::Smoky) {
+            Some(weapon) => weapon,
+            None => return,
+        };
+
+        if !self.try_reload(shooter_id, weapon.reload(), Instant::now()) {
+            return;
+        }
+
+        self.broadcast_except(shooter_id, shared(s2c::WeaponSmokyShotStatic {
+            shooter: shooter_id.to_string(),
+            hit_point,
+        }));
+    }
+
+    pub fn smoky_shot_dummy(&mut self, shooter_id: &str) {
+        let weapon = match self.active_weapon(shooter_id, WeaponKind::Smoky) {
+            Some(weapon) => weapon,
+            None => return,
+        };
+
+        if !self.try_reload(shooter_id, weapon.reload(), Instant::now()) {
+            return;
+        }
+
+        self.broadcast_except(shooter_id, shared(s2c::WeaponSmokyShotDummy {
+            shooter: shooter_id.to_string(),
+        }));
+    }
+
+    pub fn firebird_start(&mut self, shooter_id: &str) {
+        if self.active_weapon(shooter_id, WeaponKind::Firebird).is_none() {
+            return;
+        }
+
+        if let Some(user) = self.users.get_mut(shooter_id) {
+            user.tank.weapon.firing = true;
+            user.tank.weapon.last_shot = None;
+        }
+
+        self.broadcast_except(shooter_id, shared(s2c::WeaponFirebirdStart {
+            shooter: shooter_id.to_string(),
+        }));
+    }
+
+    pub fn firebird_stop(&mut self, shooter_id: &str) {
+        match self.users.get_mut(shooter_id) {
+            Some(user) if user.tank.weapon.firing => user.tank.weapon.firing = false,
+            _ => return,
+        }
+
+        self.broadcast_except(shooter_id, shared(s2c::WeaponFirebirdStop {
+            shooter: shooter_id.to_string(),
+        }));
+    }
+
+    /// The shooter reported targets within the flame.
+    /// Damage is based on the time since the previous report.
+    pub fn firebird_hit(&mut self, shooter_id: &str, targets: &[String], incarnations: &[i16]) {
+        let weapon = match self.active_weapon(shooter_id, WeaponKind::Firebird) {
+            Some(weapon) => weapon,
+            None => return,
+        };
+
+        let now = Instant::now();
+        let elapsed = match self.users.get(shooter_id) {
+            Some(user) if user.tank.weapon.firing => user.tank.weapon.last_shot
+                .map(|last_shot| now.duration_since(last_shot))
+                .unwrap_or_else(|| weapon.reload())
+                .min(MAX_STREAM_HIT_INTERVAL),
+            _ => return,
+        };
+
+        if !self.try_reload(shooter_id, weapon.reload(), now) {
+            return;
+        }
+
+        let mut rng = rand::thread_rng();
+        for (target_id, incarnation) in targets.iter().zip(incarnations.iter()) {
+            let distance = match self.validate_hit(shooter_id, target_id, *incarnation, weapon.max_distance) {
+                Some(distance) => distance,
+                None => continue,
+            };
+
+            let (damage_per_second, _) = weapon.roll_damage(&mut rng);
+            let damage = damage_per_second * elapsed.as_secs_f32() * weapon.weakening(distance);
+
+            if let Some(burn) = &weapon.burn {
+                self.heat_tank(target_id, shooter_id, burn, burn.heat_per_second * elapsed.as_secs_f32());
+            }
+
+            self.damage_tank(target_id, shooter_id, damage, false);
+        }
+    }
+
+    fn heat_tank(&mut self, target_id: &str, attacker_id: &str, burn: &WeaponBurn, heat: f32) {
+        let tank = match self.users.get_mut(target_id) {
+            Some(user) => &mut user.tank,
+            None => return,
+        };
+
+        let temperature = tank.burn.as_ref()
+            .map(|burn| burn.temperature)
+            .unwrap_or(0.0);
+        let temperature = (temperature + heat).min(1.0);
+        tank.burn = Some(TankBurn {
+            attacker: attacker_id.to_string(),
+            temperature,
+            cooling_per_second: burn.cooling_per_second,
+            damage_per_second: burn.damage_per_second,
+        });
+
+        self.broadcast(shared(s2c::TankUpdateTemperature {
+            tank_id: target_id.to_string(),
+            temperature,
+        }));
+    }
+
+    /// Apply the burn damage and cool down all burning tanks.
+    pub(super) fn tick_burning(&mut self, elapsed: Duration) {
+        let mut damages = Vec::new();
+        for user in self.users.values_mut() {
+            let tank = &mut user.tank;
+            if tank.state != TankState::Active {
+                tank.burn = None;
+                continue;
+            }
+
+            let burn = match &mut tank.burn {
+                Some(burn) => burn,
+                None => continue,
+            };
+
+            let damage = burn.damage_per_second * burn.temperature * elapsed.as_secs_f32();
+            burn.temperature = (burn.temperature - burn.cooling_per_second * elapsed.as_secs_f32()).max(0.0);
+            damages.push((user.user_id.clone(), burn.attacker.clone(), damage, burn.temperature));
+
+            if burn.temperature <= 0.0 {
+                tank.burn = None;
+            }
+        }
+
+        for (target_id, attacker_id, damage, temperature) in damages {
+            self.broadcast(shared(s2c::TankUpdateTemperature {
+                tank_id: target_id.clone(),
+                temperature,
+            }));
+            self.damage_tank(&target_id, &attacker_id, damage, false);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{WeaponRegistry, WeaponKind, WEAPONS_JSON};
+
+    #[test]
+    fn test_weapon_registry() {
+        let registry = WeaponRegistry::parse(WEAPONS_JSON).unwrap();
+        let (kind, smoky) = registry.find("smoky_m0").unwrap();
+        assert_eq!(kind, WeaponKind::Smoky);
+        assert!(registry.find("smoky_m9").is_none());
+        assert!(registry.find("railgun_m0").is_none());
+
+        let range = smoky.range.as_ref().unwrap();
+        assert_eq!(smoky.weakening(0.0), 1.0);
+        assert_eq!(smoky.weakening(range.max_damage_range), 1.0);
+        assert_eq!(smoky.weakening(range.min_damage_range * 2.0), range.min_damage_percent / 100.0);
+
+        let halfway = (range.max_damage_range + range.min_damage_range) / 2.0;
+        let expected = 1.0 - (1.0 - range.min_damage_percent / 100.0) / 2.0;
+        assert!((smoky.weakening(halfway) - expected).abs() < 0.001);
+    }
+}
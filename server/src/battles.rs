@@ -1,9 +1,311 @@
+use std::{collections::BTreeMap, sync::{Arc, Mutex, Weak}, time::{Duration, Instant}};
+
+use anyhow::Context;
+use fost_protocol::codec::{BattleCreateParameters, BattleMode, MapTheme, BattleLimits, Range, EquipmentConstraintsMode};
+use serde::{Serialize, Deserialize};
+use tokio::sync::mpsc;
+use tracing::info;
+
+use crate::{Battle, BattleContext, Quests, Progression, StorageTasksHandle, SharedPacket, MapRegistry, MapGeometry, WeaponRegistry, WEAPONS_JSON, BonusRegistry, BONUSES_JSON, SupplyRegistry, SUPPLIES_JSON, battle_mode_name, client::ClientId, storage::StorageHandle, config::ConfigHandle};
+
+pub static MAPS_JSON: &'static str = include_str!("../resources/maps.json");
+
+/// Interval in which the time based battle logic will be executed.
+const BATTLE_TICK_INTERVAL: Duration = Duration::from_millis(250);
+
+mod json {
+    use serde::{Serialize, Deserialize};
+
+    #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct Map {
+        pub map_id: String,
+        pub map_name: String,
+        pub max_people: i32,
+        pub preview: i32,
+        pub max_rank: i32,
+        pub min_rank: i32,
+        pub supported_modes: Vec<String>,
+        pub theme: String,
+    }
+
+    #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct Maps {
+        pub maps: Vec<Map>,
+    }
+}
+
+fn map_theme_name(theme: MapTheme) -> &'static str {
+    match theme {
+        MapTheme::Summer => "SUMMER",
+        MapTheme::Winter => "WINTER",
+        MapTheme::Space => "SPACE",
+        MapTheme::SummerDay => "SUMMER_DAY",
+        MapTheme::SummerNight => "SUMMER_NIGHT",
+        MapTheme::WinterDay => "WINTER_DAY",
+        MapTheme::Unknown => "UNKNOWN",
+    }
+}
+
+struct LobbySubscriber {
+    sender: mpsc::UnboundedSender<SharedPacket>,
+    selected_battle: Option<String>,
+}
+
+/// Clients viewing the battle list.
+/// Battles use the lobby to notify about changes.
+pub struct BattleLobby {
+    subscriber: BTreeMap<ClientId, LobbySubscriber>,
+}
+
+impl BattleLobby {
+    pub fn new() -> Self {
+        Self {
+            subscriber: Default::default(),
+        }
+    }
+
+    pub fn subscribe(&mut self, client_id: ClientId) -> mpsc::UnboundedReceiver<SharedPacket> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.subscriber.insert(client_id, LobbySubscriber {
+            sender: tx,
+            selected_battle: None
+        });
+        rx
+    }
+
+    pub fn unsubscribe(&mut self, client_id: ClientId) {
+        self.subscriber.remove(&client_id);
+    }
+
+    /// Select the battle the client receives detailed updates for.
+    pub fn select_battle(&mut self, client_id: ClientId, battle_id: Option<String>) {
+        if let Some(subscriber) = self.subscriber.get_mut(&client_id) {
+            subscriber.selected_battle = battle_id;
+        }
+    }
+
+    pub fn selected_battle(&self, client_id: ClientId) -> Option<&str> {
+        self.subscriber.get(&client_id)
+            .and_then(|subscriber| subscriber.selected_battle.as_deref())
+    }
+
+    /// Send a packet to every client viewing the battle list.
+    pub fn broadcast(&self, packet: SharedPacket) {
+        for subscriber in self.subscriber.values() {
+            let _ = subscriber.sender.send(packet.clone());
+        }
+    }
+
+    /// Send a packet to every client which has selected the battle.
+    pub fn broadcast_battle(&self, battle_id: &str, packet: SharedPacket) {
+        for subscriber in self.subscriber.values() {
+            if subscriber.selected_battle.as_deref() == Some(battle_id) {
+                let _ = subscriber.sender.send(packet.clone());
+            }
+        }
+    }
+}
+
+pub type LobbyHandle = Arc<Mutex<BattleLobby>>;
+
 pub struct BattleProvider {
+    config: ConfigHandle,
+    context: BattleContext,
 
+    maps: Vec<json::Map>,
+    geometries: MapRegistry,
+    battles: BTreeMap<String, Arc<Mutex<Battle>>>,
+    /// No battles can be created while the server shuts down.
+    shutting_down: bool,
 }
 
 impl BattleProvider {
-    pub fn new() -> Self {
-        Self {}
+    pub fn new(config: ConfigHandle, storage: StorageHandle, quests: Arc<Quests>, progression: Arc<Progression>, storage_tasks: StorageTasksHandle) -> anyhow::Result<Self> {
+        let maps = serde_json::from_str::<json::Maps>(MAPS_JSON)?.maps;
+        let maps_directory = config.read()
+            .ok()
+            .context("failed to read the config")?
+            .resources.maps_directory.clone();
+        let geometries = MapRegistry::load(maps_directory.as_deref())?;
+        let weapons = WeaponRegistry::parse(WEAPONS_JSON).context("failed to parse the weapons")?;
+        let bonuses = BonusRegistry::parse(BONUSES_JSON).context("failed to parse the bonuses")?;
+        let supplies = SupplyRegistry::parse(SUPPLIES_JSON).context("failed to parse the supplies")?;
+
+        let mut result = Self {
+            config,
+            context: BattleContext {
+                weapons: Arc::new(weapons),
+                bonuses: Arc::new(bonuses),
+                supplies: Arc::new(supplies),
+                storage,
+                storage_tasks,
+                quests,
+                progression,
+                lobby: Arc::new(Mutex::new(BattleLobby::new())),
+            },
+
+            maps,
+            geometries,
+            battles: Default::default(),
+            shutting_down: false,
+        };
+
+        result.create_default_battles();
+        Ok(result)
+    }
+
+    fn create_default_battles(&mut self) {
+        /* the default battle is never removed, its rounds must end to pay out the fund */
+        let parameters = BattleCreateParameters {
+            auto_balance: false,
+            battle_mode: BattleMode::Tdm,
+            equipment_constraints_mode: EquipmentConstraintsMode::None,
+            friendly_fire: false,
+            method_1309: BattleLimits {
+                score_limit: 100,
+                time_limit_in_sec: 15 * 60,
+            },
+            map_id: "map_silence_moon".to_string(),
+            max_people_count: 4,
+            name: "Default Battle".to_string(),
+            parkour_mode: false,
+            private_battle: false,
+            pro_battle: false,
+            rank_range: Range { min: 0, max: 21 },
+            re_armor_enabled: true,
+            theme: MapTheme::Summer,
+            without_bonuses: false,
+            without_crystals: false,
+            without_supplies: false,
+        };
+
+        let geometry = self.geometries.geometry(&parameters.map_id)
+            .unwrap_or_else(|| Arc::new(MapGeometry::empty(parameters.map_id.clone())));
+        self.register_battle(parameters, 952789, geometry, true);
     }
-}
\ No newline at end of file
+
+    pub fn lobby(&self) -> &LobbyHandle {
+        &self.context.lobby
+    }
+
+    pub fn find_battle(&self, battle_id: &str) -> Option<Arc<Mutex<Battle>>> {
+        self.battles.get(battle_id).cloned()
+    }
+
+    pub fn battles(&self) -> impl Iterator<Item = &Arc<Mutex<Battle>>> {
+        self.battles.values()
+    }
+
+    /// Finish all battles and pay out their funds.
+    /// Closed battles can not be joined anymore.
+    pub fn shutdown(&mut self) {
+        self.shutting_down = true;
+        for battle in self.battles.values() {
+            if let Ok(mut battle) = battle.lock() {
+                battle.shutdown();
+            }
+        }
+    }
+
+    fn generate_battle_id(&self) -> String {
+        loop {
+            let battle_id = format!("{:016x}", rand::random::<u64>());
+            if !self.battles.contains_key(&battle_id) {
+                break battle_id;
+            }
+        }
+    }
+
+    /// Validate the parameters against the map registry and create a new battle.
+    pub fn create_battle(&mut self, parameters: BattleCreateParameters) -> anyhow::Result<Arc<Mutex<Battle>>> {
+        let battle_creation = self.config.read()
+            .ok()
+            .context("failed to read the config")?
+            .features.battle_creation;
+        if !battle_creation {
+            anyhow::bail!("battle creation is disabled");
+        }
+
+        if self.shutting_down {
+            anyhow::bail!("the server is shutting down");
+        }
+
+        let name_length = parameters.name.chars().count();
+        if name_length == 0 || name_length > 64 {
+            anyhow::bail!("invalid battle name");
+        }
+
+        let mode = battle_mode_name(parameters.battle_mode);
+        let theme = map_theme_name(parameters.theme);
+        let map = self.maps.iter()
+            .find(|map| map.map_id == parameters.map_id && map.theme == theme)
+            .context("unknown map")?;
+
+        if !map.supported_modes.iter().any(|supported| supported == mode) {
+            anyhow::bail!("map does not support {}", mode);
+        }
+
+        let geometry = self.geometries.geometry(&parameters.map_id)
+            .context("map geometry not available")?;
+        if !geometry.supports_mode(parameters.battle_mode) {
+            anyhow::bail!("map geometry does not support {}", mode);
+        }
+
+        if parameters.max_people_count < 1 || parameters.max_people_count > map.max_people {
+            anyhow::bail!("invalid max people count");
+        }
+
+        if parameters.rank_range.min > parameters.rank_range.max || parameters.rank_range.max > map.max_rank {
+            anyhow::bail!("invalid rank range");
+        }
+
+        let preview = map.preview;
+        Ok(self.register_battle(parameters, preview, geometry, false))
+    }
+
+    fn register_battle(&mut self, parameters: BattleCreateParameters, preview: i32, geometry: Arc<MapGeometry>, permanent: bool) -> Arc<Mutex<Battle>> {
+        /* remove all closed battles before adding a new one */
+        self.battles.retain(|_, battle| {
+            battle.lock()
+                .map(|battle| !battle.is_closed())
+                .unwrap_or(false)
+        });
+
+        let battle_id = self.generate_battle_id();
+        let battle = Battle::new(battle_id.clone(), parameters, preview, geometry, permanent, self.context.clone());
+        let battle = Arc::new(Mutex::new(battle));
+        spawn_battle_ticker(Arc::downgrade(&battle));
+
+        info!("Created battle {}.", battle_id);
+        self.battles.insert(battle_id, battle.clone());
+        battle
+    }
+}
+
+fn spawn_battle_ticker(battle: Weak<Mutex<Battle>>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(BATTLE_TICK_INTERVAL);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+
+        loop {
+            interval.tick().await;
+
+            let battle = match battle.upgrade() {
+                Some(battle) => battle,
+                None => break,
+            };
+
+            let mut battle = match battle.lock() {
+                Ok(battle) => battle,
+                Err(_) => break,
+            };
+
+            if battle.is_closed() {
+                break;
+            }
+
+            battle.tick(Instant::now());
+        }
+    });
+}
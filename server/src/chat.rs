@@ -1,21 +1,24 @@
-use std::{collections::{BTreeMap, VecDeque}, sync::{Arc, RwLock}, task::{self, Poll}};
+use std::{collections::{BTreeMap, VecDeque}, sync::{Arc, RwLock}, task::{self, Poll}, time::{Duration, Instant}};
 
 use anyhow::Context;
 use fost_protocol::{codec::{ChatMessage, ChatCC, ChatModeratorLevel, UserStatus}, packets::{s2c, PacketDowncast, c2s}};
 use tokio::sync::mpsc::{self, error::TrySendError};
 
-use crate::{client::{ClientId, ClientComponent, Client, AuthenticationState}, server::ServerEvent};
+use crate::{Moderation, ModerationCommand, ModerationError, client::{ClientId, ClientComponent, Client, AuthenticationState}, server::ServerEvent, config::{ConfigHandle, ChatConfig}};
 
 #[derive(Debug, Clone)]
 enum ServerChatEvent {
     Message(ChatMessage),
-    MessageHistory(Vec<ChatMessage>)
+    MessageHistory(Vec<ChatMessage>),
+    /// Remove all messages of the user.
+    CleanUserMessages(String),
+    Clear,
 }
 
 pub struct ServerChat {
+    config: ConfigHandle,
     subscriber: BTreeMap<ClientId, mpsc::UnboundedSender<ServerChatEvent>>,
 
-    message_history_length: usize,
     message_history: VecDeque<ChatMessage>,
 }
 
@@ -25,17 +28,23 @@ Do not expect all features to work properly.
 Report bugs: https://github.com/WolverinDEV/protanki-protocol-rs"#;
 
 impl ServerChat {
-    pub fn new() -> Self {
+    pub fn new(config: ConfigHandle) -> Self {
         let mut result = Self {
+            config,
             subscriber: BTreeMap::new(),
 
-            message_history_length: 100,
             message_history: VecDeque::with_capacity(100),
         };
 
         result
     }
 
+    fn message_history_length(&self) -> usize {
+        self.config.read()
+            .map(|config| config.chat.history_length)
+            .unwrap_or(0)
+    }
+
     pub fn register_system_message(&mut self, text: String, warning: bool) {
         let chat_message = ChatMessage {
             source_user_status: None,
@@ -47,15 +56,17 @@ impl ServerChat {
 
         self.dispatch_server_event(&ServerChatEvent::Message(chat_message.clone()));
         self.message_history.push_back(chat_message);
-        while self.message_history.len() > self.message_history_length {
+
+        let message_history_length = self.message_history_length();
+        while self.message_history.len() > message_history_length {
             let _ = self.message_history.pop_front();
         }
     }
 
-    pub fn register_message(&mut self, user_id: &str, target_user_id: Option<String>, message: &str) {
+    pub fn register_message(&mut self, user_id: &str, moderator_level: ChatModeratorLevel, target_user_id: Option<String>, message: &str) {
         let chat_message = ChatMessage {
             source_user_status: Some(UserStatus {
-                chat_moderator_level: ChatModeratorLevel::None,
+                chat_moderator_level: moderator_level,
                 ip: "".to_string(),
                 rank_index: 2,
                 uid: user_id.to_string()
@@ -68,11 +79,27 @@ impl ServerChat {
 
         self.dispatch_server_event(&ServerChatEvent::Message(chat_message.clone()));
         self.message_history.push_back(chat_message);
-        while self.message_history.len() > self.message_history_length {
+
+        let message_history_length = self.message_history_length();
+        while self.message_history.len() > message_history_length {
             let _ = self.message_history.pop_front();
         }
     }
 
+    /// Remove all messages of the user from the history and the clients.
+    pub fn clean_user_messages(&mut self, user_id: &str) {
+        self.message_history.retain(|message| {
+            message.source_user_status.as_ref().map_or(true, |status| status.uid != user_id)
+        });
+        self.dispatch_server_event(&ServerChatEvent::CleanUserMessages(user_id.to_string()));
+    }
+
+    /// Remove all messages from the history and the clients.
+    pub fn clear(&mut self) {
+        self.message_history.clear();
+        self.dispatch_server_event(&ServerChatEvent::Clear);
+    }
+
     pub fn send_message_history(&self, client_id: u32) {
         let subscriber = match self.subscriber.get(&client_id) {
             Some(subscriber) => subscriber,
@@ -107,20 +134,66 @@ impl ServerChat {
     }
 }
 
+/// Messages exceeding this length will be cut off.
+const MAX_MESSAGE_LENGTH: usize = 256;
+/// Fraction of the antiflood typing time which must have passed between two messages.
+/// Compensates timing differences between the client and the server.
+const ANTIFLOOD_TOLERANCE: f32 = 0.5;
+
+/// Trim the message and limit its length.
+/// Returns `None` if nothing is left to send.
+pub fn sanitize_message(text: &str) -> Option<String> {
+    let text = text.trim();
+    if text.is_empty() {
+        return None;
+    }
+
+    Some(text.chars().take(MAX_MESSAGE_LENGTH).collect())
+}
+
+/// Server side enforcement of the client antiflood.
+/// A message may only be sent after the time it takes to type it
+/// (the enter cost plus the symbol cost of every character) passed since the last message.
+#[derive(Debug, Default)]
+pub struct ChatAntiflood {
+    last_message: Option<Instant>,
+}
+
+impl ChatAntiflood {
+    pub fn check(&mut self, config: &ChatConfig, message: &str, now: Instant) -> bool {
+        let typing_time_ms = config.antiflood_enter_cost as f32 + config.antiflood_symbol_cost as f32 * message.chars().count() as f32;
+        let min_interval = Duration::from_secs_f32(typing_time_ms.max(0.0) * ANTIFLOOD_TOLERANCE / 1000.0);
+        if let Some(last_message) = self.last_message {
+            if now.saturating_duration_since(last_message) < min_interval {
+                return false;
+            }
+        }
+
+        self.last_message = Some(now);
+        true
+    }
+}
+
 pub struct ServerChatComponent {
     server_chat: Arc<RwLock<ServerChat>>,
+    config: ConfigHandle,
+    moderation: Arc<Moderation>,
     subscriber: Option<mpsc::UnboundedReceiver<ServerChatEvent>>,
     waker: Option<task::Waker>,
+    antiflood: ChatAntiflood,
 
     chat_messages_shown: bool,
 }
 
 impl ServerChatComponent {
-    pub fn new(server_chat: Arc<RwLock<ServerChat>>) -> Self {
+    pub fn new(server_chat: Arc<RwLock<ServerChat>>, config: ConfigHandle, moderation: Arc<Moderation>) -> Self {
         Self {
             server_chat,
+            config,
+            moderation,
             subscriber: None,
             waker: None,
+            antiflood: Default::default(),
 
             chat_messages_shown: false
         }
@@ -155,6 +228,48 @@ impl ServerChatComponent {
         }
     }
 
+    /// Send a system message only visible to this client.
+    fn send_feedback(client: &mut Client, text: String) {
+        client.send_packet(&s2c::GlobalChatAddMessages{
+            messages: vec![
+                ChatMessage {
+                    source_user_status: None,
+                    target_user_status: None,
+                    system: true,
+                    warning: true,
+                    text,
+                }
+            ]
+        });
+    }
+
+    fn execute_command(&mut self, client: &mut Client, user_id: &str, command: Result<ModerationCommand, ModerationError>) {
+        let command = match command {
+            Ok(command) => command,
+            Err(error) => {
+                Self::send_feedback(client, error.to_string());
+                return;
+            }
+        };
+
+        let moderation = self.moderation.clone();
+        let user_id = user_id.to_string();
+        client.run_async(
+            async move { moderation.execute(&user_id, command).await },
+            |client, result| {
+                let feedback = match result {
+                    Ok(Ok(feedback)) => feedback,
+                    Ok(Err(error)) => error.to_string(),
+                    Err(error) => {
+                        tracing::error!("failed to execute a moderation command: {}", error);
+                        "Failed to execute the command.".to_string()
+                    }
+                };
+                Self::send_feedback(client, feedback);
+            }
+        );
+    }
+
     fn handle_chat_event(&mut self, client: &mut Client, event: ServerChatEvent) -> anyhow::Result<()> {
         match event {
             ServerChatEvent::Message(message) => {
@@ -169,6 +284,12 @@ impl ServerChatComponent {
                 }
 
                 client.send_packet(&s2c::GlobalChatAddMessages{ messages });
+            },
+            ServerChatEvent::CleanUserMessages(uid) => {
+                client.send_packet(&s2c::GlobalChatCleanUserMessage{ uid });
+            },
+            ServerChatEvent::Clear => {
+                client.send_packet(&s2c::GlobalChatClear{ });
             }
         }
 
@@ -179,13 +300,14 @@ impl ServerChatComponent {
 impl ClientComponent for ServerChatComponent {
     fn initialize(&mut self, client: &mut Client) -> anyhow::Result<()> {
         let user_id = client.user_id().context("missing client user id")?.to_string();
+        let moderator_level = self.moderation.moderator_level(&user_id);
         client.send_packet(&s2c::GlobalChatInitParameters{
             init_params: ChatCC {
-                admin: false,
+                admin: moderator_level == ChatModeratorLevel::Administrator,
                 antiflood_enabled: true,
                 buffer_size: 128,
                 chat_enabled: true,
-                chat_moderator_level: ChatModeratorLevel::None,
+                chat_moderator_level: moderator_level,
                 links_white_list: None,
                 min_char: 1,
                 min_word: 1,
@@ -195,9 +317,16 @@ impl ClientComponent for ServerChatComponent {
             }
         });
 
+        let (enter_cost, symbol_cost) = {
+            let config = self.config.read()
+                .ok()
+                .context("failed to read the config")?;
+
+            (config.chat.antiflood_enter_cost, config.chat.antiflood_symbol_cost)
+        };
         client.send_packet(&s2c::GlobalChatAntifloodParameters{
-            enter_cost: 880,
-            symbol_cost: 176
+            enter_cost,
+            symbol_cost
         });
 
         /* subscribe to the server chat by default */
@@ -214,6 +343,32 @@ impl ClientComponent for ServerChatComponent {
         };
 
         if let Some(packet) = packet.downcast_ref::<c2s::GlobalChatSendMessage>() {
+            let text = match sanitize_message(&packet.text) {
+                Some(text) => text,
+                None => return Ok(()),
+            };
+
+            if let Some(command) = ModerationCommand::parse(&text) {
+                self.execute_command(client, &user_id, command);
+                return Ok(());
+            }
+
+            if let Some(mute) = self.moderation.active_mute(&user_id) {
+                Self::send_feedback(client, Moderation::mute_message(&mute));
+                return Ok(());
+            }
+
+            let accepted = {
+                let config = self.config.read()
+                    .ok()
+                    .context("failed to read the config")?;
+                self.antiflood.check(&config.chat, &text, Instant::now())
+            };
+            if !accepted {
+                tracing::debug!("Dropped chat message of {} due to the antiflood.", user_id);
+                return Ok(());
+            }
+
             let mut server_chat = self.server_chat.write()
                 .ok()
                 .context("failed to accquire server chat")?;
@@ -223,7 +378,7 @@ impl ClientComponent for ServerChatComponent {
             } else {
                 None
             };
-            server_chat.register_message(&user_id, target, &packet.text);
+            server_chat.register_message(&user_id, self.moderation.moderator_level(&user_id), target, &text);
         }
 
         Ok(())    
@@ -252,3 +407,30 @@ impl ClientComponent for ServerChatComponent {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use crate::config::ChatConfig;
+    use super::{ChatAntiflood, sanitize_message};
+
+    #[test]
+    fn test_sanitize_message() {
+        assert_eq!(sanitize_message("  hello "), Some("hello".to_string()));
+        assert_eq!(sanitize_message(" \t "), None);
+        assert_eq!(sanitize_message(&"a".repeat(1000)).map(|text| text.len()), Some(256));
+    }
+
+    #[test]
+    fn test_antiflood() {
+        let config = ChatConfig { antiflood_enter_cost: 1000, antiflood_symbol_cost: 100, ..Default::default() };
+        let now = Instant::now();
+        let mut antiflood = ChatAntiflood::default();
+
+        /* 10 characters take 2 seconds to type, half of it is tolerated */
+        assert!(antiflood.check(&config, "0123456789", now));
+        assert!(!antiflood.check(&config, "0123456789", now + Duration::from_millis(500)));
+        assert!(antiflood.check(&config, "0123456789", now + Duration::from_millis(1000)));
+    }
+}
@@ -2,12 +2,13 @@ use std::{any::{Any, TypeId}, collections::BTreeMap, task::{self, Poll, Waker},
 use futures::{Future, StreamExt, channel::oneshot};
 use tokio::{time, sync::mpsc};
 use tracing::{ error, debug };
-use fost_protocol::{Connection, packets::{Packet, PacketDowncast, self, s2c}, Socket, SimplePacketDebugFilter, ProtocolError};
+use fost_protocol::{Connection, packets::{Packet, PacketDowncast, self, s2c}, codec::LayoutState, Socket, SimplePacketDebugFilter, ProtocolError};
 
 use crate::{server::{Server, ServerEvent}, client_components::{ConnectionPing}, Tasks};
 
 pub enum AuthenticationState {
-    /* InviteCode, */
+    /// The client has to submit a valid invite code before login or register.
+    InviteCode,
     Unauthenticated,
     Authenticated{ user_id: String }
 }
@@ -89,6 +90,8 @@ pub struct Client {
     components: BTreeMap<TypeId, Arc<RefCell<dyn RegisteredClientComponent>>>,
 
     language: String,
+    /// Lobby layout the client has switched to last.
+    layout: Option<LayoutState>,
 
     tasks: Rc<Tasks>,
     waker: Option<Waker>,
@@ -135,6 +138,7 @@ impl Client {
             components: Default::default(),
 
             language: init_packet.lang.to_string(),
+            layout: None,
 
             waker: None,
             tasks: Rc::new(Tasks::new()),
@@ -165,6 +169,10 @@ impl Client {
         &self.connection.address
     }
 
+    pub fn layout(&self) -> Option<LayoutState> {
+        self.layout
+    }
+
     pub fn authentication_state(&self) -> &AuthenticationState {
         &self.authentication_state
     }
@@ -178,7 +186,7 @@ impl Client {
     pub fn user_id(&mut self) -> Option<&str> {
         match &self.authentication_state {
             AuthenticationState::Authenticated { user_id } => Some(&user_id),
-            AuthenticationState::Unauthenticated => None
+            AuthenticationState::InviteCode | AuthenticationState::Unauthenticated => None
         }
     }
 
@@ -204,6 +212,12 @@ impl Client {
         Ok(())
     }
 
+    /// Remove a component from the client.
+    /// Returns false if the client had no such component.
+    pub fn unregister_component<T: ClientComponent + 'static>(&mut self) -> bool {
+        self.components.remove(&TypeId::of::<T>()).is_some()
+    }
+
     pub fn get_component<T: ClientComponent + 'static>(&self) -> Option<Ref<'_, T>> {
         self.components.get(&TypeId::of::<T>())
             .map(|c| {
@@ -242,6 +256,10 @@ impl Client {
             return;
         }
 
+        if let Some(packet) = packet.downcast_ref::<s2c::LobbyLayoutSwitchEnd>() {
+            self.layout = Some(packet.state);
+        }
+
         if let Err(error) = self.connection.send_packet(packet) {
             self.handle_protocol_error(error);
         }
@@ -281,7 +299,10 @@ impl Client {
             ConnectionState::Closed => return Box::pin(async {})
         };
         
-        Box::pin(async {})
+        Box::pin(async move {
+            /* the sender will be dropped as well if the client gets dropped */
+            let _ = rx.await;
+        })
     }
 
     fn handle_protocol_error(&mut self, error: ProtocolError) {
@@ -315,6 +336,29 @@ impl Client {
         }
     }
 
+    /// Send the packets already queued by the components (e.g. battle events)
+    /// without waiting for the client to be polled.
+    pub fn flush_components(&mut self) {
+        self.poll_components(&mut task::Context::from_waker(futures::task::noop_waker_ref()));
+        if let Some(waker) = self.waker.take() {
+            /* the components registered the noop waker */
+            waker.wake();
+        }
+    }
+
+    fn poll_components(&mut self, cx: &mut task::Context) {
+        let components = self.components.values()
+            .cloned()
+            .collect::<Vec<_>>();
+        
+        for mut component in components {
+            let mut component = component.borrow_mut();
+            if let Err(error) = component.poll(self, cx) {
+                self.handle_handle_error(error);
+            }
+        }
+    }
+
     fn do_connection_close(&mut self) {
         match std::mem::replace(&mut self.connection_state, ConnectionState::Closed) {
             ConnectionState::Closed => {
@@ -339,19 +383,7 @@ impl Future for Client {
 
         let tasks = self.tasks.clone();
         tasks.poll(&mut self, cx);
-
-        {
-            let components = self.components.values()
-                .cloned()
-                .collect::<Vec<_>>();
-            
-            for mut component in components {
-                let mut component = component.borrow_mut();
-                if let Err(error) = component.poll(&mut self, cx) {
-                    self.handle_handle_error(error);
-                }
-            }
-        }
+        self.poll_components(cx);
 
         if matches!(self.connection_state, ConnectionState::Closed) {
             /* No imidiate tasks or components pending. Signalling client closed. */
@@ -1,6 +1,7 @@
 use std::{task, time::Duration, pin::Pin, sync::{Arc, RwLock, atomic::{AtomicBool, Ordering}}};
 
 use anyhow::Context;
+use chrono::Utc;
 use fast_socks5::server::Authentication;
 use fost_protocol::{packets::{self, PacketDowncast, s2c, c2s}, codec::{CaptchaLocation, LayoutState, ChatCC, ChatModeratorLevel, UserPropertyCC}};
 use futures::FutureExt;
@@ -57,15 +58,35 @@ impl UserAuthentication {
     fn handle_authentication_result(&mut self, client: &mut Client, result: AuthenticationResult, remember: bool) {
         match result {
             AuthenticationResult::InvalidCredentials => {
-                /* TODO: keep track of failed logins and request captcha */
                 debug!("failed login attempt (credentials)");
                 client.send_packet(&s2c::AccountLoginFailure{});
             },
             AuthenticationResult::InvalidToken => {
-                /* TODO: keep track of failed logins and disconnect client */
                 debug!("failed login attempt (token)");
                 client.send_packet(&s2c::AccountLoginHashLoginFailed{});
             },
+            AuthenticationResult::Throttled { retry_after } => {
+                debug!("rejected login attempt of {} due to too many failed logins", client.peer_address());
+                let minutes = (retry_after.as_secs() + 59) / 60;
+                client.send_packet(&s2c::AlertShow{
+                    text: format!("Too many failed logins. Please try again in {} minute(s).", minutes.max(1)),
+                });
+                client.send_packet(&s2c::AccountLoginFailure{});
+            },
+            AuthenticationResult::BanTemporary { reason, expires } => {
+                debug!("rejected login of a temporary banned user");
+                let remaining = (expires - Utc::now()).max(chrono::Duration::zero());
+                client.send_packet(&s2c::BanTemporary{
+                    reason_for_user: reason,
+                    minutes: (remaining.num_minutes() % 60) as i32,
+                    hours: (remaining.num_hours() % 24) as i32,
+                    days: remaining.num_days().min(i32::MAX as i64) as i32,
+                });
+            },
+            AuthenticationResult::BanPermanent { reason } => {
+                debug!("rejected login of a permanently banned user");
+                client.send_packet(&s2c::BanPermanent{ reason_for_user: reason });
+            },
             AuthenticationResult::Success { user_id } => {
                 client.send_packet(&s2c::AccountLoginSuccess{});
                 self.handle_user_authenticated(client, &user_id, remember);
@@ -85,7 +106,7 @@ impl UserAuthentication {
 
     pub fn send_login_token(&mut self, client: &mut Client) -> anyhow::Result<()> {
         let user_id = match client.authentication_state() {
-            AuthenticationState::Unauthenticated => anyhow::bail!("client unauthenticated"),
+            AuthenticationState::InviteCode | AuthenticationState::Unauthenticated => anyhow::bail!("client unauthenticated"),
             AuthenticationState::Authenticated { user_id } => user_id.clone()
         };
 
@@ -127,7 +148,7 @@ impl ClientComponent for UserAuthentication {
             let username = packet.login.to_string();
             let remember = packet.remember;
             client.run_async(
-                user_registry.authenticate_with_credentials(packet.login.to_string(), packet.password.to_string()), 
+                user_registry.authenticate_with_credentials(packet.login.to_string(), packet.password.to_string(), client.peer_address().ip()), 
             move |client, result| {
                 login_pending.store(false, Ordering::Relaxed);
                 
@@ -143,7 +164,7 @@ impl ClientComponent for UserAuthentication {
                 .context("failed to aquite the user registry")?;
 
             client.run_async(
-                user_registry.authenticate_with_token(packet.hash.clone()),
+                user_registry.authenticate_with_token(packet.hash.clone(), client.peer_address().ip()),
                 |client, result| {
                     client.with_component_mut::<UserAuthentication, _>(
                         |client, authentication| {
@@ -1,115 +1,505 @@
-use std::{sync::{Arc, RwLock}, vec};
+use std::{sync::{Arc, RwLock, Mutex, atomic::{AtomicBool, Ordering}}, task::{self, Poll}, time::Instant};
 
-use fost_protocol::packets::s2c;
+use anyhow::Context;
+use fost_protocol::{packets::{s2c, c2s, Packet, PacketDowncast}, codec::LayoutState};
 use serde::{Serialize, Deserialize};
+use tokio::sync::mpsc;
 
-use crate::{BattleProvider, client::ClientComponent};
-
-#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct BattleInfo {
-    pub battle_id: String,
-    pub battle_mode: String,
-
-    pub map: String,
-    pub max_people: i64,
-
-    pub name: String,
-
-    pub private_battle: bool,
-    pub pro_battle: bool,
-    pub parkour_mode: bool,
-    pub equipment_constraints_mode: String,
-    
-    pub min_rank: i64,
-    pub max_rank: i64,
-    
-    pub preview: i64,
-    pub suspicion_level: String,
-    
-    #[serde(default)]
-    pub users_blue: Vec<String>,
-    #[serde(default)]
-    pub users_red: Vec<String>,
-    #[serde(default)]
-    pub users: Vec<String>,
-}
-
-lazy_static::lazy_static!{
-    static ref DUMMY_BATTLE: BattleInfo = BattleInfo{
-        battle_id: "0000000000000000".into(),
-        battle_mode: "TDM".into(),
-
-        name: "Dummy Battle".into(),
-        map: "map_silence_moon".into(),
-
-        preview: 952789,
+use crate::{BattleProvider, Battle, Garage, GarageError, SharedPacket, TankSpecification, LobbyHandle, BattleListEntry, ChatAntiflood, UserNotifierHandle, RewardBonuses, Moderation, sanitize_message, client::{ClientComponent, Client, ClientId}, config::ConfigHandle, users::UserRegistry, ResourceStage, MAPS_JSON};
 
-        equipment_constraints_mode: "NONE".into(),
-        suspicion_level: "NONE".into(),
+use super::{ClientGarage, ClientResources};
 
-        max_people: 4,
-        min_rank: 0,
-        max_rank: 21,
+/// Forward all packets from the receiver to the client.
+pub(super) fn poll_shared_packets(receiver: &mut Option<mpsc::UnboundedReceiver<SharedPacket>>, client: &mut Client, cx: &mut task::Context) {
+    loop {
+        let rx = match receiver {
+            Some(rx) => rx,
+            None => return
+        };
 
-        ..Default::default()
-    };
+        match rx.poll_recv(cx) {
+            Poll::Ready(Some(packet)) => client.send_packet(packet.as_ref()),
+            Poll::Ready(None) => {
+                *receiver = None;
+                break;
+            },
+            Poll::Pending => break,
+        }
+    }
 }
 
+/// Client handler for the battle list including
+/// selecting and joining battles.
 pub struct ClientBattleList {
-    battle_provider: Arc<RwLock<BattleProvider>>
+    battle_provider: Arc<RwLock<BattleProvider>>,
+    user_registry: Arc<RwLock<UserRegistry>>,
+    garage: Arc<Garage>,
+    config: ConfigHandle,
+    notifier: UserNotifierHandle,
+    moderation: Arc<Moderation>,
+    lobby: LobbyHandle,
+
+    client_id: ClientId,
+    receiver: Option<mpsc::UnboundedReceiver<SharedPacket>>,
+    /// A join is waiting for the map resources and the user to be loaded.
+    join_pending: Arc<AtomicBool>,
 }
 
 impl ClientBattleList {
-    pub fn new(battle_provider: Arc<RwLock<BattleProvider>>) -> Self {
-        Self {
-            battle_provider
-        }
+    pub fn new(battle_provider: Arc<RwLock<BattleProvider>>, user_registry: Arc<RwLock<UserRegistry>>, garage: Arc<Garage>, config: ConfigHandle, notifier: UserNotifierHandle, moderation: Arc<Moderation>) -> anyhow::Result<Self> {
+        let lobby = battle_provider.read()
+            .ok()
+            .context("failed to accquire the battle provider")?
+            .lobby()
+            .clone();
+
+        Ok(Self {
+            battle_provider,
+            user_registry,
+            garage,
+            config,
+            notifier,
+            moderation,
+            lobby,
+
+            client_id: 0,
+            receiver: None,
+            join_pending: Arc::new(AtomicBool::new(false)),
+        })
     }
-}
 
-impl ClientComponent for ClientBattleList {
-    fn initialize(&mut self, client: &mut crate::client::Client) -> anyhow::Result<()> {
+    pub fn send_battle_list(&self, client: &mut Client) -> anyhow::Result<()> {
         #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
         #[serde(rename_all = "camelCase")]
         struct BattleList {
-            pub battles: Vec<BattleInfo>,
+            pub battles: Vec<BattleListEntry>,
         }
 
+        let battles = {
+            let battle_provider = self.battle_provider.read()
+                .ok()
+                .context("failed to accquire the battle provider")?;
+
+            battle_provider.battles()
+                .filter_map(|battle| battle.lock().ok())
+                .filter(|battle| !battle.is_closed())
+                .map(|battle| battle.list_entry())
+                .collect::<Vec<_>>()
+        };
+
         client.send_packet(&s2c::BattleListListCreate{
-            json: serde_json::to_string(&BattleList{
-                battles: vec![
-                    DUMMY_BATTLE.clone()
-                ]
-            })?
+            json: serde_json::to_string(&BattleList{ battles })?
         });
         Ok(())
     }
+
+    fn find_battle(&self, battle_id: &str) -> anyhow::Result<Option<Arc<Mutex<Battle>>>> {
+        let battle = self.battle_provider.read()
+            .ok()
+            .context("failed to accquire the battle provider")?
+            .find_battle(battle_id)
+            .filter(|battle| battle.lock().map(|battle| !battle.is_closed()).unwrap_or(false));
+
+        Ok(battle)
+    }
+
+    fn handle_battle_select(&mut self, client: &mut Client, battle_id: &str) -> anyhow::Result<()> {
+        let battle_exists = self.find_battle(battle_id)?.is_some();
+        let mut lobby = self.lobby.lock()
+            .ok()
+            .context("failed to accquire the battle lobby")?;
+
+        if battle_exists {
+            lobby.select_battle(self.client_id, Some(battle_id.to_string()));
+            client.send_packet(&s2c::BattleListBattleSelect{ item: battle_id.to_string() });
+        } else {
+            lobby.select_battle(self.client_id, None);
+            client.send_packet(&s2c::LinkResultDead{ battle_id: battle_id.to_string() });
+        }
+
+        Ok(())
+    }
+
+    fn handle_battle_join(&mut self, client: &mut Client, packet: &c2s::BattleInfoJoinBattle) -> anyhow::Result<()> {
+        if client.get_component::<ClientBattle>().is_some() {
+            /* client already joined a battle */
+            return Ok(());
+        }
+
+        let battle_id = self.lobby.lock()
+            .ok()
+            .context("failed to accquire the battle lobby")?
+            .selected_battle(self.client_id)
+            .map(str::to_string);
+
+        let battle = match battle_id {
+            Some(battle_id) => self.find_battle(&battle_id)?,
+            None => None,
+        };
+        let battle = match battle {
+            Some(battle) => battle,
+            None => {
+                client.send_packet(&s2c::AlertShow{ text: "The battle does not exist any more.".to_string() });
+                return Ok(());
+            }
+        };
+
+        let map_id = battle.lock()
+            .ok()
+            .context("failed to lock the battle")?
+            .parameters().map_id.clone();
+        let map_loaded = client.with_component_mut::<ClientResources, _>(|client, resources| {
+            resources.await_resources_loaded(client, ResourceStage::Map(map_id))
+        }).context("missing client resources")??;
+
+        let user_id = client.user_id().context("missing client user id")?.to_string();
+        let user_query = self.user_registry.read()
+            .ok()
+            .context("failed to accquire the user registry")?
+            .find_user(user_id.clone());
+
+        let garage_query = {
+            let garage = self.garage.clone();
+            let user_id = user_id.clone();
+            async move { garage.load(&user_id).await }
+        };
+
+        let join_pending = self.join_pending.clone();
+        if join_pending.swap(true, Ordering::Relaxed) {
+            /* the client already requested to join a battle */
+            return Ok(());
+        }
+
+        let team = packet.team;
+        let garage = self.garage.clone();
+        let config = self.config.clone();
+        let notifier = self.notifier.clone();
+        let moderation = self.moderation.clone();
+        /* only join after the map resources have been loaded as the client can not react to battle events before */
+        client.run_async(async move { map_loaded.await; (user_query.await, garage_query.await) }, move |client, (user, user_garage)| {
+            join_pending.store(false, Ordering::Relaxed);
+            if client.get_component::<ClientBattle>().is_some() {
+                return;
+            }
+
+            let user = match user {
+                Some(user) => user,
+                None => return,
+            };
+
+            /* the tank is built from the mounted equipment and carries the users supplies */
+            let user_garage = match user_garage {
+                Ok(Some(user_garage)) => user_garage,
+                Ok(None) => return,
+                Err(error) => {
+                    tracing::error!("failed to load the garage: {}", error);
+                    client.send_packet(&s2c::AlertShow{ text: GarageError::Unavailable.to_string() });
+                    return;
+                }
+            };
+            let specification = TankSpecification::from_garage(garage.catalog(), &user_garage);
+
+            let reward_bonuses = match config.read() {
+                Ok(config) => RewardBonuses::new(&user, &config.premium),
+                Err(_) => return,
+            };
+
+            let (tx, rx) = mpsc::unbounded_channel();
+            let join_result = match battle.lock() {
+                Ok(mut battle) => battle.join(&user_id, user.experience, reward_bonuses, team, specification, user_garage.items, tx),
+                Err(_) => return,
+            };
+
+            if let Err(error) = join_result {
+                tracing::debug!("User {} failed to join battle: {}", user_id, error);
+                client.send_packet(&s2c::AlertShow{ text: "Failed to join the battle.".to_string() });
+                return;
+            }
+
+            client.send_packet(&s2c::LobbyLayoutSwitchStart{ state: LayoutState::Battle });
+            if let Err(error) = client.register_component(ClientBattle::new(battle.clone(), user_id.clone(), rx, config, notifier, moderation)) {
+                tracing::error!("failed to register the battle component: {}", error);
+                if let Ok(mut battle) = battle.lock() {
+                    battle.leave(&user_id);
+                }
+
+                client.send_packet(&s2c::LobbyLayoutSwitchStart{ state: LayoutState::BattleSelect });
+                if let Some(Err(error)) = client.with_component_mut::<ClientBattleList, _>(|client, battle_list| battle_list.send_battle_list(client)) {
+                    tracing::error!("failed to send the battle list: {}", error);
+                }
+                client.send_packet(&s2c::LobbyLayoutSwitchEnd{ state: LayoutState::BattleSelect, origin: LayoutState::Battle });
+            }
+        });
+
+        Ok(())
+    }
 }
 
-/// Client handler for subscribing a battle
-pub struct ClientBattleInfo {}
+impl ClientComponent for ClientBattleList {
+    fn initialize(&mut self, client: &mut Client) -> anyhow::Result<()> {
+        self.client_id = client.client_id();
+        self.receiver = Some(
+            self.lobby.lock()
+                .ok()
+                .context("failed to accquire the battle lobby")?
+                .subscribe(self.client_id)
+        );
+
+        self.send_battle_list(client)
+    }
+
+    fn on_packet(&mut self, client: &mut Client, packet: &dyn Packet) -> anyhow::Result<()> {
+        if let Some(packet) = packet.downcast_ref::<c2s::BattleListBattleSelect>() {
+            self.handle_battle_select(client, &packet.item)?;
+        } else if let Some(packet) = packet.downcast_ref::<c2s::BattleInfoJoinBattle>() {
+            self.handle_battle_join(client, packet)?;
+        }
+
+        Ok(())
+    }
+
+    fn poll(&mut self, client: &mut Client, cx: &mut task::Context) -> anyhow::Result<()> {
+        poll_shared_packets(&mut self.receiver, client, cx);
+        Ok(())
+    }
+}
 
-static MAPS_JSON: &'static str = include_str!("../../resources/maps.json");
+impl Drop for ClientBattleList {
+    fn drop(&mut self) {
+        if let Ok(mut lobby) = self.lobby.lock() {
+            lobby.unsubscribe(self.client_id);
+        }
+    }
+}
 
 /// Client handler for creating new battles
 pub struct ClientBattleCreate {
-    battle_provider: Arc<RwLock<BattleProvider>>
+    battle_provider: Arc<RwLock<BattleProvider>>,
+    config: ConfigHandle,
 }
 
 impl ClientBattleCreate {
-    pub fn new(battle_provider: Arc<RwLock<BattleProvider>>) -> Self {
+    pub fn new(battle_provider: Arc<RwLock<BattleProvider>>, config: ConfigHandle) -> Self {
         Self {
-            battle_provider
+            battle_provider,
+            config,
         }
     }
 }
 
 impl ClientComponent for ClientBattleCreate {
-    fn initialize(&mut self, client: &mut crate::client::Client) -> anyhow::Result<()> {
+    fn initialize(&mut self, client: &mut Client) -> anyhow::Result<()> {
+        let battle_creation = self.config.read()
+            .ok()
+            .context("failed to read the config")?
+            .features.battle_creation;
+
+        let mut parameters = serde_json::from_str::<serde_json::Value>(MAPS_JSON)?;
+        parameters["battleCreationDisabled"] = serde_json::Value::Bool(!battle_creation);
+
         client.send_packet(&s2c::BattleCreateParameters{
-            json: MAPS_JSON.to_string()
+            json: serde_json::to_string(&parameters)?
         });
         Ok(())
     }
-}
\ No newline at end of file
+
+    fn on_packet(&mut self, client: &mut Client, packet: &dyn Packet) -> anyhow::Result<()> {
+        if let Some(packet) = packet.downcast_ref::<c2s::BattleCreateSubmit>() {
+            let result = self.battle_provider.write()
+                .ok()
+                .context("failed to accquire the battle provider")?
+                .create_battle(packet.params.clone());
+
+            let battle = match result {
+                Ok(battle) => battle,
+                Err(error) => {
+                    tracing::debug!("Client {} failed to create a battle: {}", client.client_id(), error);
+                    client.send_packet(&s2c::AlertShow{ text: format!("Failed to create the battle: {}", error) });
+                    return Ok(());
+                }
+            };
+
+            let entry = battle.lock()
+                .ok()
+                .context("failed to lock the battle")?
+                .list_entry();
+
+            if let Ok(lobby) = self.battle_provider.read().map(|provider| provider.lobby().clone()) {
+                let packet: SharedPacket = Arc::new(s2c::BattleListBattleCreate{ json: serde_json::to_string(&entry)? });
+                lobby.lock()
+                    .ok()
+                    .context("failed to accquire the battle lobby")?
+                    .broadcast(packet);
+            }
+
+            /* select the newly created battle for the creator */
+            client.with_component_mut::<ClientBattleList, _>(|client, battle_list| {
+                battle_list.handle_battle_select(client, &entry.battle_id)
+            }).transpose()?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Client handler while the client is within a battle.
+/// Leaves the battle when dropped.
+pub struct ClientBattle {
+    battle: Arc<Mutex<Battle>>,
+    user_id: String,
+    receiver: Option<mpsc::UnboundedReceiver<SharedPacket>>,
+
+    config: ConfigHandle,
+    notifier: UserNotifierHandle,
+    moderation: Arc<Moderation>,
+    antiflood: ChatAntiflood,
+}
+
+impl ClientBattle {
+    pub fn new(battle: Arc<Mutex<Battle>>, user_id: String, receiver: mpsc::UnboundedReceiver<SharedPacket>, config: ConfigHandle, notifier: UserNotifierHandle, moderation: Arc<Moderation>) -> Self {
+        Self {
+            battle,
+            user_id,
+            receiver: Some(receiver),
+
+            config,
+            notifier,
+            moderation,
+            antiflood: Default::default(),
+        }
+    }
+
+    fn with_battle<R>(&self, callback: impl FnOnce(&mut Battle) -> R) -> anyhow::Result<R> {
+        let mut battle = self.battle.lock()
+            .ok()
+            .context("failed to lock the battle")?;
+
+        Ok(callback(&mut battle))
+    }
+
+    fn handle_chat_message(&mut self, client: &mut Client, packet: &c2s::BattleMessageSend) -> anyhow::Result<()> {
+        let text = match sanitize_message(&packet.message) {
+            Some(text) => text,
+            None => return Ok(()),
+        };
+
+        if let Some(mute) = self.moderation.active_mute(&self.user_id) {
+            client.send_packet(&s2c::BattleMessageSystemMessage{ message: Moderation::mute_message(&mute) });
+            return Ok(());
+        }
+
+        let accepted = {
+            let config = self.config.read()
+                .ok()
+                .context("failed to read the config")?;
+            self.antiflood.check(&config.chat, &text, Instant::now())
+        };
+        if !accepted {
+            tracing::debug!("Dropped battle chat message of {} due to the antiflood.", self.user_id);
+            return Ok(());
+        }
+
+        self.with_battle(|battle| battle.chat_message(&self.user_id, &text, packet.team_only))
+    }
+}
+
+impl ClientComponent for ClientBattle {
+    fn initialize(&mut self, client: &mut Client) -> anyhow::Result<()> {
+        let packets = self.with_battle(|battle| battle.initial_packets(&self.user_id))??;
+        for packet in packets {
+            client.send_packet(packet.as_ref());
+        }
+
+        client.send_packet(&s2c::LobbyLayoutSwitchEnd{ state: LayoutState::Battle, origin: LayoutState::BattleSelect });
+
+        let server_number = self.config.read()
+            .ok()
+            .context("failed to read the config")?
+            .network.server_id;
+        let battle_info = self.with_battle(|battle| battle.info_data(server_number))?;
+        if let Ok(mut notifier) = self.notifier.write() {
+            notifier.set_battle(&self.user_id, Some(battle_info));
+        }
+        Ok(())
+    }
+
+    fn on_packet(&mut self, client: &mut Client, packet: &dyn Packet) -> anyhow::Result<()> {
+        let user_id = self.user_id.as_str();
+        if packet.is_type::<c2s::TankInit>() {
+            self.with_battle(|battle| battle.tank_init(user_id))?;
+        } else if packet.is_type::<c2s::TankReady2Place>() {
+            self.with_battle(|battle| battle.tank_ready_to_place(user_id))?;
+        } else if packet.is_type::<c2s::TankReady2Activate>() {
+            self.with_battle(|battle| battle.tank_ready_to_activate(user_id))?;
+        } else if let Some(packet) = packet.downcast_ref::<c2s::TankMoveCommand>() {
+            self.with_battle(|battle| battle.tank_move(user_id, packet.specification_id, packet.move_command.clone()))?;
+        } else if let Some(packet) = packet.downcast_ref::<c2s::TankMoveTurretCommand>() {
+            self.with_battle(|battle| battle.tank_move_turret(user_id, packet.specification_id, packet.move_command.clone(), packet.turret_direction))?;
+        } else if let Some(packet) = packet.downcast_ref::<c2s::TankMoveControlFlags>() {
+            self.with_battle(|battle| battle.tank_control_flags(user_id, packet.specification_id, packet.control))?;
+        } else if let Some(packet) = packet.downcast_ref::<c2s::TankTurretCommand>() {
+            self.with_battle(|battle| battle.tank_turret(user_id, packet.incarnation_id, packet.rotate_turret_command.clone()))?;
+        } else if let Some(packet) = packet.downcast_ref::<c2s::WeaponSmokyShot>() {
+            self.with_battle(|battle| battle.smoky_shot(user_id, &packet.target, packet.incarnation_id, packet.hit_point))?;
+        } else if let Some(packet) = packet.downcast_ref::<c2s::WeaponSmokyShotStatic>() {
+            self.with_battle(|battle| battle.smoky_shot_static(user_id, packet.hit_point))?;
+        } else if packet.is_type::<c2s::WeaponSmokyShotDummy>() {
+            self.with_battle(|battle| battle.smoky_shot_dummy(user_id))?;
+        } else if packet.is_type::<c2s::WeaponFirebirdStart>() {
+            self.with_battle(|battle| battle.firebird_start(user_id))?;
+        } else if packet.is_type::<c2s::WeaponFirebirdStop>() {
+            self.with_battle(|battle| battle.firebird_stop(user_id))?;
+        } else if let Some(packet) = packet.downcast_ref::<c2s::WeaponFirebirdHit>() {
+            self.with_battle(|battle| battle.firebird_hit(user_id, &packet.targets, &packet.incarnations))?;
+        } else if let Some(packet) = packet.downcast_ref::<c2s::BattleCTFTakeFlag>() {
+            self.with_battle(|battle| battle.ctf_take_flag(user_id, packet.team))?;
+        } else if packet.is_type::<c2s::BattleCTFDropFlag>() {
+            self.with_battle(|battle| battle.ctf_drop_carried_flag(user_id))?;
+        } else if packet.is_type::<c2s::BattleCTFFlagDropFlag>() {
+            self.with_battle(|battle| battle.assault_drop_carried_flag(user_id))?;
+        } else if let Some(packet) = packet.downcast_ref::<c2s::BattleBonusTake>() {
+            self.with_battle(|battle| battle.take_bonus(user_id, &packet.bonus_id))?;
+        } else if let Some(packet) = packet.downcast_ref::<c2s::BattleDrugsActivate>() {
+            self.with_battle(|battle| battle.use_supply(user_id, &packet.item_id))?;
+        } else if let Some(packet) = packet.downcast_ref::<c2s::BattleDrugsHitMine>() {
+            self.with_battle(|battle| battle.hit_mine(user_id, &packet.mine_id))?;
+        } else if let Some(packet) = packet.downcast_ref::<c2s::BattleMessageSend>() {
+            self.handle_chat_message(client, packet)?;
+        } else if let Some(packet) = packet.downcast_ref::<c2s::LayoutSwitchExitBattle>() {
+            self.with_battle(|battle| battle.leave(user_id))?;
+            self.receiver = None;
+
+            if packet.destination == LayoutState::Garage {
+                client.with_component_mut::<ClientGarage, _>(|client, garage| {
+                    garage.open(client, LayoutState::Battle)
+                }).transpose()?;
+            } else {
+                client.send_packet(&s2c::LobbyLayoutSwitchStart{ state: LayoutState::BattleSelect });
+                client.with_component_mut::<ClientBattleList, _>(|client, battle_list| {
+                    battle_list.send_battle_list(client)
+                }).transpose()?;
+                client.send_packet(&s2c::LobbyLayoutSwitchEnd{ state: LayoutState::BattleSelect, origin: LayoutState::Battle });
+            }
+            client.unregister_component::<ClientBattle>();
+        }
+
+        Ok(())
+    }
+
+    fn poll(&mut self, client: &mut Client, cx: &mut task::Context) -> anyhow::Result<()> {
+        poll_shared_packets(&mut self.receiver, client, cx);
+        Ok(())
+    }
+}
+
+impl Drop for ClientBattle {
+    fn drop(&mut self) {
+        if let Ok(mut battle) = self.battle.lock() {
+            battle.leave(&self.user_id);
+        }
+
+        if let Ok(mut notifier) = self.notifier.write() {
+            notifier.set_battle(&self.user_id, None);
+        }
+    }
+}
@@ -18,13 +18,14 @@ pub struct CaptchaProvider {
 }
 
 impl CaptchaProvider {
-    pub fn new() -> Self {
+    pub fn new(locations: Vec<CaptchaLocation>) -> Self {
         let mut provider = Self {
             solve_states: Default::default(),
         };
 
-        provider.require_for(CaptchaLocation::RegisterForm);
-        provider.require_for(CaptchaLocation::ClientStartup);
+        for location in locations {
+            provider.require_for(location);
+        }
 
         provider
     }
@@ -55,6 +56,16 @@ impl CaptchaProvider {
         }
     }
 
+    /// Like `solved_for` but shows a new captcha to the client if the captcha has not been solved.
+    pub fn solved_or_renew(&mut self, client: &mut crate::client::Client, location: CaptchaLocation) -> bool {
+        if self.solved_for(location) {
+            return true;
+        }
+
+        self.send_new_captcha(client, location, false);
+        false
+    }
+
     pub fn invalidate_for(&mut self, location: CaptchaLocation) {
         if let Some(state) = self.solve_states.get_mut(&location) {
             *state = SolveState::Invalid;
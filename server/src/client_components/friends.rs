@@ -0,0 +1,204 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use fost_protocol::packets::{Packet, PacketDowncast, c2s, s2c};
+use tokio::sync::mpsc;
+
+use crate::{Friends, FriendsError, SharedPacket, UserNotifierHandle, friend_list_packet, mirrored_state, client::{ClientComponent, Client, ClientId}, storage::model::FriendState};
+
+use super::battles::poll_shared_packets;
+
+/// Client handler for the friends list and the status notifications of other users.
+/// Registers the user as online for as long as the component exists.
+pub struct ClientFriends {
+    friends: Arc<Friends>,
+    notifier: UserNotifierHandle,
+
+    user_id: String,
+    client_id: ClientId,
+    receiver: Option<mpsc::UnboundedReceiver<SharedPacket>>,
+}
+
+impl ClientFriends {
+    pub fn new(friends: Arc<Friends>, notifier: UserNotifierHandle) -> Self {
+        Self {
+            friends,
+            notifier,
+
+            user_id: String::new(),
+            client_id: 0,
+            receiver: None,
+        }
+    }
+
+    /// Apply a change of the friendship between the user and the friend.
+    /// The packets for the friend will only be delivered if the friend is online.
+    fn run_friend_update<F>(&self, client: &mut Client, friend_id: String, update: F, packets: Vec<(FriendState, bool)>)
+        where F: std::future::Future<Output = anyhow::Result<Result<(), FriendsError>>> + Send + 'static
+    {
+        let notifier = self.notifier.clone();
+        let user_id = self.user_id.clone();
+        client.run_async(update, move |client, result| {
+            match result {
+                Ok(Ok(())) => {},
+                Ok(Err(error)) => {
+                    client.send_packet(&s2c::AlertShow{ text: error.to_string() });
+                    return;
+                },
+                Err(error) => {
+                    tracing::error!("failed to update the friendship of {} and {}: {}", user_id, friend_id, error);
+                    return;
+                }
+            }
+
+            for (state, added) in packets.iter().cloned() {
+                client.send_packet(friend_list_packet(state, added, &friend_id).as_ref());
+            }
+
+            if let Ok(notifier) = notifier.read() {
+                for (state, added) in packets.iter().cloned() {
+                    notifier.send_to(&friend_id, friend_list_packet(mirrored_state(state), added, &user_id));
+                }
+            }
+        });
+    }
+
+    fn remove_friend(&self, client: &mut Client, friend_id: &str, state: FriendState) {
+        let friends = self.friends.clone();
+        let (user_id, friend_id) = (self.user_id.clone(), friend_id.to_string());
+        let update = {
+            let friend_id = friend_id.clone();
+            async move { friends.remove(&user_id, &friend_id, state).await }
+        };
+        self.run_friend_update(client, friend_id, update, vec![ (state, false) ]);
+    }
+
+    fn mark_viewed(&self, client: &mut Client, friend_id: &str) {
+        let friends = self.friends.clone();
+        let (user_id, friend_id) = (self.user_id.clone(), friend_id.to_string());
+        client.run_async(
+            async move { friends.mark_viewed(&user_id, &friend_id).await },
+            |_, result| {
+                if let Err(error) = result {
+                    tracing::error!("failed to mark friend as viewed: {}", error);
+                }
+            }
+        );
+    }
+}
+
+impl ClientComponent for ClientFriends {
+    fn initialize(&mut self, client: &mut Client) -> anyhow::Result<()> {
+        self.user_id = client.user_id().context("missing client user id")?.to_string();
+        self.client_id = client.client_id();
+
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.notifier.write()
+            .ok()
+            .context("failed to accquire the user notifier")?
+            .register(&self.user_id, self.client_id, tx);
+        self.receiver = Some(rx);
+
+        let friends = self.friends.clone();
+        let user_id = self.user_id.clone();
+        client.run_async(
+            async move { friends.load(&user_id).await },
+            |client, lists| {
+                match lists {
+                    Ok(lists) => client.send_packet(&lists.initialize_packet()),
+                    Err(error) => tracing::error!("failed to load friends: {}", error),
+                }
+            }
+        );
+
+        Ok(())
+    }
+
+    fn on_packet(&mut self, client: &mut Client, packet: &dyn Packet) -> anyhow::Result<()> {
+        if let Some(packet) = packet.downcast_ref::<c2s::FriendsCheckUid>() {
+            let friends = self.friends.clone();
+            let uid = packet.uid.clone();
+            client.run_async(
+                async move { friends.user_exists(&uid).await },
+                |client, exists| {
+                    match exists {
+                        Ok(true) => client.send_packet(&s2c::FriendsUidExists{ }),
+                        Ok(false) => client.send_packet(&s2c::FriendsUidNotFound{ }),
+                        Err(error) => tracing::error!("failed to check the friend uid: {}", error),
+                    }
+                }
+            );
+        } else if let Some(packet) = packet.downcast_ref::<c2s::FriendsSendRequest>() {
+            let friends = self.friends.clone();
+            let (user_id, friend_id) = (self.user_id.clone(), packet.user_id.clone());
+            let update = async move { friends.send_request(&user_id, &friend_id).await };
+            self.run_friend_update(client, packet.user_id.clone(), update, vec![ (FriendState::Outgoing, true) ]);
+        } else if let Some(packet) = packet.downcast_ref::<c2s::FriendsAccept>() {
+            let friends = self.friends.clone();
+            let (user_id, friend_id) = (self.user_id.clone(), packet.user_id.clone());
+            let update = async move { friends.accept_request(&user_id, &friend_id).await };
+            self.run_friend_update(client, packet.user_id.clone(), update, vec![ (FriendState::Incoming, false), (FriendState::Accepted, true) ]);
+        } else if let Some(packet) = packet.downcast_ref::<c2s::FriendsReject>() {
+            self.remove_friend(client, &packet.user_id, FriendState::Incoming);
+        } else if let Some(packet) = packet.downcast_ref::<c2s::FriendsRevoke>() {
+            self.remove_friend(client, &packet.user_id, FriendState::Outgoing);
+        } else if let Some(packet) = packet.downcast_ref::<c2s::FriendsRemove>() {
+            self.remove_friend(client, &packet.user_id, FriendState::Accepted);
+        } else if packet.is_type::<c2s::FriendsRejectAll>() {
+            let friends = self.friends.clone();
+            let notifier = self.notifier.clone();
+            let user_id = self.user_id.clone();
+            client.run_async(
+                {
+                    let user_id = user_id.clone();
+                    async move { friends.reject_all(&user_id).await }
+                },
+                move |client, rejected| {
+                    let rejected = match rejected {
+                        Ok(rejected) => rejected,
+                        Err(error) => {
+                            tracing::error!("failed to reject all friend requests: {}", error);
+                            return;
+                        }
+                    };
+
+                    for friend_id in rejected {
+                        client.send_packet(friend_list_packet(FriendState::Incoming, false, &friend_id).as_ref());
+                        if let Ok(notifier) = notifier.read() {
+                            notifier.send_to(&friend_id, friend_list_packet(FriendState::Outgoing, false, &user_id));
+                        }
+                    }
+                }
+            );
+        } else if let Some(packet) = packet.downcast_ref::<c2s::FriendsMarkAcceptedViewed>() {
+            self.mark_viewed(client, &packet.user_id);
+        } else if let Some(packet) = packet.downcast_ref::<c2s::FriendsMarkIncomingViewed>() {
+            self.mark_viewed(client, &packet.user_id);
+        } else if let Some(packet) = packet.downcast_ref::<c2s::UserNotifySubscribe>() {
+            self.notifier.write()
+                .ok()
+                .context("failed to accquire the user notifier")?
+                .subscribe(&self.user_id, &packet.user_id);
+        } else if let Some(packet) = packet.downcast_ref::<c2s::UserNotifyUnsubscribe>() {
+            self.notifier.write()
+                .ok()
+                .context("failed to accquire the user notifier")?
+                .unsubscribe(&self.user_id, &packet.user_ids);
+        }
+
+        Ok(())
+    }
+
+    fn poll(&mut self, client: &mut Client, cx: &mut std::task::Context) -> anyhow::Result<()> {
+        poll_shared_packets(&mut self.receiver, client, cx);
+        Ok(())
+    }
+}
+
+impl Drop for ClientFriends {
+    fn drop(&mut self) {
+        if let Ok(mut notifier) = self.notifier.write() {
+            notifier.unregister(&self.user_id, self.client_id);
+        }
+    }
+}
@@ -0,0 +1,128 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use fost_protocol::{packets::{Packet, PacketDowncast, c2s, s2c}, codec::LayoutState};
+
+use crate::{Garage, GarageError, ResourceStage, client::{ClientComponent, Client}};
+
+use super::{ClientBattleList, ClientResources};
+
+/// Client handler for the garage including the shop.
+pub struct ClientGarage {
+    garage: Arc<Garage>,
+}
+
+impl ClientGarage {
+    pub fn new(garage: Arc<Garage>) -> Self {
+        Self {
+            garage,
+        }
+    }
+
+    /// Switch to the garage layout and send the users garage.
+    pub fn open(&self, client: &mut Client, origin: LayoutState) -> anyhow::Result<()> {
+        let user_id = client.user_id().context("missing client user id")?.to_string();
+        client.send_packet(&s2c::LobbyLayoutSwitchStart{ state: LayoutState::Garage });
+
+        let catalog = self.garage.catalog();
+        let item_stages = catalog.items().iter()
+            .map(|item| item.item_id.clone())
+            .chain(catalog.kits().iter().map(|kit| kit.kit_id.clone()))
+            .map(ResourceStage::GarageItem)
+            .collect::<Vec<_>>();
+        let items_loaded = client.with_component_mut::<ClientResources, _>(|client, resources| {
+            resources.await_all_resources_loaded(client, item_stages)
+        }).context("missing client resources")??;
+
+        let garage = self.garage.clone();
+        client.run_async(
+            async move {
+                items_loaded.await;
+                let user_garage = garage.load(&user_id).await;
+                (garage, user_garage)
+            },
+            move |client, (garage, user_garage)| {
+                let user_garage = match user_garage {
+                    Ok(Some(user_garage)) => user_garage,
+                    Ok(None) => return,
+                    Err(error) => {
+                        tracing::error!("failed to load the garage: {}", error);
+                        client.send_packet(&s2c::AlertShow{ text: GarageError::Unavailable.to_string() });
+                        return;
+                    }
+                };
+
+                for item_id in user_garage.mounted_items() {
+                    client.send_packet(&s2c::GarageInitMounted{ item_id, mounted: true });
+                }
+                client.send_packet(&s2c::GarageInitDepot{ json: user_garage.depot_json(garage.catalog()) });
+                client.send_packet(&s2c::GarageInitMarket{ json: user_garage.market_json(garage.catalog()) });
+                client.send_packet(&s2c::LobbyLayoutSwitchEnd{ state: LayoutState::Garage, origin });
+            }
+        );
+
+        Ok(())
+    }
+
+    fn close(&self, client: &mut Client) -> anyhow::Result<()> {
+        client.send_packet(&s2c::LobbyLayoutSwitchStart{ state: LayoutState::BattleSelect });
+        client.with_component_mut::<ClientBattleList, _>(|client, battle_list| {
+            battle_list.send_battle_list(client)
+        }).transpose()?;
+        client.send_packet(&s2c::LobbyLayoutSwitchEnd{ state: LayoutState::BattleSelect, origin: LayoutState::Garage });
+        Ok(())
+    }
+
+    fn handle_purchase_result(client: &mut Client, result: anyhow::Result<Result<i32, GarageError>>) {
+        match result {
+            Ok(Ok(crystals)) => client.send_packet(&s2c::AccountRankUpdateCrystals{ change_by: crystals }),
+            Ok(Err(error)) => client.send_packet(&s2c::AlertShow{ text: error.to_string() }),
+            Err(error) => {
+                tracing::error!("failed to execute garage purchase: {}", error);
+                client.send_packet(&s2c::AlertShow{ text: GarageError::Unavailable.to_string() });
+            }
+        }
+    }
+}
+
+impl ClientComponent for ClientGarage {
+    fn on_packet(&mut self, client: &mut Client, packet: &dyn Packet) -> anyhow::Result<()> {
+        if packet.is_type::<c2s::LayoutSwitchGarage>() {
+            self.open(client, LayoutState::BattleSelect)?;
+        } else if packet.is_type::<c2s::LayoutSwitchBattleSelect>() {
+            self.close(client)?;
+        } else if let Some(packet) = packet.downcast_ref::<c2s::GarageBuyItem>() {
+            let user_id = client.user_id().context("missing client user id")?.to_string();
+            let garage = self.garage.clone();
+            let (item_id, count) = (packet.item.clone(), packet.count);
+            client.run_async(
+                async move { garage.buy_item(&user_id, &item_id, count).await },
+                Self::handle_purchase_result
+            );
+        } else if let Some(packet) = packet.downcast_ref::<c2s::GarageBuyKit>() {
+            let user_id = client.user_id().context("missing client user id")?.to_string();
+            let garage = self.garage.clone();
+            let kit_id = packet.item.clone();
+            client.run_async(
+                async move { garage.buy_kit(&user_id, &kit_id).await },
+                Self::handle_purchase_result
+            );
+        } else if let Some(packet) = packet.downcast_ref::<c2s::GarageMountItem>() {
+            let user_id = client.user_id().context("missing client user id")?.to_string();
+            let garage = self.garage.clone();
+            let item_id = packet.item.clone();
+            client.run_async(
+                async move { garage.mount_item(&user_id, &item_id).await },
+                |client, result| {
+                    match result {
+                        Ok(Ok(item_id)) => client.send_packet(&s2c::GarageInitMounted{ item_id, mounted: true }),
+                        Ok(Err(error)) => client.send_packet(&s2c::AlertShow{ text: error.to_string() }),
+                        Err(error) => tracing::error!("failed to mount garage item: {}", error),
+                    }
+                }
+            );
+        }
+
+        Ok(())
+    }
+}
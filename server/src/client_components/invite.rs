@@ -0,0 +1,90 @@
+use std::sync::{Arc, atomic::{AtomicBool, Ordering}};
+
+use fost_protocol::packets::{Packet, PacketDowncast, c2s, s2c};
+use tracing::debug;
+
+use crate::{Invites, InviteCheck, client::{ClientComponent, Client, AuthenticationState}};
+
+/// Invite gate which has to be passed before login or register.
+/// Only registered if the server is invite only.
+pub struct InviteCodeGate {
+    invites: Arc<Invites>,
+    check_pending: Arc<AtomicBool>,
+    /// Accepted code which can be used to register a new account.
+    registration_code: Option<String>,
+}
+
+impl InviteCodeGate {
+    pub fn new(invites: Arc<Invites>) -> Self {
+        Self {
+            invites,
+            check_pending: Arc::new(AtomicBool::new(false)),
+            registration_code: None,
+        }
+    }
+
+    pub fn registration_code(&self) -> Option<&str> {
+        self.registration_code.as_deref()
+    }
+
+    fn handle_check_result(&mut self, client: &mut Client, code: String, result: InviteCheck) {
+        match result {
+            InviteCheck::Free => {
+                *client.authentication_state_mut() = AuthenticationState::Unauthenticated;
+                self.registration_code = Some(code);
+                client.send_packet(&s2c::InviteCodeFree{});
+            },
+            InviteCheck::AlreadyActivated { nickname } => {
+                *client.authentication_state_mut() = AuthenticationState::Unauthenticated;
+                client.send_packet(&s2c::InviteCodeAlreadyActivated{ nickname });
+            },
+            InviteCheck::NotFound => {
+                debug!("rejected invite code {}", code);
+                client.send_packet(&s2c::InviteCodeNotFound{});
+            }
+        }
+    }
+}
+
+impl ClientComponent for InviteCodeGate {
+    fn initialize(&mut self, client: &mut Client) -> anyhow::Result<()> {
+        *client.authentication_state_mut() = AuthenticationState::InviteCode;
+        client.send_packet(&s2c::InviteCodeToggle{ invite_enabled: true });
+        Ok(())
+    }
+
+    fn on_packet(&mut self, client: &mut Client, packet: &dyn Packet) -> anyhow::Result<()> {
+        if let Some(packet) = packet.downcast_ref::<c2s::InviteCodeSubmit>() {
+            if !matches!(client.authentication_state(), AuthenticationState::InviteCode) {
+                anyhow::bail!("client already passed the invite gate")
+            }
+
+            let check_pending = self.check_pending.clone();
+            if check_pending.swap(true, Ordering::Relaxed) {
+                anyhow::bail!("invite code check still pending")
+            }
+
+            let invites = self.invites.clone();
+            let code = packet.code.trim().to_string();
+            client.run_async(
+                async move {
+                    let result = invites.check(&code).await;
+                    (code, result)
+                },
+                move |client, (code, result)| {
+                    check_pending.store(false, Ordering::Relaxed);
+                    let result = result.unwrap_or_else(|error| {
+                        tracing::error!("failed to check the invite code: {}", error);
+                        InviteCheck::NotFound
+                    });
+
+                    client.with_component_mut::<InviteCodeGate, _>(
+                        |client, gate| gate.handle_check_result(client, code, result)
+                    );
+                }
+            );
+        }
+
+        Ok(())
+    }
+}
@@ -10,6 +10,12 @@ pub use captcha::*;
 mod register;
 pub use register::*;
 
+mod invite;
+pub use invite::*;
+
+mod recovery;
+pub use recovery::*;
+
 mod resources;
 pub use resources::*;
 
@@ -17,4 +23,13 @@ mod settings;
 pub use settings::*;
 
 mod battles;
-pub use battles::*;
\ No newline at end of file
+pub use battles::*;
+
+mod garage;
+pub use garage::*;
+
+mod friends;
+pub use friends::*;
+
+mod quests;
+pub use quests::*;
\ No newline at end of file
@@ -0,0 +1,110 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use fost_protocol::packets::{Packet, PacketDowncast, c2s, s2c};
+
+use crate::{Quests, client::{ClientComponent, Client}};
+
+/// Client handler for the daily quest window.
+pub struct ClientQuests {
+    quests: Arc<Quests>,
+}
+
+impl ClientQuests {
+    pub fn new(quests: Arc<Quests>) -> Self {
+        Self {
+            quests,
+        }
+    }
+
+    fn skip_quest(&self, client: &mut Client, quest_id: i32, paid: bool) -> anyhow::Result<()> {
+        let user_id = client.user_id().context("missing client user id")?.to_string();
+        let quests = self.quests.clone();
+        client.run_async(
+            async move { quests.skip(&user_id, quest_id, paid).await },
+            move |client, result| {
+                match result {
+                    Ok(Ok(skip)) => {
+                        if let Some(crystals) = skip.crystals {
+                            client.send_packet(&s2c::AccountRankUpdateCrystals{ change_by: crystals });
+                        }
+                        client.send_packet(&s2c::QuestsReplace{ quest_id, new_quest: skip.quest });
+                    },
+                    Ok(Err(error)) => client.send_packet(&s2c::AlertShow{ text: error.to_string() }),
+                    Err(error) => tracing::error!("failed to skip quest {}: {}", quest_id, error),
+                }
+            }
+        );
+
+        Ok(())
+    }
+}
+
+impl ClientComponent for ClientQuests {
+    fn initialize(&mut self, client: &mut Client) -> anyhow::Result<()> {
+        let user_id = client.user_id().context("missing client user id")?.to_string();
+        let quests = self.quests.clone();
+        client.run_async(
+            async move { quests.has_completed(&user_id).await },
+            |client, completed| {
+                match completed {
+                    Ok(true) => client.send_packet(&s2c::QuestsNotifyCompleted{ }),
+                    Ok(false) => {},
+                    Err(error) => tracing::error!("failed to load quests: {}", error),
+                }
+            }
+        );
+
+        Ok(())
+    }
+
+    fn on_packet(&mut self, client: &mut Client, packet: &dyn Packet) -> anyhow::Result<()> {
+        if packet.is_type::<c2s::QuestsOpenWindow>() {
+            let user_id = client.user_id().context("missing client user id")?.to_string();
+            let quests = self.quests.clone();
+            client.run_async(
+                async move { quests.window(&user_id).await },
+                |client, window| {
+                    match window {
+                        Ok(window) => client.send_packet(&window),
+                        Err(error) => tracing::error!("failed to load quests: {}", error),
+                    }
+                }
+            );
+        } else if let Some(packet) = packet.downcast_ref::<c2s::QuestsSkipFree>() {
+            self.skip_quest(client, packet.quest_id, false)?;
+        } else if let Some(packet) = packet.downcast_ref::<c2s::QuestsSkipPaid>() {
+            self.skip_quest(client, packet.quest_id, true)?;
+        } else if let Some(packet) = packet.downcast_ref::<c2s::QuestsTakePrize>() {
+            let user_id = client.user_id().context("missing client user id")?.to_string();
+            let quests = self.quests.clone();
+            let quest_id = packet.quest_id;
+            client.run_async(
+                async move { quests.take_prize(&user_id, quest_id).await },
+                move |client, result| {
+                    match result {
+                        Ok(Ok(payout)) => {
+                            if let Some(crystals) = payout.crystals {
+                                client.send_packet(&s2c::AccountRankUpdateCrystals{ change_by: crystals });
+                            }
+                            client.send_packet(&s2c::QuestsPrizeTaken{ quest_id });
+                            if !payout.completed_remaining {
+                                client.send_packet(&s2c::QuestsNotifyClear{ });
+                            }
+                            if let Some(weekly_quest_description) = payout.weekly_quest {
+                                client.send_packet(&s2c::QuestsWeeklyUpdate{ weekly_quest_description });
+                            }
+                            if !payout.weekly_rewards.is_empty() {
+                                client.send_packet(&s2c::QuestRewardShowWeekly{ weekly_quest_reward: payout.weekly_rewards });
+                            }
+                        },
+                        Ok(Err(error)) => client.send_packet(&s2c::AlertShow{ text: error.to_string() }),
+                        Err(error) => tracing::error!("failed to take the prize of quest {}: {}", quest_id, error),
+                    }
+                }
+            );
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,154 @@
+use std::sync::{Arc, atomic::{AtomicBool, Ordering}};
+
+use anyhow::Context;
+use fost_protocol::{packets::{Packet, PacketDowncast, c2s, s2c}, codec::CaptchaLocation};
+use tracing::debug;
+
+use crate::{Accounts, CodeRedemption, client::{ClientComponent, Client, AuthenticationState}};
+
+use super::CaptchaProvider;
+
+/// Password recovery and email confirmation before login.
+pub struct AccountRecovery {
+    accounts: Arc<Accounts>,
+    request_pending: Arc<AtomicBool>,
+    /// User whose credentials may be changed as the client redeemed a password reset code.
+    recovered_user: Option<String>,
+}
+
+impl AccountRecovery {
+    pub fn new(accounts: Arc<Accounts>) -> Self {
+        Self {
+            accounts,
+            request_pending: Arc::new(AtomicBool::new(false)),
+            recovered_user: None,
+        }
+    }
+
+    fn begin_request(&self, client: &mut Client) -> anyhow::Result<Arc<AtomicBool>> {
+        if !matches!(client.authentication_state(), AuthenticationState::Unauthenticated) {
+            anyhow::bail!("client is not supposed to recover an account")
+        }
+
+        let request_pending = self.request_pending.clone();
+        if request_pending.swap(true, Ordering::Relaxed) {
+            anyhow::bail!("account recovery request still pending")
+        }
+
+        Ok(request_pending)
+    }
+
+    fn handle_redemption(&mut self, client: &mut Client, redemption: CodeRedemption) {
+        match redemption {
+            CodeRedemption::PasswordReset { user_id, email } => {
+                debug!("accepted password reset code for {}", user_id);
+                self.recovered_user = Some(user_id);
+                client.send_packet(&s2c::AccountRecoverCodeAccepted{ current_email: email });
+            },
+            CodeRedemption::EmailConfirmed { user_id, .. } => {
+                debug!("confirmed the email of {}", user_id);
+                client.send_packet(&s2c::AlertShow{ text: "Your email address has been confirmed.".to_string() });
+            },
+            CodeRedemption::Invalid => {
+                client.send_packet(&s2c::AccountRecoverCodeInvalid{});
+            }
+        }
+    }
+}
+
+fn solved_captcha(client: &mut Client, location: CaptchaLocation) -> anyhow::Result<bool> {
+    client.with_component_mut::<CaptchaProvider, _>(|client, captcha| captcha.solved_or_renew(client, location))
+        .context("missing captcha service")
+}
+
+impl ClientComponent for AccountRecovery {
+    fn on_packet(&mut self, client: &mut Client, packet: &dyn Packet) -> anyhow::Result<()> {
+        if let Some(packet) = packet.downcast_ref::<c2s::AccountRecoverRequestForEMail>() {
+            let request_pending = self.begin_request(client)?;
+            if !solved_captcha(client, CaptchaLocation::RestorePasswordForm)? {
+                request_pending.store(false, Ordering::Relaxed);
+                return Ok(());
+            }
+
+            let accounts = self.accounts.clone();
+            let email = packet.email.clone();
+            client.run_async(
+                async move { accounts.request_password_reset(&email).await },
+                move |client, result| {
+                    request_pending.store(false, Ordering::Relaxed);
+                    let email_sent = result.unwrap_or_else(|error| {
+                        tracing::error!("failed to send the password reset code: {:#}", error);
+                        false
+                    });
+
+                    if email_sent {
+                        client.send_packet(&s2c::AccountRecoverEmailSent{});
+                    } else {
+                        client.send_packet(&s2c::AccountRecoverEmailNotFound{});
+                    }
+                }
+            );
+        } else if let Some(packet) = packet.downcast_ref::<c2s::AccountRecoverSubmitCode>() {
+            let request_pending = self.begin_request(client)?;
+            if !solved_captcha(client, CaptchaLocation::EmailChangeHash)? {
+                request_pending.store(false, Ordering::Relaxed);
+                return Ok(());
+            }
+
+            let accounts = self.accounts.clone();
+            let code = packet.code.clone();
+            client.run_async(
+                async move { accounts.redeem_code(&code).await },
+                move |client, result| {
+                    request_pending.store(false, Ordering::Relaxed);
+                    let redemption = result.unwrap_or_else(|error| {
+                        tracing::error!("failed to redeem a code: {:#}", error);
+                        CodeRedemption::Invalid
+                    });
+
+                    client.with_component_mut::<AccountRecovery, _>(
+                        |client, recovery| recovery.handle_redemption(client, redemption)
+                    );
+                }
+            );
+        } else if let Some(packet) = packet.downcast_ref::<c2s::AccountRecoverChangeCredentials>() {
+            let request_pending = self.begin_request(client)?;
+            let user_id = match &self.recovered_user {
+                Some(user_id) => user_id.clone(),
+                None => {
+                    request_pending.store(false, Ordering::Relaxed);
+                    anyhow::bail!("client did not redeem a password reset code")
+                }
+            };
+
+            let accounts = self.accounts.clone();
+            let password = packet.password.clone();
+            let email = packet.email.clone();
+            client.run_async(
+                async move { accounts.change_credentials(&user_id, &password, &email).await },
+                move |client, result| {
+                    request_pending.store(false, Ordering::Relaxed);
+                    let result = match result {
+                        Ok(result) => result.map_err(|error| error.to_string()),
+                        Err(error) => {
+                            tracing::error!("failed to change the credentials: {:#}", error);
+                            Err("Failed to change the credentials.".to_string())
+                        }
+                    };
+
+                    match result {
+                        Ok(()) => {
+                            client.with_component_mut::<AccountRecovery, _>(|_, recovery| recovery.recovered_user = None);
+                            client.send_packet(&s2c::AccountRecoverChangeCredentialsResult{ success: true, error: String::new() });
+                        },
+                        Err(error) => {
+                            client.send_packet(&s2c::AccountRecoverChangeCredentialsResult{ success: false, error });
+                        }
+                    }
+                }
+            );
+        }
+
+        Ok(())
+    }
+}
@@ -1,31 +1,53 @@
 use std::sync::{Arc, Mutex, RwLock};
 
 use anyhow::Context;
-use fost_protocol::{packets::{Packet, PacketDowncast, c2s, s2c}, codec::{CaptchaLocation, ResourceReference}};
+use fost_protocol::{packets::{Packet, PacketDowncast, c2s, s2c}, codec::{CaptchaLocation, ResourceReference, ValidationStatus}};
+use tracing::debug;
 
-use crate::{client::{ClientComponent, Client, AuthenticationState}, users::UserRegistry};
+use crate::{client::{ClientComponent, Client, AuthenticationState}, users::UserRegistry, config::ConfigHandle, UidValidator};
 
-use super::{CaptchaProvider, UserAuthentication};
+use super::{CaptchaProvider, UserAuthentication, InviteCodeGate};
 
 pub struct UserRegister {
     user_registry: Arc<RwLock<UserRegistry>>,
+    uid_validator: Arc<UidValidator>,
+    config: ConfigHandle,
 }
 
 impl UserRegister {
-    pub fn new(user_registry: Arc<RwLock<UserRegistry>>) -> Self {
+    pub fn new(user_registry: Arc<RwLock<UserRegistry>>, uid_validator: Arc<UidValidator>, config: ConfigHandle) -> Self {
         Self {
             user_registry,
+            uid_validator,
+            config,
         }
     }
+
+    fn password_length_range(&self) -> anyhow::Result<(i32, i32)> {
+        let config = self.config.read()
+            .ok()
+            .context("failed to read the config")?;
+
+        Ok((config.register.min_password_length, config.register.max_password_length))
+    }
+
+    fn starter_premium(&self) -> anyhow::Result<chrono::Duration> {
+        let config = self.config.read()
+            .ok()
+            .context("failed to read the config")?;
+
+        Ok(chrono::Duration::hours(config.premium.starter_hours.max(0)))
+    }
 }
 
 impl ClientComponent for UserRegister {
     fn initialize(&mut self, client: &mut Client) -> anyhow::Result<()> {
+        let (min_password_length, max_password_length) = self.password_length_range()?;
         client.send_packet(&s2c::AccountRegisterParameters{
             bg_resource: ResourceReference{ resource_id: 122842 },
             enable_required_email: false,
-            max_password_length: 100,
-            min_password_length: 5
+            max_password_length,
+            min_password_length
         });
 
         Ok(())
@@ -37,24 +59,57 @@ impl ClientComponent for UserRegister {
                 anyhow::bail!("client is not supposed to register")
             }
 
-            let user_registry = self.user_registry.read()
-                .ok()
-                .context("failed to accquire the user registry")?;
-
+            let uid_validator = self.uid_validator.clone();
+            let uid = packet.uid.clone();
             client.run_async(
-                user_registry.is_username_free(packet.uid.clone()),
-                |client, result| {
-                    if result {
-                        client.send_packet(&s2c::AccountRegisterUidFree{ });
+                async move {
+                    let status = uid_validator.validate(&uid).await?;
+                    let suggestions = if status == ValidationStatus::NotUnique {
+                        uid_validator.suggest(&uid).await?
                     } else {
-                        client.send_packet(&s2c::AccountRegisterUidBusy{ adviced_uids: vec![] });
+                        vec![]
+                    };
+
+                    anyhow::Ok((status, suggestions))
+                },
+                |client, result| {
+                    match result {
+                        Ok((ValidationStatus::Correct, _)) => client.send_packet(&s2c::AccountRegisterUidFree{ }),
+                        Ok((ValidationStatus::NotUnique, adviced_uids)) => client.send_packet(&s2c::AccountRegisterUidBusy{ adviced_uids }),
+                        Ok(_) => client.send_packet(&s2c::AccountRegisterUidIncorrect{ }),
+                        Err(error) => {
+                            tracing::error!("failed to validate the username: {:#}", error);
+                            /* prevent the registration by default */
+                            client.send_packet(&s2c::AccountRegisterUidBusy{ adviced_uids: vec![] });
+                        }
                     }
                 }
             );
+        } else if let Some(packet) = packet.downcast_ref::<c2s::UidCheckRequest>() {
+            let uid_validator = self.uid_validator.clone();
+            let uid = packet.uid.clone();
+            client.run_async(
+                async move { uid_validator.validate(&uid).await },
+                |client, result| {
+                    let status = result.unwrap_or_else(|error| {
+                        tracing::error!("failed to validate the username: {:#}", error);
+                        ValidationStatus::NotUnique
+                    });
+
+                    client.send_packet(&s2c::UidCheckResponse{ status });
+                }
+            );
         } else if let Some(packet) = packet.downcast_ref::<c2s::AccountRegisterSubmit>() {
             if !matches!(client.authentication_state(), AuthenticationState::Unauthenticated) {
                 anyhow::bail!("client is not supposed to register")
             }
+
+            let (min_password_length, max_password_length) = self.password_length_range()?;
+            let password_length = packet.password.chars().count() as i32;
+            if password_length < min_password_length || password_length > max_password_length {
+                /* the client validates the password length as well */
+                anyhow::bail!("invalid password length")
+            }
             
             let captcha_valid = {
                 let mut captcha_service = client.get_component_mut::<CaptchaProvider>()
@@ -68,15 +123,43 @@ impl ClientComponent for UserRegister {
                 return Ok(());
             }
 
+            /* invite only servers require a code which has been accepted by the invite gate */
+            let invite_code = match client.get_component::<InviteCodeGate>() {
+                Some(gate) => Some(
+                    gate.registration_code()
+                        .context("client has no invite code to register with")?
+                        .to_string()
+                ),
+                None => None,
+            };
+
+            let starter_premium = self.starter_premium()?;
             let mut user_registry = self.user_registry.write()
                 .ok()
                 .context("failed to aquite the user registry")?;
 
             let username = packet.uid.to_string();
             let remember = packet.remember_me;
+            let uid_validator = self.uid_validator.clone();
+            let registration = user_registry.register_user(packet.uid.to_string(), packet.password.to_string(), starter_premium, invite_code);
             client.run_async(
-                user_registry.register_user(packet.uid.to_string(), packet.password.to_string()), 
-                move |client, result| {
+                async move {
+                    /* the client might skip the validation of the username */
+                    match uid_validator.validate(&username).await {
+                        Ok(ValidationStatus::Correct) => {},
+                        Ok(status) => {
+                            debug!("rejected the registration of {} ({:?})", username, status);
+                            return (username, false);
+                        },
+                        Err(error) => {
+                            tracing::error!("failed to validate the username: {:#}", error);
+                            return (username, false);
+                        }
+                    }
+
+                    (username, registration.await)
+                },
+                move |client, (username, result)| {
                     if result {
                         client.send_packet(&s2c::AccountLoginSuccess{});
                         client.with_component_mut::<UserAuthentication, _>(
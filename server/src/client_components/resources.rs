@@ -1,12 +1,11 @@
-use std::{sync::{Arc, RwLock}, time::{Instant, Duration}, pin::Pin};
+use std::{sync::{Arc, RwLock}, time::{Instant, Duration}, pin::Pin, collections::{BTreeMap, BTreeSet}};
 
 use anyhow::Context;
-use fost_protocol::packets::{s2c, PacketDowncast, c2s};
+use fost_protocol::{packets::{s2c, PacketDowncast, c2s}, resources::ResourceRegistry};
 use futures::Future;
-use serde::Serialize;
 use tokio::sync::oneshot;
 
-use crate::{ServerResources, client::{ClientComponent, Client}, ResourceStage, resources};
+use crate::{ServerResources, client::{ClientComponent, Client}, ResourceStage, ResourceId};
 
 enum LoadRequestState {
     Enqueued { json: String },
@@ -19,6 +18,9 @@ struct LoadRequest {
     stage: ResourceStage,
 
     state: LoadRequestState,
+    /// Requests which registered resources of this stage before.
+    /// The stage is only loaded once they have been loaded as well.
+    dependencies: BTreeSet<u32>,
 
     finish_listener: Vec<oneshot::Sender<()>>,
 }
@@ -30,6 +32,9 @@ pub struct ClientResources {
     request_id: u32,
 
     request_pending: bool,
+    /// Resources which have already been sent to the client with a previous stage
+    /// and the request which registered them.
+    registered: BTreeMap<ResourceId, u32>,
 }
 
 impl ClientResources {
@@ -39,53 +44,86 @@ impl ClientResources {
             requests: Default::default(),
             request_id: 0,
             request_pending: false,
+            registered: Default::default(),
         }
     }
 
     pub fn await_resources_loaded(&mut self, client: &mut Client, stage: ResourceStage) -> anyhow::Result<Pin<Box<dyn Future<Output = ()> + Send>>> {
-        let request = self.requests.iter_mut()
+        let request = self.requests.iter()
             .find(|request| request.stage == stage);
         let request = match request {
             Some(request) => request,
             None => self.enqueue_load_request(client, stage)?,
         };
 
-        if matches!(&request.state, LoadRequestState::Finished { .. }) {
-            return Ok(Box::pin(async {}))
-        }
+        let mut awaited = request.dependencies.clone();
+        awaited.insert(request.id);
+
+        let listener = self.requests.iter_mut()
+            .filter(|request| awaited.contains(&request.id))
+            .filter(|request| !matches!(&request.state, LoadRequestState::Finished { .. }))
+            .map(|request| {
+                let (tx, rx) = oneshot::channel();
+                request.finish_listener.push(tx);
+                rx
+            })
+            .collect::<Vec<_>>();
+
+        Ok(Box::pin(async move {
+            futures::future::join_all(listener).await;
+        }))
+    }
+
+    /// Load the resources of all stages (e.g. all garage items) and resolve once all stages have been loaded.
+    pub fn await_all_resources_loaded(&mut self, client: &mut Client, stages: impl IntoIterator<Item = ResourceStage>) -> anyhow::Result<Pin<Box<dyn Future<Output = ()> + Send>>> {
+        let stages_loaded = stages.into_iter()
+            .map(|stage| self.await_resources_loaded(client, stage))
+            .try_collect::<Vec<_>>()?;
 
-        let (tx, rx) = oneshot::channel();
-        request.finish_listener.push(tx);
-        return Ok(Box::pin(async move {
-            rx.await;
+        Ok(Box::pin(async move {
+            futures::future::join_all(stages_loaded).await;
         }))
     }
 
-    fn enqueue_load_request(&mut self, client: &mut Client, stage: ResourceStage) -> anyhow::Result<&mut LoadRequest> {
+    fn enqueue_load_request(&mut self, client: &mut Client, stage: ResourceStage) -> anyhow::Result<&LoadRequest> {
         let server_resources = self.server_resources.read()
             .ok()
             .context("failed to accquire server resources")?;
 
-        #[derive(Serialize)]
-        struct Resources {
-            resources: Vec<resources::json::Resource>
-        }
+        self.request_id += 1;
+        let request_id = self.request_id;
 
-        let resources = server_resources.get_resources(stage)
+        /* resources registered by a request which is still pending have to be awaited as well */
+        let mut dependencies = BTreeSet::new();
+        let resources = server_resources.get_resources(&stage)
             .into_iter()
+            .filter(|resource| match self.registered.get(&resource.id) {
+                Some(registered_by) => {
+                    dependencies.insert(*registered_by);
+                    false
+                },
+                None => {
+                    self.registered.insert(resource.id, request_id);
+                    true
+                }
+            })
             .map(|resource| resource.as_json_resource())
             .collect::<Vec<_>>();
-        let resources = Resources{ resources };
-        let json = serde_json::to_string(&resources)?;
 
-        self.request_id += 1;
-        let request_id = self.request_id;
+        /* nothing to load if all resources are already known by the client */
+        let state = if resources.is_empty() {
+            LoadRequestState::Finished { duration: Duration::ZERO }
+        } else {
+            let json = serde_json::to_string(&ResourceRegistry{ resources })?;
+            LoadRequestState::Enqueued { json }
+        };
 
         self.requests.push(LoadRequest { 
             id: request_id, 
             stage, 
             
-            state: LoadRequestState::Enqueued { json },
+            state,
+            dependencies,
             finish_listener: Default::default()
         });
 
@@ -93,7 +131,7 @@ impl ClientResources {
         self.try_send_next_load_request(client);
         
         Ok(
-            self.requests.last_mut()
+            self.requests.last()
                 .expect("to be the current request")
         )
     }
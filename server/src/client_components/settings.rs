@@ -1,11 +1,23 @@
-use fost_protocol::{packets::{Packet, PacketDowncast, c2s, s2c}, codec::{SocialNetworkPanelCC, SocialNetworkPanelParams}};
+use std::sync::{Arc, atomic::{AtomicBool, Ordering}};
 
-use crate::client::{ClientComponent, Client};
+use anyhow::Context;
+use fost_protocol::{packets::{Packet, PacketDowncast, c2s, s2c}, codec::{SocialNetworkPanelCC, SocialNetworkPanelParams, CaptchaLocation}};
+
+use crate::{Accounts, client::{ClientComponent, Client}};
+
+use super::CaptchaProvider;
+
+pub struct SettingsDialog {
+    accounts: Arc<Accounts>,
+    email_change_pending: Arc<AtomicBool>,
+}
 
-pub struct SettingsDialog {}
 impl SettingsDialog {
-    pub fn new() -> Self {
-        Self {}
+    pub fn new(accounts: Arc<Accounts>) -> Self {
+        Self {
+            accounts,
+            email_change_pending: Arc::new(AtomicBool::new(false)),
+        }
     }
 }
 impl ClientComponent for SettingsDialog {
@@ -27,6 +39,37 @@ impl ClientComponent for SettingsDialog {
             client.send_packet(&s2c::SettingsOpen{ notification_enabled: true });
         } else if let Some(_packet) = packet.downcast_ref::<c2s::SettingsRequestClose>() {
             client.send_packet(&s2c::SettingsClose{ });
+        } else if let Some(packet) = packet.downcast_ref::<c2s::AccountCredentialsChangeEmail>() {
+            let user_id = client.user_id().context("client is not authenticated")?.to_string();
+            let email_change_pending = self.email_change_pending.clone();
+            if email_change_pending.swap(true, Ordering::Relaxed) {
+                anyhow::bail!("email change still pending")
+            }
+
+            let captcha_valid = client.with_component_mut::<CaptchaProvider, _>(
+                |client, captcha| captcha.solved_or_renew(client, CaptchaLocation::AccountSettingsForm)
+            ).context("missing captcha service")?;
+            if !captcha_valid {
+                email_change_pending.store(false, Ordering::Relaxed);
+                return Ok(());
+            }
+
+            let accounts = self.accounts.clone();
+            let email = packet.email.clone();
+            client.run_async(
+                async move { accounts.change_email(&user_id, &email).await },
+                move |client, result| {
+                    email_change_pending.store(false, Ordering::Relaxed);
+                    match result {
+                        Ok(Ok(email)) => client.send_packet(&s2c::AccountCredentialsConfirmationSent{ email }),
+                        Ok(Err(error)) => client.send_packet(&s2c::AccountCredentialsChangeEmailFailed{ message: error.to_string() }),
+                        Err(error) => {
+                            tracing::error!("failed to change the email: {:#}", error);
+                            client.send_packet(&s2c::AccountCredentialsChangeEmailFailed{ message: "Failed to change the email address.".to_string() });
+                        }
+                    }
+                }
+            );
         }
 
         Ok(())
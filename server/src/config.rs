@@ -0,0 +1,791 @@
+use std::{net::SocketAddr, path::{PathBuf, Path}, sync::{Arc, RwLock}, time::{Duration, SystemTime}};
+
+use anyhow::Context;
+use clap::Parser;
+use fost_protocol::codec::CaptchaLocation;
+use serde::{Serialize, Deserialize};
+use tracing::{info, warn, error};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum ConfigCaptchaLocation {
+    LoginForm,
+    RegisterForm,
+    ClientStartup,
+    RestorePasswordForm,
+    EmailChangeHash,
+    AccountSettingsForm,
+}
+
+impl From<ConfigCaptchaLocation> for CaptchaLocation {
+    fn from(value: ConfigCaptchaLocation) -> Self {
+        match value {
+            ConfigCaptchaLocation::LoginForm => CaptchaLocation::LoginForm,
+            ConfigCaptchaLocation::RegisterForm => CaptchaLocation::RegisterForm,
+            ConfigCaptchaLocation::ClientStartup => CaptchaLocation::ClientStartup,
+            ConfigCaptchaLocation::RestorePasswordForm => CaptchaLocation::RestorePasswordForm,
+            ConfigCaptchaLocation::EmailChangeHash => CaptchaLocation::EmailChangeHash,
+            ConfigCaptchaLocation::AccountSettingsForm => CaptchaLocation::AccountSettingsForm,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum MailBackend {
+    /// Only log outgoing mails
+    Log,
+    /// Write outgoing mails into the mail directory
+    File,
+    Smtp,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum SmtpSecurity {
+    None,
+    StartTls,
+    Tls,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NetworkConfig {
+    pub bind: Vec<SocketAddr>,
+    pub listen_backlog: u32,
+    pub server_id: i32,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        Self {
+            bind: vec![ "127.0.0.1:1235".parse().expect("a valid socket address") ],
+            listen_backlog: 5,
+            server_id: 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DatabaseConfig {
+    /// Storage backend url (sqlite://<file>, postgres://<connection> or memory://)
+    pub url: String,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        Self {
+            url: "sqlite://database.sqlite".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FeatureConfig {
+    /// Locations where the client has to solve a captcha
+    pub captcha_locations: Vec<ConfigCaptchaLocation>,
+    /// Require an invite code before login or register
+    pub invite_codes: bool,
+    /// Allow users to create their own battles
+    pub battle_creation: bool,
+}
+
+impl Default for FeatureConfig {
+    fn default() -> Self {
+        Self {
+            captcha_locations: vec![
+                ConfigCaptchaLocation::RegisterForm,
+                ConfigCaptchaLocation::ClientStartup,
+            ],
+            invite_codes: false,
+            battle_creation: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChatConfig {
+    pub history_length: usize,
+    pub antiflood_enter_cost: i32,
+    pub antiflood_symbol_cost: i32,
+}
+
+impl Default for ChatConfig {
+    fn default() -> Self {
+        Self {
+            history_length: 100,
+            antiflood_enter_cost: 880,
+            antiflood_symbol_cost: 176,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RegisterConfig {
+    pub min_password_length: i32,
+    pub max_password_length: i32,
+    pub min_uid_length: usize,
+    /// Must not exceed 32 characters which is the limit of the storage
+    pub max_uid_length: usize,
+    /// Regular expression each username must match
+    pub uid_pattern: String,
+    /// Usernames containing one of these words (ignoring the case) are rejected
+    pub forbidden_uid_words: Vec<String>,
+}
+
+impl Default for RegisterConfig {
+    fn default() -> Self {
+        Self {
+            min_password_length: 5,
+            max_password_length: 100,
+            min_uid_length: 3,
+            max_uid_length: 20,
+            uid_pattern: "^[A-Za-z0-9_.-]+$".to_string(),
+            forbidden_uid_words: vec![ "administrator".to_string(), "moderator".to_string() ],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AuthenticationConfig {
+    /// Days in which an unused login token expires
+    pub token_expiry_days: i64,
+    /// Login tokens (devices) per user. The least recently used tokens will be removed.
+    pub max_tokens_per_user: usize,
+    /// Failed logins of an account within the window until further logins are rejected
+    pub max_failed_logins_per_account: u32,
+    /// Failed logins of an IP address within the window until further logins are rejected
+    pub max_failed_logins_per_address: u32,
+    pub failed_login_window_seconds: u64,
+}
+
+impl Default for AuthenticationConfig {
+    fn default() -> Self {
+        Self {
+            token_expiry_days: 30,
+            max_tokens_per_user: 10,
+            max_failed_logins_per_account: 5,
+            max_failed_logins_per_address: 20,
+            failed_login_window_seconds: 300,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct QuestsConfig {
+    /// Hour of the day (UTC) at which the daily quests will be renewed
+    pub reset_hour: u32,
+    /// Amount of daily quests each user receives
+    pub daily_count: usize,
+    /// Crystals required to skip a quest once the free skip has been used
+    pub skip_price: i32,
+}
+
+impl Default for QuestsConfig {
+    fn default() -> Self {
+        Self {
+            reset_hour: 0,
+            daily_count: 3,
+            skip_price: 1000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PremiumConfig {
+    /// Additional score in percent for users with a premium account
+    pub score_bonus_percent: i32,
+    /// Additional crystals in percent for users with a premium account
+    pub crystal_bonus_percent: i32,
+    /// Hours of premium account every new user receives
+    pub starter_hours: i64,
+    /// Hours before the end of the premium account at which the user will be reminded
+    pub reminder_hours: i64,
+    /// Days after the registration in which the beginner pass is active (0 to disable)
+    pub beginner_pass_days: i64,
+    pub beginner_pass_score_bonus_percent: i32,
+    pub beginner_pass_crystal_bonus_percent: i32,
+}
+
+impl Default for PremiumConfig {
+    fn default() -> Self {
+        Self {
+            score_bonus_percent: 50,
+            crystal_bonus_percent: 50,
+            starter_hours: 0,
+            reminder_hours: 24,
+            beginner_pass_days: 7,
+            beginner_pass_score_bonus_percent: 20,
+            beginner_pass_crystal_bonus_percent: 20,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ModerationConfig {
+    /// Users which always have the administrator level regardless of the database
+    pub administrators: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AdminConfig {
+    /// Address of the admin HTTP API. The API is disabled if not set.
+    /// The API has no authentication, only loopback addresses are accepted.
+    pub bind: Option<SocketAddr>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MailConfig {
+    pub backend: MailBackend,
+    /// Address used as sender of all mails
+    pub sender: String,
+    /// Directory the mails will be written to when using the file backend
+    pub directory: PathBuf,
+    pub smtp_host: String,
+    pub smtp_port: u16,
+    pub smtp_security: SmtpSecurity,
+    pub smtp_username: Option<String>,
+    pub smtp_password: Option<String>,
+    /// Hours in which password reset and email confirmation codes can be used
+    pub token_expiry_hours: i64,
+}
+
+impl Default for MailConfig {
+    fn default() -> Self {
+        Self {
+            backend: MailBackend::Log,
+            sender: "noreply@localhost".to_string(),
+            directory: PathBuf::from("mails"),
+            smtp_host: "localhost".to_string(),
+            smtp_port: 587,
+            smtp_security: SmtpSecurity::StartTls,
+            smtp_username: None,
+            smtp_password: None,
+            token_expiry_hours: 24,
+        }
+    }
+}
+
+/// Paths to the resource registry files for each stage.
+/// If not set the registry shipped with the server will be used.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ResourcesConfig {
+    pub registry_connect: Option<PathBuf>,
+    pub registry_auth: Option<PathBuf>,
+    pub registry_lobby: Option<PathBuf>,
+    /// Resources loaded on demand when joining a battle, keyed by the map id.
+    pub registry_maps: Option<PathBuf>,
+    /// Resources loaded on demand when opening the garage, keyed by the item or kit id.
+    pub registry_garage: Option<PathBuf>,
+    /// Directory containing map geometry files (<map_id>.xml or <map_id>.json)
+    pub maps_directory: Option<PathBuf>,
+    /// Address of the embedded HTTP server serving the resource files.
+    pub http_bind: Option<SocketAddr>,
+    /// Directory containing the resource files served by the HTTP server.
+    pub directory: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    pub network: NetworkConfig,
+    pub database: DatabaseConfig,
+    pub features: FeatureConfig,
+    pub chat: ChatConfig,
+    pub register: RegisterConfig,
+    pub authentication: AuthenticationConfig,
+    pub quests: QuestsConfig,
+    pub premium: PremiumConfig,
+    pub moderation: ModerationConfig,
+    pub admin: AdminConfig,
+    pub mail: MailConfig,
+    pub resources: ResourcesConfig,
+}
+
+impl ServerConfig {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let payload = std::fs::read_to_string(path)?;
+        let config = toml::from_str::<ServerConfig>(&payload)?;
+        Ok(config)
+    }
+
+    /// Take over all values from the new config which can be changed while the server is running.
+    /// Returns the names of the sections which changed but require a restart.
+    fn apply_reloadable(&mut self, config: ServerConfig) -> Vec<&'static str> {
+        let mut restart_required = Vec::new();
+        if self.network != config.network {
+            restart_required.push("network");
+        }
+
+        if self.database != config.database {
+            restart_required.push("database");
+        }
+
+        if self.admin != config.admin {
+            restart_required.push("admin");
+        }
+
+        if self.mail != config.mail {
+            restart_required.push("mail");
+        }
+
+        if self.resources != config.resources {
+            restart_required.push("resources");
+        }
+
+        self.features = config.features;
+        self.chat = config.chat;
+        self.register = config.register;
+        self.authentication = config.authentication;
+        self.quests = config.quests;
+        self.premium = config.premium;
+        self.moderation = config.moderation;
+        restart_required
+    }
+}
+
+pub type ConfigHandle = Arc<RwLock<ServerConfig>>;
+
+#[derive(Parser, Debug, Clone)]
+pub struct ServerArgs {
+    /// Path to the server configuration file
+    #[arg(short, long, default_value = "server.toml")]
+    pub config: PathBuf,
+
+    /// Address to listen on. Can be specified multiple times.
+    #[arg(short, long)]
+    pub bind: Vec<SocketAddr>,
+
+    #[arg(long)]
+    pub listen_backlog: Option<u32>,
+
+    #[arg(long)]
+    pub server_id: Option<i32>,
+
+    /// Storage backend url (sqlite://<file>, postgres://<connection> or memory://)
+    #[arg(long)]
+    pub database: Option<String>,
+
+    /// Locations where a captcha must be solved. Can be specified multiple times.
+    #[arg(long, value_enum)]
+    pub captcha_location: Vec<ConfigCaptchaLocation>,
+
+    /// Disable all captchas
+    #[arg(long)]
+    pub no_captcha: bool,
+
+    #[arg(long)]
+    pub invite_codes: Option<bool>,
+
+    #[arg(long)]
+    pub battle_creation: Option<bool>,
+
+    #[arg(long)]
+    pub chat_history_length: Option<usize>,
+
+    #[arg(long)]
+    pub chat_antiflood_enter_cost: Option<i32>,
+
+    #[arg(long)]
+    pub chat_antiflood_symbol_cost: Option<i32>,
+
+    #[arg(long)]
+    pub register_min_password_length: Option<i32>,
+
+    #[arg(long)]
+    pub register_max_password_length: Option<i32>,
+
+    #[arg(long)]
+    pub register_min_uid_length: Option<usize>,
+
+    #[arg(long)]
+    pub register_max_uid_length: Option<usize>,
+
+    /// Regular expression each username must match
+    #[arg(long)]
+    pub register_uid_pattern: Option<String>,
+
+    /// Word which must not be part of a username. Can be specified multiple times.
+    #[arg(long)]
+    pub forbidden_uid_word: Vec<String>,
+
+    /// Days in which an unused login token expires
+    #[arg(long)]
+    pub token_expiry_days: Option<i64>,
+
+    #[arg(long)]
+    pub max_tokens_per_user: Option<usize>,
+
+    #[arg(long)]
+    pub max_failed_logins_per_account: Option<u32>,
+
+    #[arg(long)]
+    pub max_failed_logins_per_address: Option<u32>,
+
+    #[arg(long)]
+    pub failed_login_window_seconds: Option<u64>,
+
+    /// Hour of the day (UTC) at which the daily quests will be renewed
+    #[arg(long)]
+    pub quests_reset_hour: Option<u32>,
+
+    #[arg(long)]
+    pub quests_daily_count: Option<usize>,
+
+    #[arg(long)]
+    pub quests_skip_price: Option<i32>,
+
+    #[arg(long)]
+    pub premium_score_bonus_percent: Option<i32>,
+
+    #[arg(long)]
+    pub premium_crystal_bonus_percent: Option<i32>,
+
+    /// Hours of premium account every new user receives
+    #[arg(long)]
+    pub premium_starter_hours: Option<i64>,
+
+    #[arg(long)]
+    pub premium_reminder_hours: Option<i64>,
+
+    /// Days after the registration in which the beginner pass is active (0 to disable)
+    #[arg(long)]
+    pub beginner_pass_days: Option<i64>,
+
+    #[arg(long)]
+    pub beginner_pass_score_bonus_percent: Option<i32>,
+
+    #[arg(long)]
+    pub beginner_pass_crystal_bonus_percent: Option<i32>,
+
+    /// User which always has the administrator level. Can be specified multiple times.
+    #[arg(long)]
+    pub administrator: Vec<String>,
+
+    /// Address of the admin HTTP API
+    #[arg(long)]
+    pub admin_bind: Option<SocketAddr>,
+
+    #[arg(long, value_enum)]
+    pub mail_backend: Option<MailBackend>,
+
+    #[arg(long)]
+    pub mail_sender: Option<String>,
+
+    /// Directory the mails will be written to when using the file mail backend
+    #[arg(long)]
+    pub mail_directory: Option<PathBuf>,
+
+    #[arg(long)]
+    pub smtp_host: Option<String>,
+
+    #[arg(long)]
+    pub smtp_port: Option<u16>,
+
+    #[arg(long, value_enum)]
+    pub smtp_security: Option<SmtpSecurity>,
+
+    #[arg(long)]
+    pub smtp_username: Option<String>,
+
+    #[arg(long)]
+    pub smtp_password: Option<String>,
+
+    #[arg(long)]
+    pub registry_connect: Option<PathBuf>,
+
+    #[arg(long)]
+    pub registry_auth: Option<PathBuf>,
+
+    #[arg(long)]
+    pub registry_lobby: Option<PathBuf>,
+
+    #[arg(long)]
+    pub registry_maps: Option<PathBuf>,
+
+    #[arg(long)]
+    pub registry_garage: Option<PathBuf>,
+
+    /// Directory containing map geometry files (<map_id>.xml or <map_id>.json)
+    #[arg(long)]
+    pub maps_directory: Option<PathBuf>,
+
+    /// Address of the embedded HTTP resource server
+    #[arg(long)]
+    pub resources_bind: Option<SocketAddr>,
+
+    /// Directory containing the resource files served by the HTTP resource server
+    #[arg(long)]
+    pub resources_directory: Option<PathBuf>,
+}
+
+impl ServerArgs {
+    /// Load the config file (if present) and apply all command line overrides.
+    pub fn load_config(&self) -> anyhow::Result<ServerConfig> {
+        let mut config = if self.config.exists() {
+            ServerConfig::load(&self.config)
+                .with_context(|| format!("failed to load {}", self.config.display()))?
+        } else {
+            info!("Config file {} does not exist. Using defaults.", self.config.display());
+            ServerConfig::default()
+        };
+
+        self.apply_overrides(&mut config);
+        Ok(config)
+    }
+
+    pub fn apply_overrides(&self, config: &mut ServerConfig) {
+        if !self.bind.is_empty() {
+            config.network.bind = self.bind.clone();
+        }
+
+        if let Some(value) = self.listen_backlog {
+            config.network.listen_backlog = value;
+        }
+
+        if let Some(value) = self.server_id {
+            config.network.server_id = value;
+        }
+
+        if let Some(value) = &self.database {
+            config.database.url = value.clone();
+        }
+
+        if self.no_captcha {
+            config.features.captcha_locations.clear();
+        } else if !self.captcha_location.is_empty() {
+            config.features.captcha_locations = self.captcha_location.clone();
+        }
+
+        if let Some(value) = self.invite_codes {
+            config.features.invite_codes = value;
+        }
+
+        if let Some(value) = self.battle_creation {
+            config.features.battle_creation = value;
+        }
+
+        if let Some(value) = self.chat_history_length {
+            config.chat.history_length = value;
+        }
+
+        if let Some(value) = self.chat_antiflood_enter_cost {
+            config.chat.antiflood_enter_cost = value;
+        }
+
+        if let Some(value) = self.chat_antiflood_symbol_cost {
+            config.chat.antiflood_symbol_cost = value;
+        }
+
+        if let Some(value) = self.register_min_password_length {
+            config.register.min_password_length = value;
+        }
+
+        if let Some(value) = self.register_max_password_length {
+            config.register.max_password_length = value;
+        }
+
+        if let Some(value) = self.register_min_uid_length {
+            config.register.min_uid_length = value;
+        }
+
+        if let Some(value) = self.register_max_uid_length {
+            config.register.max_uid_length = value;
+        }
+
+        if let Some(value) = &self.register_uid_pattern {
+            config.register.uid_pattern = value.clone();
+        }
+
+        if !self.forbidden_uid_word.is_empty() {
+            config.register.forbidden_uid_words = self.forbidden_uid_word.clone();
+        }
+
+        if let Some(value) = self.token_expiry_days {
+            config.authentication.token_expiry_days = value;
+        }
+
+        if let Some(value) = self.max_tokens_per_user {
+            config.authentication.max_tokens_per_user = value;
+        }
+
+        if let Some(value) = self.max_failed_logins_per_account {
+            config.authentication.max_failed_logins_per_account = value;
+        }
+
+        if let Some(value) = self.max_failed_logins_per_address {
+            config.authentication.max_failed_logins_per_address = value;
+        }
+
+        if let Some(value) = self.failed_login_window_seconds {
+            config.authentication.failed_login_window_seconds = value;
+        }
+
+        if let Some(value) = self.quests_reset_hour {
+            config.quests.reset_hour = value;
+        }
+
+        if let Some(value) = self.quests_daily_count {
+            config.quests.daily_count = value;
+        }
+
+        if let Some(value) = self.quests_skip_price {
+            config.quests.skip_price = value;
+        }
+
+        if let Some(value) = self.premium_score_bonus_percent {
+            config.premium.score_bonus_percent = value;
+        }
+
+        if let Some(value) = self.premium_crystal_bonus_percent {
+            config.premium.crystal_bonus_percent = value;
+        }
+
+        if let Some(value) = self.premium_starter_hours {
+            config.premium.starter_hours = value;
+        }
+
+        if let Some(value) = self.premium_reminder_hours {
+            config.premium.reminder_hours = value;
+        }
+
+        if let Some(value) = self.beginner_pass_days {
+            config.premium.beginner_pass_days = value;
+        }
+
+        if let Some(value) = self.beginner_pass_score_bonus_percent {
+            config.premium.beginner_pass_score_bonus_percent = value;
+        }
+
+        if let Some(value) = self.beginner_pass_crystal_bonus_percent {
+            config.premium.beginner_pass_crystal_bonus_percent = value;
+        }
+
+        if !self.administrator.is_empty() {
+            config.moderation.administrators = self.administrator.clone();
+        }
+
+        if let Some(value) = self.admin_bind {
+            config.admin.bind = Some(value);
+        }
+
+        if let Some(value) = self.mail_backend {
+            config.mail.backend = value;
+        }
+
+        if let Some(value) = &self.mail_sender {
+            config.mail.sender = value.clone();
+        }
+
+        if let Some(value) = &self.mail_directory {
+            config.mail.directory = value.clone();
+        }
+
+        if let Some(value) = &self.smtp_host {
+            config.mail.smtp_host = value.clone();
+        }
+
+        if let Some(value) = self.smtp_port {
+            config.mail.smtp_port = value;
+        }
+
+        if let Some(value) = self.smtp_security {
+            config.mail.smtp_security = value;
+        }
+
+        if let Some(value) = &self.smtp_username {
+            config.mail.smtp_username = Some(value.clone());
+        }
+
+        if let Some(value) = &self.smtp_password {
+            config.mail.smtp_password = Some(value.clone());
+        }
+
+        if let Some(value) = &self.registry_connect {
+            config.resources.registry_connect = Some(value.clone());
+        }
+
+        if let Some(value) = &self.registry_auth {
+            config.resources.registry_auth = Some(value.clone());
+        }
+
+        if let Some(value) = &self.registry_lobby {
+            config.resources.registry_lobby = Some(value.clone());
+        }
+
+        if let Some(value) = &self.registry_maps {
+            config.resources.registry_maps = Some(value.clone());
+        }
+
+        if let Some(value) = &self.registry_garage {
+            config.resources.registry_garage = Some(value.clone());
+        }
+
+        if let Some(value) = &self.maps_directory {
+            config.resources.maps_directory = Some(value.clone());
+        }
+
+        if let Some(value) = self.resources_bind {
+            config.resources.http_bind = Some(value);
+        }
+
+        if let Some(value) = &self.resources_directory {
+            config.resources.directory = Some(value.clone());
+        }
+    }
+}
+
+fn config_modified_timestamp(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/// Watch the config file and apply all changes which are safe to apply at runtime.
+pub fn spawn_config_watcher(args: ServerArgs, config: ConfigHandle, period: Duration) {
+    tokio::spawn(async move {
+        let mut last_modified = config_modified_timestamp(&args.config);
+        let mut interval = tokio::time::interval(period);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+
+        loop {
+            interval.tick().await;
+
+            let modified = config_modified_timestamp(&args.config);
+            if modified.is_none() || modified == last_modified {
+                continue;
+            }
+            last_modified = modified;
+
+            let new_config = match args.load_config() {
+                Ok(config) => config,
+                Err(error) => {
+                    error!("failed to reload config: {:#}", error);
+                    continue;
+                }
+            };
+
+            let mut config = match config.write() {
+                Ok(config) => config,
+                Err(_) => break,
+            };
+
+            let restart_required = config.apply_reloadable(new_config);
+            info!("Reloaded config from {}", args.config.display());
+            if !restart_required.is_empty() {
+                warn!("Changes to {} only apply after a server restart.", restart_required.join(", "));
+            }
+        }
+    });
+}
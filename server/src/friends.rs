@@ -0,0 +1,193 @@
+use std::sync::Arc;
+
+use fost_protocol::{codec::UserContainerCC, packets::s2c};
+use thiserror::Error;
+
+use crate::{SharedPacket, storage::{StorageHandle, model::{self, FriendState}}};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum FriendsError {
+    #[error("The user does not exist.")]
+    UserNotFound,
+    #[error("You can not add yourself as a friend.")]
+    SelfRequest,
+    #[error("You are already friends or a request is pending.")]
+    AlreadyRelated,
+    #[error("The friend request does not exist any more.")]
+    NoRelation,
+}
+
+/// State of the relation from the view of the friend.
+pub fn mirrored_state(state: FriendState) -> FriendState {
+    match state {
+        FriendState::Outgoing => FriendState::Incoming,
+        FriendState::Incoming => FriendState::Outgoing,
+        FriendState::Accepted => FriendState::Accepted,
+    }
+}
+
+/// Packet adding or removing the user from the list of the given state.
+pub fn friend_list_packet(state: FriendState, added: bool, user: &str) -> SharedPacket {
+    let user = user.to_string();
+    match (state, added) {
+        (FriendState::Accepted, true) => Arc::new(s2c::FriendsAcceptedAdd { user }),
+        (FriendState::Accepted, false) => Arc::new(s2c::FriendsAcceptedRemove { user }),
+        (FriendState::Incoming, true) => Arc::new(s2c::FriendsIncomingAdd { user }),
+        (FriendState::Incoming, false) => Arc::new(s2c::FriendsIncomingRemove { user }),
+        (FriendState::Outgoing, true) => Arc::new(s2c::FriendsOutgoingAdd { user }),
+        (FriendState::Outgoing, false) => Arc::new(s2c::FriendsOutgoingRemove { user }),
+    }
+}
+
+/// Friends of a user grouped by the state of the friendship.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FriendLists {
+    pub accepted: Vec<String>,
+    pub accepted_new: Vec<String>,
+    pub incoming: Vec<String>,
+    pub incoming_new: Vec<String>,
+    pub outgoing: Vec<String>,
+}
+
+impl FriendLists {
+    pub fn new(friends: Vec<model::UserFriend>) -> Self {
+        let mut lists = Self::default();
+        for friend in friends {
+            let (list, list_new) = match friend.state {
+                FriendState::Accepted => (&mut lists.accepted, Some(&mut lists.accepted_new)),
+                FriendState::Incoming => (&mut lists.incoming, Some(&mut lists.incoming_new)),
+                FriendState::Outgoing => (&mut lists.outgoing, None),
+            };
+
+            if let Some(list_new) = list_new.filter(|_| !friend.viewed) {
+                list_new.push(friend.friend_id.clone());
+            }
+            list.push(friend.friend_id);
+        }
+
+        lists
+    }
+
+    pub fn initialize_packet(self) -> s2c::FriendsInitialize {
+        let container = |users: Vec<String>| UserContainerCC { users: Some(users) };
+        s2c::FriendsInitialize {
+            friends_accepted: container(self.accepted),
+            friends_accepted_new: container(self.accepted_new),
+            friends_incoming: container(self.incoming),
+            friends_incoming_new: container(self.incoming_new),
+            friends_outgoing: container(self.outgoing),
+        }
+    }
+}
+
+/// Manages the persistent friendships between users.
+pub struct Friends {
+    storage: StorageHandle,
+}
+
+impl Friends {
+    pub fn new(storage: StorageHandle) -> Self {
+        Self {
+            storage,
+        }
+    }
+
+    pub async fn load(&self, user_id: &str) -> anyhow::Result<FriendLists> {
+        let friends = self.storage.find_user_friends(user_id).await?;
+        Ok(FriendLists::new(friends))
+    }
+
+    pub async fn user_exists(&self, user_id: &str) -> anyhow::Result<bool> {
+        self.storage.user_exists(user_id).await
+    }
+
+    pub async fn send_request(&self, user_id: &str, friend_id: &str) -> anyhow::Result<Result<(), FriendsError>> {
+        if user_id == friend_id {
+            return Ok(Err(FriendsError::SelfRequest));
+        }
+
+        if !self.storage.user_exists(friend_id).await? {
+            return Ok(Err(FriendsError::UserNotFound));
+        }
+
+        if !self.storage.create_friend_request(user_id, friend_id).await? {
+            return Ok(Err(FriendsError::AlreadyRelated));
+        }
+
+        Ok(Ok(()))
+    }
+
+    pub async fn accept_request(&self, user_id: &str, friend_id: &str) -> anyhow::Result<Result<(), FriendsError>> {
+        if !self.storage.accept_friend_request(user_id, friend_id).await? {
+            return Ok(Err(FriendsError::NoRelation));
+        }
+
+        Ok(Ok(()))
+    }
+
+    /// Reject an incoming request, revoke an outgoing request or end a friendship.
+    pub async fn remove(&self, user_id: &str, friend_id: &str, state: FriendState) -> anyhow::Result<Result<(), FriendsError>> {
+        if !self.storage.remove_friend(user_id, friend_id, state).await? {
+            return Ok(Err(FriendsError::NoRelation));
+        }
+
+        Ok(Ok(()))
+    }
+
+    /// Reject all incoming requests and return the users whose request has been rejected.
+    pub async fn reject_all(&self, user_id: &str) -> anyhow::Result<Vec<String>> {
+        let mut rejected = Vec::new();
+        for friend in self.storage.find_user_friends(user_id).await? {
+            if friend.state != FriendState::Incoming {
+                continue;
+            }
+
+            if self.storage.remove_friend(user_id, &friend.friend_id, FriendState::Incoming).await? {
+                rejected.push(friend.friend_id);
+            }
+        }
+
+        Ok(rejected)
+    }
+
+    pub async fn mark_viewed(&self, user_id: &str, friend_id: &str) -> anyhow::Result<()> {
+        self.storage.set_friend_viewed(user_id, friend_id).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::storage::model::{FriendState, UserFriend};
+    use super::{FriendLists, mirrored_state};
+
+    fn friend(friend_id: &str, state: FriendState, viewed: bool) -> UserFriend {
+        UserFriend {
+            user_id: "user".to_string(),
+            friend_id: friend_id.to_string(),
+            state,
+            viewed,
+        }
+    }
+
+    #[test]
+    fn test_friend_lists() {
+        let lists = FriendLists::new(vec![
+            friend("a", FriendState::Accepted, true),
+            friend("b", FriendState::Accepted, false),
+            friend("c", FriendState::Incoming, false),
+            friend("d", FriendState::Outgoing, true),
+        ]);
+
+        assert_eq!(lists.accepted, ["a", "b"]);
+        assert_eq!(lists.accepted_new, ["b"]);
+        assert_eq!(lists.incoming, ["c"]);
+        assert_eq!(lists.incoming_new, ["c"]);
+        assert_eq!(lists.outgoing, ["d"]);
+    }
+
+    #[test]
+    fn test_mirrored_state() {
+        assert_eq!(mirrored_state(FriendState::Outgoing), FriendState::Incoming);
+        assert_eq!(mirrored_state(FriendState::Accepted), FriendState::Accepted);
+    }
+}
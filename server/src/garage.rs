@@ -0,0 +1,761 @@
+use std::collections::BTreeMap;
+
+use serde::{Serialize, Deserialize};
+use thiserror::Error;
+
+use crate::{Rank, storage::{StorageHandle, model}};
+
+pub static GARAGE_JSON: &'static str = include_str!("../resources/garage.json");
+
+/// Maximum amount of supplies which can be bought at once.
+const MAX_PURCHASE_COUNT: i32 = 9999;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum GarageCategory {
+    Weapon,
+    Armor,
+    Paint,
+    Inventory,
+}
+
+impl GarageCategory {
+    /// Equipment can only be owned once and must be mounted to be used.
+    pub fn is_equipment(self) -> bool {
+        self != GarageCategory::Inventory
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            GarageCategory::Weapon => "weapon",
+            GarageCategory::Armor => "armor",
+            GarageCategory::Paint => "paint",
+            GarageCategory::Inventory => "inventory",
+        }
+    }
+
+    fn item_type(self) -> i32 {
+        match self {
+            GarageCategory::Weapon => 1,
+            GarageCategory::Armor => 2,
+            GarageCategory::Paint => 3,
+            GarageCategory::Inventory => 4,
+        }
+    }
+}
+
+/// Item type of kits within the garage json.
+const KIT_ITEM_TYPE: i32 = 6;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GarageModification {
+    /// Minimal rank required to buy the modification.
+    pub rank: u8,
+    pub price: i32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GarageItemDefinition {
+    /// Item id without the modification suffix (e.g. `smoky`).
+    /// Inventory items use the same ids as the battle supplies.
+    pub item_id: String,
+    pub category: GarageCategory,
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    pub preview_resource_id: i64,
+    /// Owned and mounted by every user from the start.
+    #[serde(default)]
+    pub starter: bool,
+    /// Inventory items only have a single modification.
+    pub modifications: Vec<GarageModification>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GarageKitItem {
+    pub item_id: String,
+    /// Modification of the equipment contained in the kit.
+    #[serde(default)]
+    pub modification: i32,
+    /// Amount of inventory items contained in the kit.
+    #[serde(default)]
+    pub count: i32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GarageKitDefinition {
+    pub kit_id: String,
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    pub preview_resource_id: i64,
+    pub rank: u8,
+    pub price: i32,
+    pub items: Vec<GarageKitItem>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GarageCatalog {
+    items: Vec<GarageItemDefinition>,
+    #[serde(default)]
+    kits: Vec<GarageKitDefinition>,
+}
+
+impl GarageCatalog {
+    pub fn parse(json: &str) -> anyhow::Result<Self> {
+        let catalog: Self = serde_json::from_str(json)?;
+        for item in catalog.items.iter() {
+            if item.modifications.is_empty() {
+                anyhow::bail!("item {} has no modifications", item.item_id);
+            }
+
+            if !item.category.is_equipment() && item.modifications.len() > 1 {
+                anyhow::bail!("inventory item {} must only have one modification", item.item_id);
+            }
+        }
+
+        for kit in catalog.kits.iter() {
+            for kit_item in kit.items.iter() {
+                let item = match catalog.find(&kit_item.item_id) {
+                    Some(item) => item,
+                    None => anyhow::bail!("kit {} contains unknown item {}", kit.kit_id, kit_item.item_id),
+                };
+
+                let valid = if item.category.is_equipment() {
+                    kit_item.modification >= 0 && (kit_item.modification as usize) < item.modifications.len()
+                } else {
+                    kit_item.count > 0
+                };
+                if !valid {
+                    anyhow::bail!("kit {} contains an invalid amount of {}", kit.kit_id, kit_item.item_id);
+                }
+            }
+        }
+
+        Ok(catalog)
+    }
+
+    pub fn items(&self) -> &[GarageItemDefinition] {
+        &self.items
+    }
+
+    pub fn kits(&self) -> &[GarageKitDefinition] {
+        &self.kits
+    }
+
+    pub fn find(&self, item_id: &str) -> Option<&GarageItemDefinition> {
+        self.items.iter().find(|item| item.item_id == item_id)
+    }
+
+    pub fn find_kit(&self, kit_id: &str) -> Option<&GarageKitDefinition> {
+        self.kits.iter().find(|kit| kit.kit_id == kit_id)
+    }
+}
+
+/// Split a client item id (e.g. `smoky_m2`) into the item id and the modification.
+/// Ids without a modification suffix refer to the first modification.
+pub fn parse_item_id(item_id: &str) -> (&str, i32) {
+    item_id.rsplit_once("_m")
+        .and_then(|(item_id, modification)| Some((item_id, modification.parse().ok()?)))
+        .unwrap_or((item_id, 0))
+}
+
+pub fn format_item_id(item_id: &str, modification: i32) -> String {
+    format!("{}_m{}", item_id, modification)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum GarageError {
+    #[error("The item does not exist.")]
+    UnknownItem,
+    #[error("Your rank is too low for this item.")]
+    RankTooLow,
+    #[error("You already own this item.")]
+    AlreadyOwned,
+    #[error("You do not own this item.")]
+    NotOwned,
+    #[error("Invalid item count.")]
+    InvalidCount,
+    #[error("You do not have enough crystals.")]
+    InsufficientCrystals,
+    #[error("The garage is currently unavailable.")]
+    Unavailable,
+}
+
+/// Equipment owned by the user.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OwnedEquipment {
+    pub modification: i32,
+    pub mounted: bool,
+}
+
+/// Validated purchase which still needs to be charged and granted.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GaragePurchase {
+    pub price: i32,
+    /// Equipment and the modification it will be upgraded to.
+    pub equipment: Vec<(String, i32)>,
+    /// Inventory items and the amount which will be added.
+    pub items: Vec<(String, i32)>,
+}
+
+/// Snapshot of the garage of a user.
+#[derive(Debug, Clone, Default)]
+pub struct UserGarage {
+    pub rank: u8,
+    pub crystals: i32,
+    pub equipment: BTreeMap<String, OwnedEquipment>,
+    pub items: BTreeMap<String, i32>,
+}
+
+impl UserGarage {
+    /// Starter equipment is always owned and mounted
+    /// unless another item of the same category has been mounted.
+    pub fn new(catalog: &GarageCatalog, user: &model::User, equipment: Vec<model::UserGarageItem>, items: Vec<model::UserItem>) -> Self {
+        let mut garage = Self {
+            rank: Rank::from_score(user.experience.max(0) as u32).value(),
+            crystals: user.crystals,
+            equipment: equipment.into_iter()
+                .map(|item| (item.item_id, OwnedEquipment { modification: item.modification, mounted: item.mounted }))
+                .collect(),
+            items: items.into_iter()
+                .map(|item| (item.item_id, item.count))
+                .collect(),
+        };
+
+        for item in catalog.items().iter().filter(|item| item.starter) {
+            let mounted = garage.mounted(catalog, item.category).is_none();
+            garage.equipment.entry(item.item_id.clone())
+                .or_insert(OwnedEquipment { modification: 0, mounted });
+        }
+
+        garage
+    }
+
+    /// Item id of the equipment mounted within the category.
+    pub fn mounted(&self, catalog: &GarageCatalog, category: GarageCategory) -> Option<&str> {
+        self.equipment.iter()
+            .filter(|(_, equipment)| equipment.mounted)
+            .map(|(item_id, _)| item_id.as_str())
+            .find(|item_id| catalog.find(item_id).map(|item| item.category) == Some(category))
+    }
+
+    /// Mounted equipment including the modification suffix.
+    pub fn mounted_items(&self) -> Vec<String> {
+        self.equipment.iter()
+            .filter(|(_, equipment)| equipment.mounted)
+            .map(|(item_id, equipment)| format_item_id(item_id, equipment.modification))
+            .collect()
+    }
+
+    /// Validate buying the item (or its next modification).
+    pub fn purchase_item(&self, catalog: &GarageCatalog, item_id: &str, count: i32) -> Result<GaragePurchase, GarageError> {
+        let (item_id, modification) = parse_item_id(item_id);
+        let item = catalog.find(item_id).ok_or(GarageError::UnknownItem)?;
+        let definition = usize::try_from(modification).ok()
+            .and_then(|modification| item.modifications.get(modification))
+            .ok_or(GarageError::UnknownItem)?;
+
+        if definition.rank > self.rank {
+            return Err(GarageError::RankTooLow);
+        }
+
+        let purchase = if item.category.is_equipment() {
+            let owned = self.equipment.get(item_id).map(|equipment| equipment.modification);
+            match owned {
+                Some(owned) if owned >= modification => return Err(GarageError::AlreadyOwned),
+                /* modifications must be bought one after another */
+                Some(owned) if owned + 1 != modification => return Err(GarageError::NotOwned),
+                None if modification > 0 => return Err(GarageError::NotOwned),
+                _ => {},
+            }
+
+            GaragePurchase {
+                price: definition.price,
+                equipment: vec![(item_id.to_string(), modification)],
+                items: Vec::new(),
+            }
+        } else {
+            if count <= 0 || count > MAX_PURCHASE_COUNT {
+                return Err(GarageError::InvalidCount);
+            }
+
+            GaragePurchase {
+                price: definition.price.checked_mul(count).ok_or(GarageError::InvalidCount)?,
+                equipment: Vec::new(),
+                items: vec![(item_id.to_string(), count)],
+            }
+        };
+
+        if purchase.price > self.crystals {
+            return Err(GarageError::InsufficientCrystals);
+        }
+
+        Ok(purchase)
+    }
+
+    /// Validate buying a kit.
+    /// Equipment of the kit the user already owns in a higher modification will be skipped.
+    pub fn purchase_kit(&self, catalog: &GarageCatalog, kit_id: &str) -> Result<GaragePurchase, GarageError> {
+        let (kit_id, _) = parse_item_id(kit_id);
+        let kit = catalog.find_kit(kit_id).ok_or(GarageError::UnknownItem)?;
+        if kit.rank > self.rank {
+            return Err(GarageError::RankTooLow);
+        }
+
+        if kit.price > self.crystals {
+            return Err(GarageError::InsufficientCrystals);
+        }
+
+        let mut purchase = GaragePurchase {
+            price: kit.price,
+            equipment: Vec::new(),
+            items: Vec::new(),
+        };
+        for kit_item in kit.items.iter() {
+            let is_equipment = catalog.find(&kit_item.item_id)
+                .map_or(false, |item| item.category.is_equipment());
+
+            if !is_equipment {
+                purchase.items.push((kit_item.item_id.clone(), kit_item.count));
+            } else if self.equipment.get(&kit_item.item_id).map_or(true, |owned| owned.modification < kit_item.modification) {
+                purchase.equipment.push((kit_item.item_id.clone(), kit_item.modification));
+            }
+        }
+
+        Ok(purchase)
+    }
+
+    /// Depot json containing all owned items.
+    pub fn depot_json(&self, catalog: &GarageCatalog) -> String {
+        let mut items = Vec::new();
+        for (index, item) in catalog.items().iter().enumerate() {
+            if item.category.is_equipment() {
+                if let Some(owned) = self.equipment.get(&item.item_id) {
+                    items.push(json::GarageItem::new(index, item, owned.modification, 0));
+                }
+            } else {
+                let count = self.items.get(&item.item_id).copied().unwrap_or(0);
+                if count > 0 {
+                    items.push(json::GarageItem::new(index, item, 0, count));
+                }
+            }
+        }
+
+        serde_json::to_string(&json::GarageItems { items }).unwrap_or_default()
+    }
+
+    /// Market json containing all items the user can buy.
+    /// Upgrades of owned equipment are offered within the depot.
+    pub fn market_json(&self, catalog: &GarageCatalog) -> String {
+        let mut items = catalog.items().iter()
+            .enumerate()
+            .filter(|(_, item)| !item.category.is_equipment() || !self.equipment.contains_key(&item.item_id))
+            .map(|(index, item)| json::GarageItem::new(index, item, 0, 0))
+            .collect::<Vec<_>>();
+
+        let index_offset = catalog.items().len();
+        items.extend(
+            catalog.kits().iter()
+                .enumerate()
+                .map(|(index, kit)| json::GarageItem::kit(index_offset + index, kit))
+        );
+
+        serde_json::to_string(&json::GarageItems { items }).unwrap_or_default()
+    }
+}
+
+/// Loads the garages of users and applies their purchases.
+pub struct Garage {
+    catalog: GarageCatalog,
+    storage: StorageHandle,
+}
+
+impl Garage {
+    pub fn new(catalog: GarageCatalog, storage: StorageHandle) -> Self {
+        Self {
+            catalog,
+            storage,
+        }
+    }
+
+    pub fn catalog(&self) -> &GarageCatalog {
+        &self.catalog
+    }
+
+    pub async fn load(&self, user_id: &str) -> anyhow::Result<Option<UserGarage>> {
+        let user = match self.storage.find_user(user_id).await? {
+            Some(user) => user,
+            None => return Ok(None),
+        };
+
+        let equipment = self.storage.find_user_garage_items(user_id).await?;
+        let items = self.storage.find_user_items(user_id).await?;
+        Ok(Some(UserGarage::new(&self.catalog, &user, equipment, items)))
+    }
+
+    /// Buy an item or the next modification of owned equipment.
+    /// Returns the new crystal balance.
+    pub async fn buy_item(&self, user_id: &str, item_id: &str, count: i32) -> anyhow::Result<Result<i32, GarageError>> {
+        let purchase = match self.load(user_id).await? {
+            Some(garage) => garage.purchase_item(&self.catalog, item_id, count),
+            None => Err(GarageError::Unavailable),
+        };
+
+        match purchase {
+            Ok(purchase) => self.apply_purchase(user_id, &purchase).await,
+            Err(error) => Ok(Err(error)),
+        }
+    }
+
+    /// Buy a kit.
+    /// Returns the new crystal balance.
+    pub async fn buy_kit(&self, user_id: &str, kit_id: &str) -> anyhow::Result<Result<i32, GarageError>> {
+        let purchase = match self.load(user_id).await? {
+            Some(garage) => garage.purchase_kit(&self.catalog, kit_id),
+            None => Err(GarageError::Unavailable),
+        };
+
+        match purchase {
+            Ok(purchase) => self.apply_purchase(user_id, &purchase).await,
+            Err(error) => Ok(Err(error)),
+        }
+    }
+
+    /// Charge the user for the purchase and add the purchased items at once.
+    /// Returns the new crystal balance.
+    async fn apply_purchase(&self, user_id: &str, purchase: &GaragePurchase) -> anyhow::Result<Result<i32, GarageError>> {
+        let result = self.storage.apply_purchase(user_id, purchase.price, &purchase.equipment, &purchase.items).await?;
+        Ok(match result {
+            model::PurchaseResult::Applied(crystals) => Ok(crystals),
+            model::PurchaseResult::InsufficientCrystals => Err(GarageError::InsufficientCrystals),
+            /* another purchase upgraded the equipment after the purchase has been validated */
+            model::PurchaseResult::AlreadyOwned => Err(GarageError::AlreadyOwned),
+        })
+    }
+
+    /// Give an item to the user without charging any crystals.
+    /// Equipment will be upgraded to the given modification, inventory items are added `count` times.
+    pub async fn grant_item(&self, user_id: &str, item_id: &str, count: i32) -> anyhow::Result<Result<(), GarageError>> {
+        let (item_id, modification) = parse_item_id(item_id);
+        let item = match self.catalog.find(item_id) {
+            Some(item) => item,
+            None => return Ok(Err(GarageError::UnknownItem)),
+        };
+
+        if usize::try_from(modification).map_or(true, |modification| modification >= item.modifications.len()) {
+            return Ok(Err(GarageError::UnknownItem));
+        }
+
+        if !self.storage.user_exists(user_id).await? {
+            return Ok(Err(GarageError::Unavailable));
+        }
+
+        if item.category.is_equipment() {
+            if !self.storage.upgrade_user_garage_item(user_id, item_id, modification).await? {
+                return Ok(Err(GarageError::AlreadyOwned));
+            }
+        } else {
+            if count <= 0 || count > MAX_PURCHASE_COUNT {
+                return Ok(Err(GarageError::InvalidCount));
+            }
+
+            self.storage.add_user_item(user_id, item_id, count).await?;
+        }
+
+        Ok(Ok(()))
+    }
+
+    /// Mount the equipment and unmount the equipment of the same category.
+    /// Returns the item id including the mounted modification.
+    pub async fn mount_item(&self, user_id: &str, item_id: &str) -> anyhow::Result<Result<String, GarageError>> {
+        let garage = match self.load(user_id).await? {
+            Some(garage) => garage,
+            None => return Ok(Err(GarageError::Unavailable)),
+        };
+
+        let (item_id, _) = parse_item_id(item_id);
+        let item = match self.catalog.find(item_id) {
+            Some(item) if item.category.is_equipment() => item,
+            _ => return Ok(Err(GarageError::UnknownItem)),
+        };
+
+        let owned = match garage.equipment.get(item_id) {
+            Some(owned) => *owned,
+            None => return Ok(Err(GarageError::NotOwned)),
+        };
+
+        if let Some(mounted) = garage.mounted(&self.catalog, item.category) {
+            if mounted != item_id {
+                /* starter equipment is only stored once it has been changed */
+                self.storage.upgrade_user_garage_item(user_id, mounted, 0).await?;
+                self.storage.set_user_garage_item_mounted(user_id, mounted, false).await?;
+            }
+        }
+
+        self.storage.upgrade_user_garage_item(user_id, item_id, owned.modification).await?;
+        self.storage.set_user_garage_item_mounted(user_id, item_id, true).await?;
+        Ok(Ok(format_item_id(item_id, owned.modification)))
+    }
+}
+
+mod json {
+    use serde::{Serialize, Deserialize};
+
+    use super::{GarageItemDefinition, GarageKitDefinition, KIT_ITEM_TYPE};
+
+    #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct GarageItems {
+        pub items: Vec<GarageItem>,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct Discount {
+        pub percent: i32,
+        pub time_left_in_seconds: i32,
+        pub time_to_start_in_seconds: i32,
+    }
+
+    impl Default for Discount {
+        fn default() -> Self {
+            Self {
+                percent: 0,
+                time_left_in_seconds: -1,
+                time_to_start_in_seconds: -1,
+            }
+        }
+    }
+
+    #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct KitItem {
+        pub id: String,
+        pub count: i32,
+    }
+
+    #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct Kit {
+        pub image: i64,
+        pub discount_in_percent: i32,
+        pub kit_items: Vec<KitItem>,
+        pub is_timeless: bool,
+        pub time_left_in_seconds: i32,
+    }
+
+    #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct GarageItem {
+        pub id: String,
+        pub index: i32,
+        pub name: String,
+        pub description: String,
+        pub category: String,
+        #[serde(rename = "type")]
+        pub item_type: i32,
+        pub base_item_id: i64,
+        pub preview_resource_id: i64,
+        pub rank: i32,
+        #[serde(rename = "next_rank")]
+        pub next_rank: i32,
+        pub price: i32,
+        #[serde(rename = "next_price")]
+        pub next_price: i32,
+        #[serde(rename = "modificationID")]
+        pub modification_id: i32,
+        pub is_inventory: bool,
+        pub count: i32,
+        pub discount: Discount,
+        pub grouped: bool,
+        pub is_for_rent: bool,
+        pub remaining_time_in_sec: i32,
+        #[serde(rename = "properts")]
+        pub properties: Vec<serde_json::Value>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub kit: Option<Kit>,
+    }
+
+    impl GarageItem {
+        pub fn new(index: usize, item: &GarageItemDefinition, modification: i32, count: i32) -> Self {
+            let current = &item.modifications[modification as usize];
+            /* fully upgraded items show their current modification as next one */
+            let next = item.modifications.get(modification as usize + 1).unwrap_or(current);
+
+            Self {
+                id: item.item_id.clone(),
+                index: index as i32,
+                name: item.name.clone(),
+                description: item.description.clone(),
+                category: item.category.name().to_string(),
+                item_type: item.category.item_type(),
+                base_item_id: item.preview_resource_id,
+                preview_resource_id: item.preview_resource_id,
+                rank: current.rank as i32,
+                next_rank: next.rank as i32,
+                price: current.price,
+                next_price: next.price,
+                modification_id: modification,
+                is_inventory: !item.category.is_equipment(),
+                count,
+                discount: Default::default(),
+                grouped: false,
+                is_for_rent: false,
+                remaining_time_in_sec: -1,
+                properties: Vec::new(),
+                kit: None,
+            }
+        }
+
+        pub fn kit(index: usize, kit: &GarageKitDefinition) -> Self {
+            Self {
+                id: kit.kit_id.clone(),
+                index: index as i32,
+                name: kit.name.clone(),
+                description: kit.description.clone(),
+                category: "kit".to_string(),
+                item_type: KIT_ITEM_TYPE,
+                base_item_id: kit.preview_resource_id,
+                preview_resource_id: kit.preview_resource_id,
+                rank: kit.rank as i32,
+                next_rank: kit.rank as i32,
+                price: kit.price,
+                next_price: kit.price,
+                modification_id: 0,
+                is_inventory: false,
+                count: 0,
+                discount: Default::default(),
+                grouped: false,
+                is_for_rent: false,
+                remaining_time_in_sec: -1,
+                properties: Vec::new(),
+                kit: Some(Kit {
+                    image: kit.preview_resource_id,
+                    discount_in_percent: 0,
+                    kit_items: kit.items.iter()
+                        .map(|item| KitItem {
+                            id: if item.count > 0 { item.item_id.clone() } else { super::format_item_id(&item.item_id, item.modification) },
+                            count: item.count.max(1),
+                        })
+                        .collect(),
+                    is_timeless: true,
+                    time_left_in_seconds: -1,
+                }),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use chrono::Utc;
+
+    use crate::storage::{MemoryStorage, Storage, StorageHandle, model};
+    use super::{Garage, GarageCatalog, GarageCategory, GarageError, GaragePurchase, UserGarage, GARAGE_JSON, parse_item_id};
+
+    fn user(crystals: i32, experience: i32) -> model::User {
+        model::User {
+            user_id: "user".to_string(),
+            email: None,
+            email_confirmed: false,
+            timestamp_register: Utc::now(),
+            timestamp_active: Utc::now(),
+            crystals,
+            double_crystals: None,
+            experience,
+            premium: None,
+
+            moderator_level: 0,
+            invite_code: None,
+        }
+    }
+
+    #[test]
+    fn test_parse_catalog() {
+        let catalog = GarageCatalog::parse(GARAGE_JSON).unwrap();
+        assert_eq!(catalog.find("smoky").map(|item| item.category), Some(GarageCategory::Weapon));
+        assert!(catalog.find_kit("scout_kit").is_some());
+    }
+
+    #[test]
+    fn test_parse_item_id() {
+        assert_eq!(parse_item_id("smoky_m2"), ("smoky", 2));
+        assert_eq!(parse_item_id("double_damage_m0"), ("double_damage", 0));
+        assert_eq!(parse_item_id("health"), ("health", 0));
+    }
+
+    #[test]
+    fn test_starter_equipment() {
+        let catalog = GarageCatalog::parse(GARAGE_JSON).unwrap();
+        let garage = UserGarage::new(&catalog, &user(0, 0), Vec::new(), Vec::new());
+        assert_eq!(garage.mounted(&catalog, GarageCategory::Weapon), Some("smoky"));
+        assert_eq!(garage.mounted(&catalog, GarageCategory::Armor), Some("hunter"));
+        assert_eq!(garage.mounted(&catalog, GarageCategory::Paint), Some("green"));
+    }
+
+    #[test]
+    fn test_purchase_item() {
+        let catalog = GarageCatalog::parse(GARAGE_JSON).unwrap();
+
+        let garage = UserGarage::new(&catalog, &user(1000, 0), Vec::new(), Vec::new());
+        assert_eq!(garage.purchase_item(&catalog, "health_m0", 2).map(|purchase| purchase.price), Ok(300));
+        assert_eq!(garage.purchase_item(&catalog, "health_m0", 0), Err(GarageError::InvalidCount));
+        assert_eq!(garage.purchase_item(&catalog, "health_m0", 10), Err(GarageError::InsufficientCrystals));
+        assert_eq!(garage.purchase_item(&catalog, "flamethrower_m0", 1), Err(GarageError::RankTooLow));
+        assert_eq!(garage.purchase_item(&catalog, "smoky_m0", 1), Err(GarageError::AlreadyOwned));
+        assert_eq!(garage.purchase_item(&catalog, "unknown_m0", 1), Err(GarageError::UnknownItem));
+
+        /* Staff Sergeant */
+        let garage = UserGarage::new(&catalog, &user(100_000, 12300), Vec::new(), Vec::new());
+        assert_eq!(garage.purchase_item(&catalog, "smoky_m1", 1).map(|purchase| purchase.equipment), Ok(vec![("smoky".to_string(), 1)]));
+        assert_eq!(garage.purchase_item(&catalog, "smoky_m2", 1), Err(GarageError::RankTooLow));
+        assert_eq!(garage.purchase_item(&catalog, "flamethrower_m1", 1), Err(GarageError::NotOwned));
+    }
+
+    #[tokio::test]
+    async fn test_purchase_is_atomic() -> anyhow::Result<()> {
+        let storage: StorageHandle = Arc::new(MemoryStorage::new());
+        storage.create_user(
+            &user(1000, 0),
+            &model::UserAuthentication {
+                user_id: "user".to_string(),
+                login_user: "user".to_string(),
+                password_hash: String::new(),
+                password_salt: String::new(),
+            },
+            &[]
+        ).await?;
+        let garage = Garage::new(GarageCatalog::parse(GARAGE_JSON)?, storage.clone());
+
+        /* another purchase already upgraded the second piece of equipment of the kit after the purchase has been validated */
+        let purchase = GaragePurchase {
+            price: 400,
+            equipment: vec![("hornet".to_string(), 0), ("wasp".to_string(), 0)],
+            items: vec![("health".to_string(), 1)],
+        };
+        assert!(storage.upgrade_user_garage_item("user", "wasp", 0).await?);
+        assert_eq!(garage.apply_purchase("user", &purchase).await?, Err(GarageError::AlreadyOwned));
+        assert_eq!(storage.add_user_crystals("user", 0).await?, Some(1000));
+        assert!(storage.find_user_items("user").await?.is_empty());
+
+        let equipment = storage.find_user_garage_items("user").await?;
+        assert_eq!(equipment.len(), 1);
+        assert_eq!(equipment[0].item_id, "wasp");
+
+        let purchase = GaragePurchase { equipment: vec![("hornet".to_string(), 0), ("wasp".to_string(), 1)], ..purchase };
+        assert_eq!(garage.apply_purchase("user", &purchase).await?, Ok(600));
+        assert_eq!(storage.find_user_garage_items("user").await?.len(), 2);
+
+        let purchase = GaragePurchase { price: 601, equipment: vec![], ..purchase };
+        assert_eq!(garage.apply_purchase("user", &purchase).await?, Err(GarageError::InsufficientCrystals));
+        Ok(())
+    }
+}
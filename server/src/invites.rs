@@ -0,0 +1,124 @@
+use chrono::{Duration, Utc};
+use rand::{distributions::Alphanumeric, Rng};
+use thiserror::Error;
+
+use crate::{config::ConfigHandle, storage::{StorageHandle, model}};
+
+/// Length of randomly generated invite codes.
+const GENERATED_CODE_LENGTH: usize = 12;
+const MAX_CODE_LENGTH: usize = 32;
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum InviteError {
+    #[error("Invite codes must consist of 1 to 32 letters, digits, dashes or underscores.")]
+    InvalidCode,
+    #[error("Invite codes must have at least one use.")]
+    InvalidMaxUses,
+    #[error("The invite code {0} already exists.")]
+    AlreadyExists(String),
+}
+
+/// Result of submitting a code at the invite gate.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InviteCheck {
+    /// The code can be used to register a new account.
+    Free,
+    /// The code can not be used to register anymore but its users may login.
+    /// The nickname is only known if exactly one account has been registered with the code.
+    AlreadyActivated { nickname: String },
+    NotFound,
+}
+
+fn is_valid_code(code: &str) -> bool {
+    !code.is_empty() && code.len() <= MAX_CODE_LENGTH && code.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Invite codes gating the login and registration when the server is invite only.
+pub struct Invites {
+    storage: StorageHandle,
+    config: ConfigHandle,
+}
+
+impl Invites {
+    pub fn new(storage: StorageHandle, config: ConfigHandle) -> Self {
+        Self {
+            storage,
+            config,
+        }
+    }
+
+    /// Clients have to pass the invite gate before login or register.
+    pub fn enabled(&self) -> bool {
+        self.config.read()
+            .map_or(false, |config| config.features.invite_codes)
+    }
+
+    pub async fn check(&self, code: &str) -> anyhow::Result<InviteCheck> {
+        let invite_code = match self.storage.find_invite_code(code).await? {
+            Some(invite_code) => invite_code,
+            None => return Ok(InviteCheck::NotFound),
+        };
+
+        if invite_code.is_usable(Utc::now()) {
+            return Ok(InviteCheck::Free);
+        }
+
+        let mut users = self.storage.find_invite_code_users(code).await?;
+        Ok(match users.len() {
+            0 => InviteCheck::NotFound,
+            1 => InviteCheck::AlreadyActivated { nickname: users.remove(0) },
+            _ => InviteCheck::AlreadyActivated { nickname: String::new() },
+        })
+    }
+
+    /// Create a code which can be used to register `max_uses` accounts.
+    /// A random code will be generated if no code is given.
+    pub async fn create(&self, created_by: &str, code: Option<String>, max_uses: i32, expires_in: Option<Duration>) -> anyhow::Result<Result<model::InviteCode, InviteError>> {
+        if max_uses < 1 {
+            return Ok(Err(InviteError::InvalidMaxUses));
+        }
+
+        let code = match code {
+            Some(code) if is_valid_code(&code) => code,
+            Some(_) => return Ok(Err(InviteError::InvalidCode)),
+            None => rand::thread_rng()
+                .sample_iter(&Alphanumeric)
+                .take(GENERATED_CODE_LENGTH)
+                .map(char::from)
+                .collect::<String>(),
+        };
+
+        let now = Utc::now();
+        let invite_code = model::InviteCode {
+            code,
+            created_by: created_by.to_string(),
+            max_uses,
+            uses: 0,
+            timestamp_created: now,
+            timestamp_expires: expires_in.map(|duration| now + duration),
+        };
+
+        if !self.storage.create_invite_code(&invite_code).await? {
+            return Ok(Err(InviteError::AlreadyExists(invite_code.code)));
+        }
+
+        Ok(Ok(invite_code))
+    }
+
+    pub async fn find(&self, code: &str) -> anyhow::Result<Option<model::InviteCode>> {
+        self.storage.find_invite_code(code).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::is_valid_code;
+
+    #[test]
+    fn test_valid_code() {
+        assert!(is_valid_code("closed-beta_2023"));
+        assert!(!is_valid_code(""));
+        assert!(!is_valid_code("with space"));
+        assert!(!is_valid_code(&"a".repeat(33)));
+    }
+}
@@ -0,0 +1,312 @@
+use std::{path::PathBuf, sync::Arc};
+
+use anyhow::Context;
+use async_trait::async_trait;
+use base64::Engine;
+use chrono::Utc;
+use rand::{distributions::Alphanumeric, Rng};
+use tokio::{io::{AsyncRead, AsyncWrite, AsyncBufReadExt, AsyncWriteExt, BufStream}, net::TcpStream};
+use tokio_rustls::{TlsConnector, rustls};
+use tracing::{info, debug};
+
+use crate::config::{MailConfig, MailBackend, SmtpSecurity};
+
+#[derive(Debug, Clone)]
+pub struct Mail {
+    pub to: String,
+    pub subject: String,
+    /// Plain text body
+    pub body: String,
+}
+
+#[async_trait]
+pub trait Mailer : Send + Sync {
+    async fn send(&self, mail: &Mail) -> anyhow::Result<()>;
+}
+
+pub type MailerHandle = Arc<dyn Mailer>;
+
+pub fn open_mailer(config: &MailConfig) -> anyhow::Result<MailerHandle> {
+    Ok(match config.backend {
+        MailBackend::Log => Arc::new(LogMailer {}),
+        MailBackend::File => {
+            std::fs::create_dir_all(&config.directory)
+                .with_context(|| format!("failed to create the mail directory {}", config.directory.display()))?;
+
+            Arc::new(FileMailer::new(config.sender.clone(), config.directory.clone()))
+        },
+        MailBackend::Smtp => {
+            if config.smtp_username.is_some() && matches!(config.smtp_security, SmtpSecurity::None) {
+                /* AUTH PLAIN would send the password in cleartext */
+                anyhow::bail!("smtp authentication requires smtp_security start_tls or tls");
+            }
+
+            Arc::new(SmtpMailer::new(config.clone()))
+        },
+    })
+}
+
+/// Format the mail as RFC 5322 message with CRLF line endings.
+fn format_message(sender: &str, mail: &Mail) -> String {
+    let message_id = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(24)
+        .map(char::from)
+        .collect::<String>();
+    let domain = sender.rsplit_once('@').map_or("localhost", |(_, domain)| domain);
+
+    let mut message = String::new();
+    message.push_str(&format!("From: {}\r\n", sender));
+    message.push_str(&format!("To: {}\r\n", mail.to));
+    message.push_str(&format!("Subject: {}\r\n", mail.subject));
+    message.push_str(&format!("Date: {}\r\n", Utc::now().to_rfc2822()));
+    message.push_str(&format!("Message-ID: <{}@{}>\r\n", message_id, domain));
+    message.push_str("MIME-Version: 1.0\r\n");
+    message.push_str("Content-Type: text/plain; charset=utf-8\r\n");
+    message.push_str("Content-Transfer-Encoding: 8bit\r\n");
+    message.push_str("\r\n");
+    for line in mail.body.lines() {
+        message.push_str(line);
+        message.push_str("\r\n");
+    }
+    message
+}
+
+/// Escape lines starting with a dot and terminate the data with a single dot line.
+fn smtp_data(message: &str) -> String {
+    let mut data = String::with_capacity(message.len() + 16);
+    for line in message.lines() {
+        if line.starts_with('.') {
+            data.push('.');
+        }
+        data.push_str(line);
+        data.push_str("\r\n");
+    }
+    data.push('.');
+    data
+}
+
+/// Only logs the mails. Used if no mail server is available.
+pub struct LogMailer {}
+
+#[async_trait]
+impl Mailer for LogMailer {
+    async fn send(&self, mail: &Mail) -> anyhow::Result<()> {
+        info!("Mail to {} ({}):\n{}", mail.to, mail.subject, mail.body);
+        Ok(())
+    }
+}
+
+/// Writes every mail into its own file within the mail directory.
+pub struct FileMailer {
+    sender: String,
+    directory: PathBuf,
+}
+
+impl FileMailer {
+    pub fn new(sender: String, directory: PathBuf) -> Self {
+        Self {
+            sender,
+            directory,
+        }
+    }
+}
+
+#[async_trait]
+impl Mailer for FileMailer {
+    async fn send(&self, mail: &Mail) -> anyhow::Result<()> {
+        let suffix = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(8)
+            .map(char::from)
+            .collect::<String>();
+
+        let path = self.directory.join(format!("{}-{}.eml", Utc::now().format("%Y%m%d%H%M%S"), suffix));
+        tokio::fs::write(&path, format_message(&self.sender, mail)).await
+            .with_context(|| format!("failed to write {}", path.display()))?;
+
+        info!("Wrote mail to {} into {}", mail.to, path.display());
+        Ok(())
+    }
+}
+
+/// Delivers the mails to a SMTP relay.
+pub struct SmtpMailer {
+    config: MailConfig,
+    tls: TlsConnector,
+}
+
+impl SmtpMailer {
+    pub fn new(config: MailConfig) -> Self {
+        let mut root_store = rustls::RootCertStore::empty();
+        root_store.add_server_trust_anchors(
+            webpki_roots::TLS_SERVER_ROOTS.0.iter().map(|anchor| {
+                rustls::OwnedTrustAnchor::from_subject_spki_name_constraints(
+                    anchor.subject,
+                    anchor.spki,
+                    anchor.name_constraints,
+                )
+            })
+        );
+
+        let tls_config = rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(root_store)
+            .with_no_client_auth();
+
+        Self {
+            config,
+            tls: TlsConnector::from(Arc::new(tls_config)),
+        }
+    }
+
+    fn server_name(&self) -> anyhow::Result<rustls::ServerName> {
+        rustls::ServerName::try_from(self.config.smtp_host.as_str())
+            .ok()
+            .with_context(|| format!("invalid smtp host {}", self.config.smtp_host))
+    }
+
+    async fn deliver<S: AsyncRead + AsyncWrite + Unpin>(&self, connection: &mut SmtpConnection<S>, mail: &Mail) -> anyhow::Result<()> {
+        if let Some(username) = &self.config.smtp_username {
+            let password = self.config.smtp_password.as_deref().unwrap_or_default();
+            let credentials = base64::engine::general_purpose::STANDARD
+                .encode(format!("\0{}\0{}", username, password));
+
+            connection.command(&format!("AUTH PLAIN {}", credentials), 235).await?;
+        }
+
+        connection.command(&format!("MAIL FROM:<{}>", self.config.sender), 250).await?;
+        connection.command(&format!("RCPT TO:<{}>", mail.to), 250).await?;
+        connection.command("DATA", 354).await?;
+
+        let data = smtp_data(&format_message(&self.config.sender, mail));
+        connection.command(&data, 250).await?;
+
+        connection.command("QUIT", 221).await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Mailer for SmtpMailer {
+    async fn send(&self, mail: &Mail) -> anyhow::Result<()> {
+        let stream = TcpStream::connect((self.config.smtp_host.as_str(), self.config.smtp_port)).await
+            .with_context(|| format!("failed to connect to {}:{}", self.config.smtp_host, self.config.smtp_port))?;
+
+        match self.config.smtp_security {
+            SmtpSecurity::None => {
+                let mut connection = SmtpConnection::open(stream).await?;
+                self.deliver(&mut connection, mail).await?;
+            },
+            SmtpSecurity::StartTls => {
+                let mut connection = SmtpConnection::open(stream).await?;
+                connection.command("STARTTLS", 220).await?;
+
+                let stream = self.tls.connect(self.server_name()?, connection.into_inner()).await
+                    .context("tls handshake failed")?;
+
+                let mut connection = SmtpConnection::new(stream);
+                connection.command("EHLO localhost", 250).await?;
+                self.deliver(&mut connection, mail).await?;
+            },
+            SmtpSecurity::Tls => {
+                let stream = self.tls.connect(self.server_name()?, stream).await
+                    .context("tls handshake failed")?;
+
+                let mut connection = SmtpConnection::open(stream).await?;
+                self.deliver(&mut connection, mail).await?;
+            },
+        }
+
+        debug!("Delivered mail to {} via {}", mail.to, self.config.smtp_host);
+        Ok(())
+    }
+}
+
+struct SmtpConnection<S> {
+    stream: BufStream<S>,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> SmtpConnection<S> {
+    fn new(stream: S) -> Self {
+        Self {
+            stream: BufStream::new(stream),
+        }
+    }
+
+    /// Await the server greeting and introduce ourself.
+    async fn open(stream: S) -> anyhow::Result<Self> {
+        let mut connection = Self::new(stream);
+        connection.expect_reply(220).await?;
+        connection.command("EHLO localhost", 250).await?;
+        Ok(connection)
+    }
+
+    fn into_inner(self) -> S {
+        self.stream.into_inner()
+    }
+
+    /// Read a (multiline) reply and return its code and text.
+    async fn read_reply(&mut self) -> anyhow::Result<(u16, String)> {
+        let mut text = String::new();
+        loop {
+            let mut line = String::new();
+            if self.stream.read_line(&mut line).await? == 0 {
+                anyhow::bail!("smtp connection closed");
+            }
+
+            let line = line.trim_end();
+            let code = line.get(0..3)
+                .and_then(|code| code.parse::<u16>().ok())
+                .with_context(|| format!("invalid smtp reply: {}", line))?;
+
+            text.push_str(line.get(4..).unwrap_or_default());
+            if line.as_bytes().get(3) != Some(&b'-') {
+                return Ok((code, text));
+            }
+            text.push('\n');
+        }
+    }
+
+    async fn expect_reply(&mut self, expected_code: u16) -> anyhow::Result<String> {
+        let (code, text) = self.read_reply().await?;
+        if code != expected_code {
+            anyhow::bail!("unexpected smtp reply {} {}", code, text);
+        }
+
+        Ok(text)
+    }
+
+    async fn command(&mut self, command: &str, expected_code: u16) -> anyhow::Result<String> {
+        self.stream.write_all(command.as_bytes()).await?;
+        self.stream.write_all(b"\r\n").await?;
+        self.stream.flush().await?;
+        self.expect_reply(expected_code).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config::{MailBackend, MailConfig, SmtpSecurity};
+    use super::{open_mailer, smtp_data};
+
+    #[test]
+    fn test_smtp_data() {
+        assert_eq!(smtp_data("Subject: test\r\n\r\n.hidden\r\nend\r\n"), "Subject: test\r\n\r\n..hidden\r\nend\r\n.");
+    }
+
+    #[test]
+    fn test_smtp_authentication_requires_tls() {
+        let config = MailConfig {
+            backend: MailBackend::Smtp,
+            smtp_security: SmtpSecurity::None,
+            smtp_username: Some("mailer".to_string()),
+            smtp_password: Some("secret".to_string()),
+            ..Default::default()
+        };
+        assert!(open_mailer(&config).is_err());
+
+        assert!(open_mailer(&MailConfig { smtp_security: SmtpSecurity::StartTls, ..config.clone() }).is_ok());
+        assert!(open_mailer(&MailConfig { smtp_username: None, ..config }).is_ok());
+    }
+}
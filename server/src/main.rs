@@ -1,22 +1,24 @@
 #![feature(iterator_try_collect)]
 #![feature(trait_alias)]
 #![allow(unused)]
-use std::{net::SocketAddr, sync::{Arc, Mutex, RwLock}, task::Poll, future::poll_fn};
+use std::{net::SocketAddr, sync::{Arc, Mutex, RwLock}, task::Poll, future::poll_fn, time::Duration};
 
 use anyhow::Context;
+use clap::Parser;
 use futures::FutureExt;
-use tokio::net::TcpSocket;
+use tokio::{net::TcpSocket, sync::mpsc};
 use tracing::{Level, info, debug, warn};
 use tracing_subscriber::EnvFilter;
 use tracing::{ error };
-use sqlx::{SqliteConnection, Connection, ConnectOptions, sqlite::{SqliteConnectOptions, SqliteJournalMode}};
 
-use crate::{client::Client, server::Server};
+use crate::{client::Client, server::Server, config::{ServerArgs, spawn_config_watcher}};
 
 mod client;
 mod server;
 mod client_components;
 mod users;
+mod storage;
+mod config;
 mod rank;
 pub use rank::*;
 
@@ -29,40 +31,103 @@ pub use resources::*;
 mod chat;
 pub use chat::*;
 
+mod maps;
+pub use maps::*;
+
 mod battle;
 pub use battle::*;
 
 mod battles;
 pub use battles::*;
 
+mod garage;
+pub use garage::*;
+
+mod notifier;
+pub use notifier::*;
+
+mod friends;
+pub use friends::*;
+
+mod quests;
+pub use quests::*;
+
+mod progression;
+pub use progression::*;
+
+mod premium;
+pub use premium::*;
+
+mod moderation;
+pub use moderation::*;
+
+mod throttle;
+pub use throttle::*;
+
+mod uids;
+pub use uids::*;
+
+mod invites;
+pub use invites::*;
+
+mod mail;
+pub use mail::*;
+
+mod accounts;
+pub use accounts::*;
+
+mod admin;
+mod resource_server;
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
+    let args = ServerArgs::parse();
     tracing_subscriber::fmt()
         .with_max_level(Level::TRACE)
         .with_env_filter(EnvFilter::from_default_env())
         .init();
 
-    let addr: SocketAddr = "127.0.0.1:1235".parse()?;
-
-    let socket = TcpSocket::new_v4()?;
-    socket.bind(addr.clone())?;
-
-    let socket = socket.listen(5)?;
-    info!("Server started on {}", addr);
+    let config = args.load_config()?;
+    let (accept_tx, mut accept_rx) = mpsc::unbounded_channel();
+    let mut listeners = Vec::with_capacity(config.network.bind.len());
+    for address in config.network.bind.iter() {
+        let socket = if address.is_ipv4() {
+            TcpSocket::new_v4()?
+        } else {
+            TcpSocket::new_v6()?
+        };
+        socket.bind(address.clone())?;
+
+        let socket = socket.listen(config.network.listen_backlog)?;
+        info!("Server started on {}", address);
+
+        let accept_tx = accept_tx.clone();
+        listeners.push(tokio::spawn(async move {
+            loop {
+                let event = socket.accept().await;
+                let failed = event.is_err();
+                if accept_tx.send(event).is_err() || failed {
+                    break;
+                }
+            }
+        }));
+    }
+    drop(accept_tx);
 
-    let mut database = SqliteConnectOptions::default()
-        .filename("database.sqlite")
-        .create_if_missing(true)
-        .journal_mode(SqliteJournalMode::Wal)
-        .connect().await?;
+    let storage = storage::open_storage(&config.database.url)
+        .await
+        .context("failed to open the storage")?;
 
-    sqlx::migrate!("./migrations")
-        .run(&mut database)
-        .await?;
+    let mailer = open_mailer(&config.mail)
+        .context("failed to open the mailer")?;
 
-    let database = Arc::new(tokio::sync::Mutex::new(database));
+    let admin_bind = config.admin.bind;
+    let resource_server_bind = config.resources.http_bind;
+    let resource_directory = config.resources.directory.clone();
+    let config = Arc::new(RwLock::new(config));
+    spawn_config_watcher(args.clone(), config.clone(), Duration::from_secs(5));
 
-    let server = Server::new(database)?;
+    let server = Server::new(config, storage, mailer)?;
     let server = Arc::new(Mutex::new(server));
 
     {
@@ -77,14 +142,30 @@ async fn main() -> anyhow::Result<()> {
         }));
     }
 
+    let admin_api = match admin_bind {
+        Some(address) => Some(admin::spawn_admin_api(address, server.clone())?),
+        None => None,
+    };
+
+    let resource_server = match resource_server_bind {
+        Some(address) => {
+            let directory = resource_directory.context("the resource server requires a resource directory")?;
+            Some(resource_server::spawn_resource_server(address, directory)?)
+        },
+        None => None,
+    };
+
+    let shutdown_signal = server.lock().unwrap().shutdown_signal();
     loop {
         let accept_event = tokio::select! {
-            event = socket.accept() => event,
+            event = accept_rx.recv() => event,
             _ = tokio::signal::ctrl_c() => break,
+            _ = shutdown_signal.notified() => break,
         };
         let (stream, socket_address) = match accept_event {
-            Ok(client) => client,
-            Err(error) => {
+            Some(Ok(client)) => client,
+            None => break,
+            Some(Err(error)) => {
                 error!("failed to accept client: {}", error);
                 break;
             }
@@ -111,7 +192,18 @@ async fn main() -> anyhow::Result<()> {
     }
 
     tracing::info!("Server shutdown");
-    drop(socket); /* close the server socket */
+    for listener in listeners {
+        /* close the server sockets */
+        listener.abort();
+    }
+
+    if let Some(admin_api) = admin_api {
+        admin_api.abort();
+    }
+
+    if let Some(resource_server) = resource_server {
+        resource_server.abort();
+    }
 
     let server_shutdown = {
         let mut server = server.lock().unwrap();
@@ -0,0 +1,554 @@
+use std::{collections::BTreeMap, path::Path, sync::Arc};
+
+use anyhow::Context;
+use fost_protocol::codec::{BattleMode, BattleTeam};
+use nalgebra::Vector3;
+use rand::seq::SliceRandom;
+use tracing::{info, debug};
+
+/// Map geometry shipped with the server.
+/// Used when no map directory has been configured.
+static BUILTIN_MAPS: &[(&str, &str)] = &[
+    ("map_silence_moon", include_str!("../resources/maps/map_silence_moon.xml")),
+];
+
+/// Tanks which fall this far below the lowest point of the map will self destruct.
+const MAP_FALL_MARGIN: f32 = 1000.0;
+
+mod json {
+    use serde::{Serialize, Deserialize};
+
+    use crate::Vector3f;
+
+    #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct SpawnPoint {
+        #[serde(default)]
+        pub mode: Option<String>,
+        #[serde(default)]
+        pub team: Option<String>,
+        pub position: Vector3f,
+        #[serde(default)]
+        pub rotation: Vector3f,
+    }
+
+    #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct Bounds {
+        pub min: Vector3f,
+        pub max: Vector3f,
+    }
+
+    #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct CtfFlags {
+        pub red: Vector3f,
+        pub blue: Vector3f,
+    }
+
+    #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct ControlPoint {
+        pub name: String,
+        pub position: Vector3f,
+    }
+
+    #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct Assault {
+        pub flags: Vec<Vector3f>,
+        pub bases: Vec<Vector3f>,
+    }
+
+    #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct BonusRegion {
+        #[serde(default)]
+        pub name: String,
+        pub bonus_types: Vec<String>,
+        #[serde(default)]
+        pub modes: Vec<String>,
+        pub min: Vector3f,
+        pub max: Vector3f,
+    }
+
+    #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct MapGeometry {
+        pub spawn_points: Vec<SpawnPoint>,
+        #[serde(default)]
+        pub bounds: Option<Bounds>,
+        #[serde(default)]
+        pub ctf_flags: Option<CtfFlags>,
+        #[serde(default)]
+        pub control_points: Vec<ControlPoint>,
+        #[serde(default)]
+        pub assault: Option<Assault>,
+        #[serde(default)]
+        pub bonus_regions: Vec<BonusRegion>,
+    }
+}
+
+/// Parse the battle mode names used within map files.
+fn parse_battle_mode(name: &str) -> Option<BattleMode> {
+    match name.to_ascii_lowercase().as_str() {
+        "dm" => Some(BattleMode::Dm),
+        "tdm" => Some(BattleMode::Tdm),
+        "ctf" => Some(BattleMode::Ctf),
+        "cp" | "dom" => Some(BattleMode::Cp),
+        "as" => Some(BattleMode::As),
+        _ => None,
+    }
+}
+
+fn parse_team(name: &str) -> Option<BattleTeam> {
+    match name.to_ascii_lowercase().as_str() {
+        "red" => Some(BattleTeam::Red),
+        "blue" => Some(BattleTeam::Blue),
+        "none" | "dm" => Some(BattleTeam::None),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpawnPoint {
+    /// Battle mode the spawn point is restricted to.
+    /// Spawn points without a mode will be used if the map has no spawn points for the mode.
+    pub mode: Option<BattleMode>,
+    /// `BattleTeam::None` for spawn points used in DM battles.
+    pub team: BattleTeam,
+    pub position: Vector3<f32>,
+    pub orientation: Vector3<f32>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MapBounds {
+    pub min: Vector3<f32>,
+    pub max: Vector3<f32>,
+}
+
+impl MapBounds {
+    fn from_points<'a>(mut points: impl Iterator<Item = &'a Vector3<f32>>) -> Option<Self> {
+        let first = points.next()?;
+        let mut bounds = Self { min: *first, max: *first };
+        for point in points {
+            bounds.min = bounds.min.inf(point);
+            bounds.max = bounds.max.sup(point);
+        }
+        Some(bounds)
+    }
+
+    pub fn contains(&self, position: &Vector3<f32>) -> bool {
+        position >= &self.min && position <= &self.max
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ControlPoint {
+    pub name: String,
+    pub position: Vector3<f32>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BonusRegion {
+    pub name: String,
+    pub bonus_types: Vec<String>,
+    /// Battle modes the region is used in. Empty for all modes.
+    pub modes: Vec<BattleMode>,
+    pub min: Vector3<f32>,
+    pub max: Vector3<f32>,
+}
+
+impl BonusRegion {
+    pub fn random_position(&self) -> Vector3<f32> {
+        let factor = Vector3::from_fn(|_, _| rand::random::<f32>());
+        self.min + (self.max - self.min).component_mul(&factor)
+    }
+}
+
+/// Server side geometry of a map.
+/// Contains everything the battle logic needs to know about a map: spawn points, bounds and the mode specific objects.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MapGeometry {
+    pub map_id: String,
+    pub spawn_points: Vec<SpawnPoint>,
+    pub bounds: Option<MapBounds>,
+
+    /// Flag bases for CTF, indexed by `team_index`.
+    pub ctf_flags: Option<[Vector3<f32>; 2]>,
+    pub control_points: Vec<ControlPoint>,
+    pub assault_flags: Vec<Vector3<f32>>,
+    pub assault_bases: Vec<Vector3<f32>>,
+    pub bonus_regions: Vec<BonusRegion>,
+}
+
+impl MapGeometry {
+    /// Geometry without any data.
+    /// Tanks will spawn at the map origin.
+    pub fn empty(map_id: String) -> Self {
+        Self {
+            map_id,
+            spawn_points: Vec::new(),
+            bounds: None,
+
+            ctf_flags: None,
+            control_points: Vec::new(),
+            assault_flags: Vec::new(),
+            assault_bases: Vec::new(),
+            bonus_regions: Vec::new(),
+        }
+    }
+
+    /// Parse a map file in the client map format (map.xml).
+    /// The bounds will be calculated from the static geometry.
+    /// Assault objects are read from the `as-flags` and `as-bases` elements which are not part of the client format.
+    pub fn parse_xml(map_id: String, payload: &str) -> anyhow::Result<Self> {
+        let document = roxmltree::Document::parse(payload)?;
+        let root = document.root_element();
+
+        let mut result = Self::empty(map_id);
+        for node in child_elements(root, "spawn-points").flat_map(|node| child_elements(node, "spawn-point")) {
+            let spawn_type = node.attribute("type").context("missing spawn point type")?;
+            let (mode, team) = match spawn_type.split_once('_') {
+                Some((mode, team)) => (Some(mode), team),
+                None => (None, spawn_type),
+            };
+
+            let mode = mode
+                .map(|mode| parse_battle_mode(mode).with_context(|| format!("invalid spawn point mode {}", mode)))
+                .transpose()?;
+            let team = parse_team(team).with_context(|| format!("invalid spawn point team {}", team))?;
+            let position = child_elements(node, "position").next()
+                .context("missing spawn point position")
+                .and_then(parse_xml_vector)?;
+            let orientation = child_elements(node, "rotation").next()
+                .map(parse_xml_vector)
+                .transpose()?
+                .unwrap_or_else(Vector3::zeros);
+
+            result.spawn_points.push(SpawnPoint { mode, team, position, orientation });
+        }
+
+        if let Some(node) = child_elements(root, "ctf-flags").next() {
+            let red = child_elements(node, "flag-red").next().context("missing red flag")?;
+            let blue = child_elements(node, "flag-blue").next().context("missing blue flag")?;
+            result.ctf_flags = Some([ parse_xml_vector(red)?, parse_xml_vector(blue)? ]);
+        }
+
+        for node in child_elements(root, "dom-keypoints").flat_map(|node| child_elements(node, "dom-keypoint")) {
+            let name = node.attribute("name").context("missing control point name")?;
+            let position = child_elements(node, "position").next()
+                .context("missing control point position")
+                .and_then(parse_xml_vector)?;
+
+            result.control_points.push(ControlPoint { name: name.to_string(), position });
+        }
+
+        for node in child_elements(root, "as-flags").flat_map(|node| child_elements(node, "as-flag")) {
+            result.assault_flags.push(parse_xml_vector(node)?);
+        }
+
+        for node in child_elements(root, "as-bases").flat_map(|node| child_elements(node, "as-base")) {
+            result.assault_bases.push(parse_xml_vector(node)?);
+        }
+
+        for node in child_elements(root, "bonus-regions").flat_map(|node| child_elements(node, "bonus-region")) {
+            let modes = child_elements(node, "game-mode")
+                .filter_map(|node| node.text())
+                .map(|mode| parse_battle_mode(mode.trim()).with_context(|| format!("invalid bonus region mode {}", mode)))
+                .try_collect::<Vec<_>>()?;
+
+            result.bonus_regions.push(BonusRegion {
+                name: node.attribute("name").unwrap_or_default().to_string(),
+                bonus_types: child_elements(node, "bonus-type")
+                    .filter_map(|node| node.text())
+                    .map(|bonus_type| bonus_type.trim().to_string())
+                    .collect(),
+                modes,
+                min: child_elements(node, "min").next().context("missing bonus region min")
+                    .and_then(parse_xml_vector)?,
+                max: child_elements(node, "max").next().context("missing bonus region max")
+                    .and_then(parse_xml_vector)?,
+            });
+        }
+
+        let props = child_elements(root, "static-geometry")
+            .flat_map(|node| child_elements(node, "prop"))
+            .filter_map(|node| child_elements(node, "position").next())
+            .map(parse_xml_vector)
+            .try_collect::<Vec<_>>()?;
+        let spawn_points = result.spawn_points.iter().map(|point| &point.position);
+        result.bounds = MapBounds::from_points(props.iter().chain(spawn_points));
+
+        Ok(result)
+    }
+
+    /// Parse a map file in the server JSON format.
+    pub fn parse_json(map_id: String, payload: &str) -> anyhow::Result<Self> {
+        let geometry = serde_json::from_str::<json::MapGeometry>(payload)?;
+
+        let mut result = Self::empty(map_id);
+        for point in geometry.spawn_points {
+            let mode = point.mode.as_deref()
+                .map(|mode| parse_battle_mode(mode).with_context(|| format!("invalid spawn point mode {}", mode)))
+                .transpose()?;
+            let team = match point.team.as_deref() {
+                Some(team) => parse_team(team).with_context(|| format!("invalid spawn point team {}", team))?,
+                None => BattleTeam::None,
+            };
+
+            result.spawn_points.push(SpawnPoint {
+                mode,
+                team,
+                position: point.position.into(),
+                orientation: point.rotation.into(),
+            });
+        }
+
+        result.ctf_flags = geometry.ctf_flags.map(|flags| [ flags.red.into(), flags.blue.into() ]);
+        result.control_points = geometry.control_points.into_iter()
+            .map(|point| ControlPoint { name: point.name, position: point.position.into() })
+            .collect();
+
+        if let Some(assault) = geometry.assault {
+            result.assault_flags = assault.flags.into_iter().map(Into::into).collect();
+            result.assault_bases = assault.bases.into_iter().map(Into::into).collect();
+        }
+
+        for region in geometry.bonus_regions {
+            let modes = region.modes.iter()
+                .map(|mode| parse_battle_mode(mode).with_context(|| format!("invalid bonus region mode {}", mode)))
+                .try_collect::<Vec<_>>()?;
+
+            result.bonus_regions.push(BonusRegion {
+                name: region.name,
+                bonus_types: region.bonus_types,
+                modes,
+                min: region.min.into(),
+                max: region.max.into(),
+            });
+        }
+
+        result.bounds = match geometry.bounds {
+            Some(bounds) => Some(MapBounds { min: bounds.min.into(), max: bounds.max.into() }),
+            None => MapBounds::from_points(result.spawn_points.iter().map(|point| &point.position)),
+        };
+
+        Ok(result)
+    }
+
+    /// Check if the map contains everything required for the battle mode.
+    pub fn supports_mode(&self, mode: BattleMode) -> bool {
+        let has_spawn_points = |team: BattleTeam| !self.spawn_points(mode, team).is_empty();
+        let has_team_spawn_points = has_spawn_points(BattleTeam::Red) && has_spawn_points(BattleTeam::Blue);
+
+        match mode {
+            BattleMode::Dm => has_spawn_points(BattleTeam::None),
+            BattleMode::Tdm => has_team_spawn_points,
+            BattleMode::Ctf => has_team_spawn_points && self.ctf_flags.is_some(),
+            BattleMode::Cp => has_team_spawn_points && !self.control_points.is_empty(),
+            BattleMode::As => has_team_spawn_points && !self.assault_flags.is_empty() && !self.assault_bases.is_empty(),
+            BattleMode::Unknown => false,
+        }
+    }
+
+    /// All spawn points of the team for the battle mode.
+    /// Falls back to the spawn points without a mode if there are no mode specific spawn points.
+    pub fn spawn_points(&self, mode: BattleMode, team: BattleTeam) -> Vec<&SpawnPoint> {
+        let matching = |spawn_mode: Option<BattleMode>| {
+            self.spawn_points.iter()
+                .filter(|point| point.team == team && point.mode == spawn_mode)
+                .collect::<Vec<_>>()
+        };
+
+        let points = matching(Some(mode));
+        if !points.is_empty() {
+            return points;
+        }
+
+        matching(None)
+    }
+
+    pub fn random_spawn_point(&self, mode: BattleMode, team: BattleTeam) -> Option<&SpawnPoint> {
+        self.spawn_points(mode, team)
+            .choose(&mut rand::thread_rng())
+            .copied()
+    }
+
+    /// Check if the tank has fallen off the map.
+    pub fn is_below_map(&self, position: &Vector3<f32>) -> bool {
+        self.bounds.as_ref()
+            .map(|bounds| position.z < bounds.min.z - MAP_FALL_MARGIN)
+            .unwrap_or(false)
+    }
+
+    pub fn bonus_regions(&self, mode: BattleMode) -> impl Iterator<Item = &BonusRegion> {
+        self.bonus_regions.iter()
+            .filter(move |region| region.modes.is_empty() || region.modes.contains(&mode))
+    }
+}
+
+fn child_elements<'a, 'input>(node: roxmltree::Node<'a, 'input>, name: &'static str) -> impl Iterator<Item = roxmltree::Node<'a, 'input>> {
+    node.children().filter(move |child| child.has_tag_name(name))
+}
+
+/// Parse a vector stored as `<x>`, `<y>` and `<z>` child elements.
+/// Missing components default to zero.
+fn parse_xml_vector(node: roxmltree::Node) -> anyhow::Result<Vector3<f32>> {
+    let mut result = Vector3::zeros();
+    for (index, name) in ["x", "y", "z"].into_iter().enumerate() {
+        if let Some(value) = child_elements(node, name).next().and_then(|node| node.text()) {
+            result[index] = value.trim().parse::<f32>()
+                .with_context(|| format!("invalid {} component {}", name, value))?;
+        }
+    }
+    Ok(result)
+}
+
+fn parse_map_file(map_id: String, extension: &str, payload: &str) -> anyhow::Result<MapGeometry> {
+    match extension {
+        "xml" => MapGeometry::parse_xml(map_id, payload),
+        "json" => MapGeometry::parse_json(map_id, payload),
+        _ => anyhow::bail!("unsupported map format {}", extension),
+    }
+}
+
+/// Geometry of all known maps, keyed by the map id.
+pub struct MapRegistry {
+    geometries: BTreeMap<String, Arc<MapGeometry>>,
+}
+
+impl MapRegistry {
+    /// Load the builtin maps and all map files within the directory.
+    /// Map files are named `<map_id>.xml` or `<map_id>.json` and replace the builtin geometry of the map.
+    pub fn load(directory: Option<&Path>) -> anyhow::Result<Self> {
+        let mut geometries = BTreeMap::new();
+        for (map_id, payload) in BUILTIN_MAPS {
+            let geometry = MapGeometry::parse_xml(map_id.to_string(), payload)
+                .with_context(|| format!("failed to parse builtin map {}", map_id))?;
+            geometries.insert(map_id.to_string(), Arc::new(geometry));
+        }
+
+        if let Some(directory) = directory {
+            let entries = std::fs::read_dir(directory)
+                .with_context(|| format!("failed to read map directory {}", directory.display()))?;
+
+            for entry in entries {
+                let path = entry?.path();
+                let (map_id, extension) = match (path.file_stem(), path.extension()) {
+                    (Some(map_id), Some(extension)) => (map_id.to_string_lossy(), extension.to_string_lossy()),
+                    _ => continue,
+                };
+
+                if extension != "xml" && extension != "json" {
+                    continue;
+                }
+
+                let payload = std::fs::read_to_string(&path)
+                    .with_context(|| format!("failed to read {}", path.display()))?;
+                let geometry = parse_map_file(map_id.to_string(), &extension, &payload)
+                    .with_context(|| format!("failed to parse {}", path.display()))?;
+
+                debug!("Loaded map {} with {} spawn points.", map_id, geometry.spawn_points.len());
+                geometries.insert(map_id.to_string(), Arc::new(geometry));
+            }
+        }
+
+        info!("Loaded the geometry of {} maps.", geometries.len());
+        Ok(Self { geometries })
+    }
+
+    pub fn geometry(&self, map_id: &str) -> Option<Arc<MapGeometry>> {
+        self.geometries.get(map_id).cloned()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use fost_protocol::codec::{BattleMode, BattleTeam};
+    use nalgebra::Vector3;
+
+    use super::MapGeometry;
+
+    const MAP_XML: &str = r#"
+        <map version="1.0">
+            <static-geometry>
+                <prop library-name="Land" group-name="Ground" name="Plane">
+                    <position><x>-1000</x><y>-1000</y><z>0</z></position>
+                </prop>
+                <prop library-name="Land" group-name="Ground" name="Plane">
+                    <position><x>1000</x><y>1000</y><z>100</z></position>
+                </prop>
+            </static-geometry>
+            <spawn-points>
+                <spawn-point type="dm">
+                    <position><x>1</x><y>2</y><z>3</z></position>
+                    <rotation><z>1.5</z></rotation>
+                </spawn-point>
+                <spawn-point type="red"><position><x>10</x></position></spawn-point>
+                <spawn-point type="blue"><position><x>20</x></position></spawn-point>
+                <spawn-point type="ctf_red"><position><x>30</x></position></spawn-point>
+            </spawn-points>
+            <ctf-flags>
+                <flag-red><x>-500</x><y>0</y><z>50</z></flag-red>
+                <flag-blue><x>500</x><y>0</y><z>50</z></flag-blue>
+            </ctf-flags>
+            <dom-keypoints>
+                <dom-keypoint name="A"><position><x>0</x><y>0</y><z>50</z></position></dom-keypoint>
+            </dom-keypoints>
+            <bonus-regions>
+                <bonus-region name="center">
+                    <bonus-type>medkit</bonus-type>
+                    <game-mode>dm</game-mode>
+                    <min><x>-10</x><y>-10</y><z>0</z></min>
+                    <max><x>10</x><y>10</y><z>0</z></max>
+                </bonus-region>
+            </bonus-regions>
+        </map>
+    "#;
+
+    #[test]
+    fn test_parse_map_xml() {
+        let geometry = MapGeometry::parse_xml("map_test".to_string(), MAP_XML).unwrap();
+        assert_eq!(geometry.spawn_points.len(), 4);
+        assert_eq!(geometry.spawn_points[0].position, Vector3::new(1.0, 2.0, 3.0));
+        assert_eq!(geometry.spawn_points[0].orientation, Vector3::new(0.0, 0.0, 1.5));
+
+        let bounds = geometry.bounds.as_ref().unwrap();
+        assert_eq!(bounds.min, Vector3::new(-1000.0, -1000.0, 0.0));
+        assert_eq!(bounds.max, Vector3::new(1000.0, 1000.0, 100.0));
+
+        /* mode specific spawn points take precedence */
+        let red = geometry.spawn_points(BattleMode::Ctf, BattleTeam::Red);
+        assert_eq!(red.len(), 1);
+        assert_eq!(red[0].position.x, 30.0);
+        assert_eq!(geometry.spawn_points(BattleMode::Ctf, BattleTeam::Blue)[0].position.x, 20.0);
+
+        assert!(geometry.supports_mode(BattleMode::Dm));
+        assert!(geometry.supports_mode(BattleMode::Ctf));
+        assert!(geometry.supports_mode(BattleMode::Cp));
+        assert!(!geometry.supports_mode(BattleMode::As));
+
+        assert_eq!(geometry.bonus_regions(BattleMode::Dm).count(), 1);
+        assert_eq!(geometry.bonus_regions(BattleMode::Tdm).count(), 0);
+    }
+
+    #[test]
+    fn test_parse_map_json() {
+        let payload = r#"{
+            "spawnPoints": [
+                { "team": "RED", "position": { "x": 1, "y": 2, "z": 3 } },
+                { "team": "BLUE", "mode": "AS", "position": { "x": 4, "y": 5, "z": 6 } }
+            ],
+            "assault": {
+                "flags": [ { "x": 0, "y": 0, "z": 0 } ],
+                "bases": [ { "x": 1, "y": 1, "z": 1 } ]
+            }
+        }"#;
+
+        let geometry = MapGeometry::parse_json("map_test".to_string(), payload).unwrap();
+        assert!(geometry.supports_mode(BattleMode::As));
+        assert!(!geometry.supports_mode(BattleMode::Tdm));
+        assert_eq!(geometry.assault_bases.len(), 1);
+    }
+}
@@ -0,0 +1,444 @@
+use std::{collections::BTreeMap, sync::{Arc, RwLock}};
+
+use anyhow::Context;
+use chrono::{DateTime, Duration, Utc};
+use fost_protocol::codec::ChatModeratorLevel;
+use thiserror::Error;
+use tokio::sync::mpsc;
+
+use crate::{ServerChat, server::ServerEvent, config::ConfigHandle, storage::{StorageHandle, model::{self, PunishmentKind}}};
+
+pub fn moderator_level_from_i32(value: i32) -> ChatModeratorLevel {
+    match value {
+        1 => ChatModeratorLevel::CommunityManager,
+        2 => ChatModeratorLevel::Administrator,
+        3 => ChatModeratorLevel::Moderator,
+        4 => ChatModeratorLevel::Candidate,
+        _ => ChatModeratorLevel::None,
+    }
+}
+
+/// Name recorded as issuer of punishments created via the admin API.
+const CONSOLE_ISSUER: &str = "console";
+const CONSOLE_AUTHORITY: u8 = u8::MAX;
+/// Longest punishment which is not permanent (100 years).
+const MAX_DURATION_MINUTES: i64 = 100 * 365 * 24 * 60;
+
+/// Power of a moderator level. Users may only punish users with less authority.
+fn authority(level: ChatModeratorLevel) -> u8 {
+    match level {
+        ChatModeratorLevel::Administrator => 3,
+        ChatModeratorLevel::CommunityManager => 2,
+        ChatModeratorLevel::Moderator => 1,
+        _ => 0,
+    }
+}
+
+/// Parse a duration like `30m`, `12h` or `7d`.
+/// `perm` results in `None` meaning the punishment never expires.
+pub fn parse_duration(value: &str) -> Option<Option<Duration>> {
+    if value == "perm" {
+        return Some(None);
+    }
+
+    let (amount, unit_minutes) = if let Some(amount) = value.strip_suffix('m') {
+        (amount, 1)
+    } else if let Some(amount) = value.strip_suffix('h') {
+        (amount, 60)
+    } else if let Some(amount) = value.strip_suffix('d') {
+        (amount, 24 * 60)
+    } else {
+        return None;
+    };
+
+    let minutes = amount.parse::<i64>().ok()
+        .filter(|amount| *amount > 0)?
+        .checked_mul(unit_minutes)
+        .filter(|minutes| *minutes <= MAX_DURATION_MINUTES)?;
+    Some(Some(Duration::minutes(minutes)))
+}
+
+/// Human readable duration rounded down to the largest unit.
+pub fn format_duration(duration: Duration) -> String {
+    let (amount, unit) = if duration.num_days() > 0 {
+        (duration.num_days(), "day")
+    } else if duration.num_hours() > 0 {
+        (duration.num_hours(), "hour")
+    } else {
+        (duration.num_minutes().max(1), "minute")
+    };
+    format!("{} {}{}", amount, unit, if amount == 1 { "" } else { "s" })
+}
+
+/// Duration of a punishment as used within a sentence.
+fn punishment_duration(duration: Option<Duration>) -> String {
+    match duration {
+        Some(duration) => format!("for {}", format_duration(duration)),
+        None => "permanently".to_string(),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ModerationError {
+    #[error("You are not allowed to use this command.")]
+    NotPermitted,
+    #[error("Unknown command. Available are /ban, /unban, /mute, /unmute, /kick, /clear and /moderator.")]
+    UnknownCommand,
+    #[error("Usage: {0}")]
+    Usage(&'static str),
+    #[error("The user {0} does not exist.")]
+    UnknownUser(String),
+    #[error("The user {0} is not punished.")]
+    NotPunished(String),
+}
+
+/// Chat command of a moderator.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ModerationCommand {
+    /// Ban the user. The ban is permanent if no duration is given.
+    Ban { user_id: String, duration: Option<Duration>, reason: String },
+    Unban { user_id: String },
+    /// Mute the user. The mute is permanent if no duration is given.
+    Mute { user_id: String, duration: Option<Duration>, reason: String },
+    Unmute { user_id: String },
+    Kick { user_id: String, reason: String },
+    /// Remove the messages of the user or the whole chat if no user is given.
+    Clear { user_id: Option<String> },
+    SetModeratorLevel { user_id: String, level: ChatModeratorLevel },
+}
+
+impl ModerationCommand {
+    /// Parse a chat message starting with a slash.
+    /// Returns `None` if the message is not a command.
+    pub fn parse(text: &str) -> Option<Result<Self, ModerationError>> {
+        let text = text.strip_prefix('/')?;
+        let mut arguments = text.split_whitespace();
+        let command = arguments.next().unwrap_or_default();
+        let user_id = arguments.next().map(str::to_string);
+        let arguments = arguments.collect::<Vec<_>>();
+
+        let punishment = |usage: &'static str| {
+            let user_id = user_id.clone().ok_or(ModerationError::Usage(usage))?;
+            let duration = arguments.first()
+                .and_then(|value| parse_duration(value))
+                .ok_or(ModerationError::Usage(usage))?;
+            Ok((user_id, duration, arguments[1..].join(" ")))
+        };
+
+        let result = match command {
+            "ban" => punishment("/ban <user> <30m|12h|7d|perm> [reason]")
+                .map(|(user_id, duration, reason)| Self::Ban { user_id, duration, reason }),
+            "unban" => user_id.ok_or(ModerationError::Usage("/unban <user>"))
+                .map(|user_id| Self::Unban { user_id }),
+            "mute" => punishment("/mute <user> <30m|12h|7d|perm> [reason]")
+                .map(|(user_id, duration, reason)| Self::Mute { user_id, duration, reason }),
+            "unmute" => user_id.ok_or(ModerationError::Usage("/unmute <user>"))
+                .map(|user_id| Self::Unmute { user_id }),
+            "kick" => match user_id {
+                Some(user_id) => Ok(Self::Kick { user_id, reason: arguments.join(" ") }),
+                None => Err(ModerationError::Usage("/kick <user> [reason]")),
+            },
+            "clear" => Ok(Self::Clear { user_id }),
+            "moderator" => {
+                let level = match arguments.first().copied() {
+                    Some("none") => Some(ChatModeratorLevel::None),
+                    Some("candidate") => Some(ChatModeratorLevel::Candidate),
+                    Some("moderator") => Some(ChatModeratorLevel::Moderator),
+                    Some("community_manager") => Some(ChatModeratorLevel::CommunityManager),
+                    Some("administrator") => Some(ChatModeratorLevel::Administrator),
+                    _ => None,
+                };
+                match (user_id, level) {
+                    (Some(user_id), Some(level)) => Ok(Self::SetModeratorLevel { user_id, level }),
+                    _ => Err(ModerationError::Usage("/moderator <user> <none|candidate|moderator|community_manager|administrator>")),
+                }
+            },
+            _ => Err(ModerationError::UnknownCommand),
+        };
+        Some(result)
+    }
+
+    fn target(&self) -> Option<&str> {
+        match self {
+            Self::Ban { user_id, .. } |
+            Self::Unban { user_id } |
+            Self::Mute { user_id, .. } |
+            Self::Unmute { user_id } |
+            Self::Kick { user_id, .. } |
+            Self::SetModeratorLevel { user_id, .. } => Some(user_id),
+            Self::Clear { user_id } => user_id.as_deref(),
+        }
+    }
+}
+
+/// Moderation state of a logged in user.
+#[derive(Debug, Clone)]
+struct ModerationStatus {
+    level: ChatModeratorLevel,
+    mute: Option<model::UserPunishment>,
+}
+
+/// Bans, mutes and moderator levels of the users.
+pub struct Moderation {
+    storage: StorageHandle,
+    config: ConfigHandle,
+    chat: Arc<RwLock<ServerChat>>,
+    server_events: mpsc::UnboundedSender<ServerEvent>,
+
+    /// Status of the users which logged in since the server started.
+    users: RwLock<BTreeMap<String, ModerationStatus>>,
+}
+
+impl Moderation {
+    pub fn new(storage: StorageHandle, config: ConfigHandle, chat: Arc<RwLock<ServerChat>>, server_events: mpsc::UnboundedSender<ServerEvent>) -> Self {
+        Self {
+            storage,
+            config,
+            chat,
+            server_events,
+
+            users: Default::default(),
+        }
+    }
+
+    fn is_administrator(&self, user_id: &str) -> bool {
+        self.config.read()
+            .map(|config| config.moderation.administrators.iter().any(|administrator| administrator == user_id))
+            .unwrap_or(false)
+    }
+
+    fn user_level(&self, user: &model::User) -> ChatModeratorLevel {
+        if self.is_administrator(&user.user_id) {
+            ChatModeratorLevel::Administrator
+        } else {
+            moderator_level_from_i32(user.moderator_level)
+        }
+    }
+
+    /// Load the moderator level and mute of the user.
+    /// Must be called before the user sends chat messages.
+    pub async fn load(&self, user_id: &str) -> anyhow::Result<()> {
+        let user = self.storage.find_user(user_id).await?
+            .context("missing user")?;
+        let mute = self.storage.find_user_punishment(user_id, PunishmentKind::Mute).await?;
+
+        let status = ModerationStatus {
+            level: self.user_level(&user),
+            mute,
+        };
+        self.users.write()
+            .ok()
+            .context("failed to lock the moderation users")?
+            .insert(user_id.to_string(), status);
+        Ok(())
+    }
+
+    fn update_status(&self, user_id: &str, callback: impl FnOnce(&mut ModerationStatus)) {
+        if let Ok(mut users) = self.users.write() {
+            if let Some(status) = users.get_mut(user_id) {
+                callback(status);
+            }
+        }
+    }
+
+    pub fn moderator_level(&self, user_id: &str) -> ChatModeratorLevel {
+        self.users.read()
+            .ok()
+            .and_then(|users| users.get(user_id).map(|status| status.level))
+            .unwrap_or(ChatModeratorLevel::None)
+    }
+
+    /// Mute of the user if it has not expired yet.
+    pub fn active_mute(&self, user_id: &str) -> Option<model::UserPunishment> {
+        let now = Utc::now();
+        self.users.read()
+            .ok()?
+            .get(user_id)?
+            .mute.clone()
+            .filter(|mute| mute.is_active(now))
+    }
+
+    /// Message telling a muted user why the message has not been sent.
+    pub fn mute_message(mute: &model::UserPunishment) -> String {
+        let remaining = mute.timestamp_expires.map(|expires| expires - Utc::now());
+        match remaining {
+            Some(remaining) => format!("You are muted for another {}. Reason: {}", format_duration(remaining), mute.reason),
+            None => format!("You are muted permanently. Reason: {}", mute.reason),
+        }
+    }
+
+    fn system_message(&self, text: String) {
+        if let Ok(mut chat) = self.chat.write() {
+            chat.register_system_message(text, false);
+        }
+    }
+
+    async fn punish(&self, issuer: &str, user_id: &str, kind: PunishmentKind, duration: Option<Duration>, reason: String) -> anyhow::Result<DateTime<Utc>> {
+        let now = Utc::now();
+        let punishment = model::UserPunishment {
+            user_id: user_id.to_string(),
+            kind,
+            reason,
+            issued_by: issuer.to_string(),
+            timestamp_created: now,
+            timestamp_expires: duration.map(|duration| now + duration),
+        };
+        self.storage.set_user_punishment(&punishment).await?;
+
+        if kind == PunishmentKind::Mute {
+            self.update_status(user_id, |status| status.mute = Some(punishment));
+        }
+        Ok(now)
+    }
+
+    /// Execute the command issued by the given user.
+    /// Returns the feedback for the issuer.
+    pub async fn execute(&self, issuer: &str, command: ModerationCommand) -> anyhow::Result<Result<String, ModerationError>> {
+        let issuer_authority = authority(self.moderator_level(issuer));
+        self.execute_with_authority(issuer, issuer_authority, command).await
+    }
+
+    /// Execute a command issued via the admin API.
+    /// The console has more authority than any user.
+    pub async fn execute_console(&self, command: ModerationCommand) -> anyhow::Result<Result<String, ModerationError>> {
+        self.execute_with_authority(CONSOLE_ISSUER, CONSOLE_AUTHORITY, command).await
+    }
+
+    async fn execute_with_authority(&self, issuer: &str, issuer_authority: u8, command: ModerationCommand) -> anyhow::Result<Result<String, ModerationError>> {
+        let required_level = match &command {
+            ModerationCommand::SetModeratorLevel { .. } => ChatModeratorLevel::Administrator,
+            _ => ChatModeratorLevel::Moderator,
+        };
+        if issuer_authority < authority(required_level) {
+            return Ok(Err(ModerationError::NotPermitted));
+        }
+
+        if let Some(target) = command.target() {
+            let user = match self.storage.find_user(target).await? {
+                Some(user) => user,
+                None => return Ok(Err(ModerationError::UnknownUser(target.to_string()))),
+            };
+
+            if target != issuer && authority(self.user_level(&user)) >= issuer_authority {
+                return Ok(Err(ModerationError::NotPermitted));
+            }
+        }
+
+        let reason_or_default = |reason: String| if reason.is_empty() { "No reason given".to_string() } else { reason };
+        let feedback = match command {
+            ModerationCommand::Ban { user_id, duration, reason } => {
+                let reason = reason_or_default(reason);
+                self.punish(issuer, &user_id, PunishmentKind::Ban, duration, reason.clone()).await?;
+                let _ = self.server_events.send(ServerEvent::KickUser {
+                    user_id: user_id.clone(),
+                    reason: format!("You have been banned {}. Reason: {}", punishment_duration(duration), reason),
+                });
+
+                self.system_message(format!("{} has been banned {}.", user_id, punishment_duration(duration)));
+                format!("Banned {} {}.", user_id, punishment_duration(duration))
+            },
+            ModerationCommand::Unban { user_id } => {
+                if !self.storage.remove_user_punishment(&user_id, PunishmentKind::Ban).await? {
+                    return Ok(Err(ModerationError::NotPunished(user_id)));
+                }
+                format!("Unbanned {}.", user_id)
+            },
+            ModerationCommand::Mute { user_id, duration, reason } => {
+                self.punish(issuer, &user_id, PunishmentKind::Mute, duration, reason_or_default(reason)).await?;
+                self.system_message(format!("{} has been muted {}.", user_id, punishment_duration(duration)));
+                format!("Muted {} {}.", user_id, punishment_duration(duration))
+            },
+            ModerationCommand::Unmute { user_id } => {
+                if !self.storage.remove_user_punishment(&user_id, PunishmentKind::Mute).await? {
+                    return Ok(Err(ModerationError::NotPunished(user_id)));
+                }
+                self.update_status(&user_id, |status| status.mute = None);
+                format!("Unmuted {}.", user_id)
+            },
+            ModerationCommand::Kick { user_id, reason } => {
+                let _ = self.server_events.send(ServerEvent::KickUser {
+                    user_id: user_id.clone(),
+                    reason: format!("You have been kicked. Reason: {}", reason_or_default(reason)),
+                });
+                format!("Kicked {}.", user_id)
+            },
+            ModerationCommand::Clear { user_id } => {
+                let mut chat = self.chat.write()
+                    .ok()
+                    .context("failed to accquire server chat")?;
+
+                match user_id {
+                    Some(user_id) => {
+                        chat.clean_user_messages(&user_id);
+                        format!("Removed the messages of {}.", user_id)
+                    },
+                    None => {
+                        chat.clear();
+                        "Cleared the chat.".to_string()
+                    },
+                }
+            },
+            ModerationCommand::SetModeratorLevel { user_id, level } => {
+                self.storage.set_user_moderator_level(&user_id, level as i32).await?;
+                self.update_status(&user_id, |status| status.level = level);
+                format!("Changed the moderator level of {} to {:?}.", user_id, level)
+            },
+        };
+
+        Ok(Ok(feedback))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::Duration;
+    use fost_protocol::codec::ChatModeratorLevel;
+
+    use super::{ModerationCommand, ModerationError, format_duration, parse_duration};
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("30m"), Some(Some(Duration::minutes(30))));
+        assert_eq!(parse_duration("12h"), Some(Some(Duration::hours(12))));
+        assert_eq!(parse_duration("7d"), Some(Some(Duration::days(7))));
+        assert_eq!(parse_duration("perm"), Some(None));
+        assert_eq!(parse_duration("0d"), None);
+        assert_eq!(parse_duration("7w"), None);
+        assert_eq!(parse_duration(""), None);
+        assert_eq!(parse_duration("5ä"), None);
+        assert_eq!(parse_duration("äm"), None);
+        assert_eq!(parse_duration("ä"), None);
+        assert_eq!(parse_duration("99999999999999999d"), None);
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(Duration::days(2)), "2 days");
+        assert_eq!(format_duration(Duration::minutes(90)), "1 hour");
+        assert_eq!(format_duration(Duration::seconds(10)), "1 minute");
+    }
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(ModerationCommand::parse("hello"), None);
+        assert_eq!(
+            ModerationCommand::parse("/mute spammer 30m too many messages"),
+            Some(Ok(ModerationCommand::Mute {
+                user_id: "spammer".to_string(),
+                duration: Some(Duration::minutes(30)),
+                reason: "too many messages".to_string(),
+            }))
+        );
+        assert_eq!(
+            ModerationCommand::parse("/ban cheater perm"),
+            Some(Ok(ModerationCommand::Ban { user_id: "cheater".to_string(), duration: None, reason: "".to_string() }))
+        );
+        assert!(matches!(ModerationCommand::parse("/ban cheater"), Some(Err(ModerationError::Usage(_)))));
+        assert_eq!(
+            ModerationCommand::parse("/moderator friend moderator"),
+            Some(Ok(ModerationCommand::SetModeratorLevel { user_id: "friend".to_string(), level: ChatModeratorLevel::Moderator }))
+        );
+        assert_eq!(ModerationCommand::parse("/clear"), Some(Ok(ModerationCommand::Clear { user_id: None })));
+        assert_eq!(ModerationCommand::parse("/fly"), Some(Err(ModerationError::UnknownCommand)));
+    }
+}
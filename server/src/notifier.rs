@@ -0,0 +1,184 @@
+use std::{collections::{BTreeMap, BTreeSet}, sync::{Arc, RwLock}};
+
+use chrono::{DateTime, Utc};
+use fost_protocol::{codec::{BattleInfoData, BattleNotifierData, OnlineNotifierData, PremiumNotifierData, RankNotifierData}, packets::s2c};
+use tokio::sync::mpsc;
+
+use crate::{SharedPacket, client::ClientId};
+
+pub type UserNotifierHandle = Arc<RwLock<UserNotifier>>;
+
+struct OnlineUser {
+    client_id: ClientId,
+    sender: mpsc::UnboundedSender<SharedPacket>,
+    /// Users whose status will be forwarded to this user.
+    subscriptions: BTreeSet<String>,
+    battle: Option<BattleInfoData>,
+    rank: Option<i32>,
+    premium: Option<DateTime<Utc>>,
+}
+
+/// Keeps track of all online users and notifies
+/// subscribed users about their online and battle status.
+pub struct UserNotifier {
+    server_number: i32,
+    online: BTreeMap<String, OnlineUser>,
+}
+
+impl UserNotifier {
+    pub fn new(server_number: i32) -> Self {
+        Self {
+            server_number,
+            online: BTreeMap::new(),
+        }
+    }
+
+    pub fn is_online(&self, user_id: &str) -> bool {
+        self.online.contains_key(user_id)
+    }
+
+    /// Send a packet to the user if online.
+    pub fn send_to(&self, user_id: &str, packet: SharedPacket) -> bool {
+        match self.online.get(user_id) {
+            Some(user) => user.sender.send(packet).is_ok(),
+            None => false,
+        }
+    }
+
+    fn notify_subscribers(&self, user_id: &str, packet: SharedPacket) {
+        for user in self.online.values().filter(|user| user.subscriptions.contains(user_id)) {
+            let _ = user.sender.send(packet.clone());
+        }
+    }
+
+    fn online_status(&self, user_id: &str, online: bool) -> SharedPacket {
+        Arc::new(s2c::UserNotifyOnlineStatus {
+            user: OnlineNotifierData {
+                online,
+                server_number: self.server_number,
+                user_id: user_id.to_string(),
+            }
+        })
+    }
+
+    /// Register the client of the user as online.
+    /// A previous client of the same user will no longer receive any notifications.
+    pub fn register(&mut self, user_id: &str, client_id: ClientId, sender: mpsc::UnboundedSender<SharedPacket>) {
+        let previous = self.online.insert(user_id.to_string(), OnlineUser {
+            client_id,
+            sender,
+            subscriptions: BTreeSet::new(),
+            battle: None,
+            rank: None,
+            premium: None,
+        });
+
+        if previous.is_none() {
+            self.notify_subscribers(user_id, self.online_status(user_id, true));
+        }
+    }
+
+    /// Remove the user if still registered by the given client.
+    pub fn unregister(&mut self, user_id: &str, client_id: ClientId) {
+        if self.online.get(user_id).map(|user| user.client_id) != Some(client_id) {
+            return;
+        }
+
+        self.online.remove(user_id);
+        self.notify_subscribers(user_id, self.online_status(user_id, false));
+    }
+
+    fn rank_status(user_id: &str, rank: i32) -> SharedPacket {
+        Arc::new(s2c::UserNotifyUserRank {
+            user: RankNotifierData { rank, user_id: user_id.to_string() }
+        })
+    }
+
+    fn premium_status(user_id: &str, premium: Option<DateTime<Utc>>) -> SharedPacket {
+        let time_left = premium.map_or(0, |premium| (premium - Utc::now()).num_seconds().clamp(0, i32::MAX as i64));
+        Arc::new(s2c::UserNotifyPremiumTimeLeft {
+            user: PremiumNotifierData { premium_time_left_in_seconds: time_left as i32, user_id: user_id.to_string() }
+        })
+    }
+
+    /// Subscribe to the status of the target and send its current status.
+    pub fn subscribe(&mut self, user_id: &str, target: &str) {
+        let mut packets = vec![ self.online_status(target, self.is_online(target)) ];
+        if let Some(target_user) = self.online.get(target) {
+            if let Some(rank) = target_user.rank {
+                packets.push(Self::rank_status(target, rank));
+            }
+
+            if target_user.premium.is_some() {
+                packets.push(Self::premium_status(target, target_user.premium));
+            }
+
+            if let Some(battle) = target_user.battle.clone() {
+                packets.push(Arc::new(s2c::UserNotifyInBattle {
+                    user: BattleNotifierData { battle_data: battle, user_id: target.to_string() }
+                }));
+            }
+        }
+
+        let user = match self.online.get_mut(user_id) {
+            Some(user) => user,
+            None => return,
+        };
+
+        user.subscriptions.insert(target.to_string());
+        for packet in packets {
+            let _ = user.sender.send(packet);
+        }
+    }
+
+    pub fn unsubscribe(&mut self, user_id: &str, targets: &[String]) {
+        if let Some(user) = self.online.get_mut(user_id) {
+            for target in targets {
+                user.subscriptions.remove(target);
+            }
+        }
+    }
+
+    /// Update the battle the user is currently in.
+    pub fn set_battle(&mut self, user_id: &str, battle: Option<BattleInfoData>) {
+        let user = match self.online.get_mut(user_id) {
+            Some(user) => user,
+            None => return,
+        };
+
+        user.battle = battle.clone();
+        let packet: SharedPacket = match battle {
+            Some(battle) => Arc::new(s2c::UserNotifyInBattle {
+                user: BattleNotifierData { battle_data: battle, user_id: user_id.to_string() }
+            }),
+            None => Arc::new(s2c::UserNotifyBattleLeave { user_id: user_id.to_string() }),
+        };
+        self.notify_subscribers(user_id, packet);
+    }
+
+    /// Update the rank of the user and notify the subscribers if it changed.
+    pub fn set_rank(&mut self, user_id: &str, rank: i32) {
+        let user = match self.online.get_mut(user_id) {
+            Some(user) => user,
+            None => return,
+        };
+
+        if user.rank.replace(rank) == Some(rank) {
+            return;
+        }
+        self.notify_subscribers(user_id, Self::rank_status(user_id, rank));
+    }
+
+    /// Update the end of the users premium account and notify the subscribers if it changed.
+    pub fn set_premium(&mut self, user_id: &str, premium: Option<DateTime<Utc>>) {
+        let user = match self.online.get_mut(user_id) {
+            Some(user) => user,
+            None => return,
+        };
+
+        if std::mem::replace(&mut user.premium, premium) == premium {
+            return;
+        }
+        self.notify_subscribers(user_id, Self::premium_status(user_id, premium));
+    }
+}
@@ -0,0 +1,335 @@
+use std::{collections::BTreeSet, sync::{Arc, Mutex}};
+
+use anyhow::Context;
+use chrono::{DateTime, Duration, Utc};
+use fost_protocol::{codec::{PremiumAccountAlertCC, PremiumNotifierCC}, packets::s2c};
+
+use crate::{SharedPacket, UserNotifierHandle, config::{ConfigHandle, PremiumConfig}, storage::{StorageHandle, model}};
+
+/// Time limited subscriptions a user can be granted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Subscription {
+    Premium,
+    DoubleCrystals,
+}
+
+impl Subscription {
+    fn until(&self, user: &model::User) -> Option<DateTime<Utc>> {
+        match self {
+            Subscription::Premium => user.premium,
+            Subscription::DoubleCrystals => user.double_crystals,
+        }
+    }
+}
+
+/// Seconds until the given timestamp or zero if it has already passed.
+pub fn seconds_left(until: Option<DateTime<Utc>>, now: DateTime<Utc>) -> i32 {
+    until.map_or(0, |until| (until - now).num_seconds().clamp(0, i32::MAX as i64) as i32)
+}
+
+fn is_active(until: Option<DateTime<Utc>>, now: DateTime<Utc>) -> bool {
+    until.map_or(false, |until| until > now)
+}
+
+/// Crystals a user receives for a battle reward.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CrystalReward {
+    pub reward: i32,
+    /// Additional crystals by the premium account and double crystals.
+    pub premium_bonus: i32,
+    /// Additional crystals by the beginner pass.
+    pub beginner_bonus: i32,
+}
+
+impl CrystalReward {
+    pub fn total(&self) -> i32 {
+        self.reward + self.premium_bonus + self.beginner_bonus
+    }
+}
+
+/// Bonuses applied to the score and crystals a user earns in battles.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RewardBonuses {
+    premium: Option<DateTime<Utc>>,
+    double_crystals: Option<DateTime<Utc>>,
+    beginner_pass: Option<DateTime<Utc>>,
+    config: PremiumConfig,
+}
+
+impl RewardBonuses {
+    pub fn new(user: &model::User, config: &PremiumConfig) -> Self {
+        Self {
+            premium: user.premium,
+            double_crystals: user.double_crystals,
+            beginner_pass: beginner_pass_end(user, config),
+            config: config.clone(),
+        }
+    }
+
+    fn percent_of(amount: i32, percent: i32) -> i32 {
+        (amount as i64 * percent as i64 / 100) as i32
+    }
+
+    /// Score including the bonuses which are active at the given time.
+    pub fn score(&self, amount: i32, now: DateTime<Utc>) -> i32 {
+        let mut score = amount;
+        if is_active(self.premium, now) {
+            score += Self::percent_of(amount, self.config.score_bonus_percent);
+        }
+        if is_active(self.beginner_pass, now) {
+            score += Self::percent_of(amount, self.config.beginner_pass_score_bonus_percent);
+        }
+        score
+    }
+
+    /// Crystals including the bonuses which are active at the given time.
+    /// Double crystals double the base reward.
+    pub fn crystals(&self, amount: i32, now: DateTime<Utc>) -> CrystalReward {
+        let mut reward = CrystalReward { reward: amount, ..Default::default() };
+        if is_active(self.premium, now) {
+            reward.premium_bonus += Self::percent_of(amount, self.config.crystal_bonus_percent);
+        }
+        if is_active(self.double_crystals, now) {
+            reward.premium_bonus += amount;
+        }
+        if is_active(self.beginner_pass, now) {
+            reward.beginner_bonus += Self::percent_of(amount, self.config.beginner_pass_crystal_bonus_percent);
+        }
+        reward
+    }
+}
+
+/// End of the beginner pass of the user or `None` if the beginner pass is disabled.
+pub fn beginner_pass_end(user: &model::User, config: &PremiumConfig) -> Option<DateTime<Utc>> {
+    if config.beginner_pass_days <= 0 {
+        return None;
+    }
+
+    Some(user.timestamp_register + Duration::days(config.beginner_pass_days))
+}
+
+/// Grants premium accounts and double crystals and notifies
+/// online users once their subscriptions expire.
+pub struct Premium {
+    storage: StorageHandle,
+    config: ConfigHandle,
+    notifier: UserNotifierHandle,
+
+    /// Expiry timers which are currently scheduled.
+    timers: Mutex<BTreeSet<(String, Subscription, DateTime<Utc>)>>,
+}
+
+impl Premium {
+    pub fn new(storage: StorageHandle, config: ConfigHandle, notifier: UserNotifierHandle) -> Self {
+        Self {
+            storage,
+            config,
+            notifier,
+
+            timers: Default::default(),
+        }
+    }
+
+    fn settings(&self) -> anyhow::Result<PremiumConfig> {
+        Ok(
+            self.config.read()
+                .ok()
+                .context("failed to read the config")?
+                .premium.clone()
+        )
+    }
+
+    fn init_packet(&self, config: &PremiumConfig, time_left: i32, completed: bool) -> SharedPacket {
+        Arc::new(s2c::AccountPremiumInit {
+            premium_account_alert_cc: PremiumAccountAlertCC {
+                need_show_notification_completion_premium: completed,
+                need_show_welcome_alert: false,
+                reminder_completion_premium_time: Duration::hours(config.reminder_hours).num_seconds() as f32,
+                was_show_alert_for_first_purchase_premium: true,
+                was_show_reminder_completion_premium: false,
+            },
+            premium_notifier_cc: PremiumNotifierCC {
+                life_time_in_seconds: time_left,
+            },
+        })
+    }
+
+    /// Packets describing the subscriptions of the user send once logged in.
+    pub fn login_packets(&self, user: &model::User) -> anyhow::Result<Vec<SharedPacket>> {
+        let config = self.settings()?;
+        let now = Utc::now();
+
+        let mut packets = vec![ self.init_packet(&config, seconds_left(user.premium, now), false) ];
+        let beginner_pass = beginner_pass_end(user, &config);
+        if is_active(beginner_pass, now) {
+            packets.push(Arc::new(s2c::BeginnerPassActivate {
+                life_time_in_seconds_from_current_date_time: seconds_left(beginner_pass, now),
+                crystal_bonus_in_percent: config.beginner_pass_crystal_bonus_percent,
+                score_bonus_in_percent: config.beginner_pass_score_bonus_percent,
+            }));
+        }
+
+        Ok(packets)
+    }
+
+    /// Publish the premium account to the friends of the user and
+    /// schedule the expiry of all active subscriptions.
+    pub fn watch(self: &Arc<Self>, user: &model::User) {
+        let now = Utc::now();
+        if let Ok(mut notifier) = self.notifier.write() {
+            notifier.set_premium(&user.user_id, user.premium.filter(|premium| *premium > now));
+        }
+
+        for subscription in [ Subscription::Premium, Subscription::DoubleCrystals ] {
+            if let Some(until) = subscription.until(user).filter(|until| *until > now) {
+                self.schedule_expiry(&user.user_id, subscription, until);
+            }
+        }
+    }
+
+    /// Extend the subscription of the user by the given duration.
+    /// Returns the new end of the subscription or `None` if the user does not exist.
+    pub async fn grant(self: &Arc<Self>, user_id: &str, subscription: Subscription, duration: Duration) -> anyhow::Result<Option<DateTime<Utc>>> {
+        let user = match self.storage.find_user(user_id).await? {
+            Some(user) => user,
+            None => return Ok(None),
+        };
+
+        let now = Utc::now();
+        let until = subscription.until(&user).filter(|until| *until > now).unwrap_or(now)
+            .checked_add_signed(duration)
+            .context("subscription end out of range")?;
+        let updated = match subscription {
+            Subscription::Premium => self.storage.set_user_premium(user_id, Some(until)).await?,
+            Subscription::DoubleCrystals => self.storage.set_user_double_crystals(user_id, Some(until)).await?,
+        };
+        if !updated {
+            return Ok(None);
+        }
+
+        if let Ok(mut notifier) = self.notifier.write() {
+            match subscription {
+                Subscription::Premium => {
+                    notifier.send_to(user_id, Arc::new(s2c::AccountPremiumActivate { left_time_in_seconds: seconds_left(Some(until), now) }));
+                    notifier.set_premium(user_id, Some(until));
+                },
+                Subscription::DoubleCrystals => {
+                    notifier.send_to(user_id, Arc::new(s2c::AccountInfoDoubleCrystal { enabled: true }));
+                },
+            }
+        }
+
+        self.schedule_expiry(user_id, subscription, until);
+        Ok(Some(until))
+    }
+
+    fn schedule_expiry(self: &Arc<Self>, user_id: &str, subscription: Subscription, until: DateTime<Utc>) {
+        let key = (user_id.to_string(), subscription, until);
+        match self.timers.lock() {
+            Ok(mut timers) => if !timers.insert(key.clone()) {
+                /* already scheduled */
+                return;
+            },
+            Err(_) => return,
+        }
+
+        let premium = self.clone();
+        tokio::spawn(async move {
+            let delay = (until - Utc::now()).to_std().unwrap_or_default();
+            tokio::time::sleep(delay).await;
+
+            if let Ok(mut timers) = premium.timers.lock() {
+                timers.remove(&key);
+            }
+
+            let (user_id, subscription, until) = key;
+            if let Err(error) = premium.expire(&user_id, subscription, until).await {
+                tracing::error!("failed to expire {:?} of {}: {}", subscription, user_id, error);
+            }
+        });
+    }
+
+    async fn expire(&self, user_id: &str, subscription: Subscription, until: DateTime<Utc>) -> anyhow::Result<()> {
+        let user = match self.storage.find_user(user_id).await? {
+            Some(user) => user,
+            None => return Ok(()),
+        };
+
+        if subscription.until(&user) != Some(until) {
+            /* the subscription has been extended in the meantime */
+            return Ok(());
+        }
+
+        let config = self.settings()?;
+        if let Ok(mut notifier) = self.notifier.write() {
+            match subscription {
+                Subscription::Premium => {
+                    notifier.send_to(user_id, self.init_packet(&config, 0, true));
+                    notifier.set_premium(user_id, None);
+                },
+                Subscription::DoubleCrystals => {
+                    notifier.send_to(user_id, Arc::new(s2c::AccountInfoDoubleCrystal { enabled: false }));
+                },
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{Duration, Utc};
+
+    use crate::{config::PremiumConfig, storage::model};
+    use super::{CrystalReward, RewardBonuses, seconds_left};
+
+    fn user(register: chrono::DateTime<Utc>) -> model::User {
+        model::User {
+            user_id: "user".to_string(),
+            email: None,
+            email_confirmed: false,
+            timestamp_register: register,
+            timestamp_active: register,
+            crystals: 0,
+            double_crystals: None,
+            experience: 0,
+            premium: None,
+
+            moderator_level: 0,
+            invite_code: None,
+        }
+    }
+
+    #[test]
+    fn test_seconds_left() {
+        let now = Utc::now();
+        assert_eq!(seconds_left(None, now), 0);
+        assert_eq!(seconds_left(Some(now - Duration::seconds(10)), now), 0);
+        assert_eq!(seconds_left(Some(now + Duration::seconds(10)), now), 10);
+    }
+
+    #[test]
+    fn test_reward_bonuses() {
+        let config = PremiumConfig::default();
+        let now = Utc::now();
+
+        let mut veteran = user(now - Duration::days(30));
+        let bonuses = RewardBonuses::new(&veteran, &config);
+        assert_eq!(bonuses.score(100, now), 100);
+        assert_eq!(bonuses.crystals(100, now), CrystalReward { reward: 100, premium_bonus: 0, beginner_bonus: 0 });
+
+        veteran.premium = Some(now + Duration::hours(1));
+        veteran.double_crystals = Some(now + Duration::hours(1));
+        let bonuses = RewardBonuses::new(&veteran, &config);
+        assert_eq!(bonuses.score(100, now), 150);
+        assert_eq!(bonuses.crystals(100, now).total(), 250);
+        /* the bonuses stop once the subscriptions expire */
+        assert_eq!(bonuses.score(100, now + Duration::hours(2)), 100);
+
+        let beginner = user(now);
+        let bonuses = RewardBonuses::new(&beginner, &config);
+        assert_eq!(bonuses.score(100, now), 120);
+        assert_eq!(bonuses.crystals(100, now), CrystalReward { reward: 100, premium_bonus: 0, beginner_bonus: 20 });
+    }
+}
@@ -0,0 +1,161 @@
+use std::sync::Arc;
+
+use fost_protocol::packets::s2c;
+
+use crate::{Rank, SharedPacket, UserNotifierHandle, StorageTasksHandle, storage::StorageHandle};
+
+/// Position of a user within the experience leaderboard.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LeaderboardEntry {
+    /// Place starting at 1 for the user with the most experience.
+    pub place: i32,
+    /// Percentage of the other users with less experience.
+    pub rating: f32,
+}
+
+impl LeaderboardEntry {
+    /// Create the entry from the amount of users with more experience and the total amount of users.
+    pub fn new(higher: i64, total: i64) -> Self {
+        let others = (total - 1).max(0);
+        let lower = (others - higher).max(0);
+        Self {
+            place: (higher + 1).min(i32::MAX as i64) as i32,
+            rating: if others > 0 { lower as f32 * 100.0 / others as f32 } else { 100.0 },
+        }
+    }
+}
+
+/// Crystals a user receives for advancing from one rank to another.
+/// Skipped ranks will be rewarded as well.
+pub fn rank_up_bonus(old_rank: Rank, new_rank: Rank) -> u32 {
+    Rank::values()
+        .iter()
+        .filter(|rank| rank.value() > old_rank.value() && rank.value() <= new_rank.value())
+        .map(Rank::bonus)
+        .sum()
+}
+
+/// Persists the experience users earn in battles and announces their rank-ups.
+pub struct Progression {
+    storage: StorageHandle,
+    notifier: UserNotifierHandle,
+    storage_tasks: StorageTasksHandle,
+}
+
+impl Progression {
+    pub fn new(storage: StorageHandle, notifier: UserNotifierHandle, storage_tasks: StorageTasksHandle) -> Self {
+        Self {
+            storage,
+            notifier,
+            storage_tasks,
+        }
+    }
+
+    pub async fn leaderboard_entry(&self, experience: i32) -> anyhow::Result<LeaderboardEntry> {
+        let (higher, total) = self.storage.count_users_by_experience(experience).await?;
+        Ok(LeaderboardEntry::new(higher, total))
+    }
+
+    /// Add experience to the account of the user.
+    /// The user receives the updated score and rank, the leaderboard position only on rank-ups.
+    pub fn add_experience(self: &Arc<Self>, user_id: &str, amount: i32) {
+        if amount <= 0 {
+            return;
+        }
+
+        let progression = self.clone();
+        let user_id = user_id.to_string();
+        self.storage_tasks.spawn(async move {
+            if let Err(error) = progression.apply_experience(&user_id, amount).await {
+                tracing::error!("failed to add {} experience to {}: {}", amount, user_id, error);
+            }
+        });
+    }
+
+    async fn apply_experience(&self, user_id: &str, amount: i32) -> anyhow::Result<()> {
+        let experience = match self.storage.add_user_experience(user_id, amount).await? {
+            Some(experience) => experience,
+            None => return Ok(()),
+        };
+
+        let old_rank = Rank::from_score((experience - amount).max(0) as u32);
+        let new_rank = Rank::from_score(experience.max(0) as u32);
+
+        let mut packets: Vec<SharedPacket> = vec![ Arc::new(s2c::AccountRankUpdateScore { score: experience }) ];
+        if new_rank.value() > old_rank.value() {
+            let bonus = rank_up_bonus(old_rank, new_rank) as i32;
+            let crystals = self.storage.add_user_crystals(user_id, bonus).await?;
+
+            packets.push(Arc::new(s2c::AccountRankRankUp {
+                rank: new_rank.value() as i32,
+                score: experience,
+                current_rank_score: new_rank.score() as i32,
+                next_rank_score: new_rank.next_rank().map_or(0, |rank| rank.score() as i32),
+                bonus_crystals: bonus,
+            }));
+            if let Some(crystals) = crystals {
+                packets.push(Arc::new(s2c::AccountRankUpdateCrystals { change_by: crystals }));
+            }
+
+            let entry = self.leaderboard_entry(experience).await?;
+            packets.push(Arc::new(s2c::AccountRankUpdateRating { rating: entry.rating, place: entry.place }));
+        }
+
+        if let Ok(mut notifier) = self.notifier.write() {
+            for packet in packets {
+                notifier.send_to(user_id, packet);
+            }
+            notifier.set_rank(user_id, new_rank.value() as i32);
+        }
+
+        Ok(())
+    }
+
+    /// Send the users their current leaderboard position.
+    /// Counting the leaderboard is too expensive for every kill, battles refresh it at the end of a round.
+    pub fn update_ratings(self: &Arc<Self>, user_ids: Vec<String>) {
+        let progression = self.clone();
+        tokio::spawn(async move {
+            for user_id in user_ids {
+                if let Err(error) = progression.update_rating(&user_id).await {
+                    tracing::error!("failed to update the rating of {}: {}", user_id, error);
+                }
+            }
+        });
+    }
+
+    async fn update_rating(&self, user_id: &str) -> anyhow::Result<()> {
+        let user = match self.storage.find_user(user_id).await? {
+            Some(user) => user,
+            None => return Ok(()),
+        };
+
+        let entry = self.leaderboard_entry(user.experience).await?;
+        if let Ok(notifier) = self.notifier.read() {
+            notifier.send_to(user_id, Arc::new(s2c::AccountRankUpdateRating { rating: entry.rating, place: entry.place }));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::Rank;
+    use super::{LeaderboardEntry, rank_up_bonus};
+
+    #[test]
+    fn test_rank_up_bonus() {
+        assert_eq!(rank_up_bonus(Rank::Recruit, Rank::Private), 10);
+        assert_eq!(rank_up_bonus(Rank::Recruit, Rank::Corporal), 10 + 40 + 120);
+        assert_eq!(rank_up_bonus(Rank::Corporal, Rank::Corporal), 0);
+    }
+
+    #[test]
+    fn test_leaderboard_entry() {
+        assert_eq!(LeaderboardEntry::new(0, 1), LeaderboardEntry { place: 1, rating: 100.0 });
+        assert_eq!(LeaderboardEntry::new(0, 5), LeaderboardEntry { place: 1, rating: 100.0 });
+        assert_eq!(LeaderboardEntry::new(4, 5), LeaderboardEntry { place: 5, rating: 0.0 });
+        assert_eq!(LeaderboardEntry::new(1, 3), LeaderboardEntry { place: 2, rating: 50.0 });
+    }
+}
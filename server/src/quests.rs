@@ -0,0 +1,622 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use chrono::{DateTime, Datelike, Utc};
+use fost_protocol::{codec::{DailyQuestInfo, DailyQuestPrizeInfo, ResourceReference, WeeklyQuestDescription, WeeklyQuestRewardItem}, packets::s2c};
+use rand::seq::SliceRandom;
+use serde::{Serialize, Deserialize};
+use thiserror::Error;
+
+use crate::{UserNotifierHandle, StorageTasksHandle, config::{ConfigHandle, QuestsConfig}, storage::{StorageHandle, model}};
+
+pub static QUESTS_JSON: &'static str = include_str!("../resources/quests.json");
+
+/// Quest ids of a day start at `day * QUEST_ID_DAY_FACTOR`.
+const QUEST_ID_DAY_FACTOR: i32 = 100;
+/// Consecutive days on which all daily quests have to be completed to finish the weekly quest.
+const WEEKLY_STREAK_DAYS: i32 = 7;
+
+/// Battle event a quest counts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum QuestKind {
+    Kills,
+    Score,
+    Flags,
+    Crystals,
+    /// Minutes spent within battles.
+    PlayTime,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QuestPrize {
+    pub name: String,
+    /// Inventory item which will be granted. Crystals if not set.
+    #[serde(default)]
+    pub item_id: Option<String>,
+    pub count: i32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QuestTemplate {
+    pub template_id: String,
+    pub kind: QuestKind,
+    /// Description shown to the user. `{}` will be replaced by the goal.
+    pub description: String,
+    pub image_resource_id: u32,
+    /// Possible goals of which one will be picked when the quest is issued.
+    pub goals: Vec<i32>,
+    pub prizes: Vec<QuestPrize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WeeklyQuestPrize {
+    /// Inventory item which will be granted. Crystals if not set.
+    #[serde(default)]
+    pub item_id: Option<String>,
+    pub count: i32,
+    pub image_resource_id: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WeeklyQuestReward {
+    pub prizes: Vec<WeeklyQuestPrize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WeeklyQuest {
+    pub quest_image_resource_id: u32,
+    pub reward_image_resource_id: u32,
+    /// Reward for completing a week indexed by the weekly quest level.
+    /// Levels beyond the last reward receive the last reward.
+    pub rewards: Vec<WeeklyQuestReward>,
+}
+
+impl WeeklyQuest {
+    pub fn reward(&self, level: i32) -> Option<&WeeklyQuestReward> {
+        let index = usize::try_from(level).unwrap_or(0).min(self.rewards.len().saturating_sub(1));
+        self.rewards.get(index)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QuestCatalog {
+    weekly: WeeklyQuest,
+    quests: Vec<QuestTemplate>,
+}
+
+impl QuestCatalog {
+    pub fn parse(json: &str) -> anyhow::Result<Self> {
+        let catalog: Self = serde_json::from_str(json)?;
+        for (index, quest) in catalog.quests.iter().enumerate() {
+            if catalog.quests[..index].iter().any(|other| other.template_id == quest.template_id) {
+                anyhow::bail!("quest {} has been defined twice", quest.template_id);
+            }
+
+            if quest.goals.is_empty() || quest.goals.iter().any(|goal| *goal <= 0) {
+                anyhow::bail!("quest {} has invalid goals", quest.template_id);
+            }
+
+            if quest.prizes.iter().any(|prize| prize.count <= 0) {
+                anyhow::bail!("quest {} has an invalid prize", quest.template_id);
+            }
+        }
+
+        if catalog.weekly.rewards.is_empty() {
+            anyhow::bail!("the weekly quest has no rewards");
+        }
+
+        if catalog.weekly.rewards.iter().flat_map(|reward| reward.prizes.iter()).any(|prize| prize.count <= 0) {
+            anyhow::bail!("the weekly quest has an invalid prize");
+        }
+
+        Ok(catalog)
+    }
+
+    pub fn weekly(&self) -> &WeeklyQuest {
+        &self.weekly
+    }
+
+    pub fn quests(&self) -> &[QuestTemplate] {
+        &self.quests
+    }
+
+    pub fn find(&self, template_id: &str) -> Option<&QuestTemplate> {
+        self.quests.iter().find(|quest| quest.template_id == template_id)
+    }
+}
+
+/// Index of the quest rotation the timestamp belongs to.
+/// A new rotation starts every day at the reset hour (UTC).
+pub fn quest_day(now: DateTime<Utc>, reset_hour: u32) -> i32 {
+    (now - chrono::Duration::hours(reset_hour.min(23) as i64))
+        .date_naive()
+        .num_days_from_ce()
+}
+
+/// Weekly quest after all daily quests of the day have been completed.
+/// The streak starts over if the previous day has been missed and a finished week raises the level.
+/// Returns the new weekly quest and if the week has been finished.
+pub fn advance_weekly_quest(user_id: &str, current: Option<&model::UserWeeklyQuest>, day: i32) -> (model::UserWeeklyQuest, bool) {
+    let level = current.map_or(0, |current| current.level);
+    let streak = current
+        .filter(|current| current.day == day - 1)
+        .map_or(0, |current| current.streak) + 1;
+
+    let finished = streak >= WEEKLY_STREAK_DAYS;
+    let weekly_quest = model::UserWeeklyQuest {
+        user_id: user_id.to_string(),
+        level: if finished { level.saturating_add(1) } else { level },
+        streak: if finished { 0 } else { streak },
+        day,
+    };
+    (weekly_quest, finished)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum QuestsError {
+    #[error("The quest does not exist any more.")]
+    UnknownQuest,
+    #[error("The quest has not been completed yet.")]
+    NotCompleted,
+    #[error("The prize has already been taken.")]
+    AlreadyRewarded,
+    #[error("You already skipped a quest for free today.")]
+    FreeSkipUsed,
+    #[error("You do not have enough crystals.")]
+    InsufficientCrystals,
+}
+
+/// Result of skipping a quest.
+#[derive(Debug, Clone)]
+pub struct QuestSkip {
+    pub quest: DailyQuestInfo,
+    /// New crystal balance if the skip has been paid.
+    pub crystals: Option<i32>,
+}
+
+/// Result of taking the prize of a quest.
+#[derive(Debug, Clone)]
+pub struct QuestPayout {
+    /// New crystal balance if crystals were part of the prize.
+    pub crystals: Option<i32>,
+    /// Other completed quests are still waiting for their prize to be taken.
+    pub completed_remaining: bool,
+    /// The weekly quest advanced as all daily quests have been completed.
+    pub weekly_quest: Option<WeeklyQuestDescription>,
+    /// Prizes paid out for finishing the week.
+    pub weekly_rewards: Vec<WeeklyQuestRewardItem>,
+}
+
+/// Issues the daily quests, tracks their progress and pays out their prizes.
+pub struct Quests {
+    catalog: QuestCatalog,
+    storage: StorageHandle,
+    config: ConfigHandle,
+    notifier: UserNotifierHandle,
+    storage_tasks: StorageTasksHandle,
+}
+
+impl Quests {
+    pub fn new(catalog: QuestCatalog, storage: StorageHandle, config: ConfigHandle, notifier: UserNotifierHandle, storage_tasks: StorageTasksHandle) -> Self {
+        Self {
+            catalog,
+            storage,
+            config,
+            notifier,
+            storage_tasks,
+        }
+    }
+
+    pub fn catalog(&self) -> &QuestCatalog {
+        &self.catalog
+    }
+
+    fn settings(&self) -> anyhow::Result<QuestsConfig> {
+        Ok(
+            self.config.read()
+                .ok()
+                .context("failed to read the config")?
+                .quests.clone()
+        )
+    }
+
+    /// Create a new quest preferring templates which are not excluded.
+    fn create_quest(&self, user_id: &str, quest_id: i32, day: i32, exclude: &[&str]) -> Option<model::UserQuest> {
+        let mut rng = rand::thread_rng();
+        let candidates = self.catalog.quests.iter()
+            .filter(|quest| !exclude.contains(&quest.template_id.as_str()))
+            .collect::<Vec<_>>();
+        let template = match candidates.choose(&mut rng) {
+            Some(template) => *template,
+            None => self.catalog.quests.choose(&mut rng)?,
+        };
+
+        Some(model::UserQuest {
+            user_id: user_id.to_string(),
+            quest_id,
+            template_id: template.template_id.clone(),
+            goal: *template.goals.choose(&mut rng)?,
+            progress: 0,
+            day,
+            can_skip_free: true,
+            rewarded: false,
+        })
+    }
+
+    /// Load the quests of the user and issue new quests if the daily rotation has passed.
+    pub async fn load(&self, user_id: &str) -> anyhow::Result<Vec<model::UserQuest>> {
+        let settings = self.settings()?;
+        let day = quest_day(Utc::now(), settings.reset_hour);
+
+        let mut quests = self.storage.find_user_quests(user_id).await?;
+        if quests.is_empty() || quests.iter().any(|quest| quest.day != day) {
+            quests.clear();
+            for index in 0..settings.daily_count.min(QUEST_ID_DAY_FACTOR as usize) {
+                let exclude = quests.iter()
+                    .map(|quest: &model::UserQuest| quest.template_id.as_str())
+                    .collect::<Vec<_>>();
+                let quest = match self.create_quest(user_id, day * QUEST_ID_DAY_FACTOR + index as i32, day, &exclude) {
+                    Some(quest) => quest,
+                    None => break,
+                };
+                quests.push(quest);
+            }
+
+            self.storage.set_user_quests(user_id, &quests).await?;
+        }
+
+        quests.sort_by_key(|quest| quest.quest_id);
+        Ok(quests)
+    }
+
+    /// Check if the user has completed quests whose prize has not been taken yet.
+    pub async fn has_completed(&self, user_id: &str) -> anyhow::Result<bool> {
+        let quests = self.load(user_id).await?;
+        Ok(quests.iter().any(|quest| !quest.rewarded && quest.progress >= quest.goal))
+    }
+
+    pub fn quest_info(&self, quest: &model::UserQuest) -> Option<DailyQuestInfo> {
+        let template = self.catalog.find(&quest.template_id)?;
+        Some(DailyQuestInfo {
+            can_skip_for_free: quest.can_skip_free,
+            description: template.description.replace("{}", &quest.goal.to_string()),
+            finish_criteria: quest.goal,
+            image: ResourceReference { resource_id: template.image_resource_id },
+            prizes: template.prizes.iter()
+                .map(|prize| DailyQuestPrizeInfo { count: prize.count, name: prize.name.clone() })
+                .collect(),
+            progress: quest.progress,
+            quest_id: quest.quest_id,
+        })
+    }
+
+    pub fn weekly_description(&self, weekly_quest: Option<&model::UserWeeklyQuest>, done_for_today: bool, day: i32) -> WeeklyQuestDescription {
+        /* the streak has been broken if the previous day has been missed */
+        let current_quest_streak = weekly_quest
+            .filter(|weekly_quest| weekly_quest.day >= day - 1)
+            .map_or(0, |weekly_quest| weekly_quest.streak);
+
+        WeeklyQuestDescription {
+            current_quest_level: weekly_quest.map_or(0, |weekly_quest| weekly_quest.level),
+            current_quest_streak,
+            done_for_today,
+            quest_image: ResourceReference { resource_id: self.catalog.weekly.quest_image_resource_id },
+            reward_image: ResourceReference { resource_id: self.catalog.weekly.reward_image_resource_id },
+        }
+    }
+
+    /// Load the quests of the user for the quest window.
+    pub async fn window(&self, user_id: &str) -> anyhow::Result<s2c::QuestsShowWindow> {
+        let quests = self.load(user_id).await?;
+        let weekly_quest = self.storage.find_user_weekly_quest(user_id).await?;
+        let day = quest_day(Utc::now(), self.settings()?.reset_hour);
+        let done_for_today = !quests.is_empty() && quests.iter().all(|quest| quest.rewarded);
+
+        Ok(s2c::QuestsShowWindow {
+            quests: quests.iter()
+                .filter(|quest| !quest.rewarded)
+                .filter_map(|quest| self.quest_info(quest))
+                .collect(),
+            weekly_quest_description: self.weekly_description(weekly_quest.as_ref(), done_for_today, day),
+        })
+    }
+
+    /// Replace a quest with a new one.
+    /// Every day one quest can be skipped for free, all other skips have to be paid.
+    pub async fn skip(&self, user_id: &str, quest_id: i32, paid: bool) -> anyhow::Result<Result<QuestSkip, QuestsError>> {
+        let quests = self.load(user_id).await?;
+        let quest = match quests.iter().find(|quest| quest.quest_id == quest_id && !quest.rewarded) {
+            Some(quest) => quest,
+            None => return Ok(Err(QuestsError::UnknownQuest)),
+        };
+
+        if !paid && !quest.can_skip_free {
+            return Ok(Err(QuestsError::FreeSkipUsed));
+        }
+
+        let exclude = quests.iter()
+            .map(|quest| quest.template_id.as_str())
+            .collect::<Vec<_>>();
+        let next_quest_id = quests.iter().map(|quest| quest.quest_id).max().unwrap_or(quest_id) + 1;
+        let mut new_quest = match self.create_quest(user_id, next_quest_id, quest.day, &exclude) {
+            Some(quest) => quest,
+            None => return Ok(Err(QuestsError::UnknownQuest)),
+        };
+        new_quest.can_skip_free = paid && quest.can_skip_free;
+
+        let price = self.settings()?.skip_price.max(0);
+        let crystals = if paid {
+            match self.storage.add_user_crystals(user_id, -price).await? {
+                Some(crystals) => Some(crystals),
+                None => return Ok(Err(QuestsError::InsufficientCrystals)),
+            }
+        } else {
+            None
+        };
+
+        if !self.storage.replace_user_quest(user_id, quest_id, &new_quest, !paid).await? {
+            if paid {
+                self.storage.add_user_crystals(user_id, price).await?;
+            }
+            return Ok(Err(QuestsError::UnknownQuest));
+        }
+
+        let quest = self.quest_info(&new_quest)
+            .context("missing template of the new quest")?;
+        Ok(Ok(QuestSkip { quest, crystals }))
+    }
+
+    /// Mark a completed quest as rewarded and pay out its prizes.
+    pub async fn take_prize(&self, user_id: &str, quest_id: i32) -> anyhow::Result<Result<QuestPayout, QuestsError>> {
+        let quests = self.load(user_id).await?;
+        let quest = match quests.iter().find(|quest| quest.quest_id == quest_id) {
+            Some(quest) => quest,
+            None => return Ok(Err(QuestsError::UnknownQuest)),
+        };
+
+        if quest.rewarded {
+            return Ok(Err(QuestsError::AlreadyRewarded));
+        }
+
+        if quest.progress < quest.goal {
+            return Ok(Err(QuestsError::NotCompleted));
+        }
+
+        let template = match self.catalog.find(&quest.template_id) {
+            Some(template) => template,
+            None => return Ok(Err(QuestsError::UnknownQuest)),
+        };
+
+        if !self.storage.set_user_quest_rewarded(user_id, quest_id).await? {
+            return Ok(Err(QuestsError::AlreadyRewarded));
+        }
+
+        let mut crystals = None;
+        for prize in template.prizes.iter() {
+            match &prize.item_id {
+                Some(item_id) => {
+                    self.storage.add_user_item(user_id, item_id, prize.count).await?;
+                },
+                None => {
+                    crystals = self.storage.add_user_crystals(user_id, prize.count).await?.or(crystals);
+                }
+            }
+        }
+
+        let completed_remaining = quests.iter()
+            .any(|other| other.quest_id != quest_id && !other.rewarded && other.progress >= other.goal);
+        let mut payout = QuestPayout {
+            crystals,
+            completed_remaining,
+            weekly_quest: None,
+            weekly_rewards: Vec::new(),
+        };
+
+        /* reload the quests as the other quests of the day might have been rewarded concurrently */
+        let day = quest.day;
+        let done_for_today = self.storage.find_user_quests(user_id).await?
+            .iter()
+            .filter(|quest| quest.day == day)
+            .all(|quest| quest.rewarded);
+        if done_for_today {
+            self.advance_weekly_quest(user_id, day, &mut payout).await?;
+        }
+
+        Ok(Ok(payout))
+    }
+
+    async fn advance_weekly_quest(&self, user_id: &str, day: i32, payout: &mut QuestPayout) -> anyhow::Result<()> {
+        let current = self.storage.find_user_weekly_quest(user_id).await?;
+        let (weekly_quest, finished) = advance_weekly_quest(user_id, current.as_ref(), day);
+        if !self.storage.advance_user_weekly_quest(&weekly_quest).await? {
+            /* already advanced today */
+            return Ok(());
+        }
+
+        if finished {
+            let level = current.map_or(0, |current| current.level);
+            for prize in self.catalog.weekly.reward(level).map_or(&[][..], |reward| &reward.prizes) {
+                match &prize.item_id {
+                    Some(item_id) => {
+                        self.storage.add_user_item(user_id, item_id, prize.count).await?;
+                    },
+                    None => {
+                        payout.crystals = self.storage.add_user_crystals(user_id, prize.count).await?.or(payout.crystals);
+                    }
+                }
+
+                payout.weekly_rewards.push(WeeklyQuestRewardItem {
+                    count: prize.count,
+                    item_image: ResourceReference { resource_id: prize.image_resource_id },
+                });
+            }
+        }
+
+        payout.weekly_quest = Some(self.weekly_description(Some(&weekly_quest), true, day));
+        Ok(())
+    }
+
+    /// Add progress to all quests of the given kind.
+    /// The user will be notified once a quest has been completed.
+    pub fn record(self: &Arc<Self>, user_id: &str, kind: QuestKind, amount: i32) {
+        if amount <= 0 {
+            return;
+        }
+
+        let quests = self.clone();
+        let user_id = user_id.to_string();
+        self.storage_tasks.spawn(async move {
+            if let Err(error) = quests.record_progress(&user_id, kind, amount).await {
+                tracing::error!("failed to record the quest progress of {}: {}", user_id, error);
+            }
+        });
+    }
+
+    async fn record_progress(&self, user_id: &str, kind: QuestKind, amount: i32) -> anyhow::Result<()> {
+        let day = quest_day(Utc::now(), self.settings()?.reset_hour);
+        let mut completed = false;
+        for quest in self.storage.find_user_quests(user_id).await? {
+            if quest.day != day || quest.rewarded || quest.progress >= quest.goal {
+                continue;
+            }
+
+            if self.catalog.find(&quest.template_id).map(|template| template.kind) != Some(kind) {
+                continue;
+            }
+
+            if let Some(progress) = self.storage.add_user_quest_progress(user_id, quest.quest_id, amount).await? {
+                completed |= progress >= quest.goal;
+            }
+        }
+
+        if completed {
+            if let Ok(notifier) = self.notifier.read() {
+                notifier.send_to(user_id, Arc::new(s2c::QuestsNotifyCompleted {}));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, RwLock};
+
+    use chrono::{TimeZone, Utc};
+
+    use crate::{UserNotifier, config::ServerConfig, storage::{MemoryStorage, StorageHandle, model}, tasks::StorageTasks};
+    use super::{Quests, QuestCatalog, QuestKind, QUESTS_JSON, advance_weekly_quest, quest_day};
+
+    #[test]
+    fn test_parse_catalog() {
+        let catalog = QuestCatalog::parse(QUESTS_JSON).expect("a valid quest catalog");
+        for kind in [ QuestKind::Kills, QuestKind::Score, QuestKind::Flags, QuestKind::Crystals, QuestKind::PlayTime ] {
+            assert!(catalog.quests().iter().any(|quest| quest.kind == kind), "missing quest for {:?}", kind);
+        }
+    }
+
+    #[test]
+    fn test_quest_day() {
+        let before_reset = Utc.with_ymd_and_hms(2023, 6, 2, 5, 59, 0).unwrap();
+        let after_reset = Utc.with_ymd_and_hms(2023, 6, 2, 6, 0, 0).unwrap();
+        assert_eq!(quest_day(before_reset, 6) + 1, quest_day(after_reset, 6));
+        assert_eq!(quest_day(before_reset, 0), quest_day(after_reset, 0));
+    }
+
+    #[test]
+    fn test_advance_weekly_quest() {
+        let (weekly_quest, finished) = advance_weekly_quest("user", None, 10);
+        assert_eq!((weekly_quest.level, weekly_quest.streak, weekly_quest.day, finished), (0, 1, 10, false));
+
+        /* a missed day starts the streak over */
+        let (weekly_quest, _) = advance_weekly_quest("user", Some(&weekly_quest), 12);
+        assert_eq!((weekly_quest.level, weekly_quest.streak), (0, 1));
+
+        let mut weekly_quest = weekly_quest;
+        for day in 13..18 {
+            let (advanced, finished) = advance_weekly_quest("user", Some(&weekly_quest), day);
+            assert!(!finished);
+            weekly_quest = advanced;
+        }
+
+        let (weekly_quest, finished) = advance_weekly_quest("user", Some(&weekly_quest), 18);
+        assert_eq!((weekly_quest.level, weekly_quest.streak, finished), (1, 0, true));
+    }
+
+    #[tokio::test]
+    async fn test_weekly_quest_reward() -> anyhow::Result<()> {
+        let storage: StorageHandle = Arc::new(MemoryStorage::new());
+        storage.create_user(
+            &model::User {
+                user_id: "user".to_string(),
+                email: None,
+                email_confirmed: false,
+                timestamp_register: Utc::now(),
+                timestamp_active: Utc::now(),
+                crystals: 0,
+                double_crystals: None,
+                experience: 0,
+                premium: None,
+
+                moderator_level: 0,
+                invite_code: None,
+            },
+            &model::UserAuthentication {
+                user_id: "user".to_string(),
+                login_user: "user".to_string(),
+                password_hash: String::new(),
+                password_salt: String::new(),
+            },
+            &[]
+        ).await?;
+
+        let quests = Quests::new(
+            QuestCatalog::parse(QUESTS_JSON)?,
+            storage.clone(),
+            Arc::new(RwLock::new(ServerConfig::default())),
+            Arc::new(RwLock::new(UserNotifier::new(0))),
+            Arc::new(StorageTasks::default()),
+        );
+
+        let mut user_quests = quests.load("user").await?;
+        for quest in user_quests.iter_mut() {
+            quest.progress = quest.goal;
+        }
+        storage.set_user_quests("user", &user_quests).await?;
+
+        /* the previous six days have been completed */
+        let day = user_quests[0].day;
+        storage.advance_user_weekly_quest(&model::UserWeeklyQuest {
+            user_id: "user".to_string(),
+            level: 0,
+            streak: 6,
+            day: day - 1,
+        }).await?;
+
+        let (last, others) = user_quests.split_last().unwrap();
+        for quest in others {
+            let payout = quests.take_prize("user", quest.quest_id).await?.unwrap();
+            assert!(payout.weekly_quest.is_none());
+        }
+
+        let payout = quests.take_prize("user", last.quest_id).await?.unwrap();
+        let weekly_quest = payout.weekly_quest.expect("the weekly quest advanced");
+        assert_eq!((weekly_quest.current_quest_level, weekly_quest.current_quest_streak), (1, 0));
+        assert!(weekly_quest.done_for_today);
+        assert!(!payout.weekly_rewards.is_empty());
+
+        let window = quests.window("user").await?;
+        assert!(window.quests.is_empty());
+        assert!(window.weekly_quest_description.done_for_today);
+        assert_eq!(window.weekly_quest_description.current_quest_level, 1);
+        Ok(())
+    }
+}
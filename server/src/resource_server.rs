@@ -0,0 +1,111 @@
+use std::{net::SocketAddr, path::{Path, PathBuf}, sync::Arc};
+
+use anyhow::Context;
+use axum::{Router, routing::get, extract::{self, State}, http::{StatusCode, header}, response::{IntoResponse, Response}};
+use fost_protocol::resources::build_resource_path;
+use tokio::task::JoinHandle;
+use tracing::info;
+
+/// Map the requested path onto a file within the resource directory.
+/// Only paths following the resource layout (`<id parts>/<octal version>/<file name>`) are accepted.
+pub fn resource_file_path(directory: &Path, path: &str) -> Option<PathBuf> {
+    let segments = path.trim_start_matches('/').split('/').collect::<Vec<_>>();
+    let [id_high, id_mid, id_byte_1, id_byte_0, version, file_name] = segments.as_slice() else {
+        return None;
+    };
+
+    let resource_id = (id_high.parse::<u32>().ok()? as u64) << 32
+        | (id_mid.parse::<u16>().ok()? as u64) << 16
+        | (id_byte_1.parse::<u8>().ok()? as u64) << 8
+        | id_byte_0.parse::<u8>().ok()? as u64;
+    let version = u64::from_str_radix(version, 8).ok()?;
+
+    /* reject non canonical paths like leading zeros or signs */
+    let resource_path = build_resource_path(resource_id, version);
+    if resource_path != segments[..5].join("/") {
+        return None;
+    }
+
+    let valid_file_name = !file_name.is_empty()
+        && !file_name.starts_with('.')
+        && file_name.chars().all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '_' || c == '-');
+    if !valid_file_name {
+        return None;
+    }
+
+    Some(directory.join(resource_path).join(file_name))
+}
+
+fn content_type(path: &Path) -> &'static str {
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("png") => "image/png",
+        Some("swf") => "application/x-shockwave-flash",
+        Some("mp3") => "audio/mpeg",
+        Some("xml") => "application/xml",
+        Some("json") => "application/json",
+        Some("txt") => "text/plain",
+        _ => "application/octet-stream",
+    }
+}
+
+async fn serve_resource(State(directory): State<Arc<PathBuf>>, extract::Path(path): extract::Path<String>) -> Response {
+    let file_path = match resource_file_path(&directory, &path) {
+        Some(file_path) => file_path,
+        None => return StatusCode::NOT_FOUND.into_response(),
+    };
+
+    match tokio::fs::read(&file_path).await {
+        Ok(payload) => ([(header::CONTENT_TYPE, content_type(&file_path))], payload).into_response(),
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => StatusCode::NOT_FOUND.into_response(),
+        Err(error) => {
+            tracing::warn!("failed to read resource {}: {}", file_path.display(), error);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Serve the resource files from the directory so the client can be used without an external resource host.
+pub fn spawn_resource_server(address: SocketAddr, directory: PathBuf) -> anyhow::Result<JoinHandle<()>> {
+    let router = Router::new()
+        .route("/*path", get(serve_resource))
+        .with_state(Arc::new(directory.clone()));
+
+    let http_server = axum::Server::try_bind(&address)
+        .with_context(|| format!("failed to bind the resource server to {}", address))?
+        .serve(router.into_make_service());
+    info!("Resource server started on {} serving {}", address, directory.display());
+
+    Ok(tokio::spawn(async move {
+        if let Err(error) = http_server.await {
+            tracing::error!("resource server error: {}", error);
+        }
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::{Path, PathBuf};
+
+    use super::resource_file_path;
+
+    #[test]
+    fn test_resource_file_path() {
+        let directory = Path::new("files");
+        assert_eq!(
+            resource_file_path(directory, "0/21/74/116/12/image.jpg"),
+            Some(PathBuf::from("files/0/21/74/116/12/image.jpg"))
+        );
+        assert_eq!(
+            resource_file_path(directory, "/0/21/74/116/1/library.swf"),
+            Some(PathBuf::from("files/0/21/74/116/1/library.swf"))
+        );
+
+        assert_eq!(resource_file_path(directory, "0/21/74/116/9/image.jpg"), None);
+        assert_eq!(resource_file_path(directory, "0/021/74/116/1/image.jpg"), None);
+        assert_eq!(resource_file_path(directory, "0/21/74/256/1/image.jpg"), None);
+        assert_eq!(resource_file_path(directory, "0/21/74/116/1/../../secret"), None);
+        assert_eq!(resource_file_path(directory, "0/21/74/116/1/.."), None);
+        assert_eq!(resource_file_path(directory, "0/21/74/116/1"), None);
+    }
+}
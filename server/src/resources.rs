@@ -1,39 +1,75 @@
 use std::{path::Path, fs::File, collections::BTreeMap, sync::Arc};
 
 use anyhow::Context;
+use fost_protocol::resources::{Resource, ResourceInfo};
 
-use crate::server::Server;
+use crate::{server::Server, config::ResourcesConfig};
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
 pub enum ResourceStage {
     Connect,
     Auth,
     Lobby,
+    /// Loaded on demand before joining a battle on the map.
+    Map(String),
+    /// Loaded on demand before opening the garage containing the item or kit.
+    GarageItem(String),
 }
 
-type ResourceId = u64;
+pub type ResourceId = u64;
 pub struct ServerResources {
     resource_stage: BTreeMap<ResourceStage, Vec<ResourceId>>,
     resources: BTreeMap<ResourceId, Arc<ServerResource>>,
 }
 
-macro_rules! load_resources_file {
-    ($filename:literal) => {{
-        let resources = serde_json::from_str::<Vec<json::Resource>>(
-            include_str!($filename)
-        )?;
-
-        resources.into_iter()
-            .map(|res| ServerResource::from_json_resource(res))
-            .try_collect::<Vec<_>>()?
-            .into_iter()
-            .map(|res| Arc::new(res))
-            .collect::<Vec<_>>()
-    }};
+fn load_resources_file(path: Option<&Path>, default: &'static str) -> anyhow::Result<Vec<Arc<ServerResource>>> {
+    let resources = match path {
+        Some(path) => {
+            let payload = std::fs::read_to_string(path)
+                .with_context(|| format!("failed to read {}", path.display()))?;
+
+            serde_json::from_str::<Vec<Resource>>(&payload)?
+        },
+        None => serde_json::from_str::<Vec<Resource>>(default)?
+    };
+
+    let resources = resources.into_iter()
+        .map(|res| ServerResource::from_json_resource(res))
+        .try_collect::<Vec<_>>()?
+        .into_iter()
+        .map(|res| Arc::new(res))
+        .collect::<Vec<_>>();
+
+    Ok(resources)
+}
+
+/// Load a registry file containing the resources of multiple on demand groups keyed by their id.
+fn load_resource_groups_file(path: Option<&Path>, default: &'static str) -> anyhow::Result<BTreeMap<String, Vec<Arc<ServerResource>>>> {
+    let groups = match path {
+        Some(path) => {
+            let payload = std::fs::read_to_string(path)
+                .with_context(|| format!("failed to read {}", path.display()))?;
+
+            serde_json::from_str::<BTreeMap<String, Vec<Resource>>>(&payload)?
+        },
+        None => serde_json::from_str::<BTreeMap<String, Vec<Resource>>>(default)?
+    };
+
+    let mut result = BTreeMap::new();
+    for (group, resources) in groups {
+        let resources = resources.into_iter()
+            .map(|res| ServerResource::from_json_resource(res).map(Arc::new))
+            .try_collect::<Vec<_>>()
+            .with_context(|| format!("invalid resource in group {}", group))?;
+
+        result.insert(group, resources);
+    }
+
+    Ok(result)
 }
 
 impl ServerResources {
-    pub fn new() -> anyhow::Result<Self> {
+    pub fn new(config: &ResourcesConfig) -> anyhow::Result<Self> {
         let mut result = Self {
             resource_stage: Default::default(),
             resources: Default::default()
@@ -41,19 +77,29 @@ impl ServerResources {
         
         result.register_resources_for_stage(
             ResourceStage::Connect,
-            load_resources_file!("../resources/registry/connect.json") 
+            load_resources_file(config.registry_connect.as_deref(), include_str!("../resources/registry/connect.json"))?
         );
 
         result.register_resources_for_stage(
             ResourceStage::Auth,
-            load_resources_file!("../resources/registry/auth.json") 
+            load_resources_file(config.registry_auth.as_deref(), include_str!("../resources/registry/auth.json"))?
         );
         
         result.register_resources_for_stage(
             ResourceStage::Lobby,
-            load_resources_file!("../resources/registry/lobby.json") 
+            load_resources_file(config.registry_lobby.as_deref(), include_str!("../resources/registry/lobby.json"))?
         );
 
+        let maps = load_resource_groups_file(config.registry_maps.as_deref(), include_str!("../resources/registry/maps.json"))?;
+        for (map_id, resources) in maps {
+            result.register_resources_for_stage(ResourceStage::Map(map_id), resources);
+        }
+
+        let garage_items = load_resource_groups_file(config.registry_garage.as_deref(), include_str!("../resources/registry/garage.json"))?;
+        for (item_id, resources) in garage_items {
+            result.register_resources_for_stage(ResourceStage::GarageItem(item_id), resources);
+        }
+
         Ok(result)
     }
 
@@ -70,13 +116,14 @@ impl ServerResources {
         );
     }
 
-    pub fn get_resources(&self, stage: ResourceStage) -> Vec<Arc<ServerResource>> {
-        let resource_ids = match self.resource_stage.get(&stage) {
+    /// Resources of the stage. Stages without any resources (e.g. maps without
+    /// own resources) return an empty list.
+    pub fn get_resources(&self, stage: &ResourceStage) -> Vec<Arc<ServerResource>> {
+        let resource_ids = match self.resource_stage.get(stage) {
             Some(resource_ids) => resource_ids,
             None => return vec![],
         };
 
-        return vec![]; // For testing do not load any resources
         resource_ids.iter()
             .filter_map(|res_id| self.resources.get(&res_id))
             .cloned()
@@ -91,159 +138,17 @@ pub struct ServerResource {
     pub info: ResourceInfo,
 }
 
-#[derive(Debug)]
-pub enum ResourceInfo {
-    SwfLibrary,
-    A3D,
-    MovieClip,
-    Sound,
-    Model3DS,
-
-    Map,
-    PropLib,
-
-    Image { alpha: bool },
-    MultiframeImage { fps: u32, height: u32, width: u32, frames: u32 },
-    LocalizedImage {  file_names: Vec<String>, alpha: bool },
-
-    Tanks3DS
-    // this.resourceRegistry.registerTypeClasses(MapResource.TYPE,MapResource);
-    // this.resourceRegistry.registerTypeClasses(PropLibResource.TYPE,PropLibResource);
-    // this.resourceRegistry.registerTypeClasses(Tanks3DSResource.TYPE,Tanks3DSResource);
-}
-
 impl ServerResource {
-    pub fn from_json_resource(resource: json::Resource) -> anyhow::Result<Self> {
-        let info = match resource.resource_type {
-            1 => ResourceInfo::SwfLibrary,
-            2 => ResourceInfo::A3D,
-            3 => ResourceInfo::MovieClip,
-            4 => ResourceInfo::Sound,
-            
-            7 => ResourceInfo::Map,
-            8 => ResourceInfo::PropLib,
-
-            9 => ResourceInfo::Model3DS,
-            10 => ResourceInfo::Image {
-                alpha: resource.alpha.context("missing alpha flag")?
-            },
-            11 => ResourceInfo::MultiframeImage { 
-                fps: resource.fps.context("missing fps")?,
-                height: resource.height.context("missing height")?,
-                width: resource.weight.context("missing width")?,
-                frames: resource.num_frames.context("missing frames")?
-            },
-            13 => ResourceInfo::LocalizedImage { 
-                file_names: resource.file_names.context("missing filenames")?, 
-                alpha: resource.alpha.context("missing alpha flag")?
-            },
-            17 => ResourceInfo::Tanks3DS,
-            _ => anyhow::bail!("unknown resource type {}", resource.resource_type)
-        };
-
+    pub fn from_json_resource(resource: Resource) -> anyhow::Result<Self> {
         Ok(Self {
-            id: (resource.idhigh.parse::<u64>()? << 32) | (resource.idlow as u64),
-            version: (resource.versionhigh.parse::<u64>()? << 32) | (resource.versionlow as u64),
+            id: resource.id()?,
+            version: resource.version()?,
             lazy: resource.lazy,
-            info
+            info: resource.info()?,
         })
     }
 
-    pub fn as_json_resource(&self) -> json::Resource {
-        let mut response = json::Resource::default();
-
-        response.idhigh = format!("{}", self.id >> 32);
-        response.idlow = (self.id & 0xFFFFFFFF) as u32;
-
-        response.versionhigh = format!("{}", self.version >> 32);
-        response.versionlow = (self.version & 0xFFFFFFFF) as u32;
-
-        response.lazy = self.lazy;
-        match &self.info {
-            ResourceInfo::SwfLibrary => {
-                response.resource_type = 1;
-            },
-            ResourceInfo::A3D => {
-                response.resource_type = 2;
-            },
-            ResourceInfo::MovieClip => {
-                response.resource_type = 3;
-            },
-            ResourceInfo::Sound => {
-                response.resource_type = 4;
-            },
-            ResourceInfo::Map => {
-                response.resource_type = 7;
-            },
-            ResourceInfo::PropLib => {
-                response.resource_type = 8;
-            },
-            ResourceInfo::Model3DS => {
-                response.resource_type = 9;
-            },
-            ResourceInfo::Image { alpha } => {
-                response.resource_type = 10;
-                response.alpha = Some(*alpha);
-            },
-            ResourceInfo::MultiframeImage { fps, height, width, frames } => {
-                response.resource_type = 11;
-                response.fps = Some(*fps);
-                response.height = Some(*height);
-                response.weight = Some(*width);
-                response.num_frames = Some(*frames);
-            },
-            ResourceInfo::LocalizedImage { alpha, file_names } => {
-                response.resource_type = 13;
-                response.alpha = Some(*alpha);
-                response.file_names = Some(file_names.clone());
-            },
-            ResourceInfo::Tanks3DS => {
-                response.resource_type = 17;
-            }
-        }
-        response
+    pub fn as_json_resource(&self) -> Resource {
+        Resource::with_info(self.id, self.version, &self.info, self.lazy)
     }
 }
-
-pub mod json {
-    use serde::{Serialize, Deserialize};
-
-    #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
-    #[serde(rename_all = "camelCase")]
-    pub struct Resource {
-        pub idhigh: String,
-        pub idlow: u32,
-
-        pub versionhigh: String,
-        pub versionlow: u32,
-
-        #[serde(rename = "type")]
-        pub resource_type: i64,
-        pub lazy: bool,
-
-        #[serde(skip_serializing_if = "Option::is_none")]
-        #[serde(default)]
-        pub alpha: Option<bool>,
-        
-        
-        #[serde(skip_serializing_if = "Option::is_none")]
-        #[serde(default)]
-        pub weight: Option<u32>,
-        
-        #[serde(skip_serializing_if = "Option::is_none")]
-        #[serde(default)]
-        pub height: Option<u32>,
-        
-        #[serde(skip_serializing_if = "Option::is_none")]
-        #[serde(default)]
-        pub num_frames: Option<u32>,
-        
-        #[serde(skip_serializing_if = "Option::is_none")]
-        #[serde(default)]
-        pub fps: Option<u32>,
-        
-        #[serde(skip_serializing_if = "Option::is_none")]
-        #[serde(default)]
-        pub file_names: Option<Vec<String>>,
-    }
-}
\ No newline at end of file
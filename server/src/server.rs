@@ -1,25 +1,52 @@
-use std::{collections::{BTreeMap}, sync::{Arc, Mutex, RwLock}, future::poll_fn, task::Poll, time::Duration, pin::Pin};
+use std::{collections::{BTreeMap}, sync::{Arc, Mutex, RwLock}, future::poll_fn, task::Poll, time::Duration, pin::Pin, net::SocketAddr};
 
 use anyhow::Context;
 use chrono::Utc;
-use fost_protocol::{packets::s2c, codec::{LayoutState, UserPropertyCC}};
+use fost_protocol::{packets::{s2c, Packet}, codec::{LayoutState, UserPropertyCC}};
 use futures::{FutureExt, Future, stream::FuturesUnordered, StreamExt};
-use sqlx::SqliteConnection;
-use tokio::{sync::mpsc, time};
+use serde::Serialize;
+use tokio::{sync::{mpsc, Notify}, task::JoinHandle, time};
 use tracing::{warn, info};
 
-use crate::{client::{Client, ClientId}, client_components::{UserAuthentication, UserRegister, CaptchaProvider, ClientResources, SettingsDialog, LoginKickoff, ClientBattleList, ClientBattleCreate}, users::UserRegistry, ServerResource, ServerResources, ServerChat, ServerChatComponent, ResourceStage, BattleProvider, Rank};
-
-pub type DatabaseConnection = SqliteConnection;
-pub type DatabaseHandle = Arc<tokio::sync::Mutex<DatabaseConnection>>;
+use crate::{client::{Client, ClientId, AuthenticationState}, client_components::{UserAuthentication, UserRegister, CaptchaProvider, ClientResources, SettingsDialog, LoginKickoff, ClientBattleList, ClientBattleCreate, ClientGarage, ClientFriends, ClientQuests, InviteCodeGate, AccountRecovery}, users::UserRegistry, ServerResource, ServerResources, ServerChat, ServerChatComponent, ResourceStage, BattleProvider, SupplyRegistry, SUPPLIES_JSON, Garage, GarageCatalog, GARAGE_JSON, Friends, UserNotifier, UserNotifierHandle, Quests, QuestCatalog, QUESTS_JSON, Progression, LeaderboardEntry, Rank, Premium, Moderation, Invites, Accounts, UidValidator, MailerHandle, StorageTasksHandle, storage::StorageHandle, config::ConfigHandle};
 
 pub enum ServerEvent {
     ClientAuthenticated(ClientId),
     ClientDisconnected(ClientId),
+    /// Show the reason to all clients of the user and disconnect them.
+    KickUser { user_id: String, reason: String },
+    /// Tell all clients the time left until the scheduled shutdown.
+    ShutdownAnnouncement(Duration),
+}
+
+/// Time to wait for pending storage writes on shutdown.
+const STORAGE_FLUSH_TIMEOUT: Duration = Duration::from_secs(30);
+/// Time to wait for the clients to receive the shutdown notification.
+const CLIENT_DISCONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Seconds before a scheduled shutdown at which the time left will be announced again.
+const SHUTDOWN_ANNOUNCEMENTS: [u64; 6] = [600, 300, 120, 60, 30, 10];
+
+/// Time left at which the next shutdown announcement is due.
+fn next_shutdown_announcement(time_left: Duration) -> Duration {
+    SHUTDOWN_ANNOUNCEMENTS.iter()
+        .map(|seconds| Duration::from_secs(*seconds))
+        .find(|announcement| *announcement < time_left)
+        .unwrap_or(Duration::ZERO)
+}
+
+/// Connected client as listed by the admin API.
+#[derive(Debug, Clone, Serialize)]
+pub struct ClientInfo {
+    pub client_id: ClientId,
+    pub address: SocketAddr,
+    pub language: String,
+    pub user_id: Option<String>,
+    pub layout: Option<String>,
 }
 
 pub struct Server {
-    server_id: i32,
+    config: ConfigHandle,
 
     clients: BTreeMap<ClientId, Arc<Mutex<Client>>>,
     client_id_index: ClientId,
@@ -31,63 +58,124 @@ pub struct Server {
     server_resources: Arc<RwLock<ServerResources>>,
     chat: Arc<RwLock<ServerChat>>,
     battles: Arc<RwLock<BattleProvider>>,
-
-    database: DatabaseHandle,
+    garage: Arc<Garage>,
+    friends: Arc<Friends>,
+    notifier: UserNotifierHandle,
+    quests: Arc<Quests>,
+    progression: Arc<Progression>,
+    premium: Arc<Premium>,
+    moderation: Arc<Moderation>,
+    invites: Arc<Invites>,
+    uid_validator: Arc<UidValidator>,
+    accounts: Arc<Accounts>,
+
+    storage: StorageHandle,
+    storage_tasks: StorageTasksHandle,
 
     is_shutdown: bool,
+    shutdown_schedule: Option<JoinHandle<()>>,
+    shutdown_signal: Arc<Notify>,
 }
 
 impl Server {
-    pub fn new(database: DatabaseHandle) -> anyhow::Result<Self> {
+    pub fn new(config: ConfigHandle, storage: StorageHandle, mailer: MailerHandle) -> anyhow::Result<Self> {
         let (events_tx, events_rx) = mpsc::unbounded_channel();
-        let resources = ServerResources::new()?;
+        let (resources, server_id) = {
+            let config = config.read()
+                .ok()
+                .context("failed to read the config")?;
+
+            (ServerResources::new(&config.resources)?, config.network.server_id)
+        };
+
+        let supplies = SupplyRegistry::parse(SUPPLIES_JSON).context("failed to parse the supplies")?;
+        let garage_catalog = GarageCatalog::parse(GARAGE_JSON).context("failed to parse the garage catalog")?;
+        let quest_catalog = QuestCatalog::parse(QUESTS_JSON).context("failed to parse the quest catalog")?;
+
+        let notifier = Arc::new(RwLock::new(UserNotifier::new(server_id)));
+        let storage_tasks = StorageTasksHandle::default();
+        let quests = Arc::new(Quests::new(quest_catalog, storage.clone(), config.clone(), notifier.clone(), storage_tasks.clone()));
+        let progression = Arc::new(Progression::new(storage.clone(), notifier.clone(), storage_tasks.clone()));
+        let premium = Arc::new(Premium::new(storage.clone(), config.clone(), notifier.clone()));
+        let chat = Arc::new(RwLock::new(ServerChat::new(config.clone())));
+        let moderation = Arc::new(Moderation::new(storage.clone(), config.clone(), chat.clone(), events_tx.clone()));
 
         Ok(Self {
-            /* we currently have only one server */
-            server_id: 1,
+            config: config.clone(),
 
             clients: Default::default(),
             client_id_index: 0,
 
             is_shutdown: false,
+            shutdown_schedule: None,
+            shutdown_signal: Default::default(),
 
             events_rx,
             events_tx,
 
-            user_registry: Arc::new(RwLock::new(UserRegistry::new(database.clone()))),
+            user_registry: Arc::new(RwLock::new(UserRegistry::new(storage.clone(), config.clone(), supplies.starter_items()))),
             server_resources: Arc::new(RwLock::new(resources)),
-            chat: Arc::new(RwLock::new(ServerChat::new())),
-            battles: Arc::new(RwLock::new(BattleProvider::new())),
-
-            database,
+            chat,
+            battles: Arc::new(RwLock::new(BattleProvider::new(config.clone(), storage.clone(), quests.clone(), progression.clone(), storage_tasks.clone())?)),
+            garage: Arc::new(Garage::new(garage_catalog, storage.clone())),
+            friends: Arc::new(Friends::new(storage.clone())),
+            notifier,
+            quests,
+            progression,
+            premium,
+            moderation,
+            invites: Arc::new(Invites::new(storage.clone(), config.clone())),
+            uid_validator: Arc::new(UidValidator::new(storage.clone(), config.clone())),
+            accounts: Arc::new(Accounts::new(storage.clone(), config.clone(), mailer)),
+
+            storage,
+            storage_tasks,
         })
     }
 
+    /// Finish all battles, persist the user state and disconnect all clients.
+    /// The returned future resolves once the server has been stopped.
     pub fn shutdown(&mut self) -> Pin<Box<dyn Future<Output = bool>>> {
         if std::mem::replace(&mut self.is_shutdown, true) {
             /* server already in shut down */
             return Box::pin(async { false });
         }
-        /* firstly shutdown battles & emit funds */
-
-        /* disconnect all clients */
-        let mut client_disconnects = FuturesUnordered::default();
-        for client in self.clients.values() {
-            let mut client = match client.lock() {
-                Ok(client) => client,
-                Err(_) => continue,
-            };
+        if let Some(schedule) = self.shutdown_schedule.take() {
+            schedule.abort();
+        }
 
-            client.send_packet(&s2c::AlertShow{ text: "server stopped".to_string() });
-            client.send_packet(&s2c::ServerHaltNotify{ });
-            client_disconnects.push(client.disconnect(true));
+        /* firstly shutdown battles & emit funds */
+        match self.battles.write() {
+            Ok(mut battles) => battles.shutdown(),
+            Err(_) => warn!("Failed to shutdown the battles."),
         }
 
+        let storage_tasks = self.storage_tasks.clone();
+        let clients = self.clients.values().cloned().collect::<Vec<_>>();
         Box::pin(async move {
+            /* persist the user state while the users can still be notified */
+            if time::timeout(STORAGE_FLUSH_TIMEOUT, storage_tasks.flush()).await.is_err() {
+                warn!("Failed to persist the state of all users in time.");
+            }
+
+            /* disconnect all clients */
+            let mut client_disconnects = FuturesUnordered::default();
+            for client in clients {
+                let mut client = match client.lock() {
+                    Ok(client) => client,
+                    Err(_) => continue,
+                };
+
+                client.flush_components();
+                client.send_packet(&s2c::AlertShow{ text: "server stopped".to_string() });
+                client.send_packet(&s2c::ServerHaltNotify{ });
+                client_disconnects.push(client.disconnect(true));
+            }
+
             /* await all clients beeing disconnected */
             tokio::select! {
                 _ = client_disconnects.count() => {},
-                _ = tokio::time::sleep(Duration::from_millis(50_000)) => {
+                _ = tokio::time::sleep(CLIENT_DISCONNECT_TIMEOUT) => {
                     tracing::warn!("Failed to properly disconnect all clients. Forcefully terminating their connection.");
                 }
             };
@@ -121,12 +209,28 @@ impl Server {
         client.register_component(ClientResources::new(self.server_resources.clone()));
 
         let user_registry = self.user_registry.clone();
+        let config = self.config.clone();
+        let invites = self.invites.clone();
+        let accounts = self.accounts.clone();
+        let uid_validator = self.uid_validator.clone();
         client.with_component_mut::<ClientResources, _>(move |client, resources| {
             let connect_resources = resources.await_resources_loaded(client, ResourceStage::Connect)?;
             client.run_async(connect_resources, move |client, _| {
+                let captcha_locations = match config.read() {
+                    Ok(config) => config.features.captcha_locations.iter()
+                        .cloned()
+                        .map(Into::into)
+                        .collect::<Vec<_>>(),
+                    Err(_) => return,
+                };
+
                 client.register_component(UserAuthentication::new(user_registry.clone()));
-                client.register_component(UserRegister::new(user_registry.clone()));
-                client.register_component(CaptchaProvider::new());
+                client.register_component(UserRegister::new(user_registry.clone(), uid_validator.clone(), config.clone()));
+                client.register_component(AccountRecovery::new(accounts.clone()));
+                client.register_component(CaptchaProvider::new(captcha_locations));
+                if invites.enabled() {
+                    client.register_component(InviteCodeGate::new(invites.clone()));
+                }
                 client.register_component(LoginKickoff::new());
             });
 
@@ -167,26 +271,61 @@ impl Server {
         client.send_packet(&s2c::LobbyLayoutSwitchStart{ state: LayoutState::BattleSelect });
 
         let user_id = client.user_id().context("missing client user id")?.to_string();
-        let server_id = self.server_id;
+        let server_id = self.config.read()
+            .ok()
+            .context("failed to read the config")?
+            .network.server_id;
+
+        let moderation_status = {
+            let moderation = self.moderation.clone();
+            let user_id = user_id.clone();
+            async move {
+                if let Err(error) = moderation.load(&user_id).await {
+                    tracing::error!("failed to load the moderation status of {}: {}", user_id, error);
+                }
+            }
+        };
+
         {
             let user_query = self.user_registry.read()
                 .expect("to lock the user registry")
                 .find_user(user_id.clone());
 
-            client.run_async(user_query, move |client, user_info| {
+            let progression = self.progression.clone();
+            let user_query = async move {
+                let user_info = user_query.await;
+                let leaderboard_entry = match &user_info {
+                    Some(info) => progression.leaderboard_entry(info.experience).await,
+                    None => Err(anyhow::anyhow!("missing user")),
+                };
+                (user_info, leaderboard_entry)
+            };
+
+            let notifier = self.notifier.clone();
+            let premium = self.premium.clone();
+            client.run_async(user_query, move |client, (user_info, leaderboard_entry)| {
                 let user_info = match user_info {
                     Some(info) => info,
                     /* should not occur and if so just do nothing and bug out the client */
                     None => return,
                 };
 
+                let leaderboard_entry = leaderboard_entry.unwrap_or_else(|error| {
+                    tracing::error!("failed to lookup the leaderboard entry of {}: {}", user_id, error);
+                    LeaderboardEntry { place: 0, rating: 0.0 }
+                });
+
                 let double_crystals = if let Some(double_crystals) = user_info.double_crystals {
-                    (double_crystals - Utc::now()).num_seconds().min(0)
+                    (double_crystals - Utc::now()).num_seconds().max(0)
                 } else {
                     0
                 };
 
-                let rank = Rank::from_score(user_info.experience);
+                let rank = Rank::from_score(user_info.experience.max(0) as u32);
+                if let Ok(mut notifier) = notifier.write() {
+                    notifier.set_rank(&user_id, rank.value() as i32);
+                }
+
                 client.send_packet(&s2c::AccountInfoProperties {
                     user_property_cc: UserPropertyCC {
                         id: user_id,
@@ -195,20 +334,24 @@ impl Server {
                         server_number: server_id,
         
                         rank: rank.value() as i8,
-                        score: user_info.experience as i32,
+                        score: user_info.experience,
                         current_rank_score: rank.score() as i32,
                         next_rank_score: rank.next_rank().map_or(0, |rank| rank.score() as i32),
 
-                        rating: 0f32,
-                        place: 1337,
+                        rating: leaderboard_entry.rating,
+                        place: leaderboard_entry.place,
         
-                        crystals: user_info.crystals as i32,
+                        crystals: user_info.crystals,
                         duration_crystal_abonement: double_crystals as i32,
                         has_double_crystal: double_crystals > 0,
                     }
                 });
                 
-                // TODO: Notify premium data
+                match premium.login_packets(&user_info) {
+                    Ok(packets) => packets.iter().for_each(|packet| client.send_packet(packet.as_ref())),
+                    Err(error) => tracing::error!("failed to create the premium packets for {}: {}", user_info.user_id, error),
+                }
+                premium.watch(&user_info);
 
                 if let Some(email) = user_info.email {
                     client.send_packet(&s2c::AccountCredentialsInit{
@@ -225,7 +368,8 @@ impl Server {
         }
 
         /* register lobby components */
-        client.register_component(SettingsDialog::new());
+        client.register_component(SettingsDialog::new(self.accounts.clone()));
+        client.register_component(ClientFriends::new(self.friends.clone(), self.notifier.clone()));
 
         // TODO: Module 23?
 
@@ -233,21 +377,163 @@ impl Server {
             resources.await_resources_loaded(client, ResourceStage::Lobby)
         }).context("missing client resources")??;
 
+        /* the chat requires the moderation status of the user */
+        let resource_task = async move {
+            moderation_status.await;
+            resource_task.await
+        };
+
         let server_chat = self.chat.clone();
         let battles = self.battles.clone();
+        let user_registry = self.user_registry.clone();
+        let garage = self.garage.clone();
+        let quests = self.quests.clone();
+        let notifier = self.notifier.clone();
+        let moderation = self.moderation.clone();
+        let config = self.config.clone();
         client.run_async(
             resource_task, 
             move |client, _| {
                 client.send_packet(&s2c::LobbyLayoutSwitchEnd{ state: LayoutState::BattleSelect, origin: LayoutState::BattleSelect });
-                client.register_component(ServerChatComponent::new(server_chat));
-                client.register_component(ClientBattleList::new(battles.clone()));
-                client.register_component(ClientBattleCreate::new(battles.clone()));
+                client.register_component(ServerChatComponent::new(server_chat, config.clone(), moderation.clone()));
+                match ClientBattleList::new(battles.clone(), user_registry, garage.clone(), config.clone(), notifier, moderation) {
+                    Ok(battle_list) => { client.register_component(battle_list); },
+                    Err(error) => tracing::error!("failed to create the battle list: {}", error),
+                }
+                client.register_component(ClientBattleCreate::new(battles.clone(), config.clone()));
+                client.register_component(ClientGarage::new(garage));
+                client.register_component(ClientQuests::new(quests));
             }
         );
 
         Ok(())
     }
 
+    pub fn storage(&self) -> &StorageHandle {
+        &self.storage
+    }
+
+    pub fn notifier(&self) -> &UserNotifierHandle {
+        &self.notifier
+    }
+
+    pub fn battles(&self) -> &Arc<RwLock<BattleProvider>> {
+        &self.battles
+    }
+
+    pub fn garage(&self) -> &Arc<Garage> {
+        &self.garage
+    }
+
+    pub fn premium(&self) -> &Arc<Premium> {
+        &self.premium
+    }
+
+    pub fn moderation(&self) -> &Arc<Moderation> {
+        &self.moderation
+    }
+
+    pub fn invites(&self) -> &Arc<Invites> {
+        &self.invites
+    }
+
+    pub fn client_infos(&self) -> Vec<ClientInfo> {
+        self.clients.values()
+            .filter_map(|client| {
+                let client = client.lock().ok()?;
+                let user_id = match client.authentication_state() {
+                    AuthenticationState::Authenticated { user_id } => Some(user_id.clone()),
+                    AuthenticationState::InviteCode | AuthenticationState::Unauthenticated => None,
+                };
+
+                Some(ClientInfo {
+                    client_id: client.client_id(),
+                    address: *client.peer_address(),
+                    language: client.language().to_string(),
+                    user_id,
+                    layout: client.layout().map(|layout| format!("{:?}", layout)),
+                })
+            })
+            .collect()
+    }
+
+    /// Send the packet to every connected client.
+    /// Returns the amount of clients the packet has been sent to.
+    pub fn broadcast_packet(&self, packet: &dyn Packet) -> usize {
+        let mut count = 0;
+        for client in self.clients.values() {
+            if let Ok(mut client) = client.lock() {
+                client.send_packet(packet);
+                count += 1;
+            }
+        }
+        count
+    }
+
+    /// Show the reason to all clients of the user and disconnect them.
+    /// Returns the amount of disconnected clients.
+    pub fn kick_user(&mut self, user_id: &str, reason: &str) -> usize {
+        let mut count = 0;
+        for client in self.clients.values() {
+            let mut client = match client.lock() {
+                Ok(client) => client,
+                Err(_) => continue,
+            };
+
+            if client.user_id() != Some(user_id) {
+                continue;
+            }
+
+            info!("Kicking client {} of {}.", client.client_id(), user_id);
+            client.send_packet(&s2c::AlertShow{ text: reason.to_string() });
+            let _ = client.disconnect(true);
+            count += 1;
+        }
+        count
+    }
+
+    /// Stop the server after the given delay.
+    /// The time left will be announced to all clients in decreasing intervals.
+    /// A previously scheduled shutdown will be replaced.
+    pub fn schedule_shutdown(&mut self, delay: Duration) {
+        self.cancel_shutdown();
+        info!("Scheduled the server shutdown in {} seconds.", delay.as_secs());
+
+        let events_tx = self.events_tx.clone();
+        let shutdown_signal = self.shutdown_signal.clone();
+        self.shutdown_schedule = Some(tokio::spawn(async move {
+            let shutdown_at = time::Instant::now() + delay;
+            loop {
+                let time_left = shutdown_at.saturating_duration_since(time::Instant::now());
+                if time_left.is_zero() {
+                    break;
+                }
+
+                let _ = events_tx.send(ServerEvent::ShutdownAnnouncement(time_left));
+                time::sleep_until(shutdown_at - next_shutdown_announcement(time_left)).await;
+            }
+
+            shutdown_signal.notify_one();
+        }));
+    }
+
+    /// Returns `false` if no shutdown has been scheduled.
+    pub fn cancel_shutdown(&mut self) -> bool {
+        match self.shutdown_schedule.take() {
+            Some(schedule) if !schedule.is_finished() => {
+                schedule.abort();
+                info!("Canceled the scheduled server shutdown.");
+                true
+            },
+            _ => false,
+        }
+    }
+
+    /// Notified once a scheduled shutdown is due.
+    pub fn shutdown_signal(&self) -> Arc<Notify> {
+        self.shutdown_signal.clone()
+    }
+
     fn handle_event(&mut self, event: ServerEvent) -> anyhow::Result<()> {
         match event {
             ServerEvent::ClientDisconnected(client_id) => {
@@ -258,6 +544,12 @@ impl Server {
             ServerEvent::ClientAuthenticated(client_id) => {
                 self.handle_client_authenticated(client_id)?;
             },
+            ServerEvent::KickUser { user_id, reason } => {
+                self.kick_user(&user_id, &reason);
+            },
+            ServerEvent::ShutdownAnnouncement(time_left) => {
+                self.broadcast_packet(&s2c::AlertServerHaltScheduled{ time_left_in_sec: time_left.as_secs_f32().round() as i32 });
+            },
             _ => {
 
             }
@@ -0,0 +1,564 @@
+use std::{collections::BTreeMap, sync::Mutex};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+
+use super::{Storage, model};
+
+#[derive(Default)]
+struct MemoryState {
+    users: BTreeMap<String, model::User>,
+    authentications: BTreeMap<String, model::UserAuthentication>,
+    tokens: BTreeMap<String, model::UserAuthenticationToken>,
+    /// Item counts keyed by the user and item id.
+    items: BTreeMap<(String, String), i32>,
+    /// Equipment keyed by the user and item id.
+    garage_items: BTreeMap<(String, String), model::UserGarageItem>,
+    /// Friendships keyed by the user and friend id.
+    friends: BTreeMap<(String, String), model::UserFriend>,
+    /// Quests keyed by the user and quest id.
+    quests: BTreeMap<(String, i32), model::UserQuest>,
+    weekly_quests: BTreeMap<String, model::UserWeeklyQuest>,
+    /// Punishments keyed by the user and their kind.
+    punishments: BTreeMap<(String, model::PunishmentKind), model::UserPunishment>,
+    invite_codes: BTreeMap<String, model::InviteCode>,
+    /// Email tokens keyed by the user and their kind.
+    email_tokens: BTreeMap<(String, model::EmailTokenKind), model::UserEmailToken>,
+}
+
+/// Volatile storage keeping everything in memory.
+/// Intended for tests and local development only.
+pub struct MemoryStorage {
+    state: Mutex<MemoryState>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self {
+            state: Mutex::new(Default::default()),
+        }
+    }
+
+    fn state(&self) -> anyhow::Result<std::sync::MutexGuard<'_, MemoryState>> {
+        self.state.lock()
+            .map_err(|_| anyhow::anyhow!("memory storage poisoned"))
+    }
+}
+
+#[async_trait]
+impl Storage for MemoryStorage {
+    async fn find_user(&self, user_id: &str) -> anyhow::Result<Option<model::User>> {
+        Ok(self.state()?.users.get(user_id).cloned())
+    }
+
+    async fn user_exists(&self, user_id: &str) -> anyhow::Result<bool> {
+        Ok(self.state()?.users.contains_key(user_id))
+    }
+
+    async fn create_user(&self, user: &model::User, authentication: &model::UserAuthentication, items: &[(String, i32)]) -> anyhow::Result<()> {
+        let mut state = self.state()?;
+        if state.users.contains_key(&user.user_id) {
+            anyhow::bail!("user {} already exists", user.user_id);
+        }
+
+        if state.authentications.values().any(|entry| entry.login_user == authentication.login_user) {
+            anyhow::bail!("login {} already exists", authentication.login_user);
+        }
+
+        state.users.insert(user.user_id.clone(), user.clone());
+        state.authentications.insert(authentication.user_id.clone(), authentication.clone());
+        for (item_id, count) in items {
+            *state.items.entry((user.user_id.clone(), item_id.clone())).or_insert(0) += count;
+        }
+        Ok(())
+    }
+
+    async fn find_authentication(&self, login_user: &str) -> anyhow::Result<Option<model::UserAuthentication>> {
+        let state = self.state()?;
+        Ok(
+            state.authentications.values()
+                .find(|entry| entry.login_user == login_user)
+                .cloned()
+        )
+    }
+
+    async fn create_authentication_token(&self, token: &model::UserAuthenticationToken) -> anyhow::Result<()> {
+        let mut state = self.state()?;
+        if state.tokens.contains_key(&token.token_hash) {
+            anyhow::bail!("token already exists");
+        }
+
+        state.tokens.insert(token.token_hash.clone(), token.clone());
+        Ok(())
+    }
+
+    async fn use_authentication_token(&self, token_hash: &str, timestamp: DateTime<Utc>) -> anyhow::Result<Option<model::UserAuthenticationToken>> {
+        let mut state = self.state()?;
+        Ok(
+            state.tokens.remove(token_hash)
+                .filter(|token| token.timestamp_expires > timestamp)
+        )
+    }
+
+    async fn find_user_authentication_tokens(&self, user_id: &str) -> anyhow::Result<Vec<model::UserAuthenticationToken>> {
+        let state = self.state()?;
+        Ok(
+            state.tokens.values()
+                .filter(|token| token.user_id == user_id)
+                .cloned()
+                .collect()
+        )
+    }
+
+    async fn remove_authentication_token(&self, token_hash: &str) -> anyhow::Result<bool> {
+        let mut state = self.state()?;
+        Ok(state.tokens.remove(token_hash).is_some())
+    }
+
+    async fn remove_user_authentication_tokens(&self, user_id: &str) -> anyhow::Result<u64> {
+        let mut state = self.state()?;
+        let count = state.tokens.len();
+        state.tokens.retain(|_, token| token.user_id != user_id);
+        Ok((count - state.tokens.len()) as u64)
+    }
+
+    async fn add_user_crystals(&self, user_id: &str, amount: i32) -> anyhow::Result<Option<i32>> {
+        let mut state = self.state()?;
+        Ok(
+            state.users.get_mut(user_id)
+                .and_then(|user| {
+                    user.crystals = user.crystals.checked_add(amount).filter(|crystals| *crystals >= 0)?;
+                    Some(user.crystals)
+                })
+        )
+    }
+
+    async fn add_user_experience(&self, user_id: &str, amount: i32) -> anyhow::Result<Option<i32>> {
+        let mut state = self.state()?;
+        Ok(
+            state.users.get_mut(user_id)
+                .and_then(|user| {
+                    user.experience = user.experience.checked_add(amount)?;
+                    Some(user.experience)
+                })
+        )
+    }
+
+    async fn count_users_by_experience(&self, experience: i32) -> anyhow::Result<(i64, i64)> {
+        let state = self.state()?;
+        let higher = state.users.values()
+            .filter(|user| user.experience > experience)
+            .count();
+        Ok((higher as i64, state.users.len() as i64))
+    }
+
+    async fn set_user_premium(&self, user_id: &str, until: Option<DateTime<Utc>>) -> anyhow::Result<bool> {
+        let mut state = self.state()?;
+        Ok(
+            state.users.get_mut(user_id)
+                .map(|user| user.premium = until)
+                .is_some()
+        )
+    }
+
+    async fn set_user_double_crystals(&self, user_id: &str, until: Option<DateTime<Utc>>) -> anyhow::Result<bool> {
+        let mut state = self.state()?;
+        Ok(
+            state.users.get_mut(user_id)
+                .map(|user| user.double_crystals = until)
+                .is_some()
+        )
+    }
+
+    async fn set_user_moderator_level(&self, user_id: &str, level: i32) -> anyhow::Result<bool> {
+        let mut state = self.state()?;
+        Ok(
+            state.users.get_mut(user_id)
+                .map(|user| user.moderator_level = level)
+                .is_some()
+        )
+    }
+
+    async fn find_user_by_email(&self, email: &str) -> anyhow::Result<Option<model::User>> {
+        let state = self.state()?;
+        Ok(
+            state.users.values()
+                .find(|user| user.email_confirmed && user.email.as_deref() == Some(email))
+                .cloned()
+        )
+    }
+
+    async fn set_user_email(&self, user_id: &str, email: Option<&str>, confirmed: bool) -> anyhow::Result<bool> {
+        let mut state = self.state()?;
+        Ok(
+            state.users.get_mut(user_id)
+                .map(|user| {
+                    user.email = email.map(str::to_string);
+                    user.email_confirmed = confirmed;
+                })
+                .is_some()
+        )
+    }
+
+    async fn set_user_password(&self, user_id: &str, password_hash: &str, password_salt: &str) -> anyhow::Result<bool> {
+        let mut state = self.state()?;
+        Ok(
+            state.authentications.get_mut(user_id)
+                .map(|authentication| {
+                    authentication.password_hash = password_hash.to_string();
+                    authentication.password_salt = password_salt.to_string();
+                })
+                .is_some()
+        )
+    }
+
+    async fn set_user_email_token(&self, token: &model::UserEmailToken) -> anyhow::Result<()> {
+        let mut state = self.state()?;
+        if !state.users.contains_key(&token.user_id) {
+            anyhow::bail!("user {} does not exist", token.user_id);
+        }
+
+        state.email_tokens.insert((token.user_id.clone(), token.kind), token.clone());
+        Ok(())
+    }
+
+    async fn use_user_email_token(&self, token: &str, timestamp: DateTime<Utc>) -> anyhow::Result<Option<model::UserEmailToken>> {
+        let mut state = self.state()?;
+        let key = state.email_tokens.iter()
+            .find(|(_, entry)| entry.token == token)
+            .map(|(key, _)| key.clone());
+
+        Ok(
+            key.and_then(|key| state.email_tokens.remove(&key))
+                .filter(|token| token.timestamp_expires > timestamp)
+        )
+    }
+
+    async fn find_user_punishment(&self, user_id: &str, kind: model::PunishmentKind) -> anyhow::Result<Option<model::UserPunishment>> {
+        let state = self.state()?;
+        Ok(state.punishments.get(&(user_id.to_string(), kind)).cloned())
+    }
+
+    async fn set_user_punishment(&self, punishment: &model::UserPunishment) -> anyhow::Result<()> {
+        let mut state = self.state()?;
+        if !state.users.contains_key(&punishment.user_id) {
+            anyhow::bail!("user {} does not exist", punishment.user_id);
+        }
+
+        state.punishments.insert((punishment.user_id.clone(), punishment.kind), punishment.clone());
+        Ok(())
+    }
+
+    async fn remove_user_punishment(&self, user_id: &str, kind: model::PunishmentKind) -> anyhow::Result<bool> {
+        let mut state = self.state()?;
+        Ok(state.punishments.remove(&(user_id.to_string(), kind)).is_some())
+    }
+
+    async fn create_invite_code(&self, invite_code: &model::InviteCode) -> anyhow::Result<bool> {
+        let mut state = self.state()?;
+        if state.invite_codes.contains_key(&invite_code.code) {
+            return Ok(false);
+        }
+
+        state.invite_codes.insert(invite_code.code.clone(), invite_code.clone());
+        Ok(true)
+    }
+
+    async fn find_invite_code(&self, code: &str) -> anyhow::Result<Option<model::InviteCode>> {
+        Ok(self.state()?.invite_codes.get(code).cloned())
+    }
+
+    async fn use_invite_code(&self, code: &str, timestamp: DateTime<Utc>) -> anyhow::Result<bool> {
+        let mut state = self.state()?;
+        match state.invite_codes.get_mut(code) {
+            Some(invite_code) if invite_code.is_usable(timestamp) => {
+                invite_code.uses += 1;
+                Ok(true)
+            },
+            _ => Ok(false),
+        }
+    }
+
+    async fn release_invite_code(&self, code: &str) -> anyhow::Result<()> {
+        let mut state = self.state()?;
+        if let Some(invite_code) = state.invite_codes.get_mut(code) {
+            invite_code.uses = (invite_code.uses - 1).max(0);
+        }
+
+        Ok(())
+    }
+
+    async fn find_invite_code_users(&self, code: &str) -> anyhow::Result<Vec<String>> {
+        let state = self.state()?;
+        let mut users = state.users.values()
+            .filter(|user| user.invite_code.as_deref() == Some(code))
+            .collect::<Vec<_>>();
+        users.sort_by_key(|user| user.timestamp_register);
+
+        Ok(users.into_iter().map(|user| user.user_id.clone()).collect())
+    }
+
+    async fn find_user_items(&self, user_id: &str) -> anyhow::Result<Vec<model::UserItem>> {
+        let state = self.state()?;
+        Ok(
+            state.items.iter()
+                .filter(|((owner, _), _)| owner == user_id)
+                .map(|((owner, item_id), count)| model::UserItem {
+                    user_id: owner.clone(),
+                    item_id: item_id.clone(),
+                    count: *count,
+                })
+                .collect()
+        )
+    }
+
+    async fn add_user_item(&self, user_id: &str, item_id: &str, amount: i32) -> anyhow::Result<Option<i32>> {
+        let mut state = self.state()?;
+        if !state.users.contains_key(user_id) {
+            return Ok(None);
+        }
+
+        let count = state.items.entry((user_id.to_string(), item_id.to_string())).or_insert(0);
+        if *count + amount < 0 {
+            return Ok(None);
+        }
+
+        *count += amount;
+        Ok(Some(*count))
+    }
+
+    async fn find_user_garage_items(&self, user_id: &str) -> anyhow::Result<Vec<model::UserGarageItem>> {
+        let state = self.state()?;
+        Ok(
+            state.garage_items.iter()
+                .filter(|((owner, _), _)| owner == user_id)
+                .map(|(_, item)| item.clone())
+                .collect()
+        )
+    }
+
+    async fn upgrade_user_garage_item(&self, user_id: &str, item_id: &str, modification: i32) -> anyhow::Result<bool> {
+        let mut state = self.state()?;
+        if !state.users.contains_key(user_id) {
+            anyhow::bail!("user {} does not exist", user_id);
+        }
+
+        let item = state.garage_items.entry((user_id.to_string(), item_id.to_string()))
+            .or_insert_with(|| model::UserGarageItem {
+                user_id: user_id.to_string(),
+                item_id: item_id.to_string(),
+                modification: -1,
+                mounted: false,
+            });
+
+        if item.modification >= modification {
+            return Ok(false);
+        }
+
+        item.modification = modification;
+        Ok(true)
+    }
+
+    async fn apply_purchase(&self, user_id: &str, price: i32, equipment: &[(String, i32)], items: &[(String, i32)]) -> anyhow::Result<model::PurchaseResult> {
+        let mut state = self.state()?;
+        let crystals = match state.users.get(user_id).and_then(|user| user.crystals.checked_sub(price)) {
+            Some(crystals) if crystals >= 0 => crystals,
+            _ => return Ok(model::PurchaseResult::InsufficientCrystals),
+        };
+
+        let already_owned = equipment.iter().any(|(item_id, modification)| {
+            state.garage_items.get(&(user_id.to_string(), item_id.clone()))
+                .map_or(false, |item| item.modification >= *modification)
+        });
+        if already_owned {
+            return Ok(model::PurchaseResult::AlreadyOwned);
+        }
+
+        if items.iter().any(|(_, count)| *count < 0) {
+            anyhow::bail!("purchased items must not be negative");
+        }
+
+        if let Some(user) = state.users.get_mut(user_id) {
+            user.crystals = crystals;
+        }
+
+        for (item_id, modification) in equipment {
+            state.garage_items.entry((user_id.to_string(), item_id.clone()))
+                .or_insert_with(|| model::UserGarageItem {
+                    user_id: user_id.to_string(),
+                    item_id: item_id.clone(),
+                    modification: -1,
+                    mounted: false,
+                })
+                .modification = *modification;
+        }
+
+        for (item_id, count) in items {
+            *state.items.entry((user_id.to_string(), item_id.clone())).or_insert(0) += count;
+        }
+
+        Ok(model::PurchaseResult::Applied(crystals))
+    }
+
+    async fn set_user_garage_item_mounted(&self, user_id: &str, item_id: &str, mounted: bool) -> anyhow::Result<bool> {
+        let mut state = self.state()?;
+        Ok(
+            state.garage_items.get_mut(&(user_id.to_string(), item_id.to_string()))
+                .map(|item| item.mounted = mounted)
+                .is_some()
+        )
+    }
+
+    async fn find_user_friends(&self, user_id: &str) -> anyhow::Result<Vec<model::UserFriend>> {
+        let state = self.state()?;
+        Ok(
+            state.friends.iter()
+                .filter(|((owner, _), _)| owner == user_id)
+                .map(|(_, friend)| friend.clone())
+                .collect()
+        )
+    }
+
+    async fn create_friend_request(&self, user_id: &str, friend_id: &str) -> anyhow::Result<bool> {
+        let mut state = self.state()?;
+        let key = (user_id.to_string(), friend_id.to_string());
+        let reverse_key = (friend_id.to_string(), user_id.to_string());
+        if state.friends.contains_key(&key) || state.friends.contains_key(&reverse_key) {
+            return Ok(false);
+        }
+
+        state.friends.insert(key, model::UserFriend {
+            user_id: user_id.to_string(),
+            friend_id: friend_id.to_string(),
+            state: model::FriendState::Outgoing,
+            viewed: true,
+        });
+        state.friends.insert(reverse_key, model::UserFriend {
+            user_id: friend_id.to_string(),
+            friend_id: user_id.to_string(),
+            state: model::FriendState::Incoming,
+            viewed: false,
+        });
+        Ok(true)
+    }
+
+    async fn accept_friend_request(&self, user_id: &str, friend_id: &str) -> anyhow::Result<bool> {
+        let mut state = self.state()?;
+        let key = (user_id.to_string(), friend_id.to_string());
+        let reverse_key = (friend_id.to_string(), user_id.to_string());
+        if state.friends.get(&key).map(|friend| friend.state) != Some(model::FriendState::Incoming) {
+            return Ok(false);
+        }
+
+        for (key, viewed) in [(key, true), (reverse_key, false)] {
+            if let Some(friend) = state.friends.get_mut(&key) {
+                friend.state = model::FriendState::Accepted;
+                friend.viewed = viewed;
+            }
+        }
+        Ok(true)
+    }
+
+    async fn remove_friend(&self, user_id: &str, friend_id: &str, friend_state: model::FriendState) -> anyhow::Result<bool> {
+        let mut state = self.state()?;
+        let key = (user_id.to_string(), friend_id.to_string());
+        if state.friends.get(&key).map(|friend| friend.state) != Some(friend_state) {
+            return Ok(false);
+        }
+
+        state.friends.remove(&key);
+        state.friends.remove(&(friend_id.to_string(), user_id.to_string()));
+        Ok(true)
+    }
+
+    async fn set_friend_viewed(&self, user_id: &str, friend_id: &str) -> anyhow::Result<()> {
+        let mut state = self.state()?;
+        if let Some(friend) = state.friends.get_mut(&(user_id.to_string(), friend_id.to_string())) {
+            friend.viewed = true;
+        }
+        Ok(())
+    }
+
+    async fn find_user_quests(&self, user_id: &str) -> anyhow::Result<Vec<model::UserQuest>> {
+        let state = self.state()?;
+        Ok(
+            state.quests.iter()
+                .filter(|((owner, _), _)| owner == user_id)
+                .map(|(_, quest)| quest.clone())
+                .collect()
+        )
+    }
+
+    async fn set_user_quests(&self, user_id: &str, quests: &[model::UserQuest]) -> anyhow::Result<()> {
+        let mut state = self.state()?;
+        state.quests.retain(|(owner, _), _| owner != user_id);
+        for quest in quests {
+            state.quests.insert((user_id.to_string(), quest.quest_id), quest.clone());
+        }
+        Ok(())
+    }
+
+    async fn replace_user_quest(&self, user_id: &str, quest_id: i32, quest: &model::UserQuest, free_skip: bool) -> anyhow::Result<bool> {
+        let mut state = self.state()?;
+        let key = (user_id.to_string(), quest_id);
+        let replaceable = match state.quests.get(&key) {
+            Some(old_quest) => !old_quest.rewarded && (!free_skip || old_quest.can_skip_free),
+            None => false,
+        };
+        if !replaceable {
+            return Ok(false);
+        }
+
+        state.quests.remove(&key);
+        state.quests.insert((user_id.to_string(), quest.quest_id), quest.clone());
+        if free_skip {
+            for ((owner, _), quest) in state.quests.iter_mut() {
+                if owner == user_id {
+                    quest.can_skip_free = false;
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    async fn add_user_quest_progress(&self, user_id: &str, quest_id: i32, amount: i32) -> anyhow::Result<Option<i32>> {
+        let mut state = self.state()?;
+        Ok(
+            state.quests.get_mut(&(user_id.to_string(), quest_id))
+                .filter(|quest| !quest.rewarded)
+                .map(|quest| {
+                    quest.progress = (quest.progress + amount).min(quest.goal);
+                    quest.progress
+                })
+        )
+    }
+
+    async fn set_user_quest_rewarded(&self, user_id: &str, quest_id: i32) -> anyhow::Result<bool> {
+        let mut state = self.state()?;
+        Ok(
+            state.quests.get_mut(&(user_id.to_string(), quest_id))
+                .filter(|quest| !quest.rewarded && quest.progress >= quest.goal)
+                .map(|quest| quest.rewarded = true)
+                .is_some()
+        )
+    }
+
+    async fn find_user_weekly_quest(&self, user_id: &str) -> anyhow::Result<Option<model::UserWeeklyQuest>> {
+        let state = self.state()?;
+        Ok(state.weekly_quests.get(user_id).cloned())
+    }
+
+    async fn advance_user_weekly_quest(&self, weekly_quest: &model::UserWeeklyQuest) -> anyhow::Result<bool> {
+        let mut state = self.state()?;
+        if !state.users.contains_key(&weekly_quest.user_id) {
+            anyhow::bail!("user {} does not exist", weekly_quest.user_id);
+        }
+
+        if state.weekly_quests.get(&weekly_quest.user_id).map_or(false, |current| current.day >= weekly_quest.day) {
+            return Ok(false);
+        }
+
+        state.weekly_quests.insert(weekly_quest.user_id.clone(), weekly_quest.clone());
+        Ok(true)
+    }
+}
@@ -0,0 +1,372 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+
+mod memory;
+pub use memory::*;
+
+mod sqlite;
+pub use sqlite::*;
+
+mod postgres;
+pub use postgres::*;
+
+pub mod model {
+    use sqlx::FromRow;
+
+    #[derive(Clone, FromRow, Debug)]
+    pub struct User {
+        pub user_id: String,
+
+        pub email: Option<String>,
+        pub email_confirmed: bool,
+
+        pub timestamp_register: chrono::DateTime<chrono::Utc>,
+        pub timestamp_active: chrono::DateTime<chrono::Utc>,
+
+        pub crystals: i32,
+        pub double_crystals: Option<chrono::DateTime<chrono::Utc>>,
+
+        pub experience: i32,
+        pub premium: Option<chrono::DateTime<chrono::Utc>>,
+
+        /// Chat moderator level as send to the client.
+        pub moderator_level: i32,
+
+        /// Invite code used to register the account.
+        pub invite_code: Option<String>,
+    }
+
+    #[derive(Clone, FromRow, Debug)]
+    pub struct UserAuthentication {
+        pub user_id: String,
+        pub login_user: String,
+        pub password_hash: String,
+        pub password_salt: String,
+    }
+
+    /// Login token of a device. Tokens are replaced on every use and only their hash is stored.
+    #[derive(Clone, FromRow, Debug)]
+    pub struct UserAuthenticationToken {
+        pub token_hash: String,
+        pub user_id: String,
+        pub timestamp_created: chrono::DateTime<chrono::Utc>,
+        pub timestamp_expires: chrono::DateTime<chrono::Utc>,
+    }
+
+    /// Countable item owned by a user (e.g. supplies).
+    #[derive(Clone, FromRow, Debug)]
+    pub struct UserItem {
+        pub user_id: String,
+        pub item_id: String,
+        pub count: i32,
+    }
+
+    /// Equipment (turret, hull or paint) owned by a user.
+    #[derive(Clone, FromRow, Debug)]
+    pub struct UserGarageItem {
+        pub user_id: String,
+        pub item_id: String,
+        pub modification: i32,
+        pub mounted: bool,
+    }
+
+    /// Outcome of charging and granting a garage purchase at once.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum PurchaseResult {
+        /// The purchase has been applied, contains the new crystal balance.
+        Applied(i32),
+        InsufficientCrystals,
+        /// The user already owns the modification of some equipment, nothing has been charged or granted.
+        AlreadyOwned,
+    }
+
+    /// State of a friendship from the view of the user.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, sqlx::Type)]
+    #[repr(i32)]
+    pub enum FriendState {
+        /// The user sent a request to the friend.
+        Outgoing = 0,
+        /// The friend sent a request to the user.
+        Incoming = 1,
+        Accepted = 2,
+    }
+
+    /// Every friendship is stored twice, once from the view of each user.
+    #[derive(Clone, FromRow, Debug)]
+    pub struct UserFriend {
+        pub user_id: String,
+        pub friend_id: String,
+        pub state: FriendState,
+        /// The user has seen the request or the accepted friendship.
+        pub viewed: bool,
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, sqlx::Type)]
+    #[repr(i32)]
+    pub enum PunishmentKind {
+        /// The user can not login.
+        Ban = 0,
+        /// The user can not send chat messages.
+        Mute = 1,
+    }
+
+    /// Ban or mute of a user. A user has at most one punishment of each kind.
+    #[derive(Clone, FromRow, Debug)]
+    pub struct UserPunishment {
+        pub user_id: String,
+        pub kind: PunishmentKind,
+        pub reason: String,
+        /// Moderator who issued the punishment.
+        pub issued_by: String,
+        pub timestamp_created: chrono::DateTime<chrono::Utc>,
+        /// The punishment is permanent if not set.
+        pub timestamp_expires: Option<chrono::DateTime<chrono::Utc>>,
+    }
+
+    impl UserPunishment {
+        pub fn is_active(&self, now: chrono::DateTime<chrono::Utc>) -> bool {
+            self.timestamp_expires.map_or(true, |expires| expires > now)
+        }
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, sqlx::Type)]
+    #[repr(i32)]
+    pub enum EmailTokenKind {
+        /// Allows to set new credentials without knowing the password.
+        PasswordReset = 0,
+        /// Confirms the email the token has been sent to.
+        EmailConfirmation = 1,
+    }
+
+    /// Token mailed to a user. A user has at most one token of each kind.
+    #[derive(Clone, FromRow, Debug)]
+    pub struct UserEmailToken {
+        pub user_id: String,
+        pub kind: EmailTokenKind,
+        pub token: String,
+        /// Email the token has been sent to.
+        pub email: String,
+        pub timestamp_created: chrono::DateTime<chrono::Utc>,
+        pub timestamp_expires: chrono::DateTime<chrono::Utc>,
+    }
+
+    /// Code required to pass the invite gate when the server is invite only.
+    #[derive(Clone, FromRow, Debug)]
+    pub struct InviteCode {
+        pub code: String,
+        /// Admin or user who created the code.
+        pub created_by: String,
+        /// Amount of accounts which can be registered with the code.
+        pub max_uses: i32,
+        pub uses: i32,
+        pub timestamp_created: chrono::DateTime<chrono::Utc>,
+        /// The code never expires if not set.
+        pub timestamp_expires: Option<chrono::DateTime<chrono::Utc>>,
+    }
+
+    impl InviteCode {
+        pub fn is_expired(&self, now: chrono::DateTime<chrono::Utc>) -> bool {
+            self.timestamp_expires.map_or(false, |expires| expires <= now)
+        }
+
+        /// Another account can be registered with the code.
+        pub fn is_usable(&self, now: chrono::DateTime<chrono::Utc>) -> bool {
+            !self.is_expired(now) && self.uses < self.max_uses
+        }
+    }
+
+    /// Daily quest issued to a user.
+    #[derive(Clone, FromRow, Debug)]
+    pub struct UserQuest {
+        pub user_id: String,
+        pub quest_id: i32,
+        /// Id of the quest template within the quest catalog.
+        pub template_id: String,
+        pub goal: i32,
+        pub progress: i32,
+        /// Rotation day the quest has been issued for.
+        pub day: i32,
+        pub can_skip_free: bool,
+        /// The prize of the quest has been paid out.
+        pub rewarded: bool,
+    }
+
+    /// Weekly quest of a user: completing all daily quests on consecutive days.
+    #[derive(Clone, FromRow, Debug, PartialEq, Eq)]
+    pub struct UserWeeklyQuest {
+        pub user_id: String,
+        /// Amount of completed weeks.
+        pub level: i32,
+        /// Consecutive days of the current week on which all daily quests have been completed.
+        pub streak: i32,
+        /// Rotation day on which the streak has last been advanced.
+        pub day: i32,
+    }
+}
+
+/// Persistent storage for users, their credentials and login tokens.
+/// Every backend must be usable concurrently. Queries are not serialized by the caller.
+#[async_trait]
+pub trait Storage : Send + Sync {
+    async fn find_user(&self, user_id: &str) -> anyhow::Result<Option<model::User>>;
+    async fn user_exists(&self, user_id: &str) -> anyhow::Result<bool>;
+
+    /// Create a new user including its authentication and starting inventory items.
+    /// Either all or none of the entries will be created.
+    async fn create_user(&self, user: &model::User, authentication: &model::UserAuthentication, items: &[(String, i32)]) -> anyhow::Result<()>;
+
+    async fn find_authentication(&self, login_user: &str) -> anyhow::Result<Option<model::UserAuthentication>>;
+
+    async fn create_authentication_token(&self, token: &model::UserAuthenticationToken) -> anyhow::Result<()>;
+
+    /// Remove the token. Returns the token if it has not expired at the given timestamp.
+    async fn use_authentication_token(&self, token_hash: &str, timestamp: DateTime<Utc>) -> anyhow::Result<Option<model::UserAuthenticationToken>>;
+
+    async fn find_user_authentication_tokens(&self, user_id: &str) -> anyhow::Result<Vec<model::UserAuthenticationToken>>;
+
+    /// Returns `false` if the token does not exist.
+    async fn remove_authentication_token(&self, token_hash: &str) -> anyhow::Result<bool>;
+
+    /// Remove all tokens of the user. Returns the amount of removed tokens.
+    async fn remove_user_authentication_tokens(&self, user_id: &str) -> anyhow::Result<u64>;
+
+    /// Add (or remove if negative) crystals to the users balance.
+    /// Returns the new balance or `None` if the user does not exist, can not afford the amount or the balance would overflow.
+    async fn add_user_crystals(&self, user_id: &str, amount: i32) -> anyhow::Result<Option<i32>>;
+
+    /// Add experience to the user.
+    /// Returns the new experience or `None` if the user does not exist or the experience would overflow.
+    async fn add_user_experience(&self, user_id: &str, amount: i32) -> anyhow::Result<Option<i32>>;
+
+    /// Count the users with more experience than given and the total amount of users.
+    async fn count_users_by_experience(&self, experience: i32) -> anyhow::Result<(i64, i64)>;
+
+    /// Set the timestamp until which the user has a premium account.
+    /// Returns `false` if the user does not exist.
+    async fn set_user_premium(&self, user_id: &str, until: Option<DateTime<Utc>>) -> anyhow::Result<bool>;
+
+    /// Set the timestamp until which the user receives double crystals.
+    /// Returns `false` if the user does not exist.
+    async fn set_user_double_crystals(&self, user_id: &str, until: Option<DateTime<Utc>>) -> anyhow::Result<bool>;
+
+    /// Returns `false` if the user does not exist.
+    async fn set_user_moderator_level(&self, user_id: &str, level: i32) -> anyhow::Result<bool>;
+
+    /// Find the user who confirmed the given email.
+    async fn find_user_by_email(&self, email: &str) -> anyhow::Result<Option<model::User>>;
+
+    /// Returns `false` if the user does not exist.
+    async fn set_user_email(&self, user_id: &str, email: Option<&str>, confirmed: bool) -> anyhow::Result<bool>;
+
+    /// Returns `false` if the user does not exist.
+    async fn set_user_password(&self, user_id: &str, password_hash: &str, password_salt: &str) -> anyhow::Result<bool>;
+
+    /// Create the token or replace the current token of the same kind.
+    async fn set_user_email_token(&self, token: &model::UserEmailToken) -> anyhow::Result<()>;
+
+    /// Remove the token. Returns the token if it has not expired at the given timestamp.
+    async fn use_user_email_token(&self, token: &str, timestamp: DateTime<Utc>) -> anyhow::Result<Option<model::UserEmailToken>>;
+
+    /// Find the punishment of the given kind. The punishment might already be expired.
+    async fn find_user_punishment(&self, user_id: &str, kind: model::PunishmentKind) -> anyhow::Result<Option<model::UserPunishment>>;
+
+    /// Create the punishment or replace the current punishment of the same kind.
+    async fn set_user_punishment(&self, punishment: &model::UserPunishment) -> anyhow::Result<()>;
+
+    /// Returns `false` if the user had no punishment of the given kind.
+    async fn remove_user_punishment(&self, user_id: &str, kind: model::PunishmentKind) -> anyhow::Result<bool>;
+
+    /// Returns `false` if the code already exists.
+    async fn create_invite_code(&self, invite_code: &model::InviteCode) -> anyhow::Result<bool>;
+
+    async fn find_invite_code(&self, code: &str) -> anyhow::Result<Option<model::InviteCode>>;
+
+    /// Count a use of the code if it is usable at the given timestamp.
+    /// Returns `false` if the code does not exist, has expired or has no uses left.
+    async fn use_invite_code(&self, code: &str, timestamp: DateTime<Utc>) -> anyhow::Result<bool>;
+
+    /// Give back a use counted by `use_invite_code`, e.g. if the registration failed afterwards.
+    async fn release_invite_code(&self, code: &str) -> anyhow::Result<()>;
+
+    /// Users registered with the code ordered by their registration.
+    async fn find_invite_code_users(&self, code: &str) -> anyhow::Result<Vec<String>>;
+
+    async fn find_user_items(&self, user_id: &str) -> anyhow::Result<Vec<model::UserItem>>;
+
+    /// Add (or remove if negative) items to the users inventory.
+    /// Returns the new count or `None` if the user does not own enough items.
+    async fn add_user_item(&self, user_id: &str, item_id: &str, amount: i32) -> anyhow::Result<Option<i32>>;
+
+    async fn find_user_garage_items(&self, user_id: &str) -> anyhow::Result<Vec<model::UserGarageItem>>;
+
+    /// Add the equipment to the users garage or upgrade it to the given modification.
+    /// Returns `false` if the user already owns the given or a higher modification.
+    async fn upgrade_user_garage_item(&self, user_id: &str, item_id: &str, modification: i32) -> anyhow::Result<bool>;
+
+    /// Charge the price, upgrade the equipment and add the inventory items at once.
+    /// Nothing is changed unless the whole purchase can be applied.
+    async fn apply_purchase(&self, user_id: &str, price: i32, equipment: &[(String, i32)], items: &[(String, i32)]) -> anyhow::Result<model::PurchaseResult>;
+
+    /// Mark the equipment as (un)mounted.
+    /// Returns `false` if the user does not own the equipment.
+    async fn set_user_garage_item_mounted(&self, user_id: &str, item_id: &str, mounted: bool) -> anyhow::Result<bool>;
+
+    async fn find_user_friends(&self, user_id: &str) -> anyhow::Result<Vec<model::UserFriend>>;
+
+    /// Create an outgoing request for the user and an incoming request for the friend.
+    /// Returns `false` if both users already have a relation.
+    async fn create_friend_request(&self, user_id: &str, friend_id: &str) -> anyhow::Result<bool>;
+
+    /// Accept the incoming request of the friend.
+    /// Returns `false` if there is no such request.
+    async fn accept_friend_request(&self, user_id: &str, friend_id: &str) -> anyhow::Result<bool>;
+
+    /// Remove the relation for both users if it has the given state from the view of the user.
+    async fn remove_friend(&self, user_id: &str, friend_id: &str, state: model::FriendState) -> anyhow::Result<bool>;
+
+    async fn set_friend_viewed(&self, user_id: &str, friend_id: &str) -> anyhow::Result<()>;
+
+    async fn find_user_quests(&self, user_id: &str) -> anyhow::Result<Vec<model::UserQuest>>;
+
+    /// Replace all quests of the user with the given quests.
+    async fn set_user_quests(&self, user_id: &str, quests: &[model::UserQuest]) -> anyhow::Result<()>;
+
+    /// Replace a quest which has not been rewarded yet with a new quest.
+    /// A free skip requires the old quest to be skippable for free and revokes the free skip of all other quests.
+    /// Returns `false` if the old quest can not be replaced.
+    async fn replace_user_quest(&self, user_id: &str, quest_id: i32, quest: &model::UserQuest, free_skip: bool) -> anyhow::Result<bool>;
+
+    /// Add progress to a quest which has not been rewarded yet. The progress will never exceed the goal.
+    /// Returns the new progress or `None` if there is no such quest.
+    async fn add_user_quest_progress(&self, user_id: &str, quest_id: i32, amount: i32) -> anyhow::Result<Option<i32>>;
+
+    /// Mark a completed quest as rewarded.
+    /// Returns `false` if the quest is not completed or has already been rewarded.
+    async fn set_user_quest_rewarded(&self, user_id: &str, quest_id: i32) -> anyhow::Result<bool>;
+
+    async fn find_user_weekly_quest(&self, user_id: &str) -> anyhow::Result<Option<model::UserWeeklyQuest>>;
+
+    /// Create or replace the weekly quest if it has last been advanced before its new day.
+    /// Returns `false` if the weekly quest has already been advanced on that day.
+    async fn advance_user_weekly_quest(&self, weekly_quest: &model::UserWeeklyQuest) -> anyhow::Result<bool>;
+}
+
+pub type StorageHandle = Arc<dyn Storage>;
+
+/// Open the storage backend described by the given url.
+/// Supported are `sqlite://<file>`, `postgres://<connection>` and `memory://`.
+pub async fn open_storage(url: &str) -> anyhow::Result<StorageHandle> {
+    let (scheme, location) = url.split_once("://")
+        .unwrap_or((url, ""));
+
+    let storage: StorageHandle = match scheme {
+        "sqlite" => Arc::new(SqliteStorage::open(location).await?),
+        "postgres" | "postgresql" => Arc::new(PostgresStorage::open(url).await?),
+        "memory" => Arc::new(MemoryStorage::new()),
+        _ => anyhow::bail!("unknown storage backend {}", scheme)
+    };
+
+    Ok(storage)
+}
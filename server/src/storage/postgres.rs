@@ -0,0 +1,681 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::{PgPool, postgres::PgPoolOptions};
+
+use super::{Storage, model};
+
+/// Storage backed by a Postgres server.
+/// Queries are executed through a connection pool.
+pub struct PostgresStorage {
+    pool: PgPool,
+}
+
+impl PostgresStorage {
+    pub async fn open(url: &str) -> anyhow::Result<Self> {
+        let pool = PgPoolOptions::new()
+            .max_connections(16)
+            .connect(url)
+            .await?;
+
+        sqlx::migrate!("./migrations/postgres")
+            .run(&pool)
+            .await?;
+
+        Ok(Self { pool })
+    }
+}
+
+#[async_trait]
+impl Storage for PostgresStorage {
+    async fn find_user(&self, user_id: &str) -> anyhow::Result<Option<model::User>> {
+        let result = sqlx::query_as::<_, model::User>(r#"SELECT * FROM "user" WHERE "user_id" = $1"#)
+            .bind(user_id)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(result)
+    }
+
+    async fn user_exists(&self, user_id: &str) -> anyhow::Result<bool> {
+        let result = sqlx::query(r#"SELECT 1 FROM "user" WHERE "user_id" = $1"#)
+            .bind(user_id)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(result.is_some())
+    }
+
+    async fn create_user(&self, user: &model::User, authentication: &model::UserAuthentication, items: &[(String, i32)]) -> anyhow::Result<()> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query(
+            r#"INSERT INTO "user"("user_id", "email", "email_confirmed", "timestamp_register", "timestamp_active", "crystals", "double_crystals", "experience", "premium", "moderator_level", "invite_code")
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)"#
+        )
+            .bind(&user.user_id)
+            .bind(&user.email)
+            .bind(user.email_confirmed)
+            .bind(&user.timestamp_register)
+            .bind(&user.timestamp_active)
+            .bind(user.crystals)
+            .bind(&user.double_crystals)
+            .bind(user.experience)
+            .bind(&user.premium)
+            .bind(user.moderator_level)
+            .bind(&user.invite_code)
+            .execute(&mut tx)
+            .await?;
+
+        sqlx::query(
+            r#"INSERT INTO "user_authentication"("user_id", "login_user", "password_hash", "password_salt")
+            VALUES ($1, $2, $3, $4)"#
+        )
+            .bind(&authentication.user_id)
+            .bind(&authentication.login_user)
+            .bind(&authentication.password_hash)
+            .bind(&authentication.password_salt)
+            .execute(&mut tx)
+            .await?;
+
+        for (item_id, count) in items {
+            sqlx::query(r#"INSERT INTO "user_item"("user_id", "item_id", "count") VALUES ($1, $2, $3) ON CONFLICT("user_id", "item_id") DO UPDATE SET "count" = "user_item"."count" + $3;"#)
+                .bind(&user.user_id)
+                .bind(item_id)
+                .bind(count)
+                .execute(&mut tx)
+                .await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn find_authentication(&self, login_user: &str) -> anyhow::Result<Option<model::UserAuthentication>> {
+        let result = sqlx::query_as::<_, model::UserAuthentication>(
+            r#"SELECT * FROM "user_authentication" WHERE "login_user" = $1"#
+        )
+            .bind(login_user)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(result)
+    }
+
+    async fn create_authentication_token(&self, token: &model::UserAuthenticationToken) -> anyhow::Result<()> {
+        sqlx::query(r#"INSERT INTO "user_authentication_token"("token_hash", "user_id", "timestamp_created", "timestamp_expires") VALUES ($1, $2, $3, $4)"#)
+            .bind(&token.token_hash)
+            .bind(&token.user_id)
+            .bind(&token.timestamp_created)
+            .bind(&token.timestamp_expires)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn use_authentication_token(&self, token_hash: &str, timestamp: DateTime<Utc>) -> anyhow::Result<Option<model::UserAuthenticationToken>> {
+        let result = sqlx::query_as::<_, model::UserAuthenticationToken>(r#"DELETE FROM "user_authentication_token" WHERE "token_hash" = $1 RETURNING *;"#)
+            .bind(token_hash)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(result.filter(|token| token.timestamp_expires > timestamp))
+    }
+
+    async fn find_user_authentication_tokens(&self, user_id: &str) -> anyhow::Result<Vec<model::UserAuthenticationToken>> {
+        let result = sqlx::query_as::<_, model::UserAuthenticationToken>(r#"SELECT * FROM "user_authentication_token" WHERE "user_id" = $1"#)
+            .bind(user_id)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(result)
+    }
+
+    async fn remove_authentication_token(&self, token_hash: &str) -> anyhow::Result<bool> {
+        let result = sqlx::query(r#"DELETE FROM "user_authentication_token" WHERE "token_hash" = $1"#)
+            .bind(token_hash)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn remove_user_authentication_tokens(&self, user_id: &str) -> anyhow::Result<u64> {
+        let result = sqlx::query(r#"DELETE FROM "user_authentication_token" WHERE "user_id" = $1"#)
+            .bind(user_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn add_user_crystals(&self, user_id: &str, amount: i32) -> anyhow::Result<Option<i32>> {
+        let result = sqlx::query_scalar::<_, i32>(r#"UPDATE "user" SET "crystals" = "crystals" + $1 WHERE "user_id" = $2 AND "crystals"::BIGINT + $1 BETWEEN 0 AND 2147483647 RETURNING "crystals";"#)
+            .bind(amount)
+            .bind(user_id)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(result)
+    }
+
+    async fn add_user_experience(&self, user_id: &str, amount: i32) -> anyhow::Result<Option<i32>> {
+        let result = sqlx::query_scalar::<_, i32>(r#"UPDATE "user" SET "experience" = "experience" + $1 WHERE "user_id" = $2 AND "experience"::BIGINT + $1 BETWEEN -2147483648 AND 2147483647 RETURNING "experience";"#)
+            .bind(amount)
+            .bind(user_id)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(result)
+    }
+
+    async fn count_users_by_experience(&self, experience: i32) -> anyhow::Result<(i64, i64)> {
+        let result = sqlx::query_as::<_, (i64, i64)>(r#"SELECT COUNT(CASE WHEN "experience" > $1 THEN 1 END), COUNT(*) FROM "user""#)
+            .bind(experience)
+            .fetch_one(&self.pool)
+            .await?;
+
+        Ok(result)
+    }
+
+    async fn set_user_premium(&self, user_id: &str, until: Option<DateTime<Utc>>) -> anyhow::Result<bool> {
+        let result = sqlx::query(r#"UPDATE "user" SET "premium" = $1 WHERE "user_id" = $2"#)
+            .bind(until)
+            .bind(user_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn set_user_double_crystals(&self, user_id: &str, until: Option<DateTime<Utc>>) -> anyhow::Result<bool> {
+        let result = sqlx::query(r#"UPDATE "user" SET "double_crystals" = $1 WHERE "user_id" = $2"#)
+            .bind(until)
+            .bind(user_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn set_user_moderator_level(&self, user_id: &str, level: i32) -> anyhow::Result<bool> {
+        let result = sqlx::query(r#"UPDATE "user" SET "moderator_level" = $1 WHERE "user_id" = $2"#)
+            .bind(level)
+            .bind(user_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn find_user_by_email(&self, email: &str) -> anyhow::Result<Option<model::User>> {
+        let result = sqlx::query_as::<_, model::User>(r#"SELECT * FROM "user" WHERE "email" = $1 AND "email_confirmed" = $2"#)
+            .bind(email)
+            .bind(true)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(result)
+    }
+
+    async fn set_user_email(&self, user_id: &str, email: Option<&str>, confirmed: bool) -> anyhow::Result<bool> {
+        let result = sqlx::query(r#"UPDATE "user" SET "email" = $1, "email_confirmed" = $2 WHERE "user_id" = $3"#)
+            .bind(email)
+            .bind(confirmed)
+            .bind(user_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn set_user_password(&self, user_id: &str, password_hash: &str, password_salt: &str) -> anyhow::Result<bool> {
+        let result = sqlx::query(r#"UPDATE "user_authentication" SET "password_hash" = $1, "password_salt" = $2 WHERE "user_id" = $3"#)
+            .bind(password_hash)
+            .bind(password_salt)
+            .bind(user_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn set_user_email_token(&self, token: &model::UserEmailToken) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"INSERT INTO "user_email_token"("user_id", "kind", "token", "email", "timestamp_created", "timestamp_expires") VALUES ($1, $2, $3, $4, $5, $6)
+                ON CONFLICT("user_id", "kind") DO UPDATE SET "token" = $3, "email" = $4, "timestamp_created" = $5, "timestamp_expires" = $6;"#
+        )
+            .bind(&token.user_id)
+            .bind(token.kind)
+            .bind(&token.token)
+            .bind(&token.email)
+            .bind(&token.timestamp_created)
+            .bind(&token.timestamp_expires)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn use_user_email_token(&self, token: &str, timestamp: DateTime<Utc>) -> anyhow::Result<Option<model::UserEmailToken>> {
+        let result = sqlx::query_as::<_, model::UserEmailToken>(r#"DELETE FROM "user_email_token" WHERE "token" = $1 RETURNING *"#)
+            .bind(token)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(result.filter(|token| token.timestamp_expires > timestamp))
+    }
+
+    async fn find_user_punishment(&self, user_id: &str, kind: model::PunishmentKind) -> anyhow::Result<Option<model::UserPunishment>> {
+        let result = sqlx::query_as::<_, model::UserPunishment>(r#"SELECT * FROM "user_punishment" WHERE "user_id" = $1 AND "kind" = $2"#)
+            .bind(user_id)
+            .bind(kind)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(result)
+    }
+
+    async fn set_user_punishment(&self, punishment: &model::UserPunishment) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"INSERT INTO "user_punishment"("user_id", "kind", "reason", "issued_by", "timestamp_created", "timestamp_expires") VALUES ($1, $2, $3, $4, $5, $6)
+                ON CONFLICT("user_id", "kind") DO UPDATE SET "reason" = $3, "issued_by" = $4, "timestamp_created" = $5, "timestamp_expires" = $6;"#
+        )
+            .bind(&punishment.user_id)
+            .bind(punishment.kind)
+            .bind(&punishment.reason)
+            .bind(&punishment.issued_by)
+            .bind(&punishment.timestamp_created)
+            .bind(&punishment.timestamp_expires)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn remove_user_punishment(&self, user_id: &str, kind: model::PunishmentKind) -> anyhow::Result<bool> {
+        let result = sqlx::query(r#"DELETE FROM "user_punishment" WHERE "user_id" = $1 AND "kind" = $2"#)
+            .bind(user_id)
+            .bind(kind)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn create_invite_code(&self, invite_code: &model::InviteCode) -> anyhow::Result<bool> {
+        let result = sqlx::query(
+            r#"INSERT INTO "invite_code"("code", "created_by", "max_uses", "uses", "timestamp_created", "timestamp_expires") VALUES ($1, $2, $3, $4, $5, $6)
+                ON CONFLICT("code") DO NOTHING;"#
+        )
+            .bind(&invite_code.code)
+            .bind(&invite_code.created_by)
+            .bind(invite_code.max_uses)
+            .bind(invite_code.uses)
+            .bind(&invite_code.timestamp_created)
+            .bind(&invite_code.timestamp_expires)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn find_invite_code(&self, code: &str) -> anyhow::Result<Option<model::InviteCode>> {
+        let result = sqlx::query_as::<_, model::InviteCode>(r#"SELECT * FROM "invite_code" WHERE "code" = $1"#)
+            .bind(code)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(result)
+    }
+
+    async fn use_invite_code(&self, code: &str, timestamp: DateTime<Utc>) -> anyhow::Result<bool> {
+        let result = sqlx::query(
+            r#"UPDATE "invite_code" SET "uses" = "uses" + 1
+                WHERE "code" = $1 AND "uses" < "max_uses" AND ("timestamp_expires" IS NULL OR "timestamp_expires" > $2)"#
+        )
+            .bind(code)
+            .bind(timestamp)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn release_invite_code(&self, code: &str) -> anyhow::Result<()> {
+        sqlx::query(r#"UPDATE "invite_code" SET "uses" = "uses" - 1 WHERE "code" = $1 AND "uses" > 0"#)
+            .bind(code)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn find_invite_code_users(&self, code: &str) -> anyhow::Result<Vec<String>> {
+        let result = sqlx::query_scalar::<_, String>(r#"SELECT "user_id" FROM "user" WHERE "invite_code" = $1 ORDER BY "timestamp_register""#)
+            .bind(code)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(result)
+    }
+
+    async fn find_user_items(&self, user_id: &str) -> anyhow::Result<Vec<model::UserItem>> {
+        let result = sqlx::query_as::<_, model::UserItem>(r#"SELECT * FROM "user_item" WHERE "user_id" = $1"#)
+            .bind(user_id)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(result)
+    }
+
+    async fn add_user_item(&self, user_id: &str, item_id: &str, amount: i32) -> anyhow::Result<Option<i32>> {
+        /* only existing entries can be decreased */
+        let query = if amount < 0 {
+            r#"UPDATE "user_item" SET "count" = "count" + $3 WHERE "user_id" = $1 AND "item_id" = $2 AND "count" + $3 >= 0 RETURNING "count";"#
+        } else {
+            r#"INSERT INTO "user_item"("user_id", "item_id", "count") VALUES ($1, $2, $3) ON CONFLICT("user_id", "item_id") DO UPDATE SET "count" = "user_item"."count" + $3 RETURNING "count";"#
+        };
+
+        let result = sqlx::query_scalar::<_, i32>(query)
+            .bind(user_id)
+            .bind(item_id)
+            .bind(amount)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(result)
+    }
+
+    async fn find_user_garage_items(&self, user_id: &str) -> anyhow::Result<Vec<model::UserGarageItem>> {
+        let result = sqlx::query_as::<_, model::UserGarageItem>(r#"SELECT * FROM "user_garage_item" WHERE "user_id" = $1"#)
+            .bind(user_id)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(result)
+    }
+
+    async fn upgrade_user_garage_item(&self, user_id: &str, item_id: &str, modification: i32) -> anyhow::Result<bool> {
+        let result = sqlx::query_scalar::<_, i32>(
+            r#"INSERT INTO "user_garage_item"("user_id", "item_id", "modification") VALUES ($1, $2, $3)
+                ON CONFLICT("user_id", "item_id") DO UPDATE SET "modification" = $3 WHERE "user_garage_item"."modification" < $3
+                RETURNING "modification";"#
+        )
+            .bind(user_id)
+            .bind(item_id)
+            .bind(modification)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(result.is_some())
+    }
+
+    async fn apply_purchase(&self, user_id: &str, price: i32, equipment: &[(String, i32)], items: &[(String, i32)]) -> anyhow::Result<model::PurchaseResult> {
+        if items.iter().any(|(_, count)| *count < 0) {
+            anyhow::bail!("purchased items must not be negative");
+        }
+
+        let mut tx = self.pool.begin().await?;
+
+        let crystals = sqlx::query_scalar::<_, i32>(r#"UPDATE "user" SET "crystals" = "crystals" - $1 WHERE "user_id" = $2 AND "crystals"::BIGINT - $1 BETWEEN 0 AND 2147483647 RETURNING "crystals";"#)
+            .bind(price)
+            .bind(user_id)
+            .fetch_optional(&mut tx)
+            .await?;
+        let crystals = match crystals {
+            Some(crystals) => crystals,
+            None => return Ok(model::PurchaseResult::InsufficientCrystals),
+        };
+
+        for (item_id, modification) in equipment {
+            let result = sqlx::query_scalar::<_, i32>(
+                r#"INSERT INTO "user_garage_item"("user_id", "item_id", "modification") VALUES ($1, $2, $3)
+                    ON CONFLICT("user_id", "item_id") DO UPDATE SET "modification" = $3 WHERE "user_garage_item"."modification" < $3
+                    RETURNING "modification";"#
+            )
+                .bind(user_id)
+                .bind(item_id)
+                .bind(modification)
+                .fetch_optional(&mut tx)
+                .await?;
+            if result.is_none() {
+                return Ok(model::PurchaseResult::AlreadyOwned);
+            }
+        }
+
+        for (item_id, count) in items {
+            sqlx::query(r#"INSERT INTO "user_item"("user_id", "item_id", "count") VALUES ($1, $2, $3) ON CONFLICT("user_id", "item_id") DO UPDATE SET "count" = "user_item"."count" + $3;"#)
+                .bind(user_id)
+                .bind(item_id)
+                .bind(count)
+                .execute(&mut tx)
+                .await?;
+        }
+
+        tx.commit().await?;
+        Ok(model::PurchaseResult::Applied(crystals))
+    }
+
+    async fn set_user_garage_item_mounted(&self, user_id: &str, item_id: &str, mounted: bool) -> anyhow::Result<bool> {
+        let result = sqlx::query(r#"UPDATE "user_garage_item" SET "mounted" = $3 WHERE "user_id" = $1 AND "item_id" = $2;"#)
+            .bind(user_id)
+            .bind(item_id)
+            .bind(mounted)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn find_user_friends(&self, user_id: &str) -> anyhow::Result<Vec<model::UserFriend>> {
+        let result = sqlx::query_as::<_, model::UserFriend>(r#"SELECT * FROM "user_friend" WHERE "user_id" = $1"#)
+            .bind(user_id)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(result)
+    }
+
+    async fn create_friend_request(&self, user_id: &str, friend_id: &str) -> anyhow::Result<bool> {
+        let mut tx = self.pool.begin().await?;
+
+        let result = sqlx::query(r#"INSERT INTO "user_friend"("user_id", "friend_id", "state", "viewed") VALUES ($1, $2, $3, TRUE) ON CONFLICT DO NOTHING;"#)
+            .bind(user_id)
+            .bind(friend_id)
+            .bind(model::FriendState::Outgoing)
+            .execute(&mut tx)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Ok(false);
+        }
+
+        let result = sqlx::query(r#"INSERT INTO "user_friend"("user_id", "friend_id", "state", "viewed") VALUES ($1, $2, $3, FALSE) ON CONFLICT DO NOTHING;"#)
+            .bind(friend_id)
+            .bind(user_id)
+            .bind(model::FriendState::Incoming)
+            .execute(&mut tx)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Ok(false);
+        }
+
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    async fn accept_friend_request(&self, user_id: &str, friend_id: &str) -> anyhow::Result<bool> {
+        let mut tx = self.pool.begin().await?;
+
+        let result = sqlx::query(r#"UPDATE "user_friend" SET "state" = $3, "viewed" = TRUE WHERE "user_id" = $1 AND "friend_id" = $2 AND "state" = $4;"#)
+            .bind(user_id)
+            .bind(friend_id)
+            .bind(model::FriendState::Accepted)
+            .bind(model::FriendState::Incoming)
+            .execute(&mut tx)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Ok(false);
+        }
+
+        sqlx::query(r#"UPDATE "user_friend" SET "state" = $3, "viewed" = FALSE WHERE "user_id" = $1 AND "friend_id" = $2;"#)
+            .bind(friend_id)
+            .bind(user_id)
+            .bind(model::FriendState::Accepted)
+            .execute(&mut tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    async fn remove_friend(&self, user_id: &str, friend_id: &str, state: model::FriendState) -> anyhow::Result<bool> {
+        let mut tx = self.pool.begin().await?;
+
+        let result = sqlx::query(r#"DELETE FROM "user_friend" WHERE "user_id" = $1 AND "friend_id" = $2 AND "state" = $3;"#)
+            .bind(user_id)
+            .bind(friend_id)
+            .bind(state)
+            .execute(&mut tx)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Ok(false);
+        }
+
+        sqlx::query(r#"DELETE FROM "user_friend" WHERE "user_id" = $1 AND "friend_id" = $2;"#)
+            .bind(friend_id)
+            .bind(user_id)
+            .execute(&mut tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    async fn set_friend_viewed(&self, user_id: &str, friend_id: &str) -> anyhow::Result<()> {
+        sqlx::query(r#"UPDATE "user_friend" SET "viewed" = TRUE WHERE "user_id" = $1 AND "friend_id" = $2;"#)
+            .bind(user_id)
+            .bind(friend_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn find_user_quests(&self, user_id: &str) -> anyhow::Result<Vec<model::UserQuest>> {
+        let result = sqlx::query_as::<_, model::UserQuest>(r#"SELECT * FROM "user_quest" WHERE "user_id" = $1"#)
+            .bind(user_id)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(result)
+    }
+
+    async fn set_user_quests(&self, user_id: &str, quests: &[model::UserQuest]) -> anyhow::Result<()> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query(r#"DELETE FROM "user_quest" WHERE "user_id" = $1;"#)
+            .bind(user_id)
+            .execute(&mut tx)
+            .await?;
+
+        for quest in quests {
+            sqlx::query(r#"INSERT INTO "user_quest"("user_id", "quest_id", "template_id", "goal", "progress", "day", "can_skip_free", "rewarded") VALUES ($1, $2, $3, $4, $5, $6, $7, $8);"#)
+                .bind(user_id)
+                .bind(quest.quest_id)
+                .bind(&quest.template_id)
+                .bind(quest.goal)
+                .bind(quest.progress)
+                .bind(quest.day)
+                .bind(quest.can_skip_free)
+                .bind(quest.rewarded)
+                .execute(&mut tx)
+                .await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn replace_user_quest(&self, user_id: &str, quest_id: i32, quest: &model::UserQuest, free_skip: bool) -> anyhow::Result<bool> {
+        let mut tx = self.pool.begin().await?;
+
+        let result = sqlx::query(r#"DELETE FROM "user_quest" WHERE "user_id" = $1 AND "quest_id" = $2 AND "rewarded" = FALSE AND ("can_skip_free" = TRUE OR $3 = FALSE);"#)
+            .bind(user_id)
+            .bind(quest_id)
+            .bind(free_skip)
+            .execute(&mut tx)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Ok(false);
+        }
+
+        sqlx::query(r#"INSERT INTO "user_quest"("user_id", "quest_id", "template_id", "goal", "progress", "day", "can_skip_free", "rewarded") VALUES ($1, $2, $3, $4, $5, $6, $7, $8);"#)
+            .bind(user_id)
+            .bind(quest.quest_id)
+            .bind(&quest.template_id)
+            .bind(quest.goal)
+            .bind(quest.progress)
+            .bind(quest.day)
+            .bind(quest.can_skip_free)
+            .bind(quest.rewarded)
+            .execute(&mut tx)
+            .await?;
+
+        if free_skip {
+            sqlx::query(r#"UPDATE "user_quest" SET "can_skip_free" = FALSE WHERE "user_id" = $1;"#)
+                .bind(user_id)
+                .execute(&mut tx)
+                .await?;
+        }
+
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    async fn add_user_quest_progress(&self, user_id: &str, quest_id: i32, amount: i32) -> anyhow::Result<Option<i32>> {
+        let result = sqlx::query_scalar::<_, i32>(r#"UPDATE "user_quest" SET "progress" = LEAST("progress" + $3, "goal") WHERE "user_id" = $1 AND "quest_id" = $2 AND "rewarded" = FALSE RETURNING "progress";"#)
+            .bind(user_id)
+            .bind(quest_id)
+            .bind(amount)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(result)
+    }
+
+    async fn set_user_quest_rewarded(&self, user_id: &str, quest_id: i32) -> anyhow::Result<bool> {
+        let result = sqlx::query(r#"UPDATE "user_quest" SET "rewarded" = TRUE WHERE "user_id" = $1 AND "quest_id" = $2 AND "rewarded" = FALSE AND "progress" >= "goal";"#)
+            .bind(user_id)
+            .bind(quest_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn find_user_weekly_quest(&self, user_id: &str) -> anyhow::Result<Option<model::UserWeeklyQuest>> {
+        let result = sqlx::query_as::<_, model::UserWeeklyQuest>(r#"SELECT * FROM "user_weekly_quest" WHERE "user_id" = $1"#)
+            .bind(user_id)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(result)
+    }
+
+    async fn advance_user_weekly_quest(&self, weekly_quest: &model::UserWeeklyQuest) -> anyhow::Result<bool> {
+        let result = sqlx::query_scalar::<_, i32>(
+            r#"INSERT INTO "user_weekly_quest"("user_id", "level", "streak", "day") VALUES ($1, $2, $3, $4)
+                ON CONFLICT("user_id") DO UPDATE SET "level" = $2, "streak" = $3, "day" = $4 WHERE "user_weekly_quest"."day" < $4
+                RETURNING "day";"#
+        )
+            .bind(&weekly_quest.user_id)
+            .bind(weekly_quest.level)
+            .bind(weekly_quest.streak)
+            .bind(weekly_quest.day)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(result.is_some())
+    }
+}
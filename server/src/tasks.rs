@@ -1,6 +1,7 @@
-use std::{task::{Waker, Poll, self}, pin::Pin, cell::RefCell};
+use std::{task::{Waker, Poll, self}, pin::Pin, cell::RefCell, sync::{Arc, Mutex}};
 
 use futures::{Future, FutureExt};
+use tokio::task::JoinHandle;
 use tracing::trace;
 
 use crate::client::{ClientComponent, Client};
@@ -88,4 +89,40 @@ impl Tasks {
             task_queue.extend(new_tasks.into_iter());
         }
     }
-}
\ No newline at end of file
+}
+
+/// Background tasks writing user state (crystals, experience, quest progress, ...) to the storage.
+/// The server awaits them on shutdown so no progress gets lost.
+#[derive(Default)]
+pub struct StorageTasks {
+    tasks: Mutex<Vec<JoinHandle<()>>>,
+}
+
+pub type StorageTasksHandle = Arc<StorageTasks>;
+
+impl StorageTasks {
+    pub fn spawn(&self, task: impl Future<Output = ()> + Send + 'static) {
+        let task = tokio::spawn(task);
+        if let Ok(mut tasks) = self.tasks.lock() {
+            tasks.retain(|task| !task.is_finished());
+            tasks.push(task);
+        }
+    }
+
+    /// Wait for all tasks including the tasks spawned while waiting.
+    pub async fn flush(&self) {
+        loop {
+            let tasks = match self.tasks.lock() {
+                Ok(mut tasks) => std::mem::take(&mut *tasks),
+                Err(_) => return,
+            };
+            if tasks.is_empty() {
+                return;
+            }
+
+            for task in tasks {
+                let _ = task.await;
+            }
+        }
+    }
+}
@@ -0,0 +1,147 @@
+use std::{collections::HashMap, hash::Hash, net::IpAddr, time::{Duration, Instant}};
+
+use crate::config::AuthenticationConfig;
+
+/// Entries kept per map before expired entries will be removed.
+const CLEANUP_THRESHOLD: usize = 1024;
+
+#[derive(Debug, Clone, Copy)]
+struct FailureWindow {
+    started: Instant,
+    failures: u32,
+}
+
+#[derive(Debug)]
+struct FailureCounter<K> {
+    windows: HashMap<K, FailureWindow>,
+}
+
+impl<K: Hash + Eq> FailureCounter<K> {
+    fn new() -> Self {
+        Self {
+            windows: HashMap::new(),
+        }
+    }
+
+    /// Time until the next attempt will be accepted.
+    fn retry_after(&self, key: &K, max_failures: u32, window: Duration, now: Instant) -> Option<Duration> {
+        let entry = self.windows.get(key)?;
+        let elapsed = now.saturating_duration_since(entry.started);
+        if elapsed >= window || entry.failures < max_failures {
+            return None;
+        }
+
+        Some(window - elapsed)
+    }
+
+    fn record_failure(&mut self, key: K, window: Duration, now: Instant) {
+        if self.windows.len() >= CLEANUP_THRESHOLD {
+            self.windows.retain(|_, entry| now.saturating_duration_since(entry.started) < window);
+        }
+
+        let entry = self.windows.entry(key).or_insert(FailureWindow { started: now, failures: 0 });
+        if now.saturating_duration_since(entry.started) >= window {
+            *entry = FailureWindow { started: now, failures: 0 };
+        }
+        entry.failures += 1;
+    }
+
+    fn reset(&mut self, key: &K) {
+        self.windows.remove(key);
+    }
+}
+
+/// Counts failed logins per account and per IP address and rejects further
+/// logins once too many logins failed within the window.
+#[derive(Debug)]
+pub struct LoginThrottle {
+    accounts: FailureCounter<String>,
+    addresses: FailureCounter<IpAddr>,
+}
+
+impl LoginThrottle {
+    pub fn new() -> Self {
+        Self {
+            accounts: FailureCounter::new(),
+            addresses: FailureCounter::new(),
+        }
+    }
+
+    /// Returns the time until a login will be accepted again or `None` if the login may be attempted.
+    /// Token logins have no account and are only throttled by their address.
+    pub fn retry_after(&self, config: &AuthenticationConfig, account: Option<&str>, address: IpAddr, now: Instant) -> Option<Duration> {
+        let window = Duration::from_secs(config.failed_login_window_seconds);
+        let account = account.and_then(|account| {
+            self.accounts.retry_after(&account.to_lowercase(), config.max_failed_logins_per_account, window, now)
+        });
+        let address = self.addresses.retry_after(&address, config.max_failed_logins_per_address, window, now);
+
+        account.max(address)
+    }
+
+    pub fn record_failure(&mut self, config: &AuthenticationConfig, account: Option<&str>, address: IpAddr, now: Instant) {
+        let window = Duration::from_secs(config.failed_login_window_seconds);
+        if let Some(account) = account {
+            self.accounts.record_failure(account.to_lowercase(), window, now);
+        }
+        self.addresses.record_failure(address, window, now);
+    }
+
+    /// Forget the failed logins of the account.
+    /// The failures of the address are kept so an attacker can not reset them with an own account.
+    pub fn record_success(&mut self, account: &str) {
+        self.accounts.reset(&account.to_lowercase());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{net::{IpAddr, Ipv4Addr}, time::{Duration, Instant}};
+
+    use crate::config::AuthenticationConfig;
+    use super::LoginThrottle;
+
+    #[test]
+    fn test_account_throttle() {
+        let config = AuthenticationConfig {
+            max_failed_logins_per_account: 2,
+            max_failed_logins_per_address: 10,
+            failed_login_window_seconds: 60,
+            ..Default::default()
+        };
+        let address = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let now = Instant::now();
+
+        let mut throttle = LoginThrottle::new();
+        throttle.record_failure(&config, Some("Tanker"), address, now);
+        assert_eq!(throttle.retry_after(&config, Some("tanker"), address, now), None);
+
+        throttle.record_failure(&config, Some("tanker"), address, now + Duration::from_secs(10));
+        assert_eq!(throttle.retry_after(&config, Some("tanker"), address, now + Duration::from_secs(20)), Some(Duration::from_secs(40)));
+        assert_eq!(throttle.retry_after(&config, Some("other"), address, now), None);
+        assert_eq!(throttle.retry_after(&config, Some("tanker"), address, now + Duration::from_secs(60)), None);
+
+        throttle.record_success("tanker");
+        assert_eq!(throttle.retry_after(&config, Some("tanker"), address, now), None);
+    }
+
+    #[test]
+    fn test_address_throttle() {
+        let config = AuthenticationConfig {
+            max_failed_logins_per_address: 3,
+            failed_login_window_seconds: 60,
+            ..Default::default()
+        };
+        let address = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let now = Instant::now();
+
+        let mut throttle = LoginThrottle::new();
+        for account in ["a", "b", "c"] {
+            throttle.record_failure(&config, Some(account), address, now);
+        }
+        throttle.record_success("c");
+
+        assert_eq!(throttle.retry_after(&config, None, address, now), Some(Duration::from_secs(60)));
+        assert_eq!(throttle.retry_after(&config, Some("d"), IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)), now), None);
+    }
+}
@@ -0,0 +1,155 @@
+use anyhow::Context;
+use fost_protocol::codec::ValidationStatus;
+use rand::Rng;
+use regex::Regex;
+
+use crate::{config::{ConfigHandle, RegisterConfig}, storage::StorageHandle};
+
+/// Length of the user id column.
+const MAX_STORED_UID_LENGTH: usize = 32;
+/// Free alternatives suggested for a taken username.
+const SUGGESTION_COUNT: usize = 3;
+const SUGGESTION_CANDIDATES: usize = 12;
+
+/// Check the length, the pattern and the forbidden words. Does not check if the username is free.
+fn check_format(uid: &str, config: &RegisterConfig, pattern: &Regex) -> ValidationStatus {
+    let length = uid.chars().count();
+    if length < config.min_uid_length {
+        return ValidationStatus::TooShort;
+    }
+
+    if length > config.max_uid_length.min(MAX_STORED_UID_LENGTH) {
+        return ValidationStatus::TooLong;
+    }
+
+    if !pattern.is_match(uid) {
+        return ValidationStatus::NotMatchPattern;
+    }
+
+    let lowercase_uid = uid.to_lowercase();
+    let forbidden = config.forbidden_uid_words.iter()
+        .filter(|word| !word.is_empty())
+        .any(|word| lowercase_uid.contains(&word.to_lowercase()));
+    if forbidden {
+        return ValidationStatus::Forbidden;
+    }
+
+    ValidationStatus::Correct
+}
+
+/// Alternatives for a taken username by appending random numbers.
+/// The username will be shortened if required to stay within the length limit.
+fn suggestion_candidates(uid: &str, config: &RegisterConfig) -> Vec<String> {
+    let max_length = config.max_uid_length.min(MAX_STORED_UID_LENGTH);
+    let mut rng = rand::thread_rng();
+    let mut candidates = Vec::with_capacity(SUGGESTION_CANDIDATES);
+    for index in 0..SUGGESTION_CANDIDATES {
+        /* use more digits the more candidates are required */
+        let digits = 2 + index as u32 / 4;
+        let suffix = rng.gen_range(10u32.pow(digits - 1)..10u32.pow(digits)).to_string();
+        let base = uid.chars()
+            .take(max_length.saturating_sub(suffix.len()))
+            .collect::<String>();
+
+        let candidate = base + &suffix;
+        if !candidates.contains(&candidate) {
+            candidates.push(candidate);
+        }
+    }
+
+    candidates
+}
+
+/// Validates usernames on registration.
+pub struct UidValidator {
+    storage: StorageHandle,
+    config: ConfigHandle,
+}
+
+impl UidValidator {
+    pub fn new(storage: StorageHandle, config: ConfigHandle) -> Self {
+        Self {
+            storage,
+            config,
+        }
+    }
+
+    fn register_config(&self) -> anyhow::Result<(RegisterConfig, Regex)> {
+        let config = self.config.read()
+            .ok()
+            .context("failed to read the config")?
+            .register.clone();
+
+        let pattern = Regex::new(&config.uid_pattern)
+            .with_context(|| format!("invalid username pattern {}", config.uid_pattern))?;
+
+        Ok((config, pattern))
+    }
+
+    /// Check the username format and if the username is still free.
+    pub async fn validate(&self, uid: &str) -> anyhow::Result<ValidationStatus> {
+        let (config, pattern) = self.register_config()?;
+        let status = check_format(uid, &config, &pattern);
+        if status != ValidationStatus::Correct {
+            return Ok(status);
+        }
+
+        if self.storage.user_exists(uid).await? {
+            return Ok(ValidationStatus::NotUnique);
+        }
+
+        Ok(ValidationStatus::Correct)
+    }
+
+    /// Free and valid alternatives for the username.
+    pub async fn suggest(&self, uid: &str) -> anyhow::Result<Vec<String>> {
+        let (config, pattern) = self.register_config()?;
+        let mut suggestions = Vec::with_capacity(SUGGESTION_COUNT);
+        for candidate in suggestion_candidates(uid, &config) {
+            if suggestions.len() >= SUGGESTION_COUNT {
+                break;
+            }
+
+            if check_format(&candidate, &config, &pattern) != ValidationStatus::Correct {
+                continue;
+            }
+
+            if !self.storage.user_exists(&candidate).await? {
+                suggestions.push(candidate);
+            }
+        }
+
+        Ok(suggestions)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use fost_protocol::codec::ValidationStatus;
+    use regex::Regex;
+
+    use crate::config::RegisterConfig;
+    use super::{check_format, suggestion_candidates};
+
+    #[test]
+    fn test_check_format() {
+        let config = RegisterConfig::default();
+        let pattern = Regex::new(&config.uid_pattern).unwrap();
+
+        assert_eq!(check_format("tanker_01", &config, &pattern), ValidationStatus::Correct);
+        assert_eq!(check_format("ab", &config, &pattern), ValidationStatus::TooShort);
+        assert_eq!(check_format(&"a".repeat(21), &config, &pattern), ValidationStatus::TooLong);
+        assert_eq!(check_format("tank er", &config, &pattern), ValidationStatus::NotMatchPattern);
+        assert_eq!(check_format("TheModerator", &config, &pattern), ValidationStatus::Forbidden);
+    }
+
+    #[test]
+    fn test_suggestion_candidates() {
+        let config = RegisterConfig::default();
+        let uid = "a".repeat(config.max_uid_length);
+        for candidate in suggestion_candidates(&uid, &config) {
+            assert_eq!(candidate.len(), config.max_uid_length);
+            assert!(candidate.starts_with("aaaaaaaaaaaaaaa"));
+        }
+    }
+}
@@ -1,68 +1,41 @@
-use std::{time::Duration, sync::{Arc, Mutex}, pin::Pin};
+use std::{collections::BTreeMap, time::{Duration, Instant}, sync::{Arc, Mutex}, pin::Pin, net::IpAddr};
 
-use chrono::Utc;
+use anyhow::Context;
+use argon2::{Argon2, PasswordHasher, PasswordVerifier, password_hash::{PasswordHash, SaltString}};
+use chrono::{DateTime, Utc};
 use futures::{Future, TryFutureExt};
 use futures::FutureExt;
 use sha2::{ Sha256, Digest };
 use rand::{distributions::Alphanumeric, Rng};
-use sqlx::Connection;
 
-use crate::server::{DatabaseConnection, DatabaseHandle};
-
-pub mod database {
-    use sqlx::FromRow;
-
-    #[derive(Clone, FromRow, Debug)]
-    pub struct User {
-        pub user_id: String,
-
-        pub email: Option<String>,
-        pub email_confirmed: bool,
-
-        pub timestamp_register: chrono::DateTime<chrono::Utc>,
-        pub timestamp_active: chrono::DateTime<chrono::Utc>,
-
-        pub crystals: u32,
-        pub double_crystals: Option<chrono::DateTime<chrono::Utc>>,
-
-        pub experience: u32,
-        pub premium: Option<chrono::DateTime<chrono::Utc>>,
-    }
-
-    #[derive(Clone, FromRow, Debug)]
-    pub struct UserAuthentication {
-        pub user_id: String,
-        pub login_user: String,
-        pub password_hash: String,
-        pub password_salt: String,
-    }
-
-    #[derive(Clone, FromRow, Debug)]
-    pub struct UserAuthenticationToken {
-        pub user_id: String,
-        pub timestamp_created: chrono::DateTime<chrono::Utc>,
-        pub timestamp_last_used: chrono::DateTime<chrono::Utc>,
-        pub token: String,
-    }
-}
+use crate::{LoginThrottle, config::{AuthenticationConfig, ConfigHandle}, storage::{Storage, StorageHandle, model}};
 
 #[derive(Debug)]
 pub enum AuthenticationResult {
     Success { user_id: String },
     InvalidCredentials,
     InvalidToken,
-    /* BanTemporary */
-    /* BanPermanent */
+    /// Too many failed credential logins. The credentials have not been checked.
+    Throttled { retry_after: Duration },
+    BanTemporary { reason: String, expires: DateTime<Utc> },
+    BanPermanent { reason: String },
 }
 
 pub struct UserRegistry {
-    database: DatabaseHandle
+    storage: StorageHandle,
+    config: ConfigHandle,
+    throttle: Arc<Mutex<LoginThrottle>>,
+    /// Items and their amount given to newly registered users.
+    starter_items: Vec<(String, i32)>,
 }
 
 impl UserRegistry {
-    pub fn new(database: DatabaseHandle) -> Self {
+    pub fn new(storage: StorageHandle, config: ConfigHandle, starter_items: Vec<(String, i32)>) -> Self {
         Self {
-            database
+            storage,
+            config,
+            throttle: Arc::new(Mutex::new(LoginThrottle::new())),
+            starter_items,
         }
     }
 }
@@ -85,53 +58,110 @@ pub enum Result<T, E> {
 }
 
 
+/// Password hashes created before Argon2 have been salted SHA-256 hashes.
+fn hash_password_legacy(password: &str, salt: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(salt.as_bytes());
+    hasher.update(password.as_bytes());
+    hasher.update(salt.as_bytes());
+    hex::encode(&hasher.finalize())
+}
+
+fn is_legacy_hash(password_hash: &str) -> bool {
+    !password_hash.starts_with('$')
+}
+
+/// Only the hash of login tokens is stored so a leaked database does not allow logins.
+fn token_hash(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+fn authentication_config(config: &ConfigHandle) -> anyhow::Result<AuthenticationConfig> {
+    config.read()
+        .ok()
+        .context("failed to read the config")
+        .map(|config| config.authentication.clone())
+}
+
 impl UserRegistry {
-    fn query_database<R: 'static, F>(&self, callback: impl FnOnce(&mut DatabaseConnection) -> F) -> impl Future<Output = anyhow::Result<R>>
-    where
-        F: Future<Output = anyhow::Result<R>>
-    {
-        let database = self.database.clone();
-        async move {
-            let mut database = database.lock().await;
-            callback(&mut *database).await;
-            drop(database);
-            anyhow::bail!("XXX");
-            // let result = {
-            //     (callback)(&mut *database).await
-            // };
-            // result
+    /// Hash the password with Argon2id. The returned PHC string contains the salt and the parameters.
+    pub(crate) async fn hash_password(password: String) -> anyhow::Result<String> {
+        tokio::task::spawn_blocking(move || {
+            let salt = SaltString::generate(&mut rand::rngs::OsRng);
+            Argon2::default()
+                .hash_password(password.as_bytes(), &salt)
+                .map(|hash| hash.to_string())
+                .map_err(|error| anyhow::anyhow!("failed to hash the password: {}", error))
+        }).await?
+    }
+
+    async fn verify_password(password: String, authentication: &model::UserAuthentication) -> anyhow::Result<bool> {
+        if is_legacy_hash(&authentication.password_hash) {
+            return Ok(hash_password_legacy(&password, &authentication.password_salt) == authentication.password_hash);
         }
+
+        let password_hash = authentication.password_hash.clone();
+        tokio::task::spawn_blocking(move || {
+            let password_hash = PasswordHash::new(&password_hash)
+                .map_err(|error| anyhow::anyhow!("invalid password hash: {}", error))?;
+
+            anyhow::Ok(Argon2::default().verify_password(password.as_bytes(), &password_hash).is_ok())
+        }).await?
     }
 
-    fn hash_password(password: &str, salt: &str) -> String {
-        let mut hasher = Sha256::new();
-        hasher.update(salt.as_bytes());
-        hasher.update(password.as_bytes());
-        hasher.update(salt.as_bytes());
-        hex::encode(&hasher.finalize())
+    /// Reject the authentication if the user is banned.
+    async fn check_ban(storage: &dyn Storage, user_id: String) -> anyhow::Result<AuthenticationResult> {
+        let ban = storage.find_user_punishment(&user_id, model::PunishmentKind::Ban).await?
+            .filter(|ban| ban.is_active(Utc::now()));
+
+        Ok(match ban {
+            Some(model::UserPunishment { reason, timestamp_expires: Some(expires), .. }) => AuthenticationResult::BanTemporary { reason, expires },
+            Some(model::UserPunishment { reason, timestamp_expires: None, .. }) => AuthenticationResult::BanPermanent { reason },
+            None => AuthenticationResult::Success { user_id },
+        })
     }
 
-    pub fn authenticate_with_credentials(&self, username: String, password: String) -> impl Future<Output = AuthenticationResult> {
-        let database = self.database.clone();
+    /// Legacy password hashes will be replaced by Argon2 hashes on a successful login.
+    pub fn authenticate_with_credentials(&self, username: String, password: String, address: IpAddr) -> impl Future<Output = AuthenticationResult> {
+        let storage = self.storage.clone();
+        let config = self.config.clone();
+        let throttle = self.throttle.clone();
         async move {
-            let mut database = database.lock().await;
-            let user_authentication = sqlx::query_as::<_, database::UserAuthentication>(
-                "SELECT * FROM `user_authentication` WHERE `login_user` = $1"
-            )
-                .bind(&username)
-                .fetch_optional(&mut *database).await?;
-            let user_authentication = if let Some(auth) = user_authentication {
-                auth
-            } else {
-                return anyhow::Ok(AuthenticationResult::InvalidCredentials);
+            let config = authentication_config(&config)?;
+            let retry_after = throttle.lock()
+                .ok()
+                .and_then(|throttle| throttle.retry_after(&config, Some(&username), address, Instant::now()));
+            if let Some(retry_after) = retry_after {
+                return anyhow::Ok(AuthenticationResult::Throttled { retry_after });
+            }
+
+            let user_authentication = storage.find_authentication(&username).await?;
+            let password_valid = match &user_authentication {
+                Some(authentication) => Self::verify_password(password.clone(), authentication).await?,
+                None => false,
             };
 
-            let hashed_password = Self::hash_password(&password, &user_authentication.password_salt);
-            anyhow::Ok(if hashed_password == user_authentication.password_hash {
-                AuthenticationResult::Success { user_id: username }
-            } else {
-                AuthenticationResult::InvalidCredentials
-            })
+            let user_authentication = match user_authentication {
+                Some(authentication) if password_valid => authentication,
+                _ => {
+                    if let Ok(mut throttle) = throttle.lock() {
+                        throttle.record_failure(&config, Some(&username), address, Instant::now());
+                    }
+                    return anyhow::Ok(AuthenticationResult::InvalidCredentials);
+                }
+            };
+
+            if let Ok(mut throttle) = throttle.lock() {
+                throttle.record_success(&username);
+            }
+
+            if is_legacy_hash(&user_authentication.password_hash) {
+                let password_hash = Self::hash_password(password).await?;
+                storage.set_user_password(&user_authentication.user_id, &password_hash, "").await?;
+                tracing::debug!("upgraded the password hash of {}", user_authentication.user_id);
+            }
+
+            Self::check_ban(storage.as_ref(), user_authentication.user_id).await
         }
         .unwrap_or_else(|err| {
             tracing::error!("failed to authenticate user via credentials: {}", err);
@@ -139,20 +169,31 @@ impl UserRegistry {
         })
     }
 
-    pub fn authenticate_with_token(&self, token: String) -> impl Future<Output = AuthenticationResult> {
-        let database = self.database.clone();
+    /// The token will be removed and must be replaced by a new token if the client should stay remembered.
+    /// Throttled token logins are rejected as invalid and the client falls back to the login form.
+    pub fn authenticate_with_token(&self, token: String, address: IpAddr) -> impl Future<Output = AuthenticationResult> {
+        let storage = self.storage.clone();
+        let config = self.config.clone();
+        let throttle = self.throttle.clone();
         async move {
-            let mut database = database.lock().await;
-            let user_token = sqlx::query_as::<_, database::UserAuthenticationToken>("UPDATE `user_authentication_token` SET `timestamp_last_used` = $1 WHERE `token` = $2 RETURNING *;")
-                .bind(Utc::now())
-                .bind(token)
-                .fetch_optional(&mut *database)
-                .await?;
-
-            anyhow::Ok(match user_token {
-                Some(token) => AuthenticationResult::Success { user_id: token.user_id },
-                None => AuthenticationResult::InvalidToken,
-            })
+            let config = authentication_config(&config)?;
+            let retry_after = throttle.lock()
+                .ok()
+                .and_then(|throttle| throttle.retry_after(&config, None, address, Instant::now()));
+            if retry_after.is_some() {
+                return anyhow::Ok(AuthenticationResult::InvalidToken);
+            }
+
+            let user_token = storage.use_authentication_token(&token_hash(&token), Utc::now()).await?;
+            match user_token {
+                Some(token) => Self::check_ban(storage.as_ref(), token.user_id).await,
+                None => {
+                    if let Ok(mut throttle) = throttle.lock() {
+                        throttle.record_failure(&config, None, address, Instant::now());
+                    }
+                    anyhow::Ok(AuthenticationResult::InvalidToken)
+                },
+            }
         }
         .unwrap_or_else(|err| {
             tracing::error!("failed to authenticate user via token: {}", err);
@@ -160,9 +201,13 @@ impl UserRegistry {
         })
     }
 
+    /// Create a new login token for a device of the user.
+    /// Expired tokens and the oldest tokens exceeding the token limit will be removed.
     pub fn create_authentication_token(&self, user: String) -> impl Future<Output = Option<String>> {
-        let database = self.database.clone();
+        let storage = self.storage.clone();
+        let config = self.config.clone();
         async move {
+            let config = authentication_config(&config)?;
             let token = rand::thread_rng()
                 .sample_iter(&Alphanumeric)
                 .take(64)
@@ -170,13 +215,20 @@ impl UserRegistry {
                 .collect::<String>();
             let now = Utc::now();
 
-            let mut database = database.lock().await;
-            sqlx::query("INSERT INTO `user_authentication_token`(`user_id`, `timestamp_created`, `timestamp_last_used`, `token`) VALUES ($1, $2, $2, $3)")
-                .bind(&user)
-                .bind(&now)
-                .bind(&token)
-                .execute(&mut *database)
-                .await?;
+            storage.create_authentication_token(&model::UserAuthenticationToken {
+                token_hash: token_hash(&token),
+                user_id: user.clone(),
+                timestamp_created: now,
+                timestamp_expires: now + chrono::Duration::days(config.token_expiry_days.max(1)),
+            }).await?;
+
+            let mut tokens = storage.find_user_authentication_tokens(&user).await?;
+            tokens.sort_by(|a, b| b.timestamp_created.cmp(&a.timestamp_created));
+            for (index, token) in tokens.iter().enumerate() {
+                if index >= config.max_tokens_per_user.max(1) || token.timestamp_expires <= now {
+                    storage.remove_authentication_token(&token.token_hash).await?;
+                }
+            }
 
             anyhow::Ok(Some(token))
         }
@@ -186,62 +238,59 @@ impl UserRegistry {
         })
     }
 
-    pub fn is_username_free(&self, username: String) -> impl Future<Output = bool> {
-        let database = self.database.clone();
+    /// Create a new user with the starter items and a premium account for the given duration.
+    /// A use of the invite code will be counted before the user is created.
+    pub fn register_user(&mut self, username: String, password: String, starter_premium: chrono::Duration, invite_code: Option<String>) -> impl Future<Output = bool> {
+        let storage = self.storage.clone();
+        let starter_items = self.starter_items.clone();
         async move {
-            let mut database = database.lock().await;
-            let user_found = sqlx::query("SELECT 1 FROM `user` WHERE `user_id` = $1")
-                .bind(&username)
-                .fetch_optional(&mut *database)
-                .await?
-                .is_none();
-
-            anyhow::Ok(user_found)
-        }
-        .unwrap_or_else(|err| {
-            tracing::error!("failed query if username already exists: {}", err);
-            false // prevent double user insertion by default
-        })
-    }
+            let hashed_password = Self::hash_password(password).await?;
+            let now = Utc::now();
 
-    pub fn register_user(&mut self, username: String, password: String) -> impl Future<Output = bool> {
-        let database = self.database.clone();
-        async move {
-            let password_salt = rand::thread_rng()
-                .sample_iter(&Alphanumeric)
-                .take(16)
-                .map(char::from)
-                .collect::<String>();
+            if let Some(code) = &invite_code {
+                if !storage.use_invite_code(code, now).await? {
+                    tracing::debug!("invite code {} can not be used anymore", code);
+                    return anyhow::Ok(false);
+                }
+            }
+
+            let created = storage.create_user(
+                &model::User {
+                    user_id: username.clone(),
+
+                    email: None,
+                    email_confirmed: false,
+
+                    timestamp_register: now,
+                    timestamp_active: now,
+
+                    crystals: 0,
+                    double_crystals: None,
+
+                    experience: 0,
+                    premium: Some(now + starter_premium).filter(|premium| *premium > now),
+
+                    moderator_level: 0,
+                    invite_code: invite_code.clone(),
+                },
+                &model::UserAuthentication {
+                    user_id: username.clone(),
+                    login_user: username.clone(),
+                    password_hash: hashed_password,
+                    password_salt: String::new(),
+                },
+                &starter_items
+            ).await;
+
+            if let Err(error) = created {
+                if let Some(code) = &invite_code {
+                    if let Err(release_error) = storage.release_invite_code(code).await {
+                        tracing::error!("failed to release a use of the invite code {}: {}", code, release_error);
+                    }
+                }
+                return Err(error);
+            }
 
-            let hashed_password = Self::hash_password(&password, &password_salt);
-
-            let mut database = database.lock().await;
-            let mut tx = database.begin().await?;
-
-            sqlx::query(
-                "INSERT INTO `user`(`user_id`, `timestamp_register`, `timestamp_active`, `crystals`, `experience`, `premium`)
-                VALUES ($1, $2, $2, $3, $4, $5)"
-            )
-                .bind(&username)
-                .bind(&Utc::now())
-                .bind(0)
-                .bind(0)
-                .bind(&None::<chrono::DateTime<Utc>>)
-                .execute(&mut tx)
-                .await?;
-
-            sqlx::query(
-                "INSERT INTO `user_authentication`(`user_id`, `login_user`, `password_hash`, `password_salt`)
-                VALUES ($1, $2, $3, $4)"
-            )
-                .bind(&username)
-                .bind(&username)
-                .bind(&hashed_password)
-                .bind(&password_salt)
-                .execute(&mut tx)
-                .await?;
-
-            tx.commit().await?;
             anyhow::Ok(true)
         }.unwrap_or_else(|err| {
             tracing::error!("failed to create new user: {}", err);
@@ -249,19 +298,99 @@ impl UserRegistry {
         })
     }
 
-    pub fn find_user(&self, user_id: String) -> impl Future<Output = Option<database::User>> {
-        let database = self.database.clone();
+    pub fn find_user(&self, user_id: String) -> impl Future<Output = Option<model::User>> {
+        let storage = self.storage.clone();
         async move {
-            let mut database = database.lock().await;
-            let result = sqlx::query_as::<_, database::User>("SELECT * FROM `user` WHERE `user_id` = $1")
-                .bind(&user_id)
-                .fetch_optional(&mut *database)
-                .await?;
-
-            anyhow::Ok(result)
+            storage.find_user(&user_id).await
         }.unwrap_or_else(|err| {
             tracing::error!("failed to find user: {}", err);
             None
         })
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod test {
+    use std::{net::{IpAddr, Ipv4Addr}, sync::{Arc, RwLock}};
+
+    use chrono::Utc;
+
+    use crate::{config::ServerConfig, storage::{MemoryStorage, Storage, StorageHandle, model}};
+    use super::{AuthenticationResult, UserRegistry, hash_password_legacy};
+
+    #[tokio::test]
+    async fn test_upgrade_legacy_password_hash() -> anyhow::Result<()> {
+        let storage: StorageHandle = Arc::new(MemoryStorage::new());
+        let now = Utc::now();
+        storage.create_user(
+            &model::User {
+                user_id: "veteran".to_string(),
+                email: None,
+                email_confirmed: false,
+                timestamp_register: now,
+                timestamp_active: now,
+                crystals: 0,
+                double_crystals: None,
+                experience: 0,
+                premium: None,
+
+                moderator_level: 0,
+                invite_code: None,
+            },
+            &model::UserAuthentication {
+                user_id: "veteran".to_string(),
+                login_user: "veteran".to_string(),
+                password_hash: hash_password_legacy("hunter2", "pepper"),
+                password_salt: "pepper".to_string(),
+            },
+            &[]
+        ).await?;
+
+        let config = Arc::new(RwLock::new(ServerConfig::default()));
+        let registry = UserRegistry::new(storage.clone(), config, vec![]);
+        let address = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+        let result = registry.authenticate_with_credentials("veteran".to_string(), "hunter2".to_string(), address).await;
+        assert!(matches!(result, AuthenticationResult::Success { .. }));
+
+        let authentication = storage.find_authentication("veteran").await?.expect("the user to exist");
+        assert!(authentication.password_hash.starts_with("$argon2id$"));
+
+        let result = registry.authenticate_with_credentials("veteran".to_string(), "hunter2".to_string(), address).await;
+        assert!(matches!(result, AuthenticationResult::Success { .. }));
+
+        let result = registry.authenticate_with_credentials("veteran".to_string(), "hunter3".to_string(), address).await;
+        assert!(matches!(result, AuthenticationResult::InvalidCredentials));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_failed_registration_releases_invite_code() -> anyhow::Result<()> {
+        let storage: StorageHandle = Arc::new(MemoryStorage::new());
+        let now = Utc::now();
+        storage.create_invite_code(&model::InviteCode {
+            code: "beta".to_string(),
+            created_by: "admin".to_string(),
+            max_uses: 1,
+            uses: 0,
+            timestamp_created: now,
+            timestamp_expires: None,
+        }).await?;
+
+        let config = Arc::new(RwLock::new(ServerConfig::default()));
+        let mut registry = UserRegistry::new(storage.clone(), config, vec![("health".to_string(), 3)]);
+        assert!(registry.register_user("taken".to_string(), "hunter2".to_string(), chrono::Duration::zero(), None).await);
+
+        /* the user already exists, the use of the code is given back */
+        assert!(!registry.register_user("taken".to_string(), "hunter2".to_string(), chrono::Duration::zero(), Some("beta".to_string())).await);
+        assert_eq!(storage.find_invite_code("beta").await?.expect("the code to exist").uses, 0);
+
+        assert!(registry.register_user("fresh".to_string(), "hunter2".to_string(), chrono::Duration::zero(), Some("beta".to_string())).await);
+        assert_eq!(storage.find_invite_code("beta").await?.expect("the code to exist").uses, 1);
+
+        /* the starter items are created together with the user */
+        let items = storage.find_user_items("fresh").await?;
+        assert_eq!(items.len(), 1);
+        assert_eq!((items[0].item_id.as_str(), items[0].count), ("health", 3));
+        Ok(())
+    }
+}
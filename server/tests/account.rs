@@ -0,0 +1,95 @@
+mod common;
+
+use std::{path::{Path, PathBuf}, time::{Duration, Instant}};
+
+use anyhow::Context;
+use common::*;
+use fost_protocol::packets::{c2s, s2c};
+
+/// Start a server writing all mails into a fresh directory.
+fn start_with_mail_directory() -> anyhow::Result<(TestServer, PathBuf)> {
+    let directory = std::env::temp_dir().join(format!("fost-mails-{}", unused_address()?.port()));
+    let _ = std::fs::remove_dir_all(&directory);
+
+    let server = TestServer::start_with_args(&[
+        "--mail-backend", "file",
+        "--mail-directory", directory.to_str().context("invalid mail directory")?,
+    ])?;
+    Ok((server, directory))
+}
+
+/// Wait for the next mail, remove it and return the code it contains.
+fn take_mailed_code(directory: &Path) -> anyhow::Result<String> {
+    let started = Instant::now();
+    loop {
+        let mail = std::fs::read_dir(directory)?
+            .filter_map(Result::ok)
+            .map(|entry| entry.path())
+            .find(|path| path.extension().map_or(false, |extension| extension == "eml"));
+
+        if let Some(mail) = mail {
+            let content = std::fs::read_to_string(&mail)?;
+            std::fs::remove_file(&mail)?;
+
+            let code = content.split("enter the code ")
+                .nth(1)
+                .and_then(|text| text.split_whitespace().next())
+                .context("mail does not contain a code")?;
+            return Ok(code.to_string());
+        }
+
+        if started.elapsed() > Duration::from_secs(10) {
+            anyhow::bail!("no mail has been sent");
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
+
+#[tokio::test]
+async fn test_confirm_email_and_reset_password() -> anyhow::Result<()> {
+    let (server, mail_directory) = start_with_mail_directory()?;
+
+    let mut session = connect_user(&server, "forgetful").await?;
+    session.connection.send_packet(&c2s::AccountCredentialsChangeEmail{ email: " Forgetful@Example.org".to_string() })?;
+    let confirmation = await_packet_type::<s2c::AccountCredentialsConfirmationSent>(&mut session).await?;
+    assert_eq!(confirmation.email, "forgetful@example.org");
+    let confirmation_code = take_mailed_code(&mail_directory)?;
+
+    /* password resets require a confirmed email */
+    let mut session = connect(&server).await?;
+    session.connection.send_packet(&c2s::AccountRecoverRequestForEMail{ email: "forgetful@example.org".to_string() })?;
+    await_packet_type::<s2c::AccountRecoverEmailNotFound>(&mut session).await?;
+
+    session.connection.send_packet(&c2s::AccountRecoverSubmitCode{ code: confirmation_code.clone() })?;
+    await_packet_type::<s2c::AlertShow>(&mut session).await?;
+
+    /* codes can only be used once */
+    session.connection.send_packet(&c2s::AccountRecoverSubmitCode{ code: confirmation_code })?;
+    await_packet_type::<s2c::AccountRecoverCodeInvalid>(&mut session).await?;
+
+    session.connection.send_packet(&c2s::AccountRecoverRequestForEMail{ email: "forgetful@example.org".to_string() })?;
+    await_packet_type::<s2c::AccountRecoverEmailSent>(&mut session).await?;
+    let reset_code = take_mailed_code(&mail_directory)?;
+
+    session.connection.send_packet(&c2s::AccountRecoverSubmitCode{ code: reset_code })?;
+    let accepted = await_packet_type::<s2c::AccountRecoverCodeAccepted>(&mut session).await?;
+    assert_eq!(accepted.current_email, "forgetful@example.org");
+
+    session.connection.send_packet(&c2s::AccountRecoverChangeCredentials{ password: "abc".to_string(), email: String::new() })?;
+    let result = await_packet_type::<s2c::AccountRecoverChangeCredentialsResult>(&mut session).await?;
+    assert!(!result.success);
+
+    session.connection.send_packet(&c2s::AccountRecoverChangeCredentials{ password: "new-password".to_string(), email: String::new() })?;
+    let result = await_packet_type::<s2c::AccountRecoverChangeCredentialsResult>(&mut session).await?;
+    assert!(result.success, "{}", result.error);
+
+    session.connection.send_packet(&c2s::AccountLoginExecute{
+        login: "forgetful".to_string(),
+        password: "new-password".to_string(),
+        remember: false,
+    })?;
+    await_packet_type::<s2c::AccountLoginSuccess>(&mut session).await?;
+
+    let _ = std::fs::remove_dir_all(&mail_directory);
+    Ok(())
+}
@@ -0,0 +1,181 @@
+mod common;
+
+use std::sync::{Arc, atomic::{AtomicBool, Ordering}};
+
+use common::*;
+use fost_protocol::{codec::{BattleMode, BattleTeam}, packets::{s2c, PacketDowncast}};
+use serde_json::{json, Value};
+
+/// Start a server with the admin api and return the base url of the api.
+fn start_with_admin_api() -> anyhow::Result<(TestServer, String)> {
+    let admin_address = unused_address()?.to_string();
+    let server = TestServer::start_with_args(&["--admin-bind", &admin_address])?;
+    Ok((server, format!("http://{}", admin_address)))
+}
+
+#[test]
+fn test_reject_public_admin_api() -> anyhow::Result<()> {
+    let status = TestServer::run_until_exit(&["--admin-bind", "0.0.0.0:0"])?;
+    assert!(!status.success());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_list_clients() -> anyhow::Result<()> {
+    let (server, api) = start_with_admin_api()?;
+    let _user = connect_user(&server, "admin_listed").await?;
+
+    let clients = reqwest::get(format!("{}/clients", api)).await?
+        .error_for_status()?
+        .json::<Value>().await?;
+
+    let client = clients.as_array()
+        .and_then(|clients| clients.iter().find(|client| client["user_id"] == "admin_listed"))
+        .expect("the client to be listed");
+    assert_eq!(client["layout"], "BattleSelect");
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_broadcast_and_grant_crystals() -> anyhow::Result<()> {
+    let (server, api) = start_with_admin_api()?;
+    let mut user = connect_user(&server, "admin_rich").await?;
+    let http = reqwest::Client::new();
+
+    http.post(format!("{}/broadcast", api))
+        .json(&json!({ "text": "maintenance soon" }))
+        .send().await?
+        .error_for_status()?;
+    let alert = await_packet_type::<s2c::AlertShow>(&mut user).await?;
+    assert_eq!(alert.text, "maintenance soon");
+
+    let response = http.post(format!("{}/users/admin_rich/crystals", api))
+        .json(&json!({ "amount": 500 }))
+        .send().await?
+        .error_for_status()?
+        .json::<Value>().await?;
+    let crystals = await_packet_type::<s2c::AccountRankUpdateCrystals>(&mut user).await?;
+    assert_eq!(response["crystals"], crystals.change_by);
+
+    /* the balance must not overflow */
+    let response = http.post(format!("{}/users/admin_rich/crystals", api))
+        .json(&json!({ "amount": i32::MAX }))
+        .send().await?;
+    assert_eq!(response.status(), reqwest::StatusCode::CONFLICT);
+
+    let response = http.post(format!("{}/users/admin_nobody/crystals", api))
+        .json(&json!({ "amount": 500 }))
+        .send().await?;
+    assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_grant_subscriptions() -> anyhow::Result<()> {
+    let (server, api) = start_with_admin_api()?;
+    let mut user = connect_user(&server, "admin_premium").await?;
+    let http = reqwest::Client::new();
+
+    let response = http.post(format!("{}/users/admin_premium/premium", api))
+        .json(&json!({ "duration_seconds": 1 }))
+        .send().await?
+        .error_for_status()?
+        .json::<Value>().await?;
+    assert!(response["until"].is_string());
+    let activated = await_packet_type::<s2c::AccountPremiumActivate>(&mut user).await?;
+    assert!(activated.left_time_in_seconds <= 1);
+
+    /* the expiry shows the completion notification */
+    let expired = await_packet(&mut user, |packet| {
+        packet.downcast_ref::<s2c::AccountPremiumInit>()
+            .filter(|packet| packet.premium_account_alert_cc.need_show_notification_completion_premium)
+            .map(|packet| packet.premium_notifier_cc.life_time_in_seconds)
+    }).await?;
+    assert_eq!(expired, 0);
+
+    http.post(format!("{}/users/admin_premium/double_crystals", api))
+        .json(&json!({ "duration_seconds": 1 }))
+        .send().await?
+        .error_for_status()?;
+    let enabled = await_packet_type::<s2c::AccountInfoDoubleCrystal>(&mut user).await?;
+    assert!(enabled.enabled);
+    let disabled = await_packet_type::<s2c::AccountInfoDoubleCrystal>(&mut user).await?;
+    assert!(!disabled.enabled);
+
+    let response = http.post(format!("{}/users/admin_premium/premium", api))
+        .json(&json!({ "duration_seconds": 0 }))
+        .send().await?;
+    assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+
+    let response = http.post(format!("{}/users/admin_nobody/premium", api))
+        .json(&json!({ "duration_seconds": 60 }))
+        .send().await?;
+    assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_kick_user() -> anyhow::Result<()> {
+    let (server, api) = start_with_admin_api()?;
+    let mut user = connect_user(&server, "admin_kicked").await?;
+
+    reqwest::Client::new()
+        .post(format!("{}/users/admin_kicked/kick", api))
+        .json(&json!({ "reason": "testing" }))
+        .send().await?
+        .error_for_status()?;
+
+    let alert = await_packet_type::<s2c::AlertShow>(&mut user).await?;
+    assert_eq!(alert.text, "You have been kicked. Reason: testing");
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_schedule_shutdown() -> anyhow::Result<()> {
+    let (server, api) = start_with_admin_api()?;
+    let mut user = connect_user(&server, "admin_halted").await?;
+    let http = reqwest::Client::new();
+
+    http.post(format!("{}/shutdown", api))
+        .json(&json!({ "delay_seconds": 300 }))
+        .send().await?
+        .error_for_status()?;
+    let scheduled = await_packet_type::<s2c::AlertServerHaltScheduled>(&mut user).await?;
+    assert_eq!(scheduled.time_left_in_sec, 300);
+
+    http.delete(format!("{}/shutdown", api))
+        .send().await?
+        .error_for_status()?;
+    let alert = await_packet_type::<s2c::AlertShow>(&mut user).await?;
+    assert_eq!(alert.text, "The server shutdown has been canceled.");
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_shutdown_finishes_battles() -> anyhow::Result<()> {
+    let (mut server, api) = start_with_admin_api()?;
+    let mut user = connect_user(&server, "admin_finished").await?;
+    let battle_id = create_battle(&mut user, BattleMode::Dm).await?;
+    join_battle(&mut user, "admin_finished", Some(&battle_id), BattleTeam::None).await?;
+
+    reqwest::Client::new()
+        .post(format!("{}/shutdown", api))
+        .json(&json!({ "delay_seconds": 1 }))
+        .send().await?
+        .error_for_status()?;
+    let scheduled = await_packet_type::<s2c::AlertServerHaltScheduled>(&mut user).await?;
+    assert_eq!(scheduled.time_left_in_sec, 1);
+
+    /* the round finish and the halt notify are received at once */
+    let round_finished = Arc::new(AtomicBool::new(false));
+    let round_finished_ = round_finished.clone();
+    await_packet(&mut user, move |packet| {
+        if packet.downcast_ref::<s2c::BattleStatisticsRoundFinish>().is_some() {
+            round_finished_.store(true, Ordering::Relaxed);
+        }
+        packet.downcast_ref::<s2c::ServerHaltNotify>().map(|_| ())
+    }).await?;
+    assert!(round_finished.load(Ordering::Relaxed));
+    server.wait_for_exit(std::time::Duration::from_secs(10))?;
+    Ok(())
+}
@@ -0,0 +1,72 @@
+mod common;
+
+use common::*;
+use fost_protocol::codec::{BattleMode, BattleTeam};
+use fost_protocol::packets::{c2s, s2c, PacketDowncast};
+use nalgebra::Vector3;
+
+const FLAG: Vector3<f32> = Vector3::new(-1500.0, 0.0, 100.0);
+const BASE: Vector3<f32> = Vector3::new(1800.0, 0.0, 100.0);
+
+async fn await_flag_taken(session: &mut fost_client_utils::Session, tank_id: &str) -> anyhow::Result<i32> {
+    let tank_id = tank_id.to_string();
+    await_packet(session, move |packet| {
+        packet.downcast_ref::<s2c::BattleCTFFlagFlagTaken>()
+            .filter(|packet| packet.tank_id == tank_id)
+            .map(|packet| packet.flag_id)
+    }).await
+}
+
+#[tokio::test]
+async fn test_assault_deliver_flag() -> anyhow::Result<()> {
+    let server = TestServer::start()?;
+
+    let mut attacker = connect_user(&server, "as_attacker").await?;
+    let battle_id = create_battle(&mut attacker, BattleMode::As).await?;
+    let incarnation = join_battle(&mut attacker, "as_attacker", None, BattleTeam::Red).await?;
+
+    let mut defender = connect_user(&server, "as_defender").await?;
+    join_battle(&mut defender, "as_defender", Some(&battle_id), BattleTeam::Blue).await?;
+
+    move_tank(&mut attacker, incarnation, FLAG)?;
+    assert_eq!(await_flag_taken(&mut attacker, "as_attacker").await?, 0);
+
+    move_tank(&mut attacker, incarnation, BASE)?;
+    await_packet_type::<s2c::BattleCTFFlagFlagDelivered>(&mut attacker).await?;
+    let delivered = await_packet_type::<s2c::BattleCTFFlagFlagDelivered>(&mut defender).await?;
+    assert_eq!(delivered.flag_id, 0);
+    assert_eq!(delivered.base_id, 0);
+    assert_eq!(delivered.deliverer_tank_id, "as_attacker");
+
+    let team_score = await_packet_type::<s2c::BattleUsersUpdateTeamScore>(&mut defender).await?;
+    assert_eq!(team_score.team, BattleTeam::Red);
+    assert_eq!(team_score.score, 1);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_assault_defender_returns_flag() -> anyhow::Result<()> {
+    let server = TestServer::start()?;
+
+    let mut attacker = connect_user(&server, "as_attacker").await?;
+    let battle_id = create_battle(&mut attacker, BattleMode::As).await?;
+    let attacker_incarnation = join_battle(&mut attacker, "as_attacker", None, BattleTeam::Red).await?;
+
+    let mut defender = connect_user(&server, "as_defender").await?;
+    let defender_incarnation = join_battle(&mut defender, "as_defender", Some(&battle_id), BattleTeam::Blue).await?;
+
+    move_tank(&mut attacker, attacker_incarnation, FLAG)?;
+    await_flag_taken(&mut attacker, "as_attacker").await?;
+
+    attacker.connection.send_packet(&c2s::BattleCTFFlagDropFlag{})?;
+    await_packet_type::<s2c::BattleCTFFlagFlagDropped>(&mut attacker).await?;
+    let dropped = await_packet_type::<s2c::BattleCTFFlagFlagDropped>(&mut defender).await?;
+    assert_eq!(dropped.position, Some(FLAG));
+
+    move_tank(&mut defender, defender_incarnation, FLAG + Vector3::new(100.0, 0.0, 0.0))?;
+    await_packet_type::<s2c::BattleCTFFlagFlagReturned>(&mut defender).await?;
+    let returned = await_packet_type::<s2c::BattleCTFFlagFlagReturned>(&mut attacker).await?;
+    assert_eq!(returned.flag_id, 0);
+    assert_eq!(returned.tank, "as_defender");
+    Ok(())
+}
@@ -0,0 +1,51 @@
+mod common;
+
+use common::*;
+use fost_protocol::codec::{BattleMode, BattleTeam};
+use fost_protocol::packets::{c2s, s2c, PacketDowncast};
+
+#[tokio::test]
+async fn test_battle_chat_routing() -> anyhow::Result<()> {
+    let server = TestServer::start()?;
+
+    let mut red = connect_user(&server, "chat_red").await?;
+    let battle_id = create_battle(&mut red, BattleMode::Tdm).await?;
+    join_battle(&mut red, "chat_red", None, BattleTeam::Red).await?;
+
+    let mut red2 = connect_user(&server, "chat_red2").await?;
+    join_battle(&mut red2, "chat_red2", Some(&battle_id), BattleTeam::Red).await?;
+
+    let joined = await_packet_type::<s2c::BattleMessageSystemMessage>(&mut red).await?;
+    assert_eq!(joined.message, "chat_red2 joined the battle.");
+
+    let mut blue = connect_user(&server, "chat_blue").await?;
+    join_battle(&mut blue, "chat_blue", Some(&battle_id), BattleTeam::Blue).await?;
+
+    red.connection.send_packet(&c2s::BattleMessageSend{ message: " attack now ".to_string(), team_only: true })?;
+    let team_message = await_packet_type::<s2c::BattleMessageTeamMessage>(&mut red).await?;
+    assert_eq!(team_message.message, "attack now");
+
+    let team_message = await_packet_type::<s2c::BattleMessageTeamMessage>(&mut red2).await?;
+    assert_eq!(team_message.user_id, "chat_red");
+    assert_eq!(team_message.team, BattleTeam::Red);
+
+    blue.connection.send_packet(&c2s::BattleMessageSend{ message: "gg".to_string(), team_only: false })?;
+
+    /* the team message of red must not have reached blue */
+    let (user_id, team_only) = await_packet(&mut blue, |packet| {
+        if let Some(packet) = packet.downcast_ref::<s2c::BattleMessageTeamMessage>() {
+            Some((packet.user_id.clone(), true))
+        } else {
+            packet.downcast_ref::<s2c::BattleMessageMessage>()
+                .map(|packet| (packet.user_id.clone(), false))
+        }
+    }).await?;
+    assert_eq!(user_id, "chat_blue");
+    assert!(!team_only);
+
+    let message = await_packet_type::<s2c::BattleMessageMessage>(&mut red2).await?;
+    assert_eq!(message.user_id, "chat_blue");
+    assert_eq!(message.message, "gg");
+    assert_eq!(message.team, BattleTeam::Blue);
+    Ok(())
+}
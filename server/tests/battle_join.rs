@@ -0,0 +1,41 @@
+mod common;
+
+use common::*;
+use fost_protocol::codec::{BattleMode, BattleTeam, LayoutState};
+use fost_protocol::packets::{c2s, s2c, PacketDowncast};
+
+#[tokio::test]
+async fn test_join_while_join_pending() -> anyhow::Result<()> {
+    let server = TestServer::start()?;
+
+    let mut joiner = connect_user(&server, "pending_joiner").await?;
+    let first_battle = create_battle(&mut joiner, BattleMode::Dm).await?;
+    let second_battle = create_battle(&mut joiner, BattleMode::Dm).await?;
+    let mut observer = connect_user(&server, "pending_observer").await?;
+
+    /* the second join arrives while the first one still awaits the map resources */
+    for battle_id in [ &first_battle, &second_battle ] {
+        joiner.connection.send_packet(&c2s::BattleListBattleSelect{ item: battle_id.to_string() })?;
+        joiner.connection.send_packet(&c2s::BattleInfoJoinBattle{ team: BattleTeam::None })?;
+    }
+    await_packet(&mut joiner, |packet| {
+        packet.downcast_ref::<s2c::LobbyLayoutSwitchEnd>()
+            .filter(|packet| packet.state == LayoutState::Battle)
+            .map(|_| ())
+    }).await?;
+
+    let mut probe = connect_user(&server, "pending_probe").await?;
+    join_battle(&mut probe, "pending_probe", Some(&second_battle), BattleTeam::None).await?;
+
+    let mut joined = Vec::new();
+    loop {
+        let packet = await_packet_type::<s2c::BattleUserListUserJoinBattle>(&mut observer).await?;
+        if packet.user_id == "pending_probe" {
+            break;
+        }
+
+        joined.push((packet.user_id, packet.battle_id));
+    }
+    assert_eq!(joined, vec![ ("pending_joiner".to_string(), first_battle) ]);
+    Ok(())
+}
@@ -0,0 +1,231 @@
+#![allow(unused)]
+
+use std::{net::{SocketAddr, TcpListener, TcpStream}, process::{Child, Command, ExitStatus, Stdio}, time::{Duration, Instant}};
+
+use anyhow::Context;
+use fost_client_utils::{Session, DummyResourceLoader, LowLevelPing, SessionPing};
+use fost_protocol::codec::{BattleCreateParameters, BattleLimits, BattleMode, BattleTeam, EquipmentConstraintsMode, LayoutState, MapTheme, MoveCommand, Range};
+use fost_protocol::packets::{c2s, s2c, Packet, PacketDowncast};
+use nalgebra::Vector3;
+
+const PACKET_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A server process listening on a random local port.
+/// The process will be killed when dropped.
+pub struct TestServer {
+    process: Child,
+    pub address: SocketAddr,
+}
+
+impl TestServer {
+    pub fn start() -> anyhow::Result<Self> {
+        Self::start_with_args(&[])
+    }
+
+    fn command(address: SocketAddr, args: &[&str]) -> Command {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_fost-server"));
+        command
+            .current_dir(env!("CARGO_MANIFEST_DIR"))
+            .env("RUST_LOG", "warn")
+            .args(["--config", "tests/server.nonexistent.toml"])
+            .args(["--database", "memory://"])
+            .args(["--bind", &address.to_string()])
+            .args(["--battle-creation", "true"])
+            .arg("--no-captcha")
+            .args(args)
+            .stdout(Stdio::null());
+        command
+    }
+
+    /// Start the server with additional command line arguments.
+    pub fn start_with_args(args: &[&str]) -> anyhow::Result<Self> {
+        let address = unused_address()?;
+        let process = Self::command(address, args)
+            .spawn()
+            .context("failed to start the server")?;
+
+        let server = Self { process, address };
+        let started = Instant::now();
+        while TcpStream::connect(address).is_err() {
+            if started.elapsed() > PACKET_TIMEOUT {
+                anyhow::bail!("server did not start listening");
+            }
+
+            std::thread::sleep(Duration::from_millis(50));
+        }
+
+        Ok(server)
+    }
+
+    /// Run the server until it exits on its own, e.g. because of an invalid configuration.
+    pub fn run_until_exit(args: &[&str]) -> anyhow::Result<ExitStatus> {
+        let address = unused_address()?;
+        let process = Self::command(address, args)
+            .stderr(Stdio::null())
+            .spawn()
+            .context("failed to start the server")?;
+
+        let mut server = Self { process, address };
+
+        server.wait_for_exit(PACKET_TIMEOUT)?;
+        Ok(server.process.try_wait()?.context("server did not exit")?)
+    }
+
+    /// Wait for the server process to exit on its own.
+    pub fn wait_for_exit(&mut self, timeout: Duration) -> anyhow::Result<()> {
+        let started = Instant::now();
+        while self.process.try_wait()?.is_none() {
+            if started.elapsed() > timeout {
+                anyhow::bail!("server did not exit");
+            }
+
+            std::thread::sleep(Duration::from_millis(50));
+        }
+
+        Ok(())
+    }
+}
+
+/// Local address which is currently not in use.
+pub fn unused_address() -> anyhow::Result<SocketAddr> {
+    Ok(TcpListener::bind("127.0.0.1:0")?.local_addr()?)
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+    }
+}
+
+/// Wait for the first packet accepted by the matcher.
+pub async fn await_packet<R: Send + 'static>(session: &mut Session, matcher: impl (Fn(&dyn Packet) -> Option<R>) + Send + 'static) -> anyhow::Result<R> {
+    tokio::time::timeout(PACKET_TIMEOUT, session.await_match(move |_, packet| matcher(packet)))
+        .await
+        .context("timed out waiting for a packet")?
+}
+
+pub async fn await_packet_type<T: Packet + Clone + 'static>(session: &mut Session) -> anyhow::Result<T> {
+    await_packet(session, |packet| packet.downcast_ref::<T>().cloned()).await
+}
+
+/// Connect to the server and wait for the login screen.
+pub async fn connect(server: &TestServer) -> anyhow::Result<Session> {
+    let mut session = Session::builder()
+        .connect(server.address)
+        .await?;
+
+    session.register_packet_handler(DummyResourceLoader{});
+    session.register_packet_handler(LowLevelPing{});
+    session.register_packet_handler(SessionPing{});
+    session.await_server_resources_loaded().await?;
+    Ok(session)
+}
+
+/// Connect to the server and register a new account.
+pub async fn connect_user(server: &TestServer, uid: &str) -> anyhow::Result<Session> {
+    let mut session = connect(server).await?;
+    register_user(&mut session, uid).await?;
+    Ok(session)
+}
+
+/// Register a new account and wait for the battle select screen.
+pub async fn register_user(session: &mut Session, uid: &str) -> anyhow::Result<()> {
+    session.connection.send_packet(&c2s::AccountRegisterSubmit{
+        uid: uid.to_string(),
+        password: "password123".to_string(),
+        remember_me: false,
+    })?;
+    await_packet(session, |packet| {
+        packet.downcast_ref::<s2c::LobbyLayoutSwitchEnd>()
+            .filter(|packet| packet.state == LayoutState::BattleSelect)
+            .map(|_| ())
+    }).await?;
+
+    Ok(())
+}
+
+/// Create a battle on Silence Moon and return the battle id.
+/// The battle will be selected for the creator before the battle list update is received.
+pub async fn create_battle(session: &mut Session, mode: BattleMode) -> anyhow::Result<String> {
+    session.connection.send_packet(&c2s::BattleCreateSubmit{
+        params: BattleCreateParameters {
+            battle_mode: mode,
+            equipment_constraints_mode: EquipmentConstraintsMode::None,
+            method_1309: BattleLimits { score_limit: 10, time_limit_in_sec: 900 },
+            map_id: "map_silence_moon".to_string(),
+            max_people_count: 4,
+            name: "integration test".to_string(),
+            rank_range: Range { min: 1, max: 30 },
+            theme: MapTheme::Space,
+            ..Default::default()
+        }
+    })?;
+
+    let entry = await_packet_type::<s2c::BattleListBattleCreate>(session).await?;
+    let entry = serde_json::from_str::<serde_json::Value>(&entry.json)?;
+    let battle_id = entry["battleId"].as_str()
+        .context("missing battle id")?
+        .to_string();
+
+    Ok(battle_id)
+}
+
+/// Join the battle in the team and activate the tank.
+/// Returns the incarnation of the spawned tank.
+pub async fn join_battle(session: &mut Session, uid: &str, battle_id: Option<&str>, team: BattleTeam) -> anyhow::Result<i16> {
+    if let Some(battle_id) = battle_id {
+        session.connection.send_packet(&c2s::BattleListBattleSelect{ item: battle_id.to_string() })?;
+        await_packet(session, |packet| {
+            packet.downcast_ref::<s2c::BattleListBattleSelect>().map(|_| ())
+        }).await?;
+    }
+
+    session.connection.send_packet(&c2s::BattleInfoJoinBattle{ team })?;
+    await_packet(session, |packet| {
+        packet.downcast_ref::<s2c::LobbyLayoutSwitchEnd>()
+            .filter(|packet| packet.state == LayoutState::Battle)
+            .map(|_| ())
+    }).await?;
+
+    session.connection.send_packet(&c2s::TankInit{})?;
+    await_packet_type::<s2c::TankSpawnLocation>(session).await?;
+
+    session.connection.send_packet(&c2s::TankReady2Place{})?;
+    let tank_id = uid.to_string();
+    let incarnation = await_packet(session, move |packet| {
+        packet.downcast_ref::<s2c::TankSpawn>()
+            .filter(|packet| packet.tank_id == tank_id)
+            .map(|packet| packet.incarnation_id)
+    }).await?;
+
+    session.connection.send_packet(&c2s::TankReady2Activate{})?;
+    let tank_id = uid.to_string();
+    await_packet(session, move |packet| {
+        packet.downcast_ref::<s2c::TankActivated>()
+            .filter(|packet| packet.tank_id == tank_id)
+            .map(|_| ())
+    }).await?;
+
+    Ok(incarnation)
+}
+
+/// Send a movement command to the server.
+/// Packets are only flushed while the session is polled,
+/// thus the caller should await a packet of the session afterwards.
+pub fn move_tank(session: &mut Session, incarnation: i16, position: Vector3<f32>) -> anyhow::Result<()> {
+    let client_session_time = session.session_timestamp();
+    session.connection.send_packet(&c2s::TankMoveCommand{
+        client_session_time,
+        specification_id: incarnation,
+        move_command: MoveCommand {
+            position: Some(position),
+            orientation: Some(Vector3::zeros()),
+            velocity: Some(Vector3::zeros()),
+            angular_velocity: Some(Vector3::zeros()),
+            control: 0,
+        },
+    })?;
+
+    Ok(())
+}
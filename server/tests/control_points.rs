@@ -0,0 +1,49 @@
+mod common;
+
+use common::*;
+use fost_client_utils::Session;
+use fost_protocol::codec::{BattleMode, BattleTeam};
+use fost_protocol::packets::s2c;
+use nalgebra::Vector3;
+
+/// Position of the point A on Silence Moon.
+const POINT_A: Vector3<f32> = Vector3::new(-1000.0, 0.0, 100.0);
+const OUTSIDE: Vector3<f32> = Vector3::new(-1000.0, 1500.0, 100.0);
+
+async fn await_progress_speed(session: &mut Session) -> anyhow::Result<f32> {
+    let progress = await_packet_type::<s2c::BattleCPSetPointProgress>(session).await?;
+    assert_eq!(progress.point_id, 0);
+    Ok(progress.progress_speed)
+}
+
+#[tokio::test]
+async fn test_control_point_capture_speed() -> anyhow::Result<()> {
+    let server = TestServer::start()?;
+
+    let mut red = connect_user(&server, "cp_red").await?;
+    let battle_id = create_battle(&mut red, BattleMode::Cp).await?;
+    let red_incarnation = join_battle(&mut red, "cp_red", None, BattleTeam::Red).await?;
+
+    let mut blue = connect_user(&server, "cp_blue").await?;
+    let blue_incarnation = join_battle(&mut blue, "cp_blue", Some(&battle_id), BattleTeam::Blue).await?;
+
+    move_tank(&mut red, red_incarnation, POINT_A)?;
+    let entered = await_packet_type::<s2c::BattleCPTankEnteredPoint>(&mut red).await?;
+    assert_eq!(entered.point_id, 0);
+    assert_eq!(entered.tank_id, "cp_red");
+    assert!(await_progress_speed(&mut red).await? < 0.0);
+
+    /* the point is contested as long as both teams are inside */
+    move_tank(&mut blue, blue_incarnation, POINT_A)?;
+    let entered = await_packet_type::<s2c::BattleCPTankEnteredPoint>(&mut blue).await?;
+    assert_eq!(entered.tank_id, "cp_red");
+    let entered = await_packet_type::<s2c::BattleCPTankEnteredPoint>(&mut blue).await?;
+    assert_eq!(entered.tank_id, "cp_blue");
+    assert_eq!(await_progress_speed(&mut blue).await?, 0.0);
+
+    move_tank(&mut red, red_incarnation, OUTSIDE)?;
+    let left = await_packet_type::<s2c::BattleCPTankLeftPoint>(&mut red).await?;
+    assert_eq!(left.tank_id, "cp_red");
+    assert!(await_progress_speed(&mut red).await? > 0.0);
+    Ok(())
+}
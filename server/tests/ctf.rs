@@ -0,0 +1,73 @@
+mod common;
+
+use common::*;
+use fost_protocol::codec::{BattleMode, BattleTeam};
+use fost_protocol::packets::{c2s, s2c, PacketDowncast};
+use nalgebra::Vector3;
+
+const RED_FLAG: Vector3<f32> = Vector3::new(-2200.0, 0.0, 100.0);
+const BLUE_FLAG: Vector3<f32> = Vector3::new(2200.0, 0.0, 100.0);
+
+async fn await_flag_taken(session: &mut fost_client_utils::Session, tank_id: &str, flag_team: BattleTeam) -> anyhow::Result<()> {
+    let tank_id = tank_id.to_string();
+    await_packet(session, move |packet| {
+        packet.downcast_ref::<s2c::BattleCTFFlagTaken>()
+            .filter(|packet| packet.tank_id == tank_id && packet.flag_team == flag_team)
+            .map(|_| ())
+    }).await
+}
+
+#[tokio::test]
+async fn test_ctf_deliver_flag() -> anyhow::Result<()> {
+    let server = TestServer::start()?;
+
+    let mut red = connect_user(&server, "ctf_red").await?;
+    let battle_id = create_battle(&mut red, BattleMode::Ctf).await?;
+    let red_incarnation = join_battle(&mut red, "ctf_red", None, BattleTeam::Red).await?;
+
+    let mut blue = connect_user(&server, "ctf_blue").await?;
+    join_battle(&mut blue, "ctf_blue", Some(&battle_id), BattleTeam::Blue).await?;
+
+    move_tank(&mut red, red_incarnation, BLUE_FLAG)?;
+    await_flag_taken(&mut red, "ctf_red", BattleTeam::Blue).await?;
+    await_flag_taken(&mut blue, "ctf_red", BattleTeam::Blue).await?;
+
+    move_tank(&mut red, red_incarnation, RED_FLAG)?;
+    await_packet_type::<s2c::BattleCTFFlagDelivered>(&mut red).await?;
+    let delivered = await_packet_type::<s2c::BattleCTFFlagDelivered>(&mut blue).await?;
+    assert_eq!(delivered.winner_team, BattleTeam::Red);
+    assert_eq!(delivered.deliverer_tank_id, "ctf_red");
+
+    let team_score = await_packet_type::<s2c::BattleUsersUpdateTeamScore>(&mut blue).await?;
+    assert_eq!(team_score.team, BattleTeam::Red);
+    assert_eq!(team_score.score, 1);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_ctf_drop_and_return_flag() -> anyhow::Result<()> {
+    let server = TestServer::start()?;
+
+    let mut red = connect_user(&server, "ctf_red").await?;
+    let battle_id = create_battle(&mut red, BattleMode::Ctf).await?;
+    let red_incarnation = join_battle(&mut red, "ctf_red", None, BattleTeam::Red).await?;
+
+    let mut blue = connect_user(&server, "ctf_blue").await?;
+    let blue_incarnation = join_battle(&mut blue, "ctf_blue", Some(&battle_id), BattleTeam::Blue).await?;
+
+    move_tank(&mut red, red_incarnation, BLUE_FLAG)?;
+    await_flag_taken(&mut red, "ctf_red", BattleTeam::Blue).await?;
+
+    red.connection.send_packet(&c2s::BattleCTFDropFlag{})?;
+    await_packet_type::<s2c::BattleCTFFlagDropped>(&mut red).await?;
+    let dropped = await_packet_type::<s2c::BattleCTFFlagDropped>(&mut blue).await?;
+    assert_eq!(dropped.flag_team, BattleTeam::Blue);
+    assert_eq!(dropped.position, Some(BLUE_FLAG));
+
+    move_tank(&mut blue, blue_incarnation, BLUE_FLAG)?;
+    await_packet_type::<s2c::BattleCTFFlagReturned>(&mut blue).await?;
+    let returned = await_packet_type::<s2c::BattleCTFFlagReturned>(&mut red).await?;
+    assert_eq!(returned.flag_team, BattleTeam::Blue);
+    assert_eq!(returned.tank, "ctf_blue");
+    Ok(())
+}
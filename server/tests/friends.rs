@@ -0,0 +1,51 @@
+mod common;
+
+use common::*;
+use fost_protocol::packets::{c2s, s2c};
+
+#[tokio::test]
+async fn test_friend_request() -> anyhow::Result<()> {
+    let server = TestServer::start()?;
+
+    let mut alice = connect_user(&server, "friend_alice").await?;
+    let mut bob = connect_user(&server, "friend_bob").await?;
+
+    alice.connection.send_packet(&c2s::FriendsSendRequest{ user_id: "friend_bob".to_string() })?;
+    let outgoing = await_packet_type::<s2c::FriendsOutgoingAdd>(&mut alice).await?;
+    assert_eq!(outgoing.user, "friend_bob");
+
+    let incoming = await_packet_type::<s2c::FriendsIncomingAdd>(&mut bob).await?;
+    assert_eq!(incoming.user, "friend_alice");
+
+    bob.connection.send_packet(&c2s::FriendsAccept{ user_id: "friend_alice".to_string() })?;
+    let accepted = await_packet_type::<s2c::FriendsAcceptedAdd>(&mut bob).await?;
+    assert_eq!(accepted.user, "friend_alice");
+
+    let accepted = await_packet_type::<s2c::FriendsAcceptedAdd>(&mut alice).await?;
+    assert_eq!(accepted.user, "friend_bob");
+
+    /* a second request is rejected as both are friends already */
+    alice.connection.send_packet(&c2s::FriendsSendRequest{ user_id: "friend_bob".to_string() })?;
+    let alert = await_packet_type::<s2c::AlertShow>(&mut alice).await?;
+    assert_eq!(alert.text, "You are already friends or a request is pending.");
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_online_status() -> anyhow::Result<()> {
+    let server = TestServer::start()?;
+
+    let mut alice = connect_user(&server, "status_alice").await?;
+    let bob = connect_user(&server, "status_bob").await?;
+
+    alice.connection.send_packet(&c2s::UserNotifySubscribe{ user_id: "status_bob".to_string() })?;
+    let status = await_packet_type::<s2c::UserNotifyOnlineStatus>(&mut alice).await?;
+    assert_eq!(status.user.user_id, "status_bob");
+    assert!(status.user.online);
+
+    drop(bob);
+    let status = await_packet_type::<s2c::UserNotifyOnlineStatus>(&mut alice).await?;
+    assert_eq!(status.user.user_id, "status_bob");
+    assert!(!status.user.online);
+    Ok(())
+}
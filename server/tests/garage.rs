@@ -0,0 +1,69 @@
+mod common;
+
+use common::*;
+use fost_protocol::codec::LayoutState;
+use fost_protocol::packets::{c2s, s2c, PacketDowncast};
+
+#[tokio::test]
+async fn test_open_garage() -> anyhow::Result<()> {
+    let server = TestServer::start()?;
+
+    let mut user = connect_user(&server, "garage_user").await?;
+    user.connection.send_packet(&c2s::LayoutSwitchGarage{ })?;
+
+    let mut mounted = Vec::new();
+    let depot = loop {
+        let packet = await_packet(&mut user, |packet| {
+            if let Some(packet) = packet.downcast_ref::<s2c::GarageInitMounted>() {
+                Some(Err(packet.item_id.clone()))
+            } else {
+                packet.downcast_ref::<s2c::GarageInitDepot>()
+                    .map(|packet| Ok(packet.json.clone()))
+            }
+        }).await?;
+
+        match packet {
+            Ok(depot) => break depot,
+            Err(item_id) => mounted.push(item_id),
+        }
+    };
+    mounted.sort();
+    assert_eq!(mounted, ["green_m0", "hunter_m0", "smoky_m0"]);
+
+    let depot: serde_json::Value = serde_json::from_str(&depot)?;
+    let health = depot["items"].as_array().unwrap()
+        .iter()
+        .find(|item| item["id"] == "health")
+        .expect("starter supplies to be in the depot");
+    assert_eq!(health["count"], 100);
+
+    let market = await_packet_type::<s2c::GarageInitMarket>(&mut user).await?;
+    let market: serde_json::Value = serde_json::from_str(&market.json)?;
+    let market_items = market["items"].as_array().unwrap();
+    assert!(market_items.iter().any(|item| item["id"] == "wasp"));
+    assert!(!market_items.iter().any(|item| item["id"] == "smoky"));
+
+    let layout = await_packet_type::<s2c::LobbyLayoutSwitchEnd>(&mut user).await?;
+    assert_eq!(layout.state, LayoutState::Garage);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_buy_without_crystals() -> anyhow::Result<()> {
+    let server = TestServer::start()?;
+
+    let mut user = connect_user(&server, "garage_poor").await?;
+    user.connection.send_packet(&c2s::GarageBuyItem{ item: "health_m0".to_string(), count: 1, var_204: 150 })?;
+    let alert = await_packet_type::<s2c::AlertShow>(&mut user).await?;
+    assert_eq!(alert.text, "You do not have enough crystals.");
+
+    user.connection.send_packet(&c2s::GarageMountItem{ item: "wasp_m0".to_string() })?;
+    let alert = await_packet_type::<s2c::AlertShow>(&mut user).await?;
+    assert_eq!(alert.text, "You do not own this item.");
+
+    user.connection.send_packet(&c2s::GarageMountItem{ item: "smoky_m0".to_string() })?;
+    let mounted = await_packet_type::<s2c::GarageInitMounted>(&mut user).await?;
+    assert_eq!(mounted.item_id, "smoky_m0");
+    assert!(mounted.mounted);
+    Ok(())
+}
@@ -0,0 +1,72 @@
+mod common;
+
+use common::*;
+use fost_protocol::packets::{c2s, s2c};
+use serde_json::{json, Value};
+
+/// Start an invite only server with the admin api and return the base url of the api.
+fn start_invite_only() -> anyhow::Result<(TestServer, String)> {
+    let admin_address = unused_address()?.to_string();
+    let server = TestServer::start_with_args(&["--admin-bind", &admin_address, "--invite-codes", "true"])?;
+    Ok((server, format!("http://{}", admin_address)))
+}
+
+async fn create_invite_code(api: &str, code: &str, max_uses: i32) -> anyhow::Result<Value> {
+    let invite_code = reqwest::Client::new()
+        .post(format!("{}/invite_codes", api))
+        .json(&json!({ "code": code, "max_uses": max_uses }))
+        .send().await?
+        .error_for_status()?
+        .json::<Value>().await?;
+
+    Ok(invite_code)
+}
+
+#[tokio::test]
+async fn test_register_with_invite_code() -> anyhow::Result<()> {
+    let (server, api) = start_invite_only()?;
+    let invite_code = create_invite_code(&api, "closed-beta", 1).await?;
+    assert_eq!(invite_code["uses"], 0);
+
+    let mut session = connect(&server).await?;
+    session.connection.send_packet(&c2s::InviteCodeSubmit{ code: "unknown".to_string() })?;
+    await_packet_type::<s2c::InviteCodeNotFound>(&mut session).await?;
+
+    session.connection.send_packet(&c2s::InviteCodeSubmit{ code: "closed-beta".to_string() })?;
+    await_packet_type::<s2c::InviteCodeFree>(&mut session).await?;
+    register_user(&mut session, "invited").await?;
+
+    let invite_code = reqwest::get(format!("{}/invite_codes/closed-beta", api)).await?
+        .error_for_status()?
+        .json::<Value>().await?;
+    assert_eq!(invite_code["uses"], 1);
+
+    /* the code has been used up and only lets its user login */
+    let mut session = connect(&server).await?;
+    session.connection.send_packet(&c2s::InviteCodeSubmit{ code: "closed-beta".to_string() })?;
+    let activated = await_packet_type::<s2c::InviteCodeAlreadyActivated>(&mut session).await?;
+    assert_eq!(activated.nickname, "invited");
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_create_duplicate_invite_code() -> anyhow::Result<()> {
+    let (_server, api) = start_invite_only()?;
+    create_invite_code(&api, "duplicate", 5).await?;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/invite_codes", api))
+        .json(&json!({ "code": "duplicate" }))
+        .send().await?;
+    assert_eq!(response.status(), reqwest::StatusCode::CONFLICT);
+
+    let generated = reqwest::Client::new()
+        .post(format!("{}/invite_codes", api))
+        .json(&json!({ "max_uses": 3, "expires_in": "7d" }))
+        .send().await?
+        .error_for_status()?
+        .json::<Value>().await?;
+    assert_eq!(generated["code"].as_str().map(str::len), Some(12));
+    assert!(generated["expires"].is_string());
+    Ok(())
+}
@@ -0,0 +1,108 @@
+mod common;
+
+use common::*;
+use fost_client_utils::Session;
+use fost_protocol::packets::{c2s, s2c, PacketDowncast};
+use serde_json::Value;
+
+/// Login with the credentials and return the login token.
+async fn login_remembered(server: &TestServer, uid: &str, password: &str) -> anyhow::Result<String> {
+    let mut session = connect(server).await?;
+    session.connection.send_packet(&c2s::AccountLoginExecute{
+        login: uid.to_string(),
+        password: password.to_string(),
+        remember: true,
+    })?;
+
+    let token = await_packet_type::<s2c::AccountLoginHashUpdate>(&mut session).await?;
+    Ok(token.hash)
+}
+
+/// Login with the token. Returns the replacement token or `None` if the token has been rejected.
+async fn login_with_token(server: &TestServer, token: &str) -> anyhow::Result<Option<String>> {
+    let mut session = connect(server).await?;
+    session.connection.send_packet(&c2s::AccountLoginHashLogin{ hash: token.to_string() })?;
+    await_packet(&mut session, |packet| {
+        if let Some(update) = packet.downcast_ref::<s2c::AccountLoginHashUpdate>() {
+            Some(Some(update.hash.clone()))
+        } else if packet.downcast_ref::<s2c::AccountLoginHashLoginFailed>().is_some() {
+            Some(None)
+        } else {
+            None
+        }
+    }).await
+}
+
+async fn login(session: &mut Session, uid: &str, password: &str) -> anyhow::Result<bool> {
+    session.connection.send_packet(&c2s::AccountLoginExecute{
+        login: uid.to_string(),
+        password: password.to_string(),
+        remember: false,
+    })?;
+
+    await_packet(session, |packet| {
+        if packet.downcast_ref::<s2c::AccountLoginSuccess>().is_some() {
+            Some(true)
+        } else if packet.downcast_ref::<s2c::AccountLoginFailure>().is_some() {
+            Some(false)
+        } else {
+            None
+        }
+    }).await
+}
+
+#[tokio::test]
+async fn test_login_tokens_rotate() -> anyhow::Result<()> {
+    let server = TestServer::start()?;
+    connect_user(&server, "roaming").await?;
+
+    /* every device receives its own token */
+    let desktop = login_remembered(&server, "roaming", "password123").await?;
+    let laptop = login_remembered(&server, "roaming", "password123").await?;
+    assert_ne!(desktop, laptop);
+
+    let rotated = login_with_token(&server, &desktop).await?
+        .expect("the token to be accepted");
+    assert_ne!(rotated, desktop);
+
+    assert_eq!(login_with_token(&server, &desktop).await?, None);
+    assert!(login_with_token(&server, &rotated).await?.is_some());
+    assert!(login_with_token(&server, &laptop).await?.is_some());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_revoke_tokens() -> anyhow::Result<()> {
+    let admin_address = unused_address()?.to_string();
+    let server = TestServer::start_with_args(&["--admin-bind", &admin_address])?;
+    connect_user(&server, "stolen").await?;
+    let token = login_remembered(&server, "stolen", "password123").await?;
+
+    let response = reqwest::Client::new()
+        .delete(format!("http://{}/users/stolen/tokens", admin_address))
+        .send().await?
+        .error_for_status()?
+        .json::<Value>().await?;
+    assert_eq!(response["revoked"], 1);
+
+    assert_eq!(login_with_token(&server, &token).await?, None);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_failed_logins_throttled() -> anyhow::Result<()> {
+    let server = TestServer::start_with_args(&["--max-failed-logins-per-account", "2"])?;
+    connect_user(&server, "targeted").await?;
+    connect_user(&server, "bystander").await?;
+
+    let mut session = connect(&server).await?;
+    assert!(!login(&mut session, "targeted", "guess-1").await?);
+    assert!(!login(&mut session, "Targeted", "guess-2").await?);
+
+    /* even the correct password is rejected until the window passed */
+    assert!(!login(&mut session, "targeted", "password123").await?);
+
+    let mut session = connect(&server).await?;
+    assert!(login(&mut session, "bystander", "password123").await?);
+    Ok(())
+}
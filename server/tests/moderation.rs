@@ -0,0 +1,64 @@
+mod common;
+
+use common::*;
+use fost_client_utils::Session;
+use fost_protocol::packets::{c2s, s2c, PacketDowncast};
+
+fn send_chat(session: &mut Session, text: &str) -> anyhow::Result<()> {
+    session.connection.send_packet(&c2s::GlobalChatSendMessage{ target: "".to_string(), text: text.to_string() })?;
+    Ok(())
+}
+
+/// Wait for a system message starting with the given prefix.
+async fn await_system_message(session: &mut Session, prefix: &'static str) -> anyhow::Result<String> {
+    await_packet(session, move |packet| {
+        packet.downcast_ref::<s2c::GlobalChatAddMessages>()?
+            .messages
+            .iter()
+            .find(|message| message.system && message.text.starts_with(prefix))
+            .map(|message| message.text.clone())
+    }).await
+}
+
+#[tokio::test]
+async fn test_mute() -> anyhow::Result<()> {
+    let server = TestServer::start_with_args(&["--administrator", "mute_admin"])?;
+
+    let mut admin = connect_user(&server, "mute_admin").await?;
+    let mut victim = connect_user(&server, "mute_victim").await?;
+
+    send_chat(&mut admin, "/mute mute_victim 30m spam")?;
+    await_system_message(&mut admin, "Muted mute_victim").await?;
+
+    send_chat(&mut victim, "hello")?;
+    let warning = await_system_message(&mut victim, "You are muted").await?;
+    assert!(warning.ends_with("Reason: spam"), "unexpected warning: {}", warning);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_ban() -> anyhow::Result<()> {
+    let server = TestServer::start_with_args(&["--administrator", "ban_admin"])?;
+
+    let mut admin = connect_user(&server, "ban_admin").await?;
+    let mut victim = connect_user(&server, "ban_victim").await?;
+
+    send_chat(&mut admin, "/ban ban_victim 1d cheating")?;
+    await_system_message(&mut admin, "Banned ban_victim").await?;
+
+    let alert = await_packet_type::<s2c::AlertShow>(&mut victim).await?;
+    assert_eq!(alert.text, "You have been banned for 1 day. Reason: cheating");
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_command_not_permitted() -> anyhow::Result<()> {
+    let server = TestServer::start()?;
+
+    let mut user = connect_user(&server, "regular_user").await?;
+    let _other = connect_user(&server, "regular_other").await?;
+
+    send_chat(&mut user, "/ban regular_other 1d")?;
+    await_system_message(&mut user, "You are not allowed").await?;
+    Ok(())
+}
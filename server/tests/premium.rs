@@ -0,0 +1,31 @@
+mod common;
+
+use common::*;
+use fost_protocol::codec::{BattleMode, BattleTeam};
+use fost_protocol::packets::s2c;
+use nalgebra::Vector3;
+
+const RED_FLAG: Vector3<f32> = Vector3::new(-2200.0, 0.0, 100.0);
+const BLUE_FLAG: Vector3<f32> = Vector3::new(2200.0, 0.0, 100.0);
+
+#[tokio::test]
+async fn test_premium_score_bonus() -> anyhow::Result<()> {
+    let server = TestServer::start_with_args(&[
+        "--premium-starter-hours", "24",
+        "--premium-score-bonus-percent", "50",
+        "--beginner-pass-score-bonus-percent", "20",
+    ])?;
+
+    let mut red = connect_user(&server, "premium_red").await?;
+    create_battle(&mut red, BattleMode::Ctf).await?;
+    let red_incarnation = join_battle(&mut red, "premium_red", None, BattleTeam::Red).await?;
+
+    move_tank(&mut red, red_incarnation, BLUE_FLAG)?;
+    await_packet_type::<s2c::BattleCTFFlagTaken>(&mut red).await?;
+    move_tank(&mut red, red_incarnation, RED_FLAG)?;
+
+    /* the delivery is worth 50 score, the premium account adds 50% and the beginner pass 20% */
+    let score = await_packet_type::<s2c::AccountRankUpdateScore>(&mut red).await?;
+    assert_eq!(score.score, 85);
+    Ok(())
+}
@@ -0,0 +1,54 @@
+mod common;
+
+use common::*;
+use fost_protocol::codec::{BattleMode, BattleTeam};
+use fost_protocol::packets::{s2c, PacketDowncast};
+use nalgebra::Vector3;
+
+const RED_FLAG: Vector3<f32> = Vector3::new(-2200.0, 0.0, 100.0);
+const BLUE_FLAG: Vector3<f32> = Vector3::new(2200.0, 0.0, 100.0);
+
+#[tokio::test]
+async fn test_rank_up() -> anyhow::Result<()> {
+    /* the beginner pass would add bonus score */
+    let server = TestServer::start_with_args(&["--beginner-pass-days", "0"])?;
+
+    let mut red = connect_user(&server, "rank_red").await?;
+    let battle_id = create_battle(&mut red, BattleMode::Ctf).await?;
+    let red_incarnation = join_battle(&mut red, "rank_red", None, BattleTeam::Red).await?;
+
+    let mut blue = connect_user(&server, "rank_blue").await?;
+    join_battle(&mut blue, "rank_blue", Some(&battle_id), BattleTeam::Blue).await?;
+
+    /* every delivered flag is worth 50 score, the second one reaches the rank private */
+    for expected_score in [ 50, 100 ] {
+        move_tank(&mut red, red_incarnation, BLUE_FLAG)?;
+        await_packet_type::<s2c::BattleCTFFlagTaken>(&mut red).await?;
+
+        move_tank(&mut red, red_incarnation, RED_FLAG)?;
+        let score = await_packet(&mut red, move |packet| {
+            packet.downcast_ref::<s2c::AccountRankUpdateScore>()
+                .filter(|packet| packet.score == expected_score)
+                .map(|packet| packet.score)
+        }).await?;
+        assert_eq!(score, expected_score);
+    }
+
+    let rank_up = await_packet_type::<s2c::AccountRankRankUp>(&mut red).await?;
+    assert_eq!(rank_up.rank, 2);
+    assert_eq!(rank_up.score, 100);
+    assert_eq!(rank_up.next_rank_score, 500);
+    assert_eq!(rank_up.bonus_crystals, 10);
+
+    let crystals = await_packet_type::<s2c::AccountRankUpdateCrystals>(&mut red).await?;
+    assert_eq!(crystals.change_by, 10);
+
+    let battle_rank_up = await_packet_type::<s2c::BattleStatisticsUserRankUp>(&mut blue).await?;
+    assert_eq!(battle_rank_up.user_id, "rank_red");
+    assert_eq!(battle_rank_up.new_rank, 2);
+
+    /* the leaderboard is ordered by experience */
+    let rating = await_packet_type::<s2c::AccountRankUpdateRating>(&mut red).await?;
+    assert_eq!(rating.place, 1);
+    Ok(())
+}
@@ -0,0 +1,43 @@
+mod common;
+
+use common::*;
+use fost_protocol::packets::{c2s, s2c};
+
+#[tokio::test]
+async fn test_daily_quests() -> anyhow::Result<()> {
+    let server = TestServer::start()?;
+
+    let mut user = connect_user(&server, "quest_user").await?;
+    user.connection.send_packet(&c2s::QuestsOpenWindow{ })?;
+    let window = await_packet_type::<s2c::QuestsShowWindow>(&mut user).await?;
+    assert_eq!(window.quests.len(), 3);
+    assert!(window.quests.iter().all(|quest| quest.can_skip_for_free && quest.progress == 0));
+    assert!(!window.weekly_quest_description.done_for_today);
+
+    let quest_id = window.quests[0].quest_id;
+    user.connection.send_packet(&c2s::QuestsTakePrize{ quest_id })?;
+    let alert = await_packet_type::<s2c::AlertShow>(&mut user).await?;
+    assert_eq!(alert.text, "The quest has not been completed yet.");
+
+    user.connection.send_packet(&c2s::QuestsSkipFree{ quest_id })?;
+    let replaced = await_packet_type::<s2c::QuestsReplace>(&mut user).await?;
+    assert_eq!(replaced.quest_id, quest_id);
+    assert_ne!(replaced.new_quest.quest_id, quest_id);
+    assert!(!replaced.new_quest.can_skip_for_free);
+
+    /* only one free skip per day */
+    user.connection.send_packet(&c2s::QuestsSkipFree{ quest_id: window.quests[1].quest_id })?;
+    let alert = await_packet_type::<s2c::AlertShow>(&mut user).await?;
+    assert_eq!(alert.text, "You already skipped a quest for free today.");
+
+    user.connection.send_packet(&c2s::QuestsSkipPaid{ quest_id: window.quests[1].quest_id })?;
+    let alert = await_packet_type::<s2c::AlertShow>(&mut user).await?;
+    assert_eq!(alert.text, "You do not have enough crystals.");
+
+    /* the quests stay the same until the next rotation */
+    user.connection.send_packet(&c2s::QuestsOpenWindow{ })?;
+    let window = await_packet_type::<s2c::QuestsShowWindow>(&mut user).await?;
+    assert_eq!(window.quests.len(), 3);
+    assert!(window.quests.iter().any(|quest| quest.quest_id == replaced.new_quest.quest_id));
+    Ok(())
+}
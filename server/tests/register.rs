@@ -0,0 +1,51 @@
+mod common;
+
+use common::*;
+use fost_protocol::{codec::ValidationStatus, packets::{c2s, s2c}};
+
+#[tokio::test]
+async fn test_validate_uid() -> anyhow::Result<()> {
+    let server = TestServer::start()?;
+    connect_user(&server, "popular").await?;
+
+    let mut session = connect(&server).await?;
+    session.connection.send_packet(&c2s::AccountRegisterValidateUid{ uid: "popular".to_string() })?;
+    let busy = await_packet_type::<s2c::AccountRegisterUidBusy>(&mut session).await?;
+    assert_eq!(busy.adviced_uids.len(), 3);
+    assert!(busy.adviced_uids.iter().all(|uid| uid.starts_with("popular") && uid != "popular"));
+
+    session.connection.send_packet(&c2s::AccountRegisterValidateUid{ uid: "unpopular".to_string() })?;
+    await_packet_type::<s2c::AccountRegisterUidFree>(&mut session).await?;
+
+    session.connection.send_packet(&c2s::AccountRegisterValidateUid{ uid: "no spaces".to_string() })?;
+    await_packet_type::<s2c::AccountRegisterUidIncorrect>(&mut session).await?;
+
+    for (uid, expected) in [
+        ("ab", ValidationStatus::TooShort),
+        ("SuperModerator", ValidationStatus::Forbidden),
+        ("popular", ValidationStatus::NotUnique),
+        ("popular2", ValidationStatus::Correct),
+    ] {
+        session.connection.send_packet(&c2s::UidCheckRequest{ uid: uid.to_string() })?;
+        let response = await_packet_type::<s2c::UidCheckResponse>(&mut session).await?;
+        assert_eq!(response.status, expected, "{}", uid);
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_register_invalid_uid() -> anyhow::Result<()> {
+    let server = TestServer::start()?;
+    let mut session = connect(&server).await?;
+
+    /* the server validates the username even if the client skipped the validation */
+    session.connection.send_packet(&c2s::AccountRegisterSubmit{
+        uid: "the_moderator".to_string(),
+        password: "password123".to_string(),
+        remember_me: false,
+    })?;
+    await_packet_type::<s2c::AccountRegisterUidIncorrect>(&mut session).await?;
+
+    register_user(&mut session, "the_tanker").await?;
+    Ok(())
+}
@@ -0,0 +1,183 @@
+mod common;
+
+use std::{path::PathBuf, time::Duration};
+
+use anyhow::Context;
+use common::*;
+use fost_client_utils::{Session, DummyResourceLoader, LowLevelPing, SessionPing};
+use fost_protocol::{codec::{BattleMode, BattleTeam, LayoutState}, packets::{c2s, s2c, PacketDowncast}};
+use serde_json::{json, Value};
+
+/// Resource id already part of the lobby registry.
+const LOBBY_RESOURCE_ID: u32 = 745565;
+
+fn image_resource(id: u32) -> Value {
+    json!({ "idhigh": "0", "idlow": id, "versionhigh": "0", "versionlow": 1, "lazy": true, "alpha": false, "type": 10 })
+}
+
+/// Fresh directory for the files of a test.
+fn test_directory(name: &str) -> anyhow::Result<PathBuf> {
+    let directory = std::env::temp_dir().join(format!("fost-{}-{}", name, unused_address()?.port()));
+    let _ = std::fs::remove_dir_all(&directory);
+    std::fs::create_dir_all(&directory)?;
+    Ok(directory)
+}
+
+/// Ids of the resources registered by the packet.
+fn registered_ids(packet: &s2c::ResourceLoaderRegisterResources) -> anyhow::Result<Vec<u64>> {
+    let payload = serde_json::from_str::<Value>(&packet.json)?;
+    payload["resources"].as_array()
+        .context("missing resources")?
+        .iter()
+        .map(|resource| resource["idlow"].as_u64().context("missing resource id"))
+        .collect()
+}
+
+/// Wait until the layout switch ended. Returns the resources registered while switching.
+async fn await_layout_resources(session: &mut Session, state: LayoutState) -> anyhow::Result<Vec<u64>> {
+    let mut resources = Vec::new();
+    loop {
+        let packet = await_packet(session, move |packet| {
+            if let Some(packet) = packet.downcast_ref::<s2c::ResourceLoaderRegisterResources>() {
+                Some(Some(packet.clone()))
+            } else {
+                packet.downcast_ref::<s2c::LobbyLayoutSwitchEnd>()
+                    .filter(|packet| packet.state == state)
+                    .map(|_| None)
+            }
+        }).await?;
+
+        match packet {
+            Some(packet) => resources.extend(registered_ids(&packet)?),
+            None => return Ok(resources),
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_registry_stages() -> anyhow::Result<()> {
+    let server = TestServer::start()?;
+    let mut session = Session::builder()
+        .connect(server.address)
+        .await?;
+    session.register_packet_handler(DummyResourceLoader{});
+    session.register_packet_handler(LowLevelPing{});
+    session.register_packet_handler(SessionPing{});
+
+    let connect = await_packet_type::<s2c::ResourceLoaderRegisterResources>(&mut session).await?;
+    assert!(!registered_ids(&connect)?.is_empty());
+
+    let auth = await_packet_type::<s2c::ResourceLoaderRegisterResources>(&mut session).await?;
+    assert!(!registered_ids(&auth)?.is_empty());
+    session.await_server_resources_loaded().await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_on_demand_resources() -> anyhow::Result<()> {
+    let directory = test_directory("registry")?;
+    let maps_registry = directory.join("maps.json");
+    std::fs::write(&maps_registry, json!({
+        "map_silence_moon": [ image_resource(700001), image_resource(LOBBY_RESOURCE_ID) ]
+    }).to_string())?;
+
+    let garage_registry = directory.join("garage.json");
+    std::fs::write(&garage_registry, json!({
+        "smoky": [ image_resource(700002) ],
+        "hunter": [ image_resource(700001) ]
+    }).to_string())?;
+
+    let server = TestServer::start_with_args(&[
+        "--registry-maps", maps_registry.to_str().context("invalid path")?,
+        "--registry-garage", garage_registry.to_str().context("invalid path")?,
+    ])?;
+    let mut session = connect_user(&server, "collector").await?;
+
+    create_battle(&mut session, BattleMode::Dm).await?;
+    session.connection.send_packet(&c2s::BattleInfoJoinBattle{ team: BattleTeam::None })?;
+    /* resources already registered with the lobby are not sent again */
+    assert_eq!(await_layout_resources(&mut session, LayoutState::Battle).await?, vec![700001]);
+
+    session.connection.send_packet(&c2s::LayoutSwitchGarage{})?;
+    assert_eq!(await_layout_resources(&mut session, LayoutState::Garage).await?, vec![700002]);
+
+    session.connection.send_packet(&c2s::LayoutSwitchBattleSelect{})?;
+    await_layout_resources(&mut session, LayoutState::BattleSelect).await?;
+    session.connection.send_packet(&c2s::LayoutSwitchGarage{})?;
+    assert!(await_layout_resources(&mut session, LayoutState::Garage).await?.is_empty());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_resource_server() -> anyhow::Result<()> {
+    let directory = test_directory("resource-files")?;
+    let resource_directory = directory.join("0/21/74/116/12");
+    std::fs::create_dir_all(&resource_directory)?;
+    std::fs::write(resource_directory.join("image.jpg"), b"jpeg payload")?;
+    std::fs::write(directory.join("secret.txt"), b"secret")?;
+
+    let address = unused_address()?.to_string();
+    let server = TestServer::start_with_args(&[
+        "--resources-bind", &address,
+        "--resources-directory", directory.to_str().context("invalid path")?,
+    ])?;
+    /* the resource server has been started once clients are accepted */
+    connect(&server).await?;
+
+    let client = reqwest::Client::new();
+    let response = client.get(format!("http://{}/0/21/74/116/12/image.jpg", address))
+        .send().await?
+        .error_for_status()?;
+    assert_eq!(response.headers()["content-type"], "image/jpeg");
+    assert_eq!(response.bytes().await?.as_ref(), b"jpeg payload");
+
+    for path in ["0/21/74/116/12/missing.jpg", "secret.txt", "0/21/74/116/12/..%2F..%2F..%2F..%2F..%2Fsecret.txt"] {
+        let response = client.get(format!("http://{}/{}", address, path))
+            .send().await?;
+        assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND, "{}", path);
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_shared_resources_await_pending_stage() -> anyhow::Result<()> {
+    let directory = test_directory("shared-registry")?;
+    let maps_registry = directory.join("maps.json");
+    std::fs::write(&maps_registry, json!({
+        "map_silence_moon": [ image_resource(700001) ]
+    }).to_string())?;
+
+    let garage_registry = directory.join("garage.json");
+    std::fs::write(&garage_registry, json!({
+        "hunter": [ image_resource(700001) ]
+    }).to_string())?;
+
+    let server = TestServer::start_with_args(&[
+        "--registry-maps", maps_registry.to_str().context("invalid path")?,
+        "--registry-garage", garage_registry.to_str().context("invalid path")?,
+    ])?;
+    let mut session = Session::builder()
+        .connect(server.address)
+        .await?;
+    let resource_loader = session.register_packet_handler(DummyResourceLoader{});
+    session.register_packet_handler(LowLevelPing{});
+    session.register_packet_handler(SessionPing{});
+    session.await_server_resources_loaded().await?;
+    register_user(&mut session, "sharer").await?;
+    create_battle(&mut session, BattleMode::Dm).await?;
+
+    /* the map resources are registered but their loading has not been confirmed */
+    session.remove_packet_handler(resource_loader);
+    session.connection.send_packet(&c2s::BattleInfoJoinBattle{ team: BattleTeam::None })?;
+    let map_request = await_packet_type::<s2c::ResourceLoaderRegisterResources>(&mut session).await?;
+    assert_eq!(registered_ids(&map_request)?, vec![700001]);
+
+    /* the garage only contains the same resource and must wait for the pending map request */
+    session.connection.send_packet(&c2s::LayoutSwitchGarage{})?;
+    let garage_opened = tokio::time::timeout(Duration::from_millis(500), await_layout_resources(&mut session, LayoutState::Garage)).await;
+    assert!(garage_opened.is_err(), "the garage opened before its resources have been loaded");
+
+    session.connection.send_packet(&c2s::ResourceLoaderResourcesRegistered{ callback_id: map_request.callback_id })?;
+    assert!(await_layout_resources(&mut session, LayoutState::Garage).await?.is_empty());
+    Ok(())
+}
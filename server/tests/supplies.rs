@@ -0,0 +1,63 @@
+mod common;
+
+use common::*;
+use fost_protocol::codec::{BattleMode, BattleTeam};
+use fost_protocol::packets::{c2s, s2c, PacketDowncast};
+use nalgebra::Vector3;
+
+const MINE_POSITION: Vector3<f32> = Vector3::new(1000.0, 1000.0, 100.0);
+
+#[tokio::test]
+async fn test_activate_supply() -> anyhow::Result<()> {
+    let server = TestServer::start()?;
+
+    let mut user = connect_user(&server, "supply_user").await?;
+    create_battle(&mut user, BattleMode::Dm).await?;
+    join_battle(&mut user, "supply_user", None, BattleTeam::None).await?;
+
+    user.connection.send_packet(&c2s::BattleDrugsActivate{ item_id: "armor".to_string() })?;
+    let activated = await_packet_type::<s2c::BattleDrugsActivated>(&mut user).await?;
+    assert_eq!(activated.item_id, "armor");
+    assert!(activated.decrease);
+    assert!(activated.time > 0);
+
+    let effect = await_packet_type::<s2c::TankEffectApply>(&mut user).await?;
+    assert_eq!(effect.tank_id, "supply_user");
+    assert_eq!(effect.effect_id, 2);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_mine_explodes() -> anyhow::Result<()> {
+    let server = TestServer::start()?;
+
+    let mut owner = connect_user(&server, "mine_owner").await?;
+    let battle_id = create_battle(&mut owner, BattleMode::Dm).await?;
+    let owner_incarnation = join_battle(&mut owner, "mine_owner", None, BattleTeam::None).await?;
+
+    let mut target = connect_user(&server, "mine_target").await?;
+    let target_incarnation = join_battle(&mut target, "mine_target", Some(&battle_id), BattleTeam::None).await?;
+
+    move_tank(&mut owner, owner_incarnation, MINE_POSITION)?;
+    owner.connection.send_packet(&c2s::BattleDrugsActivate{ item_id: "mine".to_string() })?;
+    let placed = await_packet_type::<s2c::BattleMinesPlace>(&mut owner).await?;
+    assert_eq!(placed.user_id, "mine_owner");
+    assert_eq!(Vector3::new(placed.x, placed.y, placed.z), MINE_POSITION);
+
+    let activated = await_packet_type::<s2c::BattleMinesActivate>(&mut target).await?;
+    assert_eq!(activated.mine_id, placed.mine_id);
+
+    move_tank(&mut target, target_incarnation, MINE_POSITION)?;
+    let exploded = await_packet_type::<s2c::BattleMinesExplode>(&mut target).await?;
+    assert_eq!(exploded.mine_id, placed.mine_id);
+    assert_eq!(exploded.target_id, "mine_target");
+
+    let tank_id = "mine_target".to_string();
+    let health = await_packet(&mut target, move |packet| {
+        packet.downcast_ref::<s2c::TankHealth>()
+            .filter(|packet| packet.tank_id == tank_id)
+            .map(|packet| packet.health)
+    }).await?;
+    assert!(health < 10_000.0);
+    Ok(())
+}
@@ -0,0 +1,61 @@
+mod common;
+
+use common::*;
+use fost_protocol::codec::{BattleMode, BattleTeam};
+use fost_protocol::packets::{c2s, s2c, PacketDowncast};
+use nalgebra::Vector3;
+
+const POSITION: Vector3<f32> = Vector3::new(1000.0, 1000.0, 100.0);
+
+#[tokio::test]
+async fn test_mounted_firebird() -> anyhow::Result<()> {
+    let admin_address = unused_address()?.to_string();
+    let server = TestServer::start_with_args(&["--admin-bind", &admin_address])?;
+
+    let mut shooter = connect_user(&server, "firebird_shooter").await?;
+    reqwest::Client::new()
+        .post(format!("http://{}/users/firebird_shooter/items", admin_address))
+        .json(&serde_json::json!({ "item_id": "flamethrower_m0" }))
+        .send().await?
+        .error_for_status()?;
+
+    shooter.connection.send_packet(&c2s::GarageMountItem{ item: "flamethrower_m0".to_string() })?;
+    let mounted = await_packet_type::<s2c::GarageInitMounted>(&mut shooter).await?;
+    assert_eq!(mounted.item_id, "flamethrower_m0");
+
+    let battle_id = create_battle(&mut shooter, BattleMode::Dm).await?;
+    let shooter_incarnation = join_battle(&mut shooter, "firebird_shooter", None, BattleTeam::None).await?;
+
+    let mut target = connect_user(&server, "firebird_target").await?;
+    let target_incarnation = join_battle(&mut target, "firebird_target", Some(&battle_id), BattleTeam::None).await?;
+
+    /* the chat message is echoed after the movement has been processed */
+    move_tank(&mut target, target_incarnation, POSITION)?;
+    target.connection.send_packet(&c2s::BattleMessageSend{ message: "ready".to_string(), team_only: false })?;
+    await_packet_type::<s2c::BattleMessageMessage>(&mut target).await?;
+
+    move_tank(&mut shooter, shooter_incarnation, POSITION)?;
+    shooter.connection.send_packet(&c2s::WeaponFirebirdStart{ time: 0 })?;
+    shooter.connection.send_packet(&c2s::WeaponFirebirdHit{
+        time: 200,
+        targets: vec!["firebird_target".to_string()],
+        incarnations: vec![target_incarnation],
+        var_2638: Vector3::zeros(),
+        var_782: Vector3::zeros(),
+    })?;
+    let heated = await_packet_type::<s2c::TankUpdateTemperature>(&mut shooter).await?;
+    assert_eq!(heated.tank_id, "firebird_target");
+    assert!(heated.temperature > 0.0);
+
+    let started = await_packet_type::<s2c::WeaponFirebirdStart>(&mut target).await?;
+    assert_eq!(started.shooter, "firebird_shooter");
+
+    let tank_id = "firebird_target".to_string();
+    let health = await_packet(&mut target, move |packet| {
+        packet.downcast_ref::<s2c::TankHealth>()
+            .filter(|packet| packet.tank_id == tank_id)
+            .map(|packet| packet.health)
+    }).await?;
+    assert!(health < 10_000.0);
+    Ok(())
+}